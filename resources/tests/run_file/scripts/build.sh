@@ -0,0 +1,2 @@
+echo "{{ var.message }}"
+./build.sh
@@ -1,16 +1,25 @@
+mod archive;
+pub mod events;
+mod executor;
+pub mod git;
+pub mod outputs;
+mod report;
+
 use std::{
-    collections::BTreeMap,
+    collections::{BTreeMap, VecDeque},
+    ffi::{OsStr, OsString},
     fs::File,
-    io::Write,
+    io::{BufWriter, Write},
+    num::NonZeroUsize,
     path::PathBuf,
     sync::{Arc, Mutex},
-    time::Instant,
+    time::{Instant, SystemTime},
 };
 
 use anyhow::bail;
 use console::{style, Color};
 use indicatif::{MultiProgress, ProgressBar};
-use log::debug;
+use log::{debug, warn};
 use tokio::{
     io::{AsyncBufReadExt, AsyncRead, BufReader},
     process::{ChildStderr, ChildStdout},
@@ -20,31 +29,95 @@ use tokio::{
 };
 
 use crate::{
+    baker::{
+        events::{EventStream, RecipeEvent},
+        executor::{LocalExecutor, RecipeExecutor},
+        git::GitState,
+        outputs::OutputsBackup,
+    },
     cache::{Cache, CacheResult},
-    project::{config::ToolConfig, BakeProject, Recipe, Status},
+    error::BakeError,
+    project::{
+        config::{OutputCapture, Shell, ToolConfig},
+        BakeProject, Platform, Recipe, RecipeSearch, Status,
+    },
+    template::{get_environment_values, parse_environment_entry},
 };
 
 type RecipeQueue = Arc<Mutex<BTreeMap<String, Recipe>>>;
 
-/// Bakes a project by running all recipes and their dependencies
+/// Bakes a project by running all recipes and their dependencies on this host
 ///
 /// # Arguments
 /// * `project` - The project to bake
-/// * `filter` - Optional recipe pattern to filter such as `foo:`
+/// * `search` - Criteria used to select the recipes to bake such as `foo:`
 ///
 pub async fn bake(
     project: Arc<BakeProject>,
     cache: Cache,
-    filter: Option<&str>,
+    search: &RecipeSearch,
+) -> anyhow::Result<()> {
+    bake_with_executor(project, cache, search, Arc::new(LocalExecutor)).await
+}
+
+/// Returns the warning printed when a pattern matches no recipes, suggesting the closest ones
+///
+/// # Arguments
+/// * `pattern` - The pattern that matched no recipes
+/// * `suggestions` - Existing patterns close to it, see `BakeProject::suggest_patterns`
+///
+fn no_match_warning(pattern: &str, suggestions: &[String]) -> String {
+    let mut warning = format!("No recipes matched '{}'.", pattern);
+    if !suggestions.is_empty() {
+        let suggestions: Vec<String> = suggestions
+            .iter()
+            .map(|suggestion| format!("'{}'", suggestion))
+            .collect();
+        warning.push_str(&format!(" Did you mean {}?", suggestions.join(" or ")));
+    }
+    warning
+}
+
+/// Bakes a project by running all recipes and their dependencies with the given executor, which
+/// lets tests bake without spawning processes
+///
+/// # Arguments
+/// * `project` - The project to bake
+/// * `search` - Criteria used to select the recipes to bake such as `foo:`
+/// * `executor` - Executor used to run recipes that aren't restored from cache
+///
+pub(crate) async fn bake_with_executor(
+    project: Arc<BakeProject>,
+    cache: Cache,
+    search: &RecipeSearch,
+    executor: Arc<dyn RecipeExecutor>,
 ) -> anyhow::Result<()> {
     // Create .bake directories
     project.create_project_bake_dirs()?;
 
-    let recipes = project.get_recipes(filter);
+    let start_time = Instant::now();
+    let recipes = project.get_recipes(search);
+    if let RecipeSearch::ByPattern(pattern) = search {
+        if recipes.is_empty() {
+            println!(
+                "{} {}",
+                style("!").yellow(),
+                no_match_warning(pattern, &project.suggest_patterns(pattern))
+            );
+        }
+    }
+    let recipes = project.filter_platform(recipes, &Platform::host())?;
     let recipe_queue = RecipeQueue::new(Mutex::new(recipes));
     let (shutdown_tx, mut shutdown_rx) = mpsc::unbounded_channel();
     let mut join_set = JoinSet::new();
     let arc_cache = Arc::new(cache);
+    let events = project
+        .config
+        .events_fd
+        .map(EventStream::from_fd)
+        .transpose()?
+        .map(Arc::new);
+    let git = Arc::new(GitState::new(project.root_path.clone()));
 
     let multi_progress = Arc::new(MultiProgress::new());
 
@@ -54,11 +127,17 @@ pub async fn bake(
         let recipe_queue = recipe_queue.clone();
         let multi_progress = multi_progress.clone();
         let cache = arc_cache.clone();
+        let executor = executor.clone();
+        let events = events.clone();
+        let git = git.clone();
 
         join_set.spawn(runner(
             arc_project,
             recipe_queue,
             cache,
+            executor,
+            events,
+            git,
             shutdown_tx,
             multi_progress,
         ));
@@ -76,30 +155,143 @@ pub async fn bake(
             while (join_set.join_next().await).is_some() {}
         } => {}
     }
+    // Aborted runners are only dropped once joined, releasing the project and killing their
+    // recipes' processes
+    while (join_set.join_next().await).is_some() {}
 
-    let errors: Vec<String> = recipe_queue
+    let errors: Vec<(String, Option<i32>)> = recipe_queue
         .lock()
         .unwrap()
         .iter()
         .filter_map(|(_, recipe)| {
             if matches!(recipe.run_status.status, Status::Error) {
-                Some(recipe.full_name())
+                Some((recipe.full_name(), recipe.run_status.exit_code))
             } else {
                 None
             }
         })
         .collect();
 
+    let recipes_run = recipe_queue
+        .lock()
+        .unwrap()
+        .values()
+        .filter(|recipe| matches!(recipe.run_status.status, Status::Done | Status::Error))
+        .count();
+
+    let hook = if errors.is_empty() {
+        project.config.hooks.on_success.as_ref()
+    } else {
+        project.config.hooks.on_failure.as_ref()
+    };
+    if let Some(hook) = hook {
+        run_hook(
+            &project,
+            hook,
+            &[
+                ("BAKE_FAILED_COUNT", errors.len().to_string()),
+                (
+                    "BAKE_DURATION_MS",
+                    start_time.elapsed().as_millis().to_string(),
+                ),
+                ("BAKE_RECIPES_RUN", recipes_run.to_string()),
+            ],
+        )
+        .await;
+    }
+
+    if let Some(summary_file) = project.config.summary_file.as_ref() {
+        report::write_junit_report(&project, &recipe_queue.lock().unwrap(), summary_file)?;
+    }
+
+    if let Some(report_file) = project.config.report_file.as_ref() {
+        report::write_html_report(&project, &recipe_queue.lock().unwrap(), report_file)?;
+    }
+
     if !errors.is_empty() {
-        bail!(
-            "Some recipes failed to run: \n{} {}",
-            console::style("✗").red(),
-            errors.join(&format!("\n{} ", console::style("✗").red()))
-        );
+        bail!(BakeError::RecipesFailed(errors));
     }
     Ok(())
 }
 
+/// Runs a project hook in the project root. Hook failures are only logged so they never change
+/// the outcome of a bake
+///
+/// # Arguments
+/// * `project` - The project being baked
+/// * `hook` - The shell command to run
+/// * `env` - Environment variables describing the bake result
+///
+async fn run_hook(project: &BakeProject, hook: &str, env: &[(&str, String)]) {
+    debug!("Running hook: {}", hook);
+    let result = tokio::process::Command::new("sh")
+        .current_dir(&project.root_path)
+        .arg("-c")
+        .arg(hook)
+        .envs(env.iter().map(|(k, v)| (k, v)))
+        .status()
+        .await;
+
+    match result {
+        Ok(status) if !status.success() => {
+            warn!("Hook `{}` failed with exit code {}", hook, status);
+        }
+        Ok(_) => {}
+        Err(err) => {
+            warn!("Could not spawn hook `{}`: {}", hook, err);
+        }
+    }
+}
+
+/// Returns the name of the next recipe to start, if any is ready
+///
+/// Recipes are dispatched in order of their fully qualified names: the queue is sorted, and the
+/// first idle recipe whose dependencies are done, whose weight fits in the remaining capacity
+/// and whose cookbook is under its `max_parallel` is picked. Dispatch never depends on hashing or
+/// timing, so the same plan always starts its recipes in the same order. A recipe heavier than
+/// the whole capacity can still run alone.
+///
+/// # Arguments
+/// * `project` - The project being baked, which sets the capacity and cookbook limits
+/// * `queue` - The recipes being baked and their status
+///
+fn next_ready_recipe(project: &BakeProject, queue: &BTreeMap<String, Recipe>) -> Option<String> {
+    let capacity = project
+        .config
+        .total_capacity
+        .unwrap_or(project.config.max_parallel as u32);
+    let running: Vec<&Recipe> = queue
+        .values()
+        .filter(|recipe| recipe.run_status.status == Status::Running)
+        .collect();
+    let running_weight: u32 = running.iter().map(|recipe| recipe.weight).sum();
+
+    queue
+        .iter()
+        .find(|(_, recipe)| {
+            let fits = running_weight == 0 || running_weight + recipe.weight <= capacity;
+            if recipe.run_status.status != Status::Idle || !fits {
+                return false;
+            }
+            if let Some(limit) = project.cookbook_max_parallel(&recipe.cookbook) {
+                let cookbook_running = running
+                    .iter()
+                    .filter(|running| running.cookbook == recipe.cookbook)
+                    .count();
+                if cookbook_running >= limit.get() {
+                    return false;
+                }
+            }
+            // Dependencies that aren't in the queue don't hold the recipe back
+            !recipe.dependencies.iter().flatten().any(|dependency| {
+                queue.get(&dependency.recipe).is_some_and(|dep| {
+                    matches!(dep.run_status.status, Status::Running | Status::Idle)
+                })
+            })
+        })
+        .map(|(name, _)| name.clone())
+}
+
 /// Runners are spawned in parallel to run recipes that were added to the queue
 ///
 /// runner also handles printing the progress bar to the console if needed
@@ -108,50 +300,32 @@ pub async fn bake(
 /// * `project` - The project to bake
 /// * `recipe_queue` - The shared queue of recipes
 /// * `status_map` - The shared status map
+/// * `events` - Stream to report finished recipes to
+/// * `git` - State of the git tree, checked by recipes requiring a clean tree
 /// * `shutdown_tx` - The channel to send shutdown signals
 /// * `multi_progress` - The multi progress bar
 ///
+#[allow(clippy::too_many_arguments)]
 async fn runner(
     project: Arc<BakeProject>,
     recipe_queue: RecipeQueue,
     cache: Arc<Cache>,
+    executor: Arc<dyn RecipeExecutor>,
+    events: Option<Arc<EventStream>>,
+    git: Arc<GitState>,
     shutdown_tx: mpsc::UnboundedSender<()>,
     multi_progress: Arc<MultiProgress>,
 ) -> Result<(), String> {
     loop {
         let mut next_recipe_name: Option<String> = None;
-        if let Ok(queue) = recipe_queue.lock() {
+        if let Ok(mut queue) = recipe_queue.lock() {
             // If there are no more recipes to process, quit runner loop
             if queue.is_empty() {
                 break;
             }
 
-            // Find the first Idle recipe
-            let result = queue.iter().find(|(_, recipe)| {
-                if recipe.run_status.status == Status::Idle {
-                    // If the recipe has dependencies, check if any are still running or idle
-                    if let Some(dependencies) = recipe.dependencies.as_ref() {
-                        let pending = dependencies.iter().any(|dep_name| {
-                            if let Some(dep_rec) = queue.get(dep_name) {
-                                matches!(dep_rec.run_status.status, Status::Running | Status::Idle)
-                            } else {
-                                // If the dependency is not in the queue, it is considered pending
-                                false
-                            }
-                        });
-                        !pending
-                    } else {
-                        // If the recipe has no dependencies, it can be run
-                        true
-                    }
-                } else {
-                    // If the recipe is not idle, it cannot be run
-                    false
-                }
-            });
-
             // If a recipe was found, use it as next recipe
-            if let Some((recipe_name, _)) = result {
+            if let Some(recipe_name) = next_ready_recipe(&project, &queue) {
                 // If any of the depdencies errored, quit runner loop
                 if queue
                     .iter()
@@ -159,7 +333,9 @@ async fn runner(
                 {
                     break;
                 }
-                next_recipe_name = Some(recipe_name.clone());
+                // Mark it as running right away so other runners account for its weight
+                queue.get_mut(&recipe_name).unwrap().run_status.status = Status::Running;
+                next_recipe_name = Some(recipe_name);
             } else if queue
                 .iter()
                 .all(|(_, recipe)| matches!(recipe.run_status.status, Status::Done | Status::Error))
@@ -171,7 +347,8 @@ async fn runner(
 
         if let Some(next_recipe_name) = next_recipe_name {
             let mut progress_bar: Option<ProgressBar> = None;
-            if !project.config.verbose {
+            let output = recipe_queue.lock().unwrap()[&next_recipe_name].output;
+            if project.config.output_capture(output) != OutputCapture::Tee {
                 progress_bar = Some(
                     multi_progress.add(
                         ProgressBar::new_spinner()
@@ -192,52 +369,91 @@ async fn runner(
                 } => {},
                 // Update status and run recipe asynchronously, awaiting for the result
                 _ = async {
-                    let next_recipe: Recipe;
-                    {
-                        let mut queue_mutex = recipe_queue.lock().unwrap();
-                        let recipe = queue_mutex.get_mut(&next_recipe_name).unwrap();
-                        if recipe.run_status.status == Status::Idle {
-                            recipe.run_status.status = Status::Running;
-                            next_recipe = recipe.clone();
-                        } else {
-                            return;
-                        }
-                    }
+                    let next_recipe = recipe_queue.lock().unwrap()[&next_recipe_name].clone();
 
                     // let result = run_recipe(&next_recipe, project.get_recipe_log_path(&next_recipe.full_name()), project.config.verbose).await;
+                    let recipe_start_time = Instant::now();
+                    let recipe_started_at = SystemTime::now();
                     let mut cached = false;
+                    let mut exit_code = None;
                     let result: Result<(), String>;
-                    if next_recipe.cache.is_some() && matches!(cache.get(&next_recipe.full_name()).await, CacheResult::Hit(_)) {
+                    let cache_result = if project.is_cached(&next_recipe) {
+                        cache.get(&next_recipe.full_name()).await
+                    } else {
+                        Ok(CacheResult::Miss)
+                    };
+                    match cache_result {
+                        Ok(CacheResult::Hit(_)) => {
                             println!("{}: {} (cached)", next_recipe_name, console::style("✓").green());
                             cached = true;
                             result = Ok(());
-                    } else {
-                        result = run_recipe(&next_recipe, project.get_recipe_log_path(&next_recipe.full_name()), &project.config).await;
+                        }
+                        Ok(CacheResult::Miss) => {
+                            result = match ensure_git_state(&project, &git, &next_recipe).await {
+                                Ok(_) => {
+                                    let run_result = run_recipe_with_outputs(&project, executor.as_ref(), &next_recipe).await;
+                                    exit_code = match &run_result {
+                                        Ok(_) => Some(0),
+                                        Err(err) => match err.downcast_ref::<BakeError>() {
+                                            Some(BakeError::RecipeFailed { code, .. }) => *code,
+                                            _ => None,
+                                        },
+                                    };
+                                    run_result.map_err(|err| err.to_string())
+                                }
+                                Err(err) => Err(err),
+                            };
+                        }
+                        Err(err) => {
+                            result = Err(err.to_string());
+                        }
+                    }
+
+                    let result = match result {
+                        Ok(_) if !cached && project.is_cached(&next_recipe) => {
+                            store_outputs(&project, &cache, &next_recipe).await
+                        }
+                        result => result,
+                    };
+
+                    let result = match result {
+                        Ok(_) => archive::write_output_archive(&project, &next_recipe),
+                        result => result,
+                    };
+
+                    if result.is_ok() {
+                        if let Err(err) = cache.record_outputs(&next_recipe) {
+                            warn!("{}. Dependent recipes will be keyed by its inputs", err);
+                        }
                     }
 
 
                     // let mut status_mutex = status_map.lock().unwrap();
                     // let status = status_mutex.get_mut(&next_recipe.full_name()).unwrap();
 
+                    if let Some(events) = events.as_ref() {
+                        events.emit(&RecipeEvent {
+                            fqn: next_recipe_name.clone(),
+                            status: if result.is_ok() { "success" } else { "failed" },
+                            cache_hit: cached,
+                            duration_ms: recipe_start_time.elapsed().as_millis(),
+                            exit_code,
+                            log_path: project.get_recipe_log_path(&next_recipe_name),
+                        });
+                    }
+
                     match result {
                         Ok(_) => {
                             {
                                 let mut queue_mutex = recipe_queue.lock().unwrap();
                                 let recipe = queue_mutex.get_mut(&next_recipe_name).unwrap();
                                 recipe.run_status.status = Status::Done;
+                                recipe.run_status.duration = recipe_start_time.elapsed();
+                                recipe.run_status.cached = cached;
+                                recipe.run_status.exit_code = exit_code;
+                                recipe.run_status.started_at = Some(recipe_started_at);
                             }
-                            let mut cached_str = String::new();
-                            if !cached && next_recipe.cache.is_some() {
-                                match cache.put(&next_recipe_name).await {
-                                    Ok(_) => {},
-                                    Err(err) => {
-                                        println!("Error saving output to cache: {}", err);
-                                    }
-                                }
-                            }
-                            else {
-                                cached_str = " (cached)".to_owned();
-                            }
+                            let cached_str = if cached { " (cached)" } else { "" };
 
                             if let Some(progress_bar) = progress_bar.as_ref() {
                                 progress_bar.finish_with_message(format!(
@@ -256,14 +472,21 @@ async fn runner(
                                     console::style("✗").red()
                                 ));
                             }
+                            {
+                                let mut queue_mutex = recipe_queue.lock().unwrap();
+                                let recipe = queue_mutex.get_mut(&next_recipe_name).unwrap();
+
+                                recipe.run_status.status = Status::Error;
+                                recipe.run_status.output = err;
+                                recipe.run_status.exit_code = exit_code;
+                                recipe.run_status.duration = recipe_start_time.elapsed();
+                                recipe.run_status.started_at = Some(recipe_started_at);
+                            }
+                            // Only shut down once the failure is recorded, runners are aborted
+                            // right away and the bake must still fail
                             if project.config.fast_fail {
                                 shutdown_tx.send(()).unwrap();
                             }
-                            let mut queue_mutex = recipe_queue.lock().unwrap();
-                            let recipe = queue_mutex.get_mut(&next_recipe_name).unwrap();
-
-                            recipe.run_status.status = Status::Error;
-                            recipe.run_status.output = err;
                         }
                     }
                 } => {}
@@ -276,51 +499,385 @@ async fn runner(
     Ok(())
 }
 
+/// Verifies that a recipe produced its declared outputs and stores them in the cache
+///
+/// Must only be called after a successful run, failed runs are never cached so that they are
+/// re-executed even if their inputs didn't change. Missing outputs fail the recipe if
+/// `cache.require_outputs` is set, otherwise caching is skipped. Outputs over the configured size
+/// limits are not cached either. Cache errors only fail the recipe if `cache.strict_remote` is set.
+///
+/// # Arguments
+/// * `project` - The project being baked
+/// * `cache` - The cache to store outputs in
+/// * `recipe` - The recipe that just ran
+///
+async fn store_outputs(
+    project: &BakeProject,
+    cache: &Cache,
+    recipe: &Recipe,
+) -> Result<(), String> {
+    let missing_outputs = recipe.missing_outputs();
+    if !missing_outputs.is_empty() {
+        let message = format!(
+            "Recipe {} did not produce declared outputs: {}",
+            recipe.full_name(),
+            missing_outputs.join(", ")
+        );
+        if project.config.cache.require_outputs {
+            return Err(message);
+        }
+        warn!("{}. Skipping cache", message);
+        return Ok(());
+    }
+
+    if let Err(message) = cache.check_output_size(&recipe.full_name()) {
+        warn!("{}. Skipping cache", message);
+        return Ok(());
+    }
+
+    match cache.put(&recipe.full_name()).await {
+        Ok(_) => Ok(()),
+        Err(err) if project.config.cache.strict_remote => {
+            Err(format!("Error saving output to cache: {}", err))
+        }
+        Err(err) => {
+            println!("Error saving output to cache: {}", err);
+            Ok(())
+        }
+    }
+}
+
+/// Runs a recipe, backing up its existing outputs first if `atomic_outputs` is enabled so they can
+/// be restored if the recipe fails
+///
+/// # Arguments
+/// * `project` - The project being baked
+/// * `executor` - Executor used to run the recipe
+/// * `recipe` - The recipe to run
+///
+async fn run_recipe_with_outputs(
+    project: &BakeProject,
+    executor: &dyn RecipeExecutor,
+    recipe: &Recipe,
+) -> anyhow::Result<()> {
+    let log_file_path = project.get_recipe_log_path(&recipe.full_name());
+    if !project.config.atomic_outputs || recipe.cache.is_none() {
+        return executor
+            .execute(recipe, log_file_path, &project.config)
+            .await;
+    }
+
+    let backup_dir = project.get_recipe_backup_path(&recipe.full_name());
+    let backup = match OutputsBackup::create(recipe, backup_dir) {
+        Ok(backup) => backup,
+        Err(err) => bail!(
+            "Could not back up outputs of recipe {}: {}",
+            recipe.full_name(),
+            err
+        ),
+    };
+
+    let result = executor
+        .execute(recipe, log_file_path, &project.config)
+        .await;
+    if result.is_ok() {
+        backup.discard();
+    } else {
+        backup.restore();
+    }
+    result
+}
+
+/// Refuses to run a recipe requiring a clean git tree when the tree has uncommitted changes,
+/// unless dirty trees are allowed with `--allow-dirty`
+async fn ensure_git_state(
+    project: &BakeProject,
+    git: &GitState,
+    recipe: &Recipe,
+) -> Result<(), String> {
+    if !recipe.requires_clean_git || project.config.allow_dirty {
+        return Ok(());
+    }
+    git.ensure_clean(&recipe.full_name()).await
+}
+
+/// Returns the program and arguments running a recipe's command with a shell, set up to stop at
+/// the first failing command
+///
+/// Only `sh` gets the arguments passed after `--`, as its positional parameters.
+///
+/// # Arguments
+/// * `shell` - The shell running the recipe
+/// * `recipe` - The recipe to run
+///
+fn shell_command(shell: Shell, recipe: &Recipe) -> anyhow::Result<(&'static str, Vec<String>)> {
+    if shell != Shell::Sh && !recipe.args.is_empty() {
+        bail!(
+            "Recipe {} can't take arguments, they're only passed to recipes run with sh",
+            recipe.full_name()
+        );
+    }
+    Ok(match shell {
+        Shell::Sh => {
+            let mut args = vec![
+                "-c".to_owned(),
+                format!("set -e; {}", recipe.run),
+                recipe.full_name(),
+            ];
+            args.extend(recipe.args.iter().cloned());
+            ("sh", args)
+        }
+        Shell::Cmd => {
+            // With /S, cmd strips the outer quotes and runs the rest as it is
+            let run = recipe
+                .run
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty())
+                .collect::<Vec<_>>()
+                .join(" && ");
+            let mut args: Vec<String> = ["/D", "/S", "/C"].map(str::to_owned).into();
+            args.push(format!("\"{}\"", run));
+            ("cmd", args)
+        }
+        Shell::Powershell | Shell::Pwsh => {
+            let mut script = String::from("$ErrorActionPreference = 'Stop'\n");
+            if shell == Shell::Pwsh {
+                script.push_str("$PSNativeCommandUseErrorActionPreference = $true\n");
+            }
+            script.push_str(&recipe.run);
+            let program = if shell == Shell::Pwsh {
+                "pwsh"
+            } else {
+                "powershell"
+            };
+            let mut args: Vec<String> = ["-NoProfile", "-NonInteractive", "-Command"]
+                .map(str::to_owned)
+                .into();
+            args.push(script);
+            (program, args)
+        }
+    })
+}
+
+/// Resolves the timeout of a recipe, preferring the recipe's own `timeout` over the project's
+/// `default_timeout` (which the `--recipe-timeout-default` flag overrides)
+///
+/// # Arguments
+/// * `recipe` - The recipe to resolve the timeout for
+/// * `config` - The project's tool configuration
+///
+fn recipe_timeout(recipe: &Recipe, config: &ToolConfig) -> Option<time::Duration> {
+    recipe
+        .timeout
+        .or(config.default_timeout)
+        .map(time::Duration::from_secs)
+}
+
+/// Resolves the environment a recipe's process runs with
+///
+/// With `clean_environment` only the variables declared by the recipe, its cookbook and project
+/// are passed. Otherwise the current environment is inherited, adding declared defaults for
+/// variables that aren't set. `BAKE_OUTPUT_ROOT` is set to the recipe's output root either way.
+///
+/// # Arguments
+/// * `recipe` - The recipe to resolve the environment for
+/// * `config` - The project's tool configuration
+///
+pub fn recipe_environment(recipe: &Recipe, config: &ToolConfig) -> BTreeMap<OsString, OsString> {
+    inherited_recipe_environment(recipe, config, std::env::vars_os())
+}
+
+/// Resolves the environment a recipe's process runs with, inheriting from the given environment
+/// instead of the current one. Variables that aren't valid unicode are passed as is
+///
+/// # Arguments
+/// * `recipe` - The recipe to resolve the environment for
+/// * `config` - The project's tool configuration
+/// * `parent` - The environment bake runs in
+///
+fn inherited_recipe_environment(
+    recipe: &Recipe,
+    config: &ToolConfig,
+    parent: impl IntoIterator<Item = (OsString, OsString)>,
+) -> BTreeMap<OsString, OsString> {
+    let mut environment: BTreeMap<OsString, OsString> = if config.clean_environment {
+        get_environment_values(&recipe.environment)
+            .into_iter()
+            .map(|(name, value)| (name.into(), value.into()))
+            .collect()
+    } else {
+        // Only add defaults of variables that aren't set in the parent environment
+        let parent: BTreeMap<OsString, OsString> = parent.into_iter().collect();
+        let mut environment = parent.clone();
+        for entry in &recipe.environment {
+            match parse_environment_entry(entry) {
+                (name, Some(default)) if !parent.contains_key(OsStr::new(name)) => {
+                    environment.insert(name.into(), default.into());
+                }
+                _ => {}
+            }
+        }
+        environment
+    };
+    if let Some(output_root) = recipe.output_root.as_ref() {
+        environment.insert("BAKE_OUTPUT_ROOT".into(), output_root.into());
+    }
+    environment
+}
+
+/// Parts of variable names whose values are hidden when printing environments
+const SECRET_NAME_PARTS: [&str; 6] = ["TOKEN", "SECRET", "PASSWORD", "PASSWD", "KEY", "CREDENTIAL"];
+
+/// Value printed instead of the values of variables that look like secrets
+pub const REDACTED_VALUE: &str = "<redacted>";
+
+/// Returns a variable's value, or `REDACTED_VALUE` if its name looks like a secret, such as
+/// `API_TOKEN`
+fn redact(name: &str, value: &str) -> String {
+    let upper = name.to_uppercase();
+    if SECRET_NAME_PARTS.iter().any(|part| upper.contains(part)) {
+        REDACTED_VALUE.to_owned()
+    } else {
+        value.to_owned()
+    }
+}
+
+/// Resolves the environment a recipe's process runs with for printing, redacting the values of
+/// variables whose name looks like a secret
+///
+/// # Arguments
+/// * `recipe` - The recipe to resolve the environment for
+/// * `config` - The project's tool configuration
+///
+pub fn redacted_recipe_environment(
+    recipe: &Recipe,
+    config: &ToolConfig,
+) -> BTreeMap<String, String> {
+    redact_environment(recipe_environment(recipe, config))
+}
+
+/// Converts an environment to printable names and values, redacting secrets
+fn redact_environment(environment: BTreeMap<OsString, OsString>) -> BTreeMap<String, String> {
+    environment
+        .into_iter()
+        .map(|(name, value)| {
+            let name = name.to_string_lossy().into_owned();
+            let value = redact(&name, &value.to_string_lossy());
+            (name, value)
+        })
+        .collect()
+}
+
+/// Change made by a recipe's environment to a variable of bake's own environment
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EnvChange {
+    /// The variable isn't set in bake's environment
+    Added(String),
+
+    /// The variable is set to another value in bake's environment
+    Changed { from: String, to: String },
+
+    /// The variable isn't passed to the recipe, eg. with `clean_environment`
+    Removed,
+}
+
+/// Returns the variables of a recipe's environment that differ from the environment bake runs
+/// in, keyed by name. Values of variables whose name looks like a secret, such as `API_TOKEN`,
+/// are redacted
+///
+/// # Arguments
+/// * `recipe` - The recipe to resolve the environment for
+/// * `config` - The project's tool configuration
+/// * `parent` - The environment bake runs in
+///
+pub fn recipe_environment_diff(
+    recipe: &Recipe,
+    config: &ToolConfig,
+    parent: &BTreeMap<String, String>,
+) -> BTreeMap<String, EnvChange> {
+    let environment: BTreeMap<String, String> = recipe_environment(recipe, config)
+        .into_iter()
+        .map(|(name, value)| {
+            (
+                name.to_string_lossy().into_owned(),
+                value.to_string_lossy().into_owned(),
+            )
+        })
+        .collect();
+    let mut diff: BTreeMap<String, EnvChange> = environment
+        .iter()
+        .filter_map(|(name, value)| {
+            let change = match parent.get(name) {
+                None => EnvChange::Added(redact(name, value)),
+                Some(from) if from != value => EnvChange::Changed {
+                    from: redact(name, from),
+                    to: redact(name, value),
+                },
+                Some(_) => return None,
+            };
+            Some((name.clone(), change))
+        })
+        .collect();
+    diff.extend(
+        parent
+            .keys()
+            .filter(|name| !environment.contains_key(*name))
+            .map(|name| (name.clone(), EnvChange::Removed)),
+    );
+    diff
+}
+
 /// Runs a single recipe as a system process and handles the output
 ///
 /// # Arguments
 /// * `recipe` - The recipe to run
-/// * `project_root` - The root path of the project
-/// * `verbose` - Whether to print verbose output
+/// * `log_file_path` - File where the recipe's output should be written
+/// * `config` - The project's tool configuration, which decides where the output goes
 ///
 pub async fn run_recipe(
     recipe: &Recipe,
     log_file_path: PathBuf,
     config: &ToolConfig,
-) -> Result<(), String> {
+) -> anyhow::Result<()> {
     debug!("Running recipe: {}", recipe.full_name());
-    let env_values: Vec<(String, String)> = recipe
-        .environment
-        .iter()
-        .map(|name| (name.clone(), std::env::var(name).unwrap_or_default()))
-        .collect();
-
-    let mut cmd = tokio::process::Command::new("sh");
-    let run_cmd = if config.clean_environment {
-        cmd.env_clear().envs(env_values)
-    } else {
-        &mut cmd
-    };
+    let shell = config.shell(recipe.shell);
+    let (program, shell_args) = shell_command(shell, recipe)?;
+    let mut cmd = tokio::process::Command::new(program);
+    let run_cmd = cmd.env_clear().envs(recipe_environment(recipe, config));
+    for arg in shell_args {
+        // cmd doesn't split its command line like other programs, so its quoted command is
+        // passed as it is
+        #[cfg(windows)]
+        if shell == Shell::Cmd {
+            run_cmd.raw_arg(arg);
+            continue;
+        }
+        run_cmd.arg(arg);
+    }
 
     debug!("Spawning command for recipe: {}", recipe.full_name());
     let start_time = Instant::now();
-    if config.verbose {
+    let output_capture = config.output_capture(recipe.output);
+    if output_capture == OutputCapture::Tee {
         println_recipe(
             "============== Started baking recipe ==============",
             &recipe.full_name(),
         )
     }
-    let result = run_cmd
+    run_cmd
         .current_dir(recipe.config_path.parent().unwrap())
-        .arg("-c")
-        .arg(format!("set -e; {}", recipe.run.clone()))
         .stdout(std::process::Stdio::piped())
-        .stderr(std::process::Stdio::piped())
-        .spawn();
+        .stderr(std::process::Stdio::piped());
+    // Run in its own process group so processes started by the recipe are killed too
+    #[cfg(unix)]
+    run_cmd.process_group(0);
+    let result = run_cmd.spawn();
 
     debug!("Process finished for recipe: {}", recipe.full_name());
     match result {
         Ok(mut child) => {
+            let mut process_group = ProcessGroupGuard(child.id());
             let stdout = child.stdout.take().unwrap();
             let stderr = child.stderr.take().unwrap();
             let process_handle = tokio::spawn(process_output(
@@ -328,27 +885,49 @@ pub async fn run_recipe(
                 stderr,
                 recipe.full_name(),
                 log_file_path,
-                config.verbose,
+                output_capture,
+                config.logs.max_lines,
             ));
-            if let Ok(exit_code) = child.wait().await {
+            let wait_result = match recipe_timeout(recipe, config) {
+                Some(timeout) => match time::timeout(timeout, child.wait()).await {
+                    Ok(wait_result) => wait_result,
+                    Err(_) => {
+                        drop(process_group);
+                        if let Err(err) = child.wait().await {
+                            warn!("Could not kill recipe {}: {}", recipe.full_name(), err);
+                        }
+                        // Processes holding the output open are gone, so this doesn't block
+                        if let Err(err) = process_handle.await {
+                            warn!("Could wait for process output thread: {}", err);
+                        }
+                        bail!(
+                            "Recipe {} timed out after {}s",
+                            recipe.full_name(),
+                            timeout.as_secs()
+                        );
+                    }
+                },
+                None => child.wait().await,
+            };
+            process_group.0 = None;
+            if let Ok(exit_code) = wait_result {
                 if !exit_code.success() {
-                    return Err(format!(
-                        "Recipe {} failed with exit code {}",
-                        recipe.full_name(),
-                        exit_code
-                    ));
+                    bail!(BakeError::RecipeFailed {
+                        fqn: recipe.full_name(),
+                        code: exit_code.code(),
+                    });
                 }
             }
             if let Err(err) = process_handle.await {
-                return Err(format!("Could wait for process output thread: {}", err));
+                bail!("Could wait for process output thread: {}", err);
             }
         }
         Err(err) => {
-            return Err(format!("Could not spawn process: {}", err));
+            bail!("Could not spawn process: {}", err);
         }
     }
     let elapsed = start_time.elapsed();
-    if config.verbose {
+    if output_capture == OutputCapture::Tee {
         println_recipe(
             &format!(
                 "============== Finished baking recipe ({:.2?}) =============",
@@ -360,6 +939,50 @@ pub async fn run_recipe(
     Ok(())
 }
 
+/// Kills a process and every process in its group, the process must have been spawned as the
+/// leader of its own group
+///
+/// # Arguments
+/// * `pid` - Id of the group leader
+///
+#[cfg(unix)]
+pub fn kill_process_group(pid: u32) {
+    // SAFETY: kill only sends a signal and has no memory safety requirements
+    unsafe {
+        libc::kill(-(pid as libc::pid_t), libc::SIGKILL);
+    }
+}
+
+/// Kills a process and every process it started. Without process groups, the process tree is
+/// killed with `taskkill`
+///
+/// # Arguments
+/// * `pid` - Id of the process
+///
+#[cfg(not(unix))]
+pub fn kill_process_group(pid: u32) {
+    let result = std::process::Command::new("taskkill")
+        .args(["/F", "/T", "/PID", &pid.to_string()])
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .status();
+    if let Err(err) = result {
+        warn!("Could not kill process {}: {}", pid, err);
+    }
+}
+
+/// Kills a recipe's process group when dropped, unless the process already exited, so recipes
+/// timing out or interrupted by Ctrl-C don't leave processes behind
+struct ProcessGroupGuard(Option<u32>);
+
+impl Drop for ProcessGroupGuard {
+    fn drop(&mut self) {
+        if let Some(pid) = self.0 {
+            kill_process_group(pid);
+        }
+    }
+}
+
 fn name_to_term_color(string: &str) -> Color {
     use std::collections::hash_map::DefaultHasher;
     use std::hash::{Hash, Hasher};
@@ -380,38 +1003,45 @@ fn name_to_term_color(string: &str) -> Color {
     Color::Color256(color_num as u8)
 }
 
-/// Processes the output of a process saving it to a file and printing to console if in verbose
-/// mode
+/// Processes the output of a process saving it to a file and printing it to console, depending on
+/// the output capture mode
 ///
 /// # Arguments
 /// * `stdout` - The stdout of the process
 /// * `stderr` - The stderr of the process
 /// * `recipe_name` - The name of the recipe
-/// * `project_root` - The root path of the project
-/// * `verbose` - Whether to print verbose output
+/// * `log_file_path` - File where the output is written, left empty when it is discarded
+/// * `output_capture` - Where the output goes
+/// * `max_lines` - Maximum number of lines written to the log file, see `LogBuffer`
 ///
 async fn process_output(
     stdout: ChildStdout,
     stderr: ChildStderr,
     recipe_name: String,
     log_file_path: PathBuf,
-    verbose: bool,
+    output_capture: OutputCapture,
+    max_lines: Option<NonZeroUsize>,
 ) -> Result<(), String> {
     let mut join_set = JoinSet::new();
-    let output_str = Arc::new(Mutex::new(String::new()));
+    let output_str = Arc::new(Mutex::new(LogBuffer::new(log_file_path, max_lines)));
 
     async fn collect_output<T: AsyncRead + Unpin>(
         output: T,
         recipe_name: String,
-        output_string: Arc<Mutex<String>>,
-        verbose: bool,
+        output_string: Arc<Mutex<LogBuffer>>,
+        output_capture: OutputCapture,
     ) {
         let mut reader = BufReader::new(output).lines();
         while let Some(line) = reader.next_line().await.unwrap() {
-            if verbose {
-                println_recipe(&line, &recipe_name);
+            match output_capture {
+                OutputCapture::Tee => {
+                    println_recipe(&line, &recipe_name);
+                    output_string.lock().unwrap().push(line);
+                }
+                OutputCapture::File => output_string.lock().unwrap().push(line),
+                // Keep reading so the process doesn't block on a full pipe
+                OutputCapture::Null => {}
             }
-            output_string.lock().unwrap().push_str(&(line + "\n"));
         }
     }
 
@@ -419,38 +1049,143 @@ async fn process_output(
         stdout,
         recipe_name.clone(),
         output_str.clone(),
-        verbose,
+        output_capture,
     ));
 
     join_set.spawn(collect_output(
         stderr,
         recipe_name.clone(),
         output_str.clone(),
-        verbose,
+        output_capture,
     ));
 
+    // Written lines are buffered, flush them regularly so the log can be followed
+    let flusher = tokio::spawn({
+        let output_str = output_str.clone();
+        async move {
+            let mut interval = time::interval(LOG_FLUSH_INTERVAL);
+            loop {
+                interval.tick().await;
+                output_str.lock().unwrap().flush();
+            }
+        }
+    });
+
     while (join_set.join_next().await).is_some() {}
+    flusher.abort();
+
+    let result = output_str.lock().unwrap().finish();
+    result
+}
+
+/// How often the lines written to a recipe's log are flushed to the file while it runs
+const LOG_FLUSH_INTERVAL: time::Duration = time::Duration::from_millis(100);
+
+/// Lines of a recipe's output written to its log file
+///
+/// Lines are written as they arrive through a buffer flushed every `LOG_FLUSH_INTERVAL`, so the
+/// log can be followed while the recipe runs. With a maximum number of lines, only the first and
+/// last lines are kept, half of the limit each, so that logs of very verbose recipes stay
+/// navigable. The last lines are kept in a ring buffer and written when the recipe finishes,
+/// after a marker counting the lines dropped in between.
+struct LogBuffer {
+    path: PathBuf,
+    file: Option<BufWriter<File>>,
+    error: Option<String>,
+    head_lines: usize,
+    tail: VecDeque<String>,
+    truncated: usize,
+    max_lines: Option<NonZeroUsize>,
+}
 
-    match File::create(log_file_path.clone()) {
-        Ok(mut file) => {
-            if let Err(err) = file.write_all(output_str.lock().unwrap().as_bytes()) {
-                return Err(format!(
-                    "could not write log file {}: {}",
-                    log_file_path.display(),
+impl LogBuffer {
+    /// Creates the log file, keeping the error to return it once the output has been read
+    fn new(path: PathBuf, max_lines: Option<NonZeroUsize>) -> Self {
+        let (file, error) = match File::create(&path) {
+            Ok(file) => (Some(BufWriter::new(file)), None),
+            Err(err) => (
+                None,
+                Some(format!(
+                    "could not create log file {}: {}",
+                    path.display(),
                     err
-                ));
-            };
+                )),
+            ),
+        };
+        Self {
+            path,
+            file,
+            error,
+            head_lines: 0,
+            tail: VecDeque::new(),
+            truncated: 0,
+            max_lines,
         }
-        Err(err) => {
-            return Err(format!(
-                "could not create log file {}: {}",
-                log_file_path.display(),
-                err
-            ));
+    }
+
+    fn push(&mut self, line: String) {
+        let Some(max_lines) = self.max_lines else {
+            self.write_line(&line);
+            return;
+        };
+        if self.head_lines < max_lines.get().div_ceil(2) {
+            self.head_lines += 1;
+            self.write_line(&line);
+            return;
+        }
+        self.tail.push_back(line);
+        if self.tail.len() > max_lines.get() - max_lines.get().div_ceil(2) {
+            self.tail.pop_front();
+            self.truncated += 1;
         }
     }
 
-    Ok(())
+    /// Writes a line to the buffer of the log file
+    fn write_line(&mut self, line: &str) {
+        let Some(file) = self.file.as_mut() else {
+            return;
+        };
+        let result = file
+            .write_all(line.as_bytes())
+            .and_then(|_| file.write_all(b"\n"));
+        self.check(result);
+    }
+
+    /// Flushes the lines written so far to the log file
+    fn flush(&mut self) {
+        if let Some(file) = self.file.as_mut() {
+            let result = file.flush();
+            self.check(result);
+        }
+    }
+
+    /// Keeps the first error writing the log file, dropping the file after it
+    fn check(&mut self, result: std::io::Result<()>) {
+        if let Err(err) = result {
+            self.error = Some(format!(
+                "could not write log file {}: {}",
+                self.path.display(),
+                err
+            ));
+            self.file = None;
+        }
+    }
+
+    /// Writes the last lines kept and returns the first error writing the log
+    fn finish(&mut self) -> Result<(), String> {
+        if self.truncated > 0 {
+            let marker = format!("... ({} lines truncated) ...", self.truncated);
+            self.write_line(&marker);
+        }
+        for line in std::mem::take(&mut self.tail) {
+            self.write_line(&line);
+        }
+        self.flush();
+        match self.error.take() {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
 }
 
 fn println_recipe(line: &str, recipe_name: &str) {
@@ -461,18 +1196,33 @@ fn println_recipe(line: &str, recipe_name: &str) {
 
 #[cfg(test)]
 mod tests {
-    use std::{path::PathBuf, sync::Arc};
+    use std::{
+        collections::BTreeMap,
+        ffi::{OsStr, OsString},
+        num::NonZeroUsize,
+        os::unix::ffi::{OsStrExt, OsStringExt},
+        path::PathBuf,
+        sync::{Arc, Mutex},
+    };
 
     use async_trait::async_trait;
+    use test_case::test_case;
 
     use crate::{
         cache::{
-            Cache, CacheBuilder, CacheResult, CacheResultData, CacheStrategy, ARCHIVE_EXTENSION,
+            local::LocalCacheStrategy, Cache, CacheBuilder, CacheResult, CacheResultData,
+            CacheStrategy, ARCHIVE_EXTENSION,
+        },
+        error::BakeError,
+        project::{
+            config::{OutputCapture, Shell, ToolConfig},
+            BakeProject, Recipe, RecipeCacheConfig, RecipeDependency, RecipeSearch, Status,
         },
-        project::{BakeProject, Status},
-        test_utils::TestProjectBuilder,
+        test_utils::{wait_until, TestProjectBuilder},
     };
 
+    use super::{executor::RecipeExecutor, EnvChange};
+
     #[derive(Clone, Debug)]
     struct TestCacheStrategy {
         pub hit: bool,
@@ -480,13 +1230,13 @@ mod tests {
 
     #[async_trait]
     impl CacheStrategy for TestCacheStrategy {
-        async fn get(&self, _: &str) -> CacheResult {
+        async fn get(&self, _: &str) -> anyhow::Result<CacheResult> {
             if self.hit {
-                CacheResult::Hit(CacheResultData {
+                Ok(CacheResult::Hit(CacheResultData {
                     archive_path: PathBuf::from(format!("foo.{}", ARCHIVE_EXTENSION)),
-                })
+                }))
             } else {
-                CacheResult::Miss
+                Ok(CacheResult::Miss)
             }
         }
         async fn put(&self, _: &str, _: PathBuf) -> anyhow::Result<()> {
@@ -508,6 +1258,17 @@ mod tests {
             .unwrap()
     }
 
+    fn create_missing_output_project(require_outputs: bool) -> BakeProject {
+        let mut project = create_test_project();
+        project.config.max_parallel = 2;
+        project.config.cache.require_outputs = require_outputs;
+        project.recipes.get_mut("foo:build").unwrap().cache = Some(RecipeCacheConfig {
+            outputs: vec!["dist/app".into()],
+            ..Default::default()
+        });
+        project
+    }
+
     fn create_test_project() -> BakeProject {
         let mut project = TestProjectBuilder::new()
             .with_cookbook("foo", &["build", "test"])
@@ -525,7 +1286,7 @@ mod tests {
     async fn run_all_recipes() {
         let project = Arc::new(create_test_project());
         let cache = build_cache(project.clone()).await;
-        let res = super::bake(project.clone(), cache, None).await;
+        let res = super::bake(project.clone(), cache, &RecipeSearch::All).await;
         assert!(res.is_ok());
     }
 
@@ -535,7 +1296,12 @@ mod tests {
         project.config.verbose = true;
         let project = Arc::new(project);
         let cache = build_cache(project.clone()).await;
-        let res = super::bake(project.clone(), cache, Some("bar:")).await;
+        let res = super::bake(
+            project.clone(),
+            cache,
+            &RecipeSearch::ByPattern("bar:".to_owned()),
+        )
+        .await;
         assert!(res.is_ok());
     }
 
@@ -543,13 +1309,1089 @@ mod tests {
     async fn run_error_recipes() {
         let mut project = create_test_project();
         project.recipes.get_mut("bar:test").unwrap().run = String::from("false; echo 'hello!'");
-        project.recipes.get_mut("bar:build").unwrap().dependencies =
-            Some(vec![String::from("bar:test")]);
+        project.recipes.get_mut("bar:build").unwrap().dependencies = Some(vec!["bar:test".into()]);
         let project = Arc::new(project);
         let cache = build_cache(project.clone()).await;
-        let res = super::bake(project.clone(), cache, Some("bar:")).await;
+        let res = super::bake(
+            project.clone(),
+            cache,
+            &RecipeSearch::ByPattern("bar:".to_owned()),
+        )
+        .await;
 
         assert!(project.recipes.get("bar:build").unwrap().run_status.status == Status::Idle);
         assert!(res.is_err());
     }
+
+    #[tokio::test]
+    async fn exit_codes() {
+        let mut project = TestProjectBuilder::new()
+            .with_cookbook("codes", &["a", "b", "c", "d"])
+            .build();
+        project.config.max_parallel = 4;
+        project.config.fast_fail = false;
+        project.config.default_timeout = None;
+        for (name, run) in [
+            ("codes:a", "exit 0"),
+            ("codes:b", "exit 4"),
+            ("codes:c", "exit 7"),
+            ("codes:d", "kill -9 $$"),
+        ] {
+            project.recipes.get_mut(name).unwrap().run = String::from(run);
+        }
+        let project = Arc::new(project);
+        let cache = build_cache(project.clone()).await;
+        let err = super::bake(project.clone(), cache, &RecipeSearch::All)
+            .await
+            .unwrap_err()
+            .downcast::<BakeError>()
+            .unwrap();
+
+        assert_eq!(
+            err,
+            BakeError::RecipesFailed(vec![
+                (String::from("codes:b"), Some(4)),
+                (String::from("codes:c"), Some(7)),
+                (String::from("codes:d"), None),
+            ])
+        );
+        // The first failed recipe decides the exit code
+        assert_eq!(err.exit_code(), 4);
+        assert_eq!(
+            BakeError::RecipesFailed(vec![(String::from("codes:d"), None)]).exit_code(),
+            1
+        );
+        assert_eq!(BakeError::ValidationError(String::new()).exit_code(), 1);
+    }
+
+    #[tokio::test]
+    async fn run_hooks() {
+        let mut project = create_test_project();
+        project.config.max_parallel = 2;
+        project.config.hooks.on_success = Some(String::from(
+            "echo \"success $BAKE_FAILED_COUNT $BAKE_RECIPES_RUN\" > hook.txt",
+        ));
+        project.config.hooks.on_failure = Some(String::from(
+            "echo \"failure $BAKE_FAILED_COUNT $BAKE_DURATION_MS\" > hook.txt; exit 1",
+        ));
+        project.recipes.get_mut("bar:test").unwrap().run = String::from("false");
+        let project = Arc::new(project);
+        let hook_output = project.root_path.join("hook.txt");
+
+        let cache = build_cache(project.clone()).await;
+        let res = super::bake(
+            project.clone(),
+            cache,
+            &RecipeSearch::ByPattern("foo:".to_owned()),
+        )
+        .await;
+        assert!(res.is_ok());
+        assert_eq!(
+            std::fs::read_to_string(&hook_output).unwrap().trim(),
+            "success 0 2"
+        );
+
+        let cache = build_cache(project.clone()).await;
+        let res = super::bake(
+            project.clone(),
+            cache,
+            &RecipeSearch::ByPattern("bar:test".to_owned()),
+        )
+        .await;
+        assert!(res.is_err());
+        let output = std::fs::read_to_string(&hook_output).unwrap();
+        let mut parts = output.split_whitespace();
+        assert_eq!(parts.next(), Some("failure"));
+        assert_eq!(parts.next(), Some("1"));
+        assert!(parts.next().unwrap().parse::<u128>().is_ok());
+    }
+
+    #[tokio::test]
+    async fn run_missing_outputs() {
+        let project = Arc::new(create_missing_output_project(true));
+        let cache = build_cache(project.clone()).await;
+        let res = super::bake(
+            project.clone(),
+            cache,
+            &RecipeSearch::ByPattern("foo:build".to_owned()),
+        )
+        .await;
+        assert!(res.is_err());
+
+        let project = Arc::new(create_missing_output_project(false));
+        let cache = build_cache(project.clone()).await;
+        let res = super::bake(
+            project.clone(),
+            cache,
+            &RecipeSearch::ByPattern("foo:build".to_owned()),
+        )
+        .await;
+        assert!(res.is_ok());
+    }
+
+    #[tokio::test]
+    async fn run_environment_defaults() {
+        std::env::remove_var("TEST_RUN_ENV_DEFAULT");
+        let mut project = create_test_project();
+        project.config.max_parallel = 2;
+        let recipe = project.recipes.get_mut("foo:build").unwrap();
+        recipe.environment = vec![String::from("TEST_RUN_ENV_DEFAULT=fallback")];
+        recipe.run = String::from("test \"$TEST_RUN_ENV_DEFAULT\" = fallback");
+        let project = Arc::new(project);
+        let cache = build_cache(project.clone()).await;
+        let res = super::bake(
+            project.clone(),
+            cache,
+            &RecipeSearch::ByPattern("foo:build".to_owned()),
+        )
+        .await;
+        assert!(res.is_ok());
+    }
+
+    #[test]
+    fn recipe_environment() {
+        let parent = || {
+            [
+                (
+                    OsString::from("TEST_RECIPE_ENV_INHERITED"),
+                    OsString::from("inherited"),
+                ),
+                (OsString::from("PATH"), OsString::from("/bin")),
+                (
+                    OsString::from("TEST_RECIPE_ENV_NOT_UNICODE"),
+                    OsString::from_vec(vec![0x66, 0x6f, 0xff]),
+                ),
+            ]
+        };
+        let mut project = TestProjectBuilder::new()
+            .with_cookbook("foo", &["build"])
+            .build();
+        // Project and cookbook entries come first, the recipe's own entries override them
+        project.recipes.get_mut("foo:build").unwrap().environment = vec![
+            String::from("TEST_RECIPE_ENV_LEVEL=project"),
+            String::from("TEST_RECIPE_ENV_LEVEL=recipe"),
+            String::from("TEST_RECIPE_ENV_INHERITED=default"),
+        ];
+        let recipe = &project.recipes["foo:build"];
+
+        let environment = super::inherited_recipe_environment(recipe, &project.config, parent());
+        assert_eq!(environment[OsStr::new("TEST_RECIPE_ENV_LEVEL")], "recipe");
+        assert_eq!(
+            environment[OsStr::new("TEST_RECIPE_ENV_INHERITED")],
+            "inherited"
+        );
+        assert_eq!(
+            environment[OsStr::new("TEST_RECIPE_ENV_NOT_UNICODE")].as_bytes(),
+            [0x66, 0x6f, 0xff]
+        );
+        assert!(environment.contains_key(OsStr::new("PATH")));
+
+        project.config.clean_environment = true;
+        let recipe = &project.recipes["foo:build"];
+        let environment = super::inherited_recipe_environment(recipe, &project.config, parent());
+        assert_eq!(environment[OsStr::new("TEST_RECIPE_ENV_LEVEL")], "recipe");
+        assert!(!environment.contains_key(OsStr::new("PATH")));
+        assert!(!environment.contains_key(OsStr::new("TEST_RECIPE_ENV_NOT_UNICODE")));
+    }
+
+    #[test]
+    fn redacted_recipe_environment() {
+        let mut project = TestProjectBuilder::new()
+            .with_cookbook("foo", &["build"])
+            .build();
+        project.recipes.get_mut("foo:build").unwrap().environment = vec![
+            String::from("TEST_DUMP_API_TOKEN=hunter2"),
+            String::from("TEST_DUMP_LEVEL=recipe"),
+        ];
+        let recipe = &project.recipes["foo:build"];
+        let parent = [(OsString::from("DB_PASSWORD"), OsString::from("secret"))];
+
+        let environment = super::redact_environment(super::inherited_recipe_environment(
+            recipe,
+            &project.config,
+            parent,
+        ));
+        assert_eq!(environment["TEST_DUMP_API_TOKEN"], super::REDACTED_VALUE);
+        assert_eq!(environment["DB_PASSWORD"], super::REDACTED_VALUE);
+        assert_eq!(environment["TEST_DUMP_LEVEL"], "recipe");
+    }
+
+    #[test]
+    fn recipe_environment_diff() {
+        std::env::set_var("TEST_ENV_DIFF_SAME", "parent");
+        let mut project = TestProjectBuilder::new()
+            .with_cookbook("foo", &["build"])
+            .build();
+        project.recipes.get_mut("foo:build").unwrap().environment = vec![
+            String::from("TEST_ENV_DIFF_ADDED=recipe"),
+            String::from("TEST_ENV_DIFF_SAME=default"),
+            String::from("TEST_ENV_DIFF_TOKEN=hunter2"),
+        ];
+        let parent: BTreeMap<String, String> = std::env::vars().collect();
+
+        let recipe = &project.recipes["foo:build"];
+        let diff = super::recipe_environment_diff(recipe, &project.config, &parent);
+        assert_eq!(
+            diff["TEST_ENV_DIFF_ADDED"],
+            EnvChange::Added(String::from("recipe"))
+        );
+        assert_eq!(
+            diff["TEST_ENV_DIFF_TOKEN"],
+            EnvChange::Added(String::from(super::REDACTED_VALUE))
+        );
+        assert!(!diff.contains_key("TEST_ENV_DIFF_SAME"));
+        assert!(!diff.contains_key("PATH"));
+
+        // A clean environment drops everything that isn't declared
+        project.config.clean_environment = true;
+        let recipe = &project.recipes["foo:build"];
+        let diff = super::recipe_environment_diff(recipe, &project.config, &parent);
+        assert_eq!(diff["PATH"], EnvChange::Removed);
+        assert!(!diff.contains_key("TEST_ENV_DIFF_SAME"));
+
+        let parent = BTreeMap::from([(String::from("TEST_ENV_DIFF_SAME"), String::from("old"))]);
+        let diff = super::recipe_environment_diff(recipe, &project.config, &parent);
+        assert_eq!(
+            diff["TEST_ENV_DIFF_SAME"],
+            EnvChange::Changed {
+                from: String::from("old"),
+                to: String::from("parent")
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn rerun_failed_recipes() {
+        let mut project = create_test_project();
+        project.config.max_parallel = 2;
+        let recipe = project.recipes.get_mut("foo:build").unwrap();
+        recipe.cache = Some(RecipeCacheConfig::default());
+        recipe.run = String::from("echo run >> runs.txt; false");
+        let project = Arc::new(project);
+
+        for _ in 0..2 {
+            let cache = CacheBuilder::new(project.clone())
+                .add_strategy("local", LocalCacheStrategy::from_config)
+                .build()
+                .await
+                .unwrap();
+            let res = super::bake(
+                project.clone(),
+                cache,
+                &RecipeSearch::ByPattern("foo:build".to_owned()),
+            )
+            .await;
+            assert!(res.is_err());
+        }
+
+        // Failed run must not be cached, so unchanged inputs run again
+        let runs = std::fs::read_to_string(project.root_path.join("runs.txt")).unwrap();
+        assert_eq!(runs.lines().count(), 2);
+    }
+
+    #[tokio::test]
+    async fn restore_outputs_on_failure() {
+        let mut project = create_test_project();
+        project.config.atomic_outputs = true;
+        project.config.max_parallel = 2;
+        let recipe = project.recipes.get_mut("foo:build").unwrap();
+        recipe.cache = Some(RecipeCacheConfig {
+            outputs: vec!["dist".into()],
+            ..Default::default()
+        });
+        recipe.run = String::from("echo partial > dist/partial.txt; false");
+        let dist = project.root_path.join("dist");
+        std::fs::create_dir_all(&dist).unwrap();
+        std::fs::write(dist.join("old.txt"), "old").unwrap();
+        let project = Arc::new(project);
+
+        let cache = build_cache(project.clone()).await;
+        let res = super::bake(
+            project.clone(),
+            cache,
+            &RecipeSearch::ByPattern("foo:build".to_owned()),
+        )
+        .await;
+        assert!(res.is_err());
+        assert!(!dist.join("partial.txt").exists());
+        assert_eq!(
+            std::fs::read_to_string(dist.join("old.txt")).unwrap(),
+            "old"
+        );
+        assert!(!project.get_recipe_backup_path("foo:build").exists());
+    }
+
+    #[test_case(Some(5), Some(10) => Some(5); "Recipe timeout wins")]
+    #[test_case(None, Some(10) => Some(10); "Project default")]
+    #[test_case(Some(5), None => Some(5); "Recipe timeout only")]
+    #[test_case(None, None => None; "No timeout")]
+    fn recipe_timeout(recipe_timeout: Option<u64>, default_timeout: Option<u64>) -> Option<u64> {
+        let mut project = create_test_project();
+        project.config.default_timeout = default_timeout;
+        let recipe = project.recipes.get_mut("foo:build").unwrap();
+        recipe.timeout = recipe_timeout;
+        super::recipe_timeout(recipe, &project.config).map(|timeout| timeout.as_secs())
+    }
+
+    #[test_case(Shell::Sh => ("sh", vec!["-c".into(), "set -e; echo \"a b\"\ncargo test\n".into(), "foo:build".into()]); "sh")]
+    #[test_case(Shell::Cmd => ("cmd", vec!["/D".into(), "/S".into(), "/C".into(), "\"echo \"a b\" && cargo test\"".into()]); "cmd")]
+    #[test_case(Shell::Powershell => ("powershell", vec!["-NoProfile".into(), "-NonInteractive".into(), "-Command".into(), "$ErrorActionPreference = 'Stop'\necho \"a b\"\ncargo test\n".into()]); "powershell")]
+    #[test_case(Shell::Pwsh => ("pwsh", vec!["-NoProfile".into(), "-NonInteractive".into(), "-Command".into(), "$ErrorActionPreference = 'Stop'\n$PSNativeCommandUseErrorActionPreference = $true\necho \"a b\"\ncargo test\n".into()]); "pwsh")]
+    fn shell_command(shell: Shell) -> (&'static str, Vec<String>) {
+        let mut project = create_test_project();
+        let recipe = project.recipes.get_mut("foo:build").unwrap();
+        recipe.run = "echo \"a b\"\ncargo test\n".to_owned();
+        let command = super::shell_command(shell, recipe).unwrap();
+
+        recipe.args = vec!["--release".to_owned()];
+        let with_args = super::shell_command(shell, recipe);
+        match shell {
+            Shell::Sh => assert_eq!(with_args.unwrap().1.last().unwrap(), "--release"),
+            _ => assert!(with_args.is_err()),
+        }
+
+        // Recipes without a shell use the project's on Windows and sh elsewhere
+        project.config.windows_shell = Shell::Pwsh;
+        let expected = if cfg!(windows) {
+            Shell::Pwsh
+        } else {
+            Shell::Sh
+        };
+        assert_eq!(project.config.shell(None), expected);
+        assert_eq!(project.config.shell(Some(shell)), shell);
+        command
+    }
+
+    #[test_case(None, Some(OutputCapture::Tee) => "hello\n"; "Tee")]
+    #[test_case(None, Some(OutputCapture::File) => "hello\n"; "File")]
+    #[test_case(None, None => "hello\n"; "File by default")]
+    #[test_case(None, Some(OutputCapture::Null) => ""; "Null")]
+    #[test_case(Some(OutputCapture::File), Some(OutputCapture::Null) => "hello\n"; "Recipe overrides run")]
+    #[test_case(Some(OutputCapture::Null), Some(OutputCapture::Tee) => ""; "Recipe discards output")]
+    #[tokio::test]
+    async fn output_capture(
+        recipe_output: Option<OutputCapture>,
+        output_capture: Option<OutputCapture>,
+    ) -> String {
+        let mut project = create_test_project();
+        project.config.max_parallel = 2;
+        project.config.output_capture = output_capture;
+        let recipe = project.recipes.get_mut("foo:build").unwrap();
+        recipe.run = String::from("echo hello");
+        recipe.output = recipe_output;
+        let project = Arc::new(project);
+        let cache = build_cache(project.clone()).await;
+        super::bake(
+            project.clone(),
+            cache,
+            &RecipeSearch::ByNames(vec!["foo:build".to_owned()]),
+        )
+        .await
+        .unwrap();
+
+        // The log file is always written so failures and cache entries can refer to it
+        std::fs::read_to_string(project.get_recipe_log_path("foo:build")).unwrap()
+    }
+
+    #[tokio::test]
+    async fn log_written_while_running() {
+        let mut project = create_test_project();
+        project.config.max_parallel = 2;
+        // The recipe runs until the test has seen its log, bounded by its timeout
+        let recipe = project.recipes.get_mut("foo:build").unwrap();
+        recipe.run = String::from("echo started; while [ ! -e done ]; do sleep 0.05; done");
+        recipe.timeout = Some(10);
+        let done_path = recipe.config_path.parent().unwrap().join("done");
+        let project = Arc::new(project);
+        let cache = build_cache(project.clone()).await;
+        let bake = tokio::spawn({
+            let project = project.clone();
+            async move {
+                super::bake(
+                    project,
+                    cache,
+                    &RecipeSearch::ByNames(vec!["foo:build".to_owned()]),
+                )
+                .await
+            }
+        });
+
+        let log_path = project.get_recipe_log_path("foo:build");
+        let log = || std::fs::read_to_string(&log_path).unwrap_or_default();
+        wait_until(|| log() == "started\n").await;
+        assert_eq!(log(), "started\n");
+        assert!(!bake.is_finished());
+        std::fs::write(done_path, "").unwrap();
+        bake.await.unwrap().unwrap();
+    }
+
+    #[test_case(None => (100, None); "Unlimited")]
+    #[test_case(Some(100) => (100, None); "At the limit")]
+    #[test_case(Some(10) => (11, Some("... (90 lines truncated) ...".to_owned())); "Even limit")]
+    #[test_case(Some(5) => (6, Some("... (95 lines truncated) ...".to_owned())); "Odd limit")]
+    #[tokio::test]
+    async fn max_log_lines(max_lines: Option<usize>) -> (usize, Option<String>) {
+        let mut project = create_test_project();
+        project.config.max_parallel = 2;
+        project.config.logs.max_lines = max_lines.and_then(NonZeroUsize::new);
+        project.recipes.get_mut("foo:build").unwrap().run = String::from("seq 1 100");
+        let project = Arc::new(project);
+        let cache = build_cache(project.clone()).await;
+        super::bake(
+            project.clone(),
+            cache,
+            &RecipeSearch::ByNames(vec!["foo:build".to_owned()]),
+        )
+        .await
+        .unwrap();
+
+        let log = std::fs::read_to_string(project.get_recipe_log_path("foo:build")).unwrap();
+        let lines: Vec<&str> = log.lines().collect();
+        // The first and last lines are always kept
+        assert_eq!(lines.first(), Some(&"1"));
+        assert_eq!(lines.last(), Some(&"100"));
+        let marker = lines
+            .iter()
+            .find(|line| line.starts_with("..."))
+            .map(|line| line.to_string());
+        if let Some(max_lines) = max_lines.filter(|max_lines| *max_lines < 100) {
+            let head_lines = max_lines.div_ceil(2);
+            assert_eq!(lines[head_lines - 1], head_lines.to_string());
+            assert_eq!(
+                lines[head_lines + 1],
+                (100 - max_lines + head_lines + 1).to_string()
+            );
+        }
+        (lines.len(), marker)
+    }
+
+    #[tokio::test]
+    async fn no_recipes_matched() {
+        let project = Arc::new(create_test_project());
+        let cache = build_cache(project.clone()).await;
+        // Matching nothing is only a warning, as it always was
+        super::bake(
+            project.clone(),
+            cache,
+            &RecipeSearch::ByPattern("foo:buidl".to_owned()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            super::no_match_warning("foo:buidl", &project.suggest_patterns("foo:buidl")),
+            "No recipes matched 'foo:buidl'. Did you mean 'foo:build'?"
+        );
+    }
+
+    #[tokio::test]
+    async fn run_timeout() {
+        let mut project = create_test_project();
+        project.config.max_parallel = 2;
+        project.config.default_timeout = Some(1);
+        // The pipe's processes keep the output open, so all of them must be killed
+        project.recipes.get_mut("foo:build").unwrap().run = String::from("sleep 10 | cat");
+        let project = Arc::new(project);
+
+        let cache = build_cache(project.clone()).await;
+        let start = std::time::Instant::now();
+        let res = super::bake(
+            project.clone(),
+            cache,
+            &RecipeSearch::ByPattern("foo:build".to_owned()),
+        )
+        .await;
+        assert!(res.is_err());
+        assert!(start.elapsed() < std::time::Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn force_rebuild() {
+        let mut project = TestProjectBuilder::new()
+            .with_cookbook("forced", &["build"])
+            .build();
+        project.config.max_parallel = 2;
+        let runs_path = project.root_path.join("runs.txt");
+        let recipe = project.recipes.get_mut("forced:build").unwrap();
+        recipe.cache = Some(RecipeCacheConfig::default());
+        recipe.run = format!("echo run >> {}", runs_path.display());
+        let project = Arc::new(project);
+        let search = RecipeSearch::ByPattern("forced:".to_owned());
+
+        for force_rebuild in [false, false, true] {
+            let cache = CacheBuilder::new(project.clone())
+                .add_strategy("local", LocalCacheStrategy::from_config)
+                .search(search.clone())
+                .force_rebuild(force_rebuild)
+                .build()
+                .await
+                .unwrap();
+            super::bake(project.clone(), cache, &search).await.unwrap();
+        }
+
+        // Second bake is cached, the forced one runs again
+        assert_eq!(std::fs::read_to_string(&runs_path).unwrap(), "run\nrun\n");
+    }
+
+    #[test_case(false => "run\nrun\nrun\n"; "Always run")]
+    #[test_case(true => "run\nrun\n"; "Skipped while inputs don't change")]
+    #[tokio::test]
+    async fn cache_empty_outputs(cache_empty_outputs: bool) -> String {
+        let mut project = TestProjectBuilder::new()
+            .with_cookbook("deploy", &["app"])
+            .build();
+        project.config.max_parallel = 2;
+        project.config.cache.cache_empty_outputs = cache_empty_outputs;
+        let input_path = project.root_path.join("app.txt");
+        let runs_path = project.root_path.join("runs.txt");
+        std::fs::write(&input_path, "v1").unwrap();
+        let recipe = project.recipes.get_mut("deploy:app").unwrap();
+        recipe.cache = Some(RecipeCacheConfig {
+            inputs: vec![String::from("app.txt")],
+            ..Default::default()
+        });
+        recipe.run = format!("echo run >> {}", runs_path.display());
+        let project = Arc::new(project);
+
+        for input in ["v1", "v1", "v2"] {
+            std::fs::write(&input_path, input).unwrap();
+            let cache = CacheBuilder::new(project.clone())
+                .add_strategy("local", LocalCacheStrategy::from_config)
+                .build()
+                .await
+                .unwrap();
+            super::bake(project.clone(), cache, &RecipeSearch::All)
+                .await
+                .unwrap();
+        }
+        std::fs::read_to_string(&runs_path).unwrap()
+    }
+
+    #[tokio::test]
+    async fn shared_cache_dir() {
+        let cache_dir = TestProjectBuilder::temp_dir(&[]).join("cache");
+        let runs_path = cache_dir.with_file_name("runs.txt");
+        let search = RecipeSearch::All;
+
+        for _ in 0..2 {
+            let mut project = TestProjectBuilder::new()
+                .with_cookbook("shared", &["build"])
+                .build();
+            project.config.max_parallel = 2;
+            project.config.cache.local.path = Some(cache_dir.clone());
+            let recipe = project.recipes.get_mut("shared:build").unwrap();
+            recipe.cache = Some(RecipeCacheConfig::default());
+            recipe.run = format!("echo run >> {}", runs_path.display());
+            let project = Arc::new(project);
+            let cache = CacheBuilder::new(project.clone())
+                .add_strategy("local", LocalCacheStrategy::from_config)
+                .build()
+                .await
+                .unwrap();
+            super::bake(project.clone(), cache, &search).await.unwrap();
+
+            assert!(LocalCacheStrategy::is_shared(&project));
+            assert!(crate::clean::all_paths(&project)
+                .iter()
+                .all(|path| !path.starts_with(&cache_dir)));
+        }
+
+        // The second project is restored from the entry stored by the first one
+        assert_eq!(std::fs::read_to_string(&runs_path).unwrap(), "run\n");
+        assert_eq!(std::fs::read_dir(&cache_dir).unwrap().count(), 1);
+    }
+
+    #[tokio::test]
+    async fn output_size_limits() {
+        let mut project = TestProjectBuilder::new()
+            .with_cookbook("sizes", &["big", "small"])
+            .with_output("sizes:big", "head -c 8192 /dev/zero", "big.bin")
+            .with_output("sizes:small", "echo small", "small.bin")
+            .build();
+        project.config.max_parallel = 2;
+        project.config.cache.max_output_size = Some(4096);
+        let project = Arc::new(project);
+        let search = RecipeSearch::ByPattern("sizes:".to_owned());
+
+        let mut cache_builder = CacheBuilder::new(project.clone());
+        cache_builder
+            .add_strategy("local", LocalCacheStrategy::from_config)
+            .search(search.clone());
+        let cache = cache_builder.build().await.unwrap();
+        // Recipes with outputs over the limit still succeed
+        super::bake(project.clone(), cache, &search).await.unwrap();
+
+        let hashes = cache_builder.calculate_all_hashes().unwrap();
+        let cached: Vec<String> = crate::cache::list_local_entries(&project, &hashes)
+            .unwrap()
+            .into_iter()
+            .filter_map(|entry| entry.recipe)
+            .collect();
+        assert_eq!(cached, vec!["sizes:small"]);
+    }
+
+    #[tokio::test]
+    async fn root_relative_outputs() {
+        let root = TestProjectBuilder::temp_dir(&[
+            ("bake.yml", "name: outputs\n"),
+            (
+                "app/cookbook.yml",
+                "name: app\nrecipes:\n  build:\n    cache:\n      outputs:\n        - //dist\n    \
+                 run: mkdir -p ../dist && echo built > ../dist/app.txt && echo run >> ../runs.txt\n",
+            ),
+        ]);
+        let mut project = BakeProject::from(&root, indexmap::IndexMap::new()).unwrap();
+        project.config.max_parallel = 2;
+        let project = Arc::new(project);
+        let search = RecipeSearch::ByPattern("app:".to_owned());
+
+        // The second bake restores the output from the cache
+        for _ in 0..2 {
+            let _ = std::fs::remove_dir_all(root.join("dist"));
+            let cache = CacheBuilder::new(project.clone())
+                .add_strategy("local", LocalCacheStrategy::from_config)
+                .search(search.clone())
+                .build()
+                .await
+                .unwrap();
+            super::bake(project.clone(), cache, &search).await.unwrap();
+            assert_eq!(
+                std::fs::read_to_string(root.join("dist").join("app.txt")).unwrap(),
+                "built\n"
+            );
+        }
+        assert_eq!(
+            std::fs::read_to_string(root.join("runs.txt")).unwrap(),
+            "run\n"
+        );
+
+        crate::clean::remove_paths(crate::clean::recipe_paths(&project, &search)).unwrap();
+        assert!(!root.join("dist").exists());
+    }
+
+    #[tokio::test]
+    async fn output_root() {
+        let root = TestProjectBuilder::temp_dir(&[
+            ("bake.yml", "name: outputs\n"),
+            (
+                "app/cookbook.yml",
+                "name: app\nrecipes:\n  build:\n    cache:\n      outputs:\n        - //dist\n    \
+                 run: mkdir -p $BAKE_OUTPUT_ROOT/dist && echo built > $BAKE_OUTPUT_ROOT/dist/app.txt \
+                 && echo run >> ../runs.txt\n",
+            ),
+        ]);
+        let search = RecipeSearch::ByPattern("app:".to_owned());
+        let load = |output_root: Option<PathBuf>| {
+            let mut project = BakeProject::from(&root, indexmap::IndexMap::new()).unwrap();
+            project.config.max_parallel = 2;
+            if let Some(output_root) = output_root {
+                project.set_output_root(output_root);
+            }
+            Arc::new(project)
+        };
+        let key = |project: Arc<BakeProject>| {
+            CacheBuilder::new(project)
+                .search(search.clone())
+                .calculate_all_hashes()
+                .unwrap()
+                .remove("app:build")
+                .unwrap()
+        };
+        let default_key = key(load(None));
+
+        // Each output root gets its own outputs, restored from the cache after the first bake
+        for name in ["first", "second"] {
+            let output_root = root.join("out").join(name);
+            let project = load(Some(output_root.clone()));
+            assert_eq!(key(project.clone()), default_key);
+            let cache = CacheBuilder::new(project.clone())
+                .add_strategy("local", LocalCacheStrategy::from_config)
+                .search(search.clone())
+                .build()
+                .await
+                .unwrap();
+            super::bake(project.clone(), cache, &search).await.unwrap();
+            assert_eq!(
+                std::fs::read_to_string(output_root.join("dist").join("app.txt")).unwrap(),
+                "built\n"
+            );
+        }
+        assert!(!root.join("dist").exists());
+        assert_eq!(
+            std::fs::read_to_string(root.join("runs.txt")).unwrap(),
+            "run\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn events_stream() {
+        use std::os::fd::AsRawFd;
+
+        let mut project = TestProjectBuilder::new()
+            .with_cookbook("events", &["build", "test"])
+            .with_dependency("events:test", "events:build")
+            .build();
+        project.config.max_parallel = 2;
+        project.recipes.get_mut("events:build").unwrap().run = String::from("exit 0");
+        project.recipes.get_mut("events:test").unwrap().run = String::from("exit 3");
+        let events_path = project.root_path.join("events.ndjson");
+        let events_file = std::fs::File::create(&events_path).unwrap();
+        project.config.events_fd = Some(events_file.as_raw_fd());
+        let project = Arc::new(project);
+        let cache = build_cache(project.clone()).await;
+
+        assert!(super::bake(project.clone(), cache, &RecipeSearch::All)
+            .await
+            .is_err());
+
+        let events: Vec<serde_json::Value> = std::fs::read_to_string(&events_path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0]["fqn"], "events:build");
+        assert_eq!(events[0]["status"], "success");
+        assert_eq!(events[0]["cache_hit"], false);
+        assert_eq!(events[0]["exit_code"], 0);
+        assert_eq!(
+            events[0]["log_path"],
+            project
+                .get_recipe_log_path("events:build")
+                .display()
+                .to_string()
+        );
+        assert_eq!(events[1]["fqn"], "events:test");
+        assert_eq!(events[1]["status"], "failed");
+        assert_eq!(events[1]["exit_code"], 3);
+        assert!(events[1]["duration_ms"].is_u64());
+    }
+
+    #[tokio::test]
+    async fn requires_clean_git() {
+        let mut project = TestProjectBuilder::new()
+            .with_cookbook("release", &["publish"])
+            .build();
+        project.config.max_parallel = 2;
+        let recipe = project.recipes.get_mut("release:publish").unwrap();
+        recipe.run = String::from("exit 0");
+        recipe.requires_clean_git = true;
+        let root = project.root_path.clone();
+        assert!(std::process::Command::new("git")
+            .current_dir(&root)
+            .args(["init", "--quiet"])
+            .status()
+            .unwrap()
+            .success());
+        std::fs::write(root.join(".git/info/exclude"), ".bake\n").unwrap();
+
+        let project = Arc::new(project);
+        let cache = build_cache(project.clone()).await;
+        super::bake(project.clone(), cache, &RecipeSearch::All)
+            .await
+            .unwrap();
+
+        std::fs::write(root.join("dirty.txt"), "").unwrap();
+        let cache = build_cache(project.clone()).await;
+        assert!(super::bake(project.clone(), cache, &RecipeSearch::All)
+            .await
+            .is_err());
+
+        let mut project = TestProjectBuilder::new()
+            .with_cookbook("release", &["publish"])
+            .build();
+        project.config.max_parallel = 2;
+        project.config.allow_dirty = true;
+        project.root_path = root;
+        let recipe = project.recipes.get_mut("release:publish").unwrap();
+        recipe.run = String::from("exit 0");
+        recipe.requires_clean_git = true;
+        let project = Arc::new(project);
+        let cache = build_cache(project.clone()).await;
+        super::bake(project, cache, &RecipeSearch::All)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn recipe_args() {
+        let mut project = TestProjectBuilder::new()
+            .with_cookbook("foo", &["serve", "build"])
+            .with_dependency("foo:serve", "foo:build")
+            .build();
+        project.config.max_parallel = 2;
+        project.recipes.get_mut("foo:serve").unwrap().run = crate::template::parse_template(
+            "printf '%s|' {{args}}",
+            &[],
+            &indexmap::IndexMap::new(),
+            &indexmap::IndexMap::new(),
+        )
+        .unwrap();
+        let args = vec![String::from("--port"), String::from("8080")];
+        let err = project
+            .set_recipe_args(&RecipeSearch::ByPattern(String::from("foo:")), args.clone())
+            .unwrap_err();
+        assert!(err.to_string().contains("but 2 recipes are selected"));
+
+        let search = RecipeSearch::ByPattern(String::from("foo:serve"));
+        project.set_recipe_args(&search, args).unwrap();
+        let project = Arc::new(project);
+        let cache = build_cache(project.clone()).await;
+        super::bake(project.clone(), cache, &search).await.unwrap();
+
+        let log = std::fs::read_to_string(project.get_recipe_log_path("foo:serve")).unwrap();
+        assert_eq!(log.trim(), "--port|8080|");
+        assert!(project.recipes["foo:build"].args.is_empty());
+    }
+
+    #[tokio::test]
+    async fn weighted_recipes() {
+        let mut project = TestProjectBuilder::new()
+            .with_cookbook("weights", &["heavy", "medium", "light1", "light2", "huge"])
+            .build();
+        project.config.max_parallel = 4;
+        project.config.total_capacity = Some(3);
+        let trace_path = project.root_path.join("trace.txt");
+        for (name, weight) in [
+            ("weights:heavy", 2),
+            ("weights:medium", 2),
+            ("weights:light1", 1),
+            ("weights:light2", 1),
+            ("weights:huge", 5),
+        ] {
+            let recipe = project.recipes.get_mut(name).unwrap();
+            recipe.weight = weight;
+            recipe.run = format!(
+                "echo +{1} >> {0}; sleep 0.2; echo -{1} >> {0}",
+                trace_path.display(),
+                weight
+            );
+        }
+        let project = Arc::new(project);
+        let cache = build_cache(project.clone()).await;
+        super::bake(project.clone(), cache, &RecipeSearch::All)
+            .await
+            .unwrap();
+
+        let trace = std::fs::read_to_string(&trace_path).unwrap();
+        let (mut running, mut weight) = (0, 0);
+        let mut max_weight = 0;
+        for line in trace.lines() {
+            let value: i32 = line[1..].parse().unwrap();
+            if line.starts_with('+') {
+                running += 1;
+                weight += value;
+            } else {
+                running -= 1;
+                weight -= value;
+            }
+            // Only a recipe heavier than the capacity may exceed it, and it runs alone
+            assert!(weight <= 3 || (running == 1 && value == 5), "{}", trace);
+            max_weight = max_weight.max(weight);
+        }
+        assert_eq!(trace.lines().count(), 10);
+        assert_eq!(max_weight, 5);
+    }
+
+    #[tokio::test]
+    async fn cookbook_max_parallel() {
+        let mut project = TestProjectBuilder::new()
+            .with_cookbook("db", &["a", "b", "c", "d"])
+            .with_cookbook("api", &["a", "b", "c"])
+            .build();
+        project.config.max_parallel = 6;
+        project.cookbooks.get_mut("db").unwrap().max_parallel = NonZeroUsize::new(2);
+        let trace_path = project.root_path.join("trace.txt");
+        for recipe in project.recipes.values_mut() {
+            recipe.run = format!(
+                "echo +{1} >> {0}; sleep 0.2; echo -{1} >> {0}",
+                trace_path.display(),
+                recipe.cookbook
+            );
+        }
+        let project = Arc::new(project);
+        let cache = build_cache(project.clone()).await;
+        super::bake(project.clone(), cache, &RecipeSearch::All)
+            .await
+            .unwrap();
+
+        let trace = std::fs::read_to_string(&trace_path).unwrap();
+        let mut running = BTreeMap::<&str, i32>::new();
+        let mut max_running = BTreeMap::<&str, i32>::new();
+        for line in trace.lines() {
+            let count = running.entry(&line[1..]).or_default();
+            *count += if line.starts_with('+') { 1 } else { -1 };
+            let max = max_running.entry(&line[1..]).or_default();
+            *max = (*max).max(*count);
+        }
+        assert_eq!(trace.lines().count(), 14);
+        // The limited cookbook never exceeds its limit, the others only have the global one.
+        // How many recipes actually overlap depends on timing, so only the bounds are checked
+        assert!(max_running["db"] <= 2, "{}", trace);
+        assert!(max_running["api"] <= 3, "{}", trace);
+    }
+
+    #[test]
+    fn dispatch_order() {
+        let dispatch = |cookbooks: &[&str]| {
+            let mut project = cookbooks
+                .iter()
+                .fold(TestProjectBuilder::new(), |builder, name| {
+                    builder.with_cookbook(name, &["build", "test"])
+                })
+                .with_dependency("app:build", "lib:build")
+                .with_dependency("app:test", "app:build")
+                .with_dependency("lib:test", "lib:build")
+                .build();
+            project.config.max_parallel = 2;
+            let mut queue = project.get_recipes(&RecipeSearch::All);
+            let mut order = vec![];
+            loop {
+                match super::next_ready_recipe(&project, &queue) {
+                    Some(name) => {
+                        queue.get_mut(&name).unwrap().run_status.status = Status::Running;
+                        order.push(name);
+                    }
+                    None if queue.values().all(|r| r.run_status.status == Status::Done) => break,
+                    // Finish the running recipes to unlock the next ones
+                    None => queue
+                        .values_mut()
+                        .filter(|r| r.run_status.status == Status::Running)
+                        .for_each(|r| r.run_status.status = Status::Done),
+                }
+            }
+            order
+        };
+
+        let order = dispatch(&["lib", "app", "docs"]);
+        assert_eq!(
+            order,
+            vec![
+                "docs:build",
+                "docs:test",
+                "lib:build",
+                "app:build",
+                "lib:test",
+                "app:test"
+            ]
+        );
+        assert_eq!(dispatch(&["docs", "app", "lib"]), order);
+    }
+
+    #[test]
+    fn order_only_dispatch() {
+        let mut project = TestProjectBuilder::new()
+            .with_cookbook("it", &["up", "test"])
+            .with_dependency("it:test", "it:up")
+            .build();
+        project.recipes.get_mut("it:test").unwrap().dependencies = Some(vec![RecipeDependency {
+            recipe: String::from("it:up"),
+            order_only: true,
+        }]);
+        let mut queue = project.get_recipes(&RecipeSearch::ByNames(vec![String::from("it:test")]));
+
+        // Order-only dependencies are still selected and run first
+        assert_eq!(
+            super::next_ready_recipe(&project, &queue),
+            Some(String::from("it:up"))
+        );
+        queue.get_mut("it:up").unwrap().run_status.status = Status::Running;
+        assert_eq!(super::next_ready_recipe(&project, &queue), None);
+        queue.get_mut("it:up").unwrap().run_status.status = Status::Done;
+        assert_eq!(
+            super::next_ready_recipe(&project, &queue),
+            Some(String::from("it:test"))
+        );
+    }
+
+    #[tokio::test]
+    async fn early_cutoff() {
+        let mut project = TestProjectBuilder::new()
+            .with_cookbook("cutoff", &["gen", "use"])
+            .with_dependency("cutoff:use", "cutoff:gen")
+            .build();
+        project.config.max_parallel = 2;
+        let root = project.root_path.clone();
+        let recipe = project.recipes.get_mut("cutoff:gen").unwrap();
+        recipe.cache = Some(RecipeCacheConfig {
+            inputs: vec![String::from("gen-input.txt")],
+            outputs: vec!["gen.txt".into()],
+            ..Default::default()
+        });
+        recipe.run = format!(
+            "echo same > gen.txt; echo run >> {}",
+            root.join("gen-runs.txt").display()
+        );
+        let recipe = project.recipes.get_mut("cutoff:use").unwrap();
+        recipe.cache = Some(RecipeCacheConfig::default());
+        recipe.run = format!("echo run >> {}", root.join("use-runs.txt").display());
+        let project = Arc::new(project);
+        let search = RecipeSearch::ByPattern("cutoff:".to_owned());
+
+        for input in ["1", "2"] {
+            std::fs::write(root.join("gen-input.txt"), input).unwrap();
+            let cache = CacheBuilder::new(project.clone())
+                .add_strategy("local", LocalCacheStrategy::from_config)
+                .search(search.clone())
+                .build()
+                .await
+                .unwrap();
+            super::bake(project.clone(), cache, &search).await.unwrap();
+        }
+
+        // gen re-runs because its input changed but its output is identical, so use is cached
+        let read_runs = |name: &str| std::fs::read_to_string(root.join(name)).unwrap();
+        assert_eq!(read_runs("gen-runs.txt"), "run\nrun\n");
+        assert_eq!(read_runs("use-runs.txt"), "run\n");
+    }
+
+    #[derive(Default)]
+    struct FakeExecutor {
+        executed: Mutex<Vec<String>>,
+    }
+
+    #[async_trait]
+    impl RecipeExecutor for FakeExecutor {
+        async fn execute(&self, recipe: &Recipe, _: PathBuf, _: &ToolConfig) -> anyhow::Result<()> {
+            self.executed.lock().unwrap().push(recipe.full_name());
+            // Write back declared outputs as a remote worker would
+            let cookbook_path = recipe.config_path.parent().unwrap();
+            for output in recipe.cache.iter().flat_map(|cache| &cache.outputs) {
+                std::fs::write(cookbook_path.join(output), recipe.full_name()).unwrap();
+            }
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn custom_executor() {
+        let mut project = TestProjectBuilder::new()
+            .with_cookbook("remote", &["build", "test"])
+            .with_dependency("remote:test", "remote:build")
+            .build();
+        project.config.max_parallel = 2;
+        for recipe in project.recipes.values_mut() {
+            // Would fail if run locally
+            recipe.run = String::from("false");
+        }
+        project.recipes.get_mut("remote:build").unwrap().cache = Some(RecipeCacheConfig {
+            outputs: vec!["remote-build.txt".into()],
+            ..Default::default()
+        });
+        let project = Arc::new(project);
+        let executor = Arc::new(FakeExecutor::default());
+
+        let cache = build_cache(project.clone()).await;
+        super::bake_with_executor(project.clone(), cache, &RecipeSearch::All, executor.clone())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            *executor.executed.lock().unwrap(),
+            vec!["remote:build", "remote:test"]
+        );
+        assert_eq!(
+            std::fs::read_to_string(project.root_path.join("remote-build.txt")).unwrap(),
+            "remote:build"
+        );
+    }
 }
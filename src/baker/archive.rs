@@ -0,0 +1,234 @@
+use std::{
+    fs::{File, Metadata},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use log::debug;
+
+use crate::project::{BakeProject, Recipe};
+
+/// zstd level used for `.zst` archives. Fixed so identical outputs produce identical archives
+const ARCHIVE_COMPRESSION_LEVEL: i32 = 3;
+
+/// Packs the declared outputs of a recipe into the archive set in its `cache.archive`, if any
+///
+/// Archives are reproducible: entries are sorted by path, timestamps and owners are zeroed and
+/// permissions are normalized to 0644, or 0755 for directories and executables, so identical
+/// outputs always produce byte-identical archives. Entries are named relative to the project
+/// root, and archives ending in `.zst` are compressed with zstd.
+///
+/// # Arguments
+/// * `project` - The project the recipe belongs to
+/// * `recipe` - The recipe whose outputs are packed
+///
+pub fn write_output_archive(project: &BakeProject, recipe: &Recipe) -> Result<(), String> {
+    let Some(archive) = recipe
+        .cache
+        .as_ref()
+        .and_then(|cache| cache.archive.as_ref())
+    else {
+        return Ok(());
+    };
+    let cookbook_path = recipe.config_path.parent().unwrap();
+    let archive_path = cookbook_path.join(archive);
+    debug!(
+        "Writing output archive {} for recipe {}",
+        archive_path.display(),
+        recipe.full_name()
+    );
+
+    let mut entries = Vec::new();
+    for output in recipe.cache.iter().flat_map(|cache| &cache.outputs) {
+        collect_entries(&recipe.output_path(output), &mut entries)
+            .map_err(|err| format!("Could not read output {}: {}", output, err))?;
+    }
+    // Outputs moved with `--output-root` are named as if they were in the project root
+    let roots: Vec<PathBuf> = recipe
+        .output_root
+        .iter()
+        .chain([&project.root_path])
+        .map(|root| root.canonicalize().unwrap_or(root.clone()))
+        .collect();
+    let mut entries: Vec<(PathBuf, PathBuf)> = entries
+        .into_iter()
+        .filter(|path| *path != archive_path)
+        .map(|path| {
+            let normalized = normalize(&path);
+            let name = roots
+                .iter()
+                .find_map(|root| normalized.strip_prefix(root).ok())
+                .map(Path::to_path_buf)
+                .unwrap_or_else(|| path.clone());
+            (name, path)
+        })
+        .collect();
+    entries.sort();
+    entries.dedup();
+
+    // Write next to the destination first so a failed write never leaves a partial archive
+    let temp_path = archive_path.with_extension(format!("{}.tmp", std::process::id()));
+    let result = write_archive(&temp_path, archive, &entries)
+        .and_then(|_| std::fs::rename(&temp_path, &archive_path));
+    if let Err(err) = result {
+        let _ = std::fs::remove_file(&temp_path);
+        return Err(format!(
+            "Could not write output archive {} for recipe {}: {}",
+            archive_path.display(),
+            recipe.full_name(),
+            err
+        ));
+    }
+    Ok(())
+}
+
+fn write_archive(path: &Path, name: &str, entries: &[(PathBuf, PathBuf)]) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let file = File::create(path)?;
+    if name.ends_with(".zst") {
+        let encoder = zstd::stream::Encoder::new(file, ARCHIVE_COMPRESSION_LEVEL)?;
+        let mut tar = tar::Builder::new(encoder);
+        append_entries(&mut tar, entries)?;
+        // Finish explicitly so that errors writing the end of the stream aren't lost on drop
+        tar.into_inner()?.finish()?;
+        Ok(())
+    } else {
+        let mut tar = tar::Builder::new(file);
+        append_entries(&mut tar, entries)?;
+        tar.into_inner()?.flush()
+    }
+}
+
+/// Appends entries to an archive with normalized metadata, so that the same outputs always
+/// produce the same archive
+fn append_entries<W: Write>(
+    tar: &mut tar::Builder<W>,
+    entries: &[(PathBuf, PathBuf)],
+) -> io::Result<()> {
+    for (name, path) in entries {
+        let metadata = path.symlink_metadata()?;
+        let mut header = tar::Header::new_gnu();
+        header.set_mtime(0);
+        header.set_uid(0);
+        header.set_gid(0);
+        let executable = is_executable(&metadata);
+        if metadata.is_symlink() {
+            header.set_entry_type(tar::EntryType::Symlink);
+            header.set_mode(0o777);
+            header.set_size(0);
+            tar.append_link(&mut header, name, std::fs::read_link(path)?)?;
+        } else if metadata.is_dir() {
+            header.set_entry_type(tar::EntryType::Directory);
+            header.set_mode(0o755);
+            header.set_size(0);
+            tar.append_data(&mut header, name, io::empty())?;
+        } else {
+            header.set_entry_type(tar::EntryType::Regular);
+            header.set_mode(if executable { 0o755 } else { 0o644 });
+            header.set_size(metadata.len());
+            tar.append_data(&mut header, name, File::open(path)?)?;
+        }
+    }
+    Ok(())
+}
+
+/// Adds a path and, for directories, everything under it without following symlinks
+fn collect_entries(path: &Path, entries: &mut Vec<PathBuf>) -> io::Result<()> {
+    let metadata = path.symlink_metadata()?;
+    entries.push(path.to_path_buf());
+    if metadata.is_dir() {
+        for entry in std::fs::read_dir(path)? {
+            collect_entries(&entry?.path(), entries)?;
+        }
+    }
+    Ok(())
+}
+
+/// Resolves the parent directories of a path without resolving the path itself, which may be a
+/// symlink
+fn normalize(path: &Path) -> PathBuf {
+    match (path.parent().map(Path::canonicalize), path.file_name()) {
+        (Some(Ok(parent)), Some(file_name)) => parent.join(file_name),
+        _ => path.to_path_buf(),
+    }
+}
+
+/// Returns whether a file can be executed by anyone. Files are never marked executable outside of
+/// unix
+#[cfg(unix)]
+fn is_executable(metadata: &Metadata) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    metadata.permissions().mode() & 0o111 != 0
+}
+
+#[cfg(not(unix))]
+fn is_executable(_metadata: &Metadata) -> bool {
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{os::unix::fs::PermissionsExt, time::SystemTime};
+
+    use crate::{project::RecipeCacheConfig, test_utils::TestProjectBuilder};
+
+    #[test]
+    fn reproducible() {
+        let mut project = TestProjectBuilder::new()
+            .with_cookbook("dist", &["package"])
+            .build();
+        project.recipes.get_mut("dist:package").unwrap().cache = Some(RecipeCacheConfig {
+            outputs: vec!["out".into(), "app".into()],
+            archive: Some(String::from("dist.tar.zst")),
+            ..Default::default()
+        });
+        let recipe = &project.recipes["dist:package"];
+        let cookbook_path = recipe.config_path.parent().unwrap().to_path_buf();
+        let archive_path = cookbook_path.join("dist.tar.zst");
+
+        let write_outputs = |mode: u32, modified: SystemTime| {
+            let _ = std::fs::remove_dir_all(cookbook_path.join("out"));
+            std::fs::create_dir_all(cookbook_path.join("out/nested")).unwrap();
+            for (name, contents) in [
+                ("out/b.txt", "b"),
+                ("out/nested/a.txt", "a"),
+                ("app", "bin"),
+            ] {
+                let path = cookbook_path.join(name);
+                std::fs::write(&path, contents).unwrap();
+                let file = std::fs::File::options().write(true).open(&path).unwrap();
+                file.set_modified(modified).unwrap();
+                file.set_permissions(std::fs::Permissions::from_mode(mode))
+                    .unwrap();
+            }
+        };
+
+        write_outputs(0o644, SystemTime::UNIX_EPOCH);
+        super::write_output_archive(&project, recipe).unwrap();
+        let first = std::fs::read(&archive_path).unwrap();
+
+        // Timestamps and group or other permission bits don't change the archive
+        write_outputs(0o600, SystemTime::now());
+        super::write_output_archive(&project, recipe).unwrap();
+        assert_eq!(std::fs::read(&archive_path).unwrap(), first);
+
+        let mut archive = tar::Archive::new(zstd::stream::Decoder::new(first.as_slice()).unwrap());
+        let names: Vec<String> = archive
+            .entries()
+            .unwrap()
+            .map(|entry| entry.unwrap().path().unwrap().display().to_string())
+            .collect();
+        assert_eq!(
+            names,
+            vec!["app", "out", "out/b.txt", "out/nested", "out/nested/a.txt"]
+        );
+
+        // Contents do
+        std::fs::write(cookbook_path.join("app"), "new").unwrap();
+        super::write_output_archive(&project, recipe).unwrap();
+        assert_ne!(std::fs::read(&archive_path).unwrap(), first);
+    }
+}
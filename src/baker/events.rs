@@ -0,0 +1,86 @@
+use std::{fs::File, io::Write, path::PathBuf, sync::Mutex};
+
+use anyhow::bail;
+use log::warn;
+use serde::Serialize;
+
+/// Result of a recipe, emitted as soon as it finishes
+#[derive(Debug, Serialize)]
+pub struct RecipeEvent {
+    /// Fully qualified name of the recipe
+    pub fqn: String,
+
+    /// Either `success` or `failed`
+    pub status: &'static str,
+
+    /// Whether the recipe was restored from cache instead of running
+    pub cache_hit: bool,
+
+    pub duration_ms: u128,
+
+    /// Exit code of the recipe's process, None if it didn't run, eg. when restored from cache,
+    /// or was killed by a signal
+    pub exit_code: Option<i32>,
+
+    pub log_path: PathBuf,
+}
+
+/// Stream of newline delimited JSON recipe events, written to a file descriptor so they don't
+/// mix with the human readable output
+#[derive(Debug)]
+pub struct EventStream {
+    file: Mutex<File>,
+}
+
+impl EventStream {
+    /// Creates a stream writing to a file descriptor inherited from the parent process, eg. `3`
+    /// for `bake --events-fd 3 3>events.ndjson`
+    ///
+    /// The descriptor is duplicated so it stays open when the stream is dropped. Only supported
+    /// on unix.
+    #[cfg(unix)]
+    pub fn from_fd(fd: i32) -> anyhow::Result<Self> {
+        use std::os::fd::FromRawFd;
+
+        // SAFETY: dup only creates a new descriptor, which is owned by the returned file
+        let duplicated = unsafe { libc::dup(fd) };
+        if duplicated < 0 {
+            bail!(
+                "Could not open events file descriptor {}: {}",
+                fd,
+                std::io::Error::last_os_error()
+            );
+        }
+        Ok(Self {
+            // SAFETY: the duplicated descriptor is valid and not owned by anything else
+            file: Mutex::new(unsafe { File::from_raw_fd(duplicated) }),
+        })
+    }
+
+    #[cfg(not(unix))]
+    pub fn from_fd(fd: i32) -> anyhow::Result<Self> {
+        bail!(
+            "Could not open events file descriptor {}: only supported on unix",
+            fd
+        )
+    }
+
+    /// Writes an event as a single line. Errors are only logged so they never fail a bake
+    pub fn emit(&self, event: &RecipeEvent) {
+        let mut line = match serde_json::to_vec(event) {
+            Ok(line) => line,
+            Err(err) => {
+                warn!(
+                    "Could not serialize event for recipe {}: {}",
+                    event.fqn, err
+                );
+                return;
+            }
+        };
+        line.push(b'\n');
+        let mut file = self.file.lock().unwrap();
+        if let Err(err) = file.write_all(&line).and_then(|_| file.flush()) {
+            warn!("Could not write event for recipe {}: {}", event.fqn, err);
+        }
+    }
+}
@@ -0,0 +1,44 @@
+use std::path::PathBuf;
+
+use async_trait::async_trait;
+
+use crate::project::{config::ToolConfig, Recipe};
+
+/// Executes the `run` command of a recipe
+///
+/// Baking always uses [`LocalExecutor`], which spawns the command on this host. The trait is an
+/// internal seam so tests can substitute recipe runs, outputs are cached after a successful
+/// execution the same way regardless of the executor.
+#[async_trait]
+pub(crate) trait RecipeExecutor: Send + Sync {
+    /// Runs a recipe to completion, failing with `BakeError::RecipeFailed` if its command exited
+    /// unsuccessfully so that its exit code is reported
+    ///
+    /// # Arguments
+    /// * `recipe` - The recipe to run
+    /// * `log_file_path` - File where the recipe's output should be written
+    /// * `config` - The project's tool configuration
+    ///
+    async fn execute(
+        &self,
+        recipe: &Recipe,
+        log_file_path: PathBuf,
+        config: &ToolConfig,
+    ) -> anyhow::Result<()>;
+}
+
+/// Runs recipes as processes on this host
+#[derive(Debug, Default, Clone)]
+pub(crate) struct LocalExecutor;
+
+#[async_trait]
+impl RecipeExecutor for LocalExecutor {
+    async fn execute(
+        &self,
+        recipe: &Recipe,
+        log_file_path: PathBuf,
+        config: &ToolConfig,
+    ) -> anyhow::Result<()> {
+        super::run_recipe(recipe, log_file_path, config).await
+    }
+}
@@ -0,0 +1,119 @@
+use std::path::PathBuf;
+
+use tokio::sync::OnceCell;
+
+/// State of the project's git working tree, checked at most once per bake
+///
+/// Recipes with `requires_clean_git`, such as releases, refuse to run when the tree has
+/// uncommitted changes so that published artifacts always match a commit.
+#[derive(Debug)]
+pub struct GitState {
+    root_path: PathBuf,
+    status: OnceCell<Result<Vec<String>, String>>,
+}
+
+impl GitState {
+    /// Creates a git state for the repository containing the given directory
+    ///
+    /// # Arguments
+    /// * `root_path` - Directory to run git in, usually the project root
+    ///
+    pub fn new(root_path: PathBuf) -> Self {
+        Self {
+            root_path,
+            status: OnceCell::new(),
+        }
+    }
+
+    /// Fails if the working tree has uncommitted changes or its state can't be read
+    ///
+    /// # Arguments
+    /// * `recipe_name` - Name of the recipe requiring a clean tree, used in error messages
+    ///
+    pub async fn ensure_clean(&self, recipe_name: &str) -> Result<(), String> {
+        let status = self
+            .status
+            .get_or_init(|| async { self.porcelain_status().await })
+            .await;
+        match status {
+            Ok(changes) if changes.is_empty() => Ok(()),
+            Ok(changes) => Err(format!(
+                "Recipe {} requires a clean git tree but {} paths have uncommitted changes (first: {}). Commit them or use --allow-dirty",
+                recipe_name,
+                changes.len(),
+                changes[0]
+            )),
+            Err(err) => Err(format!(
+                "Recipe {} requires a clean git tree: {}",
+                recipe_name, err
+            )),
+        }
+    }
+
+    /// Runs `git status --porcelain` and returns its changed entries, leaving out the project's
+    /// `.bake` directory that bake itself writes to
+    async fn porcelain_status(&self) -> Result<Vec<String>, String> {
+        let output = match tokio::process::Command::new("git")
+            .current_dir(&self.root_path)
+            .args(["status", "--porcelain", "--", ":(exclude).bake"])
+            .output()
+            .await
+        {
+            Ok(output) => output,
+            Err(err) => return Err(format!("Could not run git: {}", err)),
+        };
+        if !output.status.success() {
+            return Err(format!(
+                "git status failed with {}: {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| line.trim().to_owned())
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::Command;
+
+    use crate::test_utils::TestProjectBuilder;
+
+    use super::GitState;
+
+    #[tokio::test]
+    async fn ensure_clean() {
+        let root_path = TestProjectBuilder::temp_dir(&[]);
+        assert!(GitState::new(root_path.clone())
+            .ensure_clean("release:publish")
+            .await
+            .unwrap_err()
+            .contains("git status failed"));
+
+        assert!(Command::new("git")
+            .current_dir(&root_path)
+            .args(["init", "--quiet"])
+            .status()
+            .unwrap()
+            .success());
+        // Logs and caches written by bake don't make the tree dirty
+        std::fs::create_dir_all(root_path.join(".bake/logs")).unwrap();
+        std::fs::write(root_path.join(".bake/logs/foo.build.log"), "").unwrap();
+        let git = GitState::new(root_path.clone());
+        assert!(git.ensure_clean("release:publish").await.is_ok());
+
+        // The result is cached for the whole bake
+        std::fs::write(root_path.join("dirty.txt"), "").unwrap();
+        assert!(git.ensure_clean("release:publish").await.is_ok());
+
+        let err = GitState::new(root_path)
+            .ensure_clean("release:publish")
+            .await
+            .unwrap_err();
+        assert!(err.contains("?? dirty.txt"));
+    }
+}
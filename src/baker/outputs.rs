@@ -0,0 +1,115 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use log::{debug, warn};
+
+use crate::project::Recipe;
+
+/// Backup of a recipe's declared outputs taken before it runs
+///
+/// Existing outputs are moved aside so the recipe starts from a clean slate. Outputs that were
+/// directories are recreated empty. If the recipe fails, whatever it wrote is removed and the
+/// previous outputs are moved back into place, so a failed run never leaves partial outputs.
+#[derive(Debug)]
+pub struct OutputsBackup {
+    backup_dir: PathBuf,
+    /// Output paths and the location of their backups, if they existed before running
+    outputs: Vec<(PathBuf, Option<PathBuf>)>,
+}
+
+impl OutputsBackup {
+    /// Moves the existing outputs of a recipe into a backup directory
+    ///
+    /// # Arguments
+    /// * `recipe` - The recipe about to run
+    /// * `backup_dir` - Directory to move existing outputs to
+    ///
+    pub fn create(recipe: &Recipe, backup_dir: PathBuf) -> io::Result<Self> {
+        let mut backup = Self {
+            backup_dir,
+            outputs: Vec::new(),
+        };
+        remove_path(&backup.backup_dir)?;
+
+        let outputs = recipe
+            .cache
+            .as_ref()
+            .map(|cache| cache.outputs.as_slice())
+            .unwrap_or_default();
+        for (idx, output) in outputs.iter().enumerate() {
+            let output_path = recipe.output_path(output);
+            if output_path.symlink_metadata().is_err() {
+                backup.outputs.push((output_path, None));
+                continue;
+            }
+
+            let backup_path = backup.backup_dir.join(idx.to_string());
+            std::fs::create_dir_all(&backup.backup_dir)?;
+            debug!(
+                "Moving output {} to {}",
+                output_path.display(),
+                backup_path.display()
+            );
+            let is_dir = output_path.is_dir() && !output_path.is_symlink();
+            if let Err(err) = std::fs::rename(&output_path, &backup_path) {
+                // Put back anything already moved before giving up
+                backup.restore();
+                return Err(err);
+            }
+            if is_dir {
+                std::fs::create_dir_all(&output_path)?;
+            }
+            backup.outputs.push((output_path, Some(backup_path)));
+        }
+
+        Ok(backup)
+    }
+
+    /// Removes outputs written by a failed run and moves the previous outputs back into place
+    pub fn restore(self) {
+        for (output_path, backup_path) in &self.outputs {
+            if let Err(err) = remove_path(output_path) {
+                warn!(
+                    "Could not remove partial output {}: {}",
+                    output_path.display(),
+                    err
+                );
+                continue;
+            }
+            if let Some(backup_path) = backup_path {
+                if let Err(err) = std::fs::rename(backup_path, output_path) {
+                    warn!(
+                        "Could not restore output {} from {}: {}",
+                        output_path.display(),
+                        backup_path.display(),
+                        err
+                    );
+                }
+            }
+        }
+        self.discard();
+    }
+
+    /// Drops the previous outputs after a successful run
+    pub fn discard(self) {
+        if let Err(err) = remove_path(&self.backup_dir) {
+            warn!(
+                "Could not remove outputs backup {}: {}",
+                self.backup_dir.display(),
+                err
+            );
+        }
+    }
+}
+
+/// Removes a file, symlink or directory, doing nothing if it doesn't exist
+pub fn remove_path(path: &Path) -> io::Result<()> {
+    match path.symlink_metadata() {
+        Ok(metadata) if metadata.is_dir() => std::fs::remove_dir_all(path),
+        Ok(_) => std::fs::remove_file(path),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(err),
+    }
+}
@@ -0,0 +1,457 @@
+use std::{collections::BTreeMap, fmt::Write, path::Path, time::Duration};
+
+use anyhow::bail;
+use handlebars::Handlebars;
+use serde_json::json;
+
+use crate::project::{BakeProject, Recipe, RunStatus, Status};
+
+/// Number of log lines included in the report for failed recipes
+const LOG_TAIL_LINES: usize = 50;
+
+/// Template of the HTML report, rendered with the recipes' results
+const HTML_REPORT_TEMPLATE: &str = include_str!("../../resources/report.html.hbs");
+
+/// Size of a recipe box in the dependency graph and the gaps between them, in pixels
+const GRAPH_NODE_WIDTH: usize = 200;
+const GRAPH_NODE_HEIGHT: usize = 30;
+const GRAPH_COLUMN_GAP: usize = 60;
+const GRAPH_ROW_GAP: usize = 15;
+
+/// Writes a JUnit XML report of a bake where each cookbook is a test suite and each recipe is a
+/// test case
+///
+/// # Arguments
+/// * `project` - The baked project
+/// * `recipes` - The recipes that were selected for baking with their final status
+/// * `path` - Path of the report file
+///
+pub fn write_junit_report(
+    project: &BakeProject,
+    recipes: &BTreeMap<String, Recipe>,
+    path: &Path,
+) -> anyhow::Result<()> {
+    if let Err(err) = std::fs::write(path, junit_report(project, recipes)) {
+        bail!("Could not write summary file {}: {}", path.display(), err);
+    }
+    Ok(())
+}
+
+fn junit_report(project: &BakeProject, recipes: &BTreeMap<String, Recipe>) -> String {
+    let mut suites: BTreeMap<&str, Vec<&Recipe>> = BTreeMap::new();
+    for recipe in recipes.values() {
+        suites.entry(&recipe.cookbook).or_default().push(recipe);
+    }
+
+    let count = |recipes: &[&Recipe], status: Status| {
+        recipes
+            .iter()
+            .filter(|recipe| recipe.run_status.status == status)
+            .count()
+    };
+    let time = |recipes: &[&Recipe]| {
+        recipes
+            .iter()
+            .map(|recipe| recipe.run_status.duration.as_secs_f64())
+            .sum::<f64>()
+    };
+
+    let all_recipes: Vec<&Recipe> = recipes.values().collect();
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    let _ = writeln!(
+        xml,
+        "<testsuites name=\"{}\" tests=\"{}\" failures=\"{}\" time=\"{:.3}\">",
+        escape_xml(&project.name),
+        all_recipes.len(),
+        count(&all_recipes, Status::Error),
+        time(&all_recipes),
+    );
+
+    for (cookbook, recipes) in suites {
+        let _ = writeln!(
+            xml,
+            "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" skipped=\"{}\" time=\"{:.3}\">",
+            escape_xml(cookbook),
+            recipes.len(),
+            count(&recipes, Status::Error),
+            count(&recipes, Status::Idle),
+            time(&recipes),
+        );
+
+        for recipe in recipes {
+            let _ = writeln!(
+                xml,
+                "    <testcase name=\"{}\" classname=\"{}\" time=\"{:.3}\">",
+                escape_xml(&recipe.name),
+                escape_xml(&recipe.cookbook),
+                recipe.run_status.duration.as_secs_f64(),
+            );
+            let _ = writeln!(
+                xml,
+                "      <properties>\n        <property name=\"cached\" value=\"{}\"/>\n      </properties>",
+                recipe.run_status.cached
+            );
+            match recipe.run_status.status {
+                Status::Error => {
+                    let log =
+                        std::fs::read_to_string(project.get_recipe_log_path(&recipe.full_name()))
+                            .unwrap_or_default();
+                    let lines: Vec<&str> = log.lines().collect();
+                    let tail = lines[lines.len().saturating_sub(LOG_TAIL_LINES)..].join("\n");
+                    let _ = writeln!(
+                        xml,
+                        "      <failure message=\"{}\">{}</failure>",
+                        escape_xml(&recipe.run_status.output),
+                        escape_xml(&tail)
+                    );
+                }
+                Status::Idle | Status::Running => {
+                    xml.push_str("      <skipped/>\n");
+                }
+                Status::Done => {}
+            }
+            xml.push_str("    </testcase>\n");
+        }
+
+        xml.push_str("  </testsuite>\n");
+    }
+
+    xml.push_str("</testsuites>\n");
+    xml
+}
+
+/// Writes a self-contained HTML report of a bake with a timeline of the recipes, cache statistics,
+/// the dependency graph and links to each recipe's log
+///
+/// # Arguments
+/// * `project` - The baked project
+/// * `recipes` - The recipes that were selected for baking with their final status
+/// * `path` - Path of the report file
+///
+pub fn write_html_report(
+    project: &BakeProject,
+    recipes: &BTreeMap<String, Recipe>,
+    path: &Path,
+) -> anyhow::Result<()> {
+    let html = html_report(project, recipes)?;
+    if let Err(err) = std::fs::write(path, html) {
+        bail!("Could not write report file {}: {}", path.display(), err);
+    }
+    Ok(())
+}
+
+fn html_report(
+    project: &BakeProject,
+    recipes: &BTreeMap<String, Recipe>,
+) -> anyhow::Result<String> {
+    // The timeline spans from the first recipe started to the last one finished
+    let start = recipes
+        .values()
+        .filter_map(|recipe| recipe.run_status.started_at)
+        .min();
+    let end = recipes
+        .values()
+        .filter_map(|recipe| {
+            let status = &recipe.run_status;
+            status
+                .started_at
+                .map(|started_at| started_at + status.duration)
+        })
+        .max();
+    let total = match (start, end) {
+        (Some(start), Some(end)) => end.duration_since(start).unwrap_or_default(),
+        _ => Duration::ZERO,
+    }
+    .max(Duration::from_millis(1));
+    let percent = |duration: Duration| duration.as_secs_f64() / total.as_secs_f64() * 100.0;
+
+    let mut ordered: Vec<&Recipe> = recipes.values().collect();
+    ordered.sort_by_key(|recipe| {
+        (
+            recipe.run_status.started_at.is_none(),
+            recipe.run_status.started_at,
+        )
+    });
+    let rows: Vec<serde_json::Value> = ordered
+        .iter()
+        .map(|recipe| {
+            let status = &recipe.run_status;
+            let offset = match (start, status.started_at) {
+                (Some(start), Some(started_at)) => {
+                    percent(started_at.duration_since(start).unwrap_or_default())
+                }
+                _ => 0.0,
+            };
+            let log_path = project.get_recipe_log_path(&recipe.full_name());
+            json!({
+                "name": recipe.full_name(),
+                "status": status_label(status),
+                "duration": format!("{:.2}s", status.duration.as_secs_f64()),
+                "offset": format!("{:.2}", offset),
+                "width": format!("{:.2}", percent(status.duration)),
+                "log_path": log_path.is_file().then(|| log_path.display().to_string()),
+            })
+        })
+        .collect();
+
+    let count = |label: &str| {
+        recipes
+            .values()
+            .filter(|recipe| status_label(&recipe.run_status) == label)
+            .count()
+    };
+    let misses = recipes
+        .values()
+        .filter(|recipe| {
+            project.is_cached(recipe)
+                && !recipe.run_status.cached
+                && matches!(recipe.run_status.status, Status::Done | Status::Error)
+        })
+        .count();
+    let data = json!({
+        "project": project.name,
+        "stats": {
+            "recipes": recipes.len(),
+            "done": count("done") + count("cached"),
+            "failed": count("failed"),
+            "skipped": count("skipped"),
+            "hits": count("cached"),
+            "misses": misses,
+            "duration": format!("{:.2}s", total.as_secs_f64()),
+        },
+        "recipes": rows,
+        "graph": dependency_graph_svg(recipes),
+    });
+
+    let mut handlebars = Handlebars::new();
+    handlebars.register_template_string("report", HTML_REPORT_TEMPLATE)?;
+    match handlebars.render("report", &data) {
+        Ok(html) => Ok(html),
+        Err(err) => bail!("Failed to render report: {}", err),
+    }
+}
+
+fn status_label(status: &RunStatus) -> &'static str {
+    match status.status {
+        Status::Done if status.cached => "cached",
+        Status::Done => "done",
+        Status::Error => "failed",
+        Status::Idle | Status::Running => "skipped",
+    }
+}
+
+/// Renders the dependency graph of the baked recipes as an inline SVG, with recipes laid out in
+/// columns by the length of their longest dependency chain
+fn dependency_graph_svg(recipes: &BTreeMap<String, Recipe>) -> String {
+    fn depth<'a>(
+        fqn: &'a str,
+        recipes: &'a BTreeMap<String, Recipe>,
+        depths: &mut BTreeMap<&'a str, usize>,
+    ) -> usize {
+        if let Some(depth) = depths.get(fqn) {
+            return *depth;
+        }
+        let value = recipes[fqn]
+            .dependencies
+            .iter()
+            .flatten()
+            .filter(|dependency| recipes.contains_key(&dependency.recipe))
+            .map(|dependency| depth(&dependency.recipe, recipes, depths) + 1)
+            .max()
+            .unwrap_or(0);
+        depths.insert(fqn, value);
+        value
+    }
+
+    let mut depths = BTreeMap::new();
+    let mut columns: Vec<Vec<&str>> = Vec::new();
+    for fqn in recipes.keys() {
+        let column = depth(fqn, recipes, &mut depths);
+        if columns.len() <= column {
+            columns.resize(column + 1, Vec::new());
+        }
+        columns[column].push(fqn);
+    }
+
+    let mut positions = BTreeMap::new();
+    for (column, fqns) in columns.iter().enumerate() {
+        for (row, fqn) in fqns.iter().enumerate() {
+            let x = column * (GRAPH_NODE_WIDTH + GRAPH_COLUMN_GAP);
+            let y = row * (GRAPH_NODE_HEIGHT + GRAPH_ROW_GAP);
+            positions.insert(*fqn, (x, y));
+        }
+    }
+    let width = columns.len() * (GRAPH_NODE_WIDTH + GRAPH_COLUMN_GAP);
+    let height =
+        columns.iter().map(Vec::len).max().unwrap_or(0) * (GRAPH_NODE_HEIGHT + GRAPH_ROW_GAP);
+
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" font-family=\"sans-serif\" font-size=\"12\">\n",
+        width, height
+    );
+    svg.push_str("  <defs><marker id=\"arrow\" viewBox=\"0 0 10 10\" refX=\"10\" refY=\"5\" markerWidth=\"6\" markerHeight=\"6\" orient=\"auto\"><path d=\"M 0 0 L 10 5 L 0 10 z\"/></marker></defs>\n");
+    // Edges go from each dependency to the recipes depending on it
+    for (fqn, recipe) in recipes {
+        let (x, y) = positions[fqn.as_str()];
+        for dependency in recipe.dependencies.iter().flatten() {
+            if let Some((from_x, from_y)) = positions.get(dependency.recipe.as_str()) {
+                let _ = writeln!(
+                    svg,
+                    "  <line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\" stroke=\"#888\" marker-end=\"url(#arrow)\"/>",
+                    from_x + GRAPH_NODE_WIDTH,
+                    from_y + GRAPH_NODE_HEIGHT / 2,
+                    x,
+                    y + GRAPH_NODE_HEIGHT / 2
+                );
+            }
+        }
+    }
+    for (fqn, recipe) in recipes {
+        let (x, y) = positions[fqn.as_str()];
+        let fill = match status_label(&recipe.run_status) {
+            "done" => "#c8e6c9",
+            "cached" => "#bbdefb",
+            "failed" => "#ffcdd2",
+            _ => "#eeeeee",
+        };
+        let _ = writeln!(
+            svg,
+            "  <g><rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" rx=\"4\" fill=\"{}\" stroke=\"#666\"/><text x=\"{}\" y=\"{}\">{}</text></g>",
+            x,
+            y,
+            GRAPH_NODE_WIDTH,
+            GRAPH_NODE_HEIGHT,
+            fill,
+            x + 8,
+            y + GRAPH_NODE_HEIGHT / 2 + 4,
+            escape_xml(fqn)
+        );
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+fn escape_xml(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use xmlparser::{ElementEnd, Token, Tokenizer};
+
+    use crate::{project::Status, test_utils::TestProjectBuilder};
+
+    #[test]
+    fn junit_report() {
+        let mut project = TestProjectBuilder::new()
+            .with_cookbook("foo", &["build", "test"])
+            .with_cookbook("bar", &["build"])
+            .build();
+        project.create_project_bake_dirs().unwrap();
+        std::fs::write(
+            project.get_recipe_log_path("foo:test"),
+            "assertion <failed> & more",
+        )
+        .unwrap();
+
+        let status = &mut project.recipes.get_mut("foo:build").unwrap().run_status;
+        status.status = Status::Done;
+        status.cached = true;
+        status.duration = Duration::from_millis(1500);
+        let status = &mut project.recipes.get_mut("foo:test").unwrap().run_status;
+        status.status = Status::Error;
+        status.output = String::from("Recipe foo:test failed with exit code 1");
+
+        let xml = super::junit_report(&project, &project.recipes);
+
+        // Check that all elements are balanced and collect test cases
+        let mut stack = Vec::new();
+        let mut testcases = Vec::new();
+        for token in Tokenizer::from(xml.as_str()) {
+            match token.unwrap() {
+                Token::ElementStart { local, .. } => stack.push(local.as_str().to_owned()),
+                Token::Attribute { local, value, .. }
+                    if stack.last().map(String::as_str) == Some("testcase")
+                        && local.as_str() == "name" =>
+                {
+                    testcases.push(value.as_str().to_owned());
+                }
+                Token::ElementEnd { end, .. } => match end {
+                    ElementEnd::Empty => {
+                        stack.pop();
+                    }
+                    ElementEnd::Close(_, local) => {
+                        assert_eq!(stack.pop().as_deref(), Some(local.as_str()));
+                    }
+                    ElementEnd::Open => {}
+                },
+                _ => {}
+            }
+        }
+        assert!(stack.is_empty());
+        assert_eq!(testcases, vec!["build", "build", "test"]);
+
+        assert!(xml.contains("<testsuite name=\"foo\" tests=\"2\" failures=\"1\" skipped=\"0\""));
+        assert!(xml.contains("<testcase name=\"build\" classname=\"foo\" time=\"1.500\">"));
+        assert!(xml.contains("<property name=\"cached\" value=\"true\"/>"));
+        assert!(xml.contains("assertion &lt;failed&gt; &amp; more</failure>"));
+        assert!(xml.contains("<skipped/>"));
+    }
+
+    #[test]
+    fn html_report() {
+        let mut project = TestProjectBuilder::new()
+            .with_cookbook("foo", &["build", "test"])
+            .with_cookbook("bar", &["build"])
+            .with_dependency("foo:test", "foo:build")
+            .build();
+        project.create_project_bake_dirs().unwrap();
+        std::fs::write(project.get_recipe_log_path("foo:test"), "failed").unwrap();
+
+        let started_at = SystemTime::now();
+        let status = &mut project.recipes.get_mut("foo:build").unwrap().run_status;
+        status.status = Status::Done;
+        status.cached = true;
+        status.started_at = Some(started_at);
+        status.duration = Duration::from_millis(500);
+        let status = &mut project.recipes.get_mut("foo:test").unwrap().run_status;
+        status.status = Status::Error;
+        status.started_at = Some(started_at + Duration::from_millis(500));
+        status.duration = Duration::from_millis(1500);
+
+        let html = super::html_report(&project, &project.recipes).unwrap();
+        for (name, status) in [
+            ("foo:build", "cached"),
+            ("foo:test", "failed"),
+            ("bar:build", "skipped"),
+        ] {
+            assert!(
+                html.contains(&format!(
+                    "<td>{}</td>\n    <td class=\"{1}\">{1}</td>",
+                    name, status
+                )),
+                "{}",
+                html
+            );
+            assert!(html.contains(&format!(">{}</text>", name)));
+        }
+
+        // The failed recipe starts a quarter into the bake and takes the rest of it
+        assert!(html.contains("style=\"margin-left: 25.00%; width: 75.00%\""));
+        assert!(html.contains(&format!(
+            "<a href=\"{}\">log</a>",
+            project.get_recipe_log_path("foo:test").display()
+        )));
+        assert!(html.contains(
+            "<td>3</td><td>1</td><td>1</td><td>1</td><td>1</td><td>0</td><td>2.00s</td>"
+        ));
+        assert!(html.contains("<line x1=\"200\" y1=\"60\" x2=\"260\" y2=\"15\""));
+    }
+}
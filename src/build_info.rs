@@ -0,0 +1,34 @@
+use aws_sdk_s3::primitives::{DateTime, DateTimeFormat};
+
+/// Remote cache backends compiled into this binary
+const REMOTE_BACKENDS: &[&str] = &["s3", "gcs"];
+
+/// Returns a description of this build for bug reports: version, git commit, build date, rustc
+/// version and the available remote cache backends
+pub fn build_info() -> String {
+    let timestamp = env!("BAKE_BUILD_TIMESTAMP")
+        .parse::<i64>()
+        .unwrap_or_default();
+    let build_date = DateTime::from_secs(timestamp)
+        .fmt(DateTimeFormat::DateTime)
+        .unwrap_or_else(|_| timestamp.to_string());
+
+    format!(
+        "bake {}\ncommit: {}\nbuilt: {}\nrustc: {}\nremote caches: {}",
+        env!("CARGO_PKG_VERSION"),
+        env!("BAKE_GIT_HASH"),
+        build_date,
+        env!("BAKE_RUSTC_VERSION"),
+        REMOTE_BACKENDS.join(", ")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn build_info() {
+        let info = super::build_info();
+        assert!(info.starts_with(&format!("bake {}\n", env!("CARGO_PKG_VERSION"))));
+        assert!(info.contains("remote caches: s3, gcs"));
+    }
+}
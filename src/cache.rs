@@ -1,24 +1,86 @@
 pub mod builder;
+pub mod credentials;
 pub mod gcs;
 pub mod local;
+pub mod memory;
 pub mod s3;
 
-use std::{collections::HashMap, fs::File, io::Seek, path::PathBuf, sync::Arc};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs::File,
+    io::{Read, Seek, Write},
+    path::{Component, Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
 
 use anyhow::{anyhow, bail};
 use async_trait::async_trait;
-use log::warn;
+use log::{debug, warn};
+use rand::{
+    distributions::{Alphanumeric, DistString},
+    Rng,
+};
 
-use crate::project::BakeProject;
+use crate::{
+    baker::outputs::remove_path,
+    error::BakeError,
+    project::{config::CompressionLevel, BakeProject, OutputMode, Recipe},
+};
 
 pub use builder::CacheBuilder;
 
 pub const ARCHIVE_EXTENSION: &str = "tar.zst";
 
+/// Delay before the first retry of a failed remote cache operation, doubled on each attempt
+const REMOTE_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// Longest delay between retries of a remote cache operation, however many retries are allowed
+const REMOTE_RETRY_MAX_DELAY: Duration = Duration::from_secs(30);
+
 #[async_trait]
 pub trait CacheStrategy: Send + Sync {
-    async fn get(&self, key: &str) -> CacheResult;
+    async fn get(&self, key: &str) -> anyhow::Result<CacheResult>;
     async fn put(&self, key: &str, archive_path: PathBuf) -> anyhow::Result<()>;
+
+    /// Whether the strategy stores entries outside of the local machine. Errors from remote
+    /// strategies are only fatal when `cache.strict_remote` is enabled.
+    fn is_remote(&self) -> bool {
+        true
+    }
+
+    /// Removes the entry stored with a key. Only used to clean up after `check`
+    async fn delete(&self, _key: &str) -> anyhow::Result<()> {
+        bail!("Deleting entries is not supported")
+    }
+
+    /// Checks the strategy can be used by looking up, storing and deleting a tiny entry under a
+    /// random key, so credentials and permissions are validated without baking anything
+    async fn check(&self) -> anyhow::Result<()> {
+        let key = format!(
+            "bake-check-{}",
+            Alphanumeric.sample_string(&mut rand::thread_rng(), 8)
+        );
+        if let Err(err) = self.get(&key).await {
+            bail!("Could not read: {}", err);
+        }
+
+        let archive_path = std::env::temp_dir().join(format!("{}.{}", key, ARCHIVE_EXTENSION));
+        if let Err(err) = std::fs::write(&archive_path, "") {
+            bail!("Could not create {}: {}", archive_path.display(), err);
+        }
+        let result = self.put(&key, archive_path.clone()).await;
+        let _ = std::fs::remove_file(&archive_path);
+        if let Err(err) = result {
+            bail!("Could not write: {}", err);
+        }
+
+        if let Err(err) = self.delete(&key).await {
+            bail!("Could not delete test entry {}: {}", key, err);
+        }
+        Ok(())
+    }
+
     async fn from_config(config: Arc<BakeProject>) -> anyhow::Result<Box<dyn CacheStrategy>>
     where
         Self: Sized;
@@ -35,6 +97,240 @@ pub enum CacheResult {
     Miss,
 }
 
+/// Information about a recipe entry stored in the local cache
+#[derive(Debug)]
+pub struct CacheEntry {
+    /// Fully qualified name of the recipe whose current key the entry is stored under, None for
+    /// stale entries that no recipe uses anymore
+    pub recipe: Option<String>,
+
+    /// Cache key of the entry
+    pub key: String,
+
+    /// Last time the entry was written
+    pub modified: SystemTime,
+
+    /// Total uncompressed size of the stored files
+    pub size: u64,
+
+    /// Number of files stored in the entry
+    pub files: usize,
+}
+
+/// Lists every entry stored in the local cache, naming the recipes of the entries stored under
+/// their current key. Entries of recipes are sorted by name, followed by stale entries
+///
+/// # Arguments
+/// * `project` - The project owning the cache
+/// * `hashes` - Map of recipe names to their current cache keys
+///
+pub fn list_local_entries(
+    project: &BakeProject,
+    hashes: &HashMap<String, String>,
+) -> anyhow::Result<Vec<CacheEntry>> {
+    let cache_path = local::LocalCacheStrategy::path_from_config(project);
+    let mut entries = Vec::new();
+    if !cache_path.is_dir() {
+        return Ok(entries);
+    }
+
+    let recipes: HashMap<&str, &str> = hashes
+        .iter()
+        .map(|(recipe, key)| (key.as_str(), recipe.as_str()))
+        .collect();
+    let extension = format!(".{}", ARCHIVE_EXTENSION);
+    for entry in std::fs::read_dir(&cache_path)? {
+        let archive_path = entry?.path();
+        // Entries being written are hidden until they're complete
+        let Some(key) = archive_path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .filter(|name| !name.starts_with('.'))
+            .and_then(|name| name.strip_suffix(&extension))
+        else {
+            continue;
+        };
+        if !archive_path.is_file() {
+            continue;
+        }
+
+        let modified = archive_path.metadata()?.modified()?;
+        let mut archive = tar::Archive::new(archive_reader(File::open(&archive_path)?)?);
+        let (mut size, mut files) = (0, 0);
+        for entry in archive.entries()? {
+            let entry = entry?;
+            if entry.header().entry_type().is_file() {
+                size += entry.size();
+                files += 1;
+            }
+        }
+
+        entries.push(CacheEntry {
+            recipe: recipes.get(key).map(|recipe| recipe.to_string()),
+            key: key.to_owned(),
+            modified,
+            size,
+            files,
+        });
+    }
+
+    entries.sort_by(|a, b| {
+        (a.recipe.is_none(), &a.recipe, &a.key).cmp(&(b.recipe.is_none(), &b.recipe, &b.key))
+    });
+    Ok(entries)
+}
+
+/// Returns the blake3 hashes of the regular files stored in an archive, keyed by their path
+/// relative to the project root
+///
+/// # Arguments
+/// * `archive_path` - Path to the archive
+///
+pub fn archive_file_hashes(archive_path: &Path) -> anyhow::Result<BTreeMap<PathBuf, String>> {
+    let mut archive = tar::Archive::new(archive_reader(File::open(archive_path)?)?);
+    let mut hashes = BTreeMap::new();
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let path = entry.path()?.to_path_buf();
+        let mut hasher = blake3::Hasher::new();
+        std::io::copy(&mut entry, &mut hasher)?;
+        hashes.insert(path, hasher.finalize().to_hex().to_string());
+    }
+    Ok(hashes)
+}
+
+/// Removes the entries stored in the local cache for the given recipe hashes
+///
+/// Returns the number of entries removed
+///
+/// # Arguments
+/// * `project` - The project owning the cache
+/// * `hashes` - Map of recipe names to their cache keys
+///
+pub fn remove_local_entries(
+    project: &BakeProject,
+    hashes: &HashMap<String, String>,
+) -> anyhow::Result<usize> {
+    let cache_path = local::LocalCacheStrategy::path_from_config(project);
+    let mut removed = 0;
+
+    for (recipe, key) in hashes {
+        let archive_path = cache_path.join(format!("{}.{}", key, ARCHIVE_EXTENSION));
+        if !archive_path.is_file() {
+            continue;
+        }
+        debug!("Removing cache entry {} for recipe {}", key, recipe);
+        if let Err(err) = std::fs::remove_file(&archive_path) {
+            bail!(BakeError::CacheError(format!(
+                "Could not remove cache entry for recipe {}: {}",
+                recipe, err
+            )));
+        }
+        removed += 1;
+    }
+
+    Ok(removed)
+}
+
+/// Upper bounds of the archive size buckets reported by `local_compression_stats`, matching the
+/// thresholds of the `auto` compression level. Larger archives fall in a last, unbounded bucket
+const COMPRESSION_STATS_BUCKETS: [u64; 3] = [4096, 1_048_576, 67_108_864];
+
+/// Compression of a group of archives stored in the local cache
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct CompressionStats {
+    /// Number of archives
+    pub archives: usize,
+
+    /// Total size of the archives once decompressed
+    pub logical_size: u64,
+
+    /// Total size of the archives on disk
+    pub physical_size: u64,
+}
+
+impl CompressionStats {
+    /// Returns how many times smaller the archives are on disk, 1 when nothing is stored
+    pub fn ratio(&self) -> f64 {
+        if self.physical_size == 0 {
+            return 1.0;
+        }
+        self.logical_size as f64 / self.physical_size as f64
+    }
+
+    fn add(&mut self, logical_size: u64, physical_size: u64) {
+        self.archives += 1;
+        self.logical_size += logical_size;
+        self.physical_size += physical_size;
+    }
+}
+
+/// Compression of every archive stored in the local cache, overall and by decompressed size
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct CompressionReport {
+    /// Stats of all archives
+    pub total: CompressionStats,
+
+    /// Stats of the archives in each size bucket, with the bucket's exclusive upper bound or None
+    /// for the last one. Empty buckets are included
+    pub buckets: Vec<(Option<u64>, CompressionStats)>,
+}
+
+/// Reads every archive in the local cache to compare its size on disk with its decompressed size
+///
+/// Only the local cache is read, whatever recipes are selected, and nothing is modified.
+///
+/// # Arguments
+/// * `project` - The project owning the cache
+///
+pub fn local_compression_stats(project: &BakeProject) -> anyhow::Result<CompressionReport> {
+    let cache_path = local::LocalCacheStrategy::path_from_config(project);
+    let mut report = CompressionReport {
+        buckets: COMPRESSION_STATS_BUCKETS
+            .iter()
+            .map(|max| Some(*max))
+            .chain([None])
+            .map(|max| (max, CompressionStats::default()))
+            .collect(),
+        ..Default::default()
+    };
+    if !cache_path.is_dir() {
+        return Ok(report);
+    }
+
+    let extension = format!(".{}", ARCHIVE_EXTENSION);
+    for entry in std::fs::read_dir(&cache_path)? {
+        let path = entry?.path();
+        let is_archive = path.file_name().and_then(|name| name.to_str()).is_some_and(
+            // Entries being written are hidden until they're complete
+            |name| name.ends_with(&extension) && !name.starts_with('.'),
+        );
+        if !is_archive || !path.is_file() {
+            continue;
+        }
+
+        let physical_size = path.metadata()?.len();
+        let logical_size = std::io::copy(
+            &mut archive_reader(File::open(&path)?)?,
+            &mut std::io::sink(),
+        )
+        .map_err(|err| anyhow!("Could not read cache entry {}: {}", path.display(), err))?;
+
+        report.total.add(logical_size, physical_size);
+        let (_, bucket) = report
+            .buckets
+            .iter_mut()
+            .find(|(max, _)| max.map_or(true, |max| logical_size < max))
+            .unwrap();
+        bucket.add(logical_size, physical_size);
+    }
+
+    Ok(report)
+}
+
 /// Cache manages caching of bake outputs by using caching strategies defined in
 /// configuration files
 pub struct Cache {
@@ -44,83 +340,260 @@ pub struct Cache {
     /// List of cache strategies
     pub strategies: Vec<Arc<Box<dyn CacheStrategy>>>,
 
-    /// Map of recipe hashes so we don't have to recompute them
+    /// Map of recipe cache keys computed before baking
     pub hashes: HashMap<String, String>,
+
+    /// Map of recipe hashes not including their dependencies
+    pub recipe_hashes: HashMap<String, String>,
+
+    /// Map of output hashes of recipes that others depend on, updated as recipes finish
+    pub output_hashes: Mutex<HashMap<String, String>>,
+
+    /// Recipes that must be rebuilt, they always miss the cache but still store their outputs
+    pub forced: HashSet<String>,
+
+    /// Size of the outputs cached during this run, limited by `cache.max_total_output_size`
+    pub total_output_size: Mutex<u64>,
 }
 
 impl Cache {
+    /// Returns the current cache key of a recipe, taking into account the latest outputs of its
+    /// dependencies
+    pub fn key(&self, recipe_name: &str) -> String {
+        builder::calculate_hash_with_deps(
+            &self.project,
+            recipe_name,
+            &self.recipe_hashes,
+            &self.output_hashes.lock().unwrap(),
+        )
+    }
+
+    /// Updates the output hash of a recipe after it finished baking so that recipes depending on
+    /// it are keyed by its actual outputs
+    pub fn record_outputs(&self, recipe: &Recipe) -> anyhow::Result<()> {
+        let recipe_name = recipe.full_name();
+        if !builder::is_dependency(&self.project, &recipe_name) {
+            return Ok(());
+        }
+
+        let mut output_hashes = self.output_hashes.lock().unwrap();
+        match recipe.get_outputs_hash()? {
+            Some(hash) => output_hashes.insert(recipe_name, hash),
+            None => output_hashes.remove(&recipe_name),
+        };
+        Ok(())
+    }
+
     // Tries to get a cached result for the given recipe
-    pub async fn get(&self, recipe_name: &str) -> CacheResult {
-        let hash = self.hashes.get(recipe_name).unwrap();
+    pub async fn get(&self, recipe_name: &str) -> anyhow::Result<CacheResult> {
+        if self.forced.contains(recipe_name) {
+            debug!("Forcing rebuild of recipe {}", recipe_name);
+            return Ok(CacheResult::Miss);
+        }
+
+        let hash = &self.key(recipe_name);
         for strategy in &self.strategies {
-            if let CacheResult::Hit(data) = strategy.get(hash).await {
-                if let Ok(mut tar_gz) = File::open(&data.archive_path) {
-                    if let Err(err) = tar_gz.rewind() {
-                        warn!(
-                            "Failed to rewind archive file: {}. Error: {:?}",
-                            &data.archive_path.display(),
-                            err
-                        );
-                        return CacheResult::Miss;
+            let result = with_retries(
+                strategy.as_ref().as_ref(),
+                self.project.config.cache.remote_retries,
+                || strategy.get(hash),
+            )
+            .await;
+            let data = match result {
+                Ok(CacheResult::Hit(data)) => data,
+                Ok(CacheResult::Miss) => continue,
+                Err(err) => {
+                    if strategy.is_remote() && !self.project.config.cache.strict_remote {
+                        warn!("Ignoring remote cache error for recipe {recipe_name}: {err}");
+                        continue;
                     }
-                    let compressed = zstd::stream::Decoder::new(tar_gz).unwrap();
-                    let mut archive = tar::Archive::new(compressed);
-                    if let Err(err) = archive.unpack(self.project.root_path.clone()) {
+                    bail!(BakeError::CacheError(format!(
+                        "Failed to get cache for recipe {}: {}",
+                        recipe_name, err
+                    )));
+                }
+            };
+
+            let recipe = self.project.recipes.get(recipe_name).unwrap();
+            if let Some(ttl) = recipe.cache.as_ref().and_then(|cache| cache.ttl) {
+                let log_path = self.project.get_recipe_log_path(recipe_name);
+                let relative_log_path = log_path.strip_prefix(&self.project.root_path).unwrap();
+                let age = archive_created_at(&data.archive_path, relative_log_path)
+                    .and_then(|created_at| created_at.elapsed().ok());
+                if age.map_or(true, |age| age > ttl) {
+                    debug!(
+                        "Cached result of recipe {} is older than its ttl of {}s",
+                        recipe_name,
+                        ttl.as_secs()
+                    );
+                    continue;
+                }
+            }
+
+            if let Ok(mut tar_gz) = File::open(&data.archive_path) {
+                if let Err(err) = tar_gz.rewind() {
+                    warn!(
+                        "Failed to rewind archive file: {}. Error: {:?}",
+                        &data.archive_path.display(),
+                        err
+                    );
+                    return Ok(CacheResult::Miss);
+                }
+                let reader = match archive_reader(tar_gz) {
+                    Ok(reader) => reader,
+                    Err(err) => {
                         warn!(
-                            "Failed to unpack archive file: {}. Error: {:?}",
-                            &data.archive_path.display(),
+                            "Failed to read archive file: {}. Error: {:?}",
+                            data.archive_path.display(),
                             err
                         );
-                        return CacheResult::Miss;
+                        return Ok(CacheResult::Miss);
                     }
+                };
+                let mut archive = tar::Archive::new(reader);
+                // Restore mode bits exactly as recorded so executable outputs stay executable
+                archive.set_preserve_permissions(true);
+                let tree_outputs = tree_output_paths(&self.project, recipe);
+                let moved_outputs = moved_output_paths(&self.project, recipe);
+                let result = if tree_outputs.is_empty() && moved_outputs.is_empty() {
+                    archive.unpack(&self.project.root_path)
+                } else {
+                    let output_root = recipe
+                        .output_root
+                        .as_ref()
+                        .unwrap_or(&self.project.root_path);
+                    unpack_outputs(
+                        archive,
+                        &self.project.root_path,
+                        &tree_outputs,
+                        output_root,
+                        &moved_outputs,
+                    )
+                };
+                if let Err(err) = result {
+                    warn!(
+                        "Failed to unpack archive file: {}. Error: {:?}",
+                        &data.archive_path.display(),
+                        err
+                    );
+                    return Ok(CacheResult::Miss);
                 }
-                return CacheResult::Hit(data);
             }
+            return Ok(CacheResult::Hit(data));
         }
 
-        CacheResult::Miss
+        Ok(CacheResult::Miss)
     }
 
-    // Puts the given recipe's outputs in the cache
+    /// Checks that a recipe's outputs fit in `cache.max_output_size` and
+    /// `cache.max_total_output_size`, counting them towards the run's total when they do
+    ///
+    /// Returns a message explaining which limit was exceeded otherwise.
+    pub fn check_output_size(&self, recipe_name: &str) -> Result<(), String> {
+        let config = &self.project.config.cache;
+        if config.max_output_size.is_none() && config.max_total_output_size.is_none() {
+            return Ok(());
+        }
+
+        let recipe = self.project.recipes.get(recipe_name).unwrap();
+        let size = archive_input_size(recipe, &self.project.get_recipe_log_path(recipe_name));
+        if let Some(max_size) = config.max_output_size {
+            if size > max_size {
+                return Err(format!(
+                    "Outputs of recipe {} take {} bytes, over cache.max_output_size of {} bytes",
+                    recipe_name, size, max_size
+                ));
+            }
+        }
+        if let Some(max_total_size) = config.max_total_output_size {
+            let mut total_size = self.total_output_size.lock().unwrap();
+            if *total_size + size > max_total_size {
+                return Err(format!(
+                    "Outputs of recipe {} take {} bytes, over the {} bytes left of \
+                     cache.max_total_output_size",
+                    recipe_name,
+                    size,
+                    max_total_size - *total_size
+                ));
+            }
+            *total_size += size;
+        }
+        Ok(())
+    }
+
+    // Puts the given recipe's outputs in the cache. Only successful runs should be stored since
+    // any entry found for a recipe's hash is restored as a success.
     pub async fn put(&self, recipe_name: &str) -> anyhow::Result<()> {
-        // Create archive in temp dir
+        // Create archive in temp dir, under a random name so that concurrent bakes of the same
+        // recipe never write to or upload each other's archives
         let archive_path = std::env::temp_dir().join(format!(
-            "{}.{}",
+            "{}.{}.{}",
             recipe_name.replace(':', "."),
+            Alphanumeric.sample_string(&mut rand::thread_rng(), 8),
             ARCHIVE_EXTENSION
         ));
-        let tar_gz = File::create(archive_path.clone());
+        let result = self.put_archive(recipe_name, &archive_path).await;
+        let _ = std::fs::remove_file(&archive_path);
+        result
+    }
+
+    /// Archives a recipe's outputs and log to the given path and stores the archive with each
+    /// strategy
+    ///
+    /// # Arguments
+    /// * `recipe_name` - Full name of the recipe
+    /// * `archive_path` - Temporary path the archive is written to
+    ///
+    async fn put_archive(&self, recipe_name: &str, archive_path: &Path) -> anyhow::Result<()> {
+        let tar_gz = File::create(archive_path);
+        let recipe = self.project.recipes.get(recipe_name).unwrap();
+        let level = match self.project.config.cache.local.compression_level {
+            CompressionLevel::Level(level) => Some(level),
+            CompressionLevel::Auto => auto_compression_level(archive_input_size(
+                recipe,
+                &self.project.get_recipe_log_path(recipe_name),
+            )),
+        };
 
         match tar_gz {
             Ok(tar_gz) => {
                 // let enc = GzEncoder::new(tar_gz, Compression::default());
-                let enc = match zstd::stream::Encoder::new(tar_gz, 1) {
-                    Ok(z) => z.auto_finish(),
-                    Err(err) => bail!("Failed creating zstd encoder: {}", err),
+                let enc: Box<dyn Write> = match level {
+                    Some(level) => match zstd::stream::Encoder::new(tar_gz, level) {
+                        Ok(z) => Box::new(z.auto_finish()),
+                        Err(err) => bail!("Failed creating zstd encoder: {}", err),
+                    },
+                    None => Box::new(tar_gz),
                 };
                 let mut tar = tar::Builder::new(enc);
-                let recipe = self.project.recipes.get(recipe_name).unwrap();
+                // Store symlinks as links instead of copying their targets' contents
+                tar.follow_symlinks(false);
+                // Record full mode bits so they can be restored
+                tar.mode(tar::HeaderMode::Complete);
 
                 // Add outputs to archive
                 if let Some(cache) = &recipe.cache {
                     for output in &cache.outputs {
                         // Resolve relative paths by trying to get canonical form
-                        let full_output_path = match recipe
-                            .config_path
-                            .parent()
-                            .unwrap()
-                            .join(output)
-                            .canonicalize()
-                        {
-                            Ok(path) => path,
-                            Err(err) => {
-                                bail!("Failed to get canonical path for output {output}: {err}");
-                            }
-                        };
+                        let full_output_path =
+                            match canonicalize_output(&recipe.output_path(output)) {
+                                Ok(path) => path,
+                                Err(err) => {
+                                    bail!(
+                                        "Failed to get canonical path for output {output}: {err}"
+                                    );
+                                }
+                            };
 
-                        let relative_output_path = match full_output_path
-                            .strip_prefix(&self.project.root_path.canonicalize().unwrap())
-                        {
+                        // Outputs relative to the project root keep their name wherever they're
+                        // written so that they can be restored under another output root
+                        let relative_output_path = match output.root_path.as_ref() {
+                            Some(root_path) => Ok(PathBuf::from(root_path)),
+                            None => full_output_path
+                                .strip_prefix(&self.project.root_path.canonicalize().unwrap())
+                                .map(Path::to_path_buf),
+                        };
+                        let relative_output_path = match relative_output_path {
                             Ok(path) => path,
                             Err(err) => {
                                 return Err(anyhow!(
@@ -129,7 +602,7 @@ impl Cache {
                             }
                         };
 
-                        let res = if full_output_path.is_dir() {
+                        let res = if full_output_path.is_dir() && !full_output_path.is_symlink() {
                             tar.append_dir_all(relative_output_path, full_output_path.clone())
                         } else {
                             tar.append_path_with_name(
@@ -178,32 +651,351 @@ impl Cache {
             }
         }
 
-        let hash = self.hashes.get(recipe_name).unwrap();
+        let hash = &self.key(recipe_name);
         for strategy in self.strategies.iter() {
-            strategy.put(hash, archive_path.clone()).await?;
+            let result = with_retries(
+                strategy.as_ref().as_ref(),
+                self.project.config.cache.remote_retries,
+                || strategy.put(hash, archive_path.to_path_buf()),
+            )
+            .await;
+            if let Err(err) = result {
+                if strategy.is_remote() && !self.project.config.cache.strict_remote {
+                    warn!("Ignoring remote cache error for recipe {recipe_name}: {err}");
+                    continue;
+                }
+                return Err(err);
+            }
         }
 
         Ok(())
     }
 }
 
+/// Runs a cache operation, retrying remote ones that fail with exponential backoff and jitter
+///
+/// Only errors that strategies report as `BakeError::TransientCacheError`, such as timeouts or
+/// server errors, are retried. Others, eg. missing permissions, fail right away, and local
+/// strategies are never retried.
+///
+/// # Arguments
+/// * `strategy` - Strategy the operation runs against
+/// * `retries` - Maximum number of retries after the first attempt
+/// * `operation` - Creates the future performing the operation for each attempt
+///
+async fn with_retries<T, F, Fut>(
+    strategy: &dyn CacheStrategy,
+    retries: u32,
+    mut operation: F,
+) -> anyhow::Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = anyhow::Result<T>>,
+{
+    let retries = if strategy.is_remote() { retries } else { 0 };
+    let mut attempt = 0;
+    loop {
+        match operation().await {
+            Err(err)
+                if attempt < retries
+                    && matches!(
+                        err.downcast_ref::<BakeError>(),
+                        Some(BakeError::TransientCacheError(_))
+                    ) =>
+            {
+                let delay = retry_delay(attempt);
+                // Wait between half and the full delay so parallel runners don't retry in sync
+                let delay = delay / 2 + delay.mul_f64(rand::thread_rng().gen::<f64>() / 2.0);
+                attempt += 1;
+                debug!(
+                    "Retrying remote cache operation in {:?} ({}/{}): {}",
+                    delay, attempt, retries, err
+                );
+                tokio::time::sleep(delay).await;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Returns the delay before a retry, doubling `REMOTE_RETRY_DELAY` on each attempt up to
+/// `REMOTE_RETRY_MAX_DELAY`
+fn retry_delay(attempt: u32) -> Duration {
+    2u32.checked_pow(attempt)
+        .and_then(|factor| REMOTE_RETRY_DELAY.checked_mul(factor))
+        .map_or(REMOTE_RETRY_MAX_DELAY, |delay| {
+            delay.min(REMOTE_RETRY_MAX_DELAY)
+        })
+}
+
+/// Magic number at the start of zstd frames
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// Returns a reader for the tar contents of an archive, which may be stored uncompressed
+fn archive_reader(mut file: File) -> std::io::Result<Box<dyn Read>> {
+    let mut magic = [0; 4];
+    let is_zstd = file.read_exact(&mut magic).is_ok() && magic == ZSTD_MAGIC;
+    file.rewind()?;
+    if is_zstd {
+        Ok(Box::new(zstd::stream::Decoder::new(file)?))
+    } else {
+        Ok(Box::new(file))
+    }
+}
+
+/// Returns when an archive was created, which is when the recipe log it contains was written.
+/// None if the archive can't be read or has no log
+///
+/// # Arguments
+/// * `archive_path` - Path of the archive
+/// * `log_path` - Path of the recipe's log relative to the project root, as stored in the archive
+///
+fn archive_created_at(archive_path: &Path, log_path: &Path) -> Option<SystemTime> {
+    let mut archive = tar::Archive::new(archive_reader(File::open(archive_path).ok()?).ok()?);
+    for entry in archive.entries().ok()? {
+        let entry = entry.ok()?;
+        if entry.path().ok()? == log_path {
+            let mtime = entry.header().mtime().ok()?;
+            return Some(SystemTime::UNIX_EPOCH + Duration::from_secs(mtime));
+        }
+    }
+    None
+}
+
+/// Picks the zstd level for an archive of the given uncompressed size, None to store it
+/// uncompressed since compressing tiny archives costs more than it saves
+fn auto_compression_level(size: u64) -> Option<i32> {
+    match size {
+        0..4096 => None,
+        4096..1_048_576 => Some(1),
+        _ => Some(6),
+    }
+}
+
+/// Returns the total size of the files that will be archived for a recipe
+fn archive_input_size(recipe: &Recipe, log_path: &Path) -> u64 {
+    recipe
+        .cache
+        .iter()
+        .flat_map(|cache| &cache.outputs)
+        .map(|output| path_size(&recipe.output_path(output)))
+        .sum::<u64>()
+        + path_size(log_path)
+}
+
+fn path_size(path: &Path) -> u64 {
+    match path.symlink_metadata() {
+        Ok(metadata) if metadata.is_dir() => std::fs::read_dir(path)
+            .map(|entries| {
+                entries
+                    .filter_map(|entry| entry.ok())
+                    .map(|entry| path_size(&entry.path()))
+                    .sum()
+            })
+            .unwrap_or(0),
+        Ok(metadata) => metadata.len(),
+        Err(_) => 0,
+    }
+}
+
+/// Returns the paths of a recipe's tree outputs relative to the project root
+fn tree_output_paths(project: &BakeProject, recipe: &Recipe) -> Vec<PathBuf> {
+    let root_path = project
+        .root_path
+        .canonicalize()
+        .unwrap_or(project.root_path.clone());
+    let cookbook_path = recipe.config_path.parent().unwrap();
+    let cookbook_path = cookbook_path
+        .canonicalize()
+        .unwrap_or(cookbook_path.to_path_buf());
+    recipe
+        .cache
+        .iter()
+        .flat_map(|cache| &cache.outputs)
+        .filter(|output| output.mode == OutputMode::Tree)
+        .filter_map(|output| {
+            if let Some(relative_path) = output.root_path.as_ref() {
+                return Some(PathBuf::from(relative_path));
+            }
+            // Resolved lexically since the output may not exist before it's restored
+            let mut path = PathBuf::new();
+            for component in cookbook_path.join(output).components() {
+                match component {
+                    Component::ParentDir => {
+                        path.pop();
+                    }
+                    Component::CurDir => {}
+                    component => path.push(component),
+                }
+            }
+            path.strip_prefix(&root_path).ok().map(Path::to_path_buf)
+        })
+        .collect()
+}
+
+/// Returns the paths, relative to the project root, of a recipe's outputs that are restored under
+/// its output root instead of the project root
+fn moved_output_paths(project: &BakeProject, recipe: &Recipe) -> Vec<PathBuf> {
+    if recipe
+        .output_root
+        .as_ref()
+        .map_or(true, |output_root| *output_root == project.root_path)
+    {
+        return vec![];
+    }
+    recipe
+        .cache
+        .iter()
+        .flat_map(|cache| &cache.outputs)
+        .filter_map(|output| output.root_path.as_ref().map(PathBuf::from))
+        .collect()
+}
+
+/// Unpacks an archive in the project root, unpacking the entries of tree outputs next to their
+/// destination first and swapping each tree in place once it's complete
+///
+/// # Arguments
+/// * `archive` - The archive to unpack
+/// * `root_path` - Root of the project
+/// * `tree_outputs` - Tree outputs, relative to the project root
+/// * `output_root` - Directory moved outputs are unpacked in instead of the project root
+/// * `moved_outputs` - Outputs moved with `--output-root`, relative to the project root
+///
+fn unpack_outputs<R: Read>(
+    mut archive: tar::Archive<R>,
+    root_path: &Path,
+    tree_outputs: &[PathBuf],
+    output_root: &Path,
+    moved_outputs: &[PathBuf],
+) -> std::io::Result<()> {
+    if !moved_outputs.is_empty() {
+        std::fs::create_dir_all(output_root)?;
+    }
+    let destination = |path: &Path| {
+        if moved_outputs.iter().any(|output| path.starts_with(output)) {
+            output_root
+        } else {
+            root_path
+        }
+    };
+    let staged: Vec<(PathBuf, PathBuf)> = tree_outputs
+        .iter()
+        .map(|output| {
+            (
+                output.clone(),
+                sibling_path(&destination(output).join(output), "restore"),
+            )
+        })
+        .collect();
+    for (_, staged_path) in &staged {
+        let _ = remove_path(staged_path);
+    }
+
+    let result = (|| {
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            let path = entry.path()?.into_owned();
+            let Some((output, staged_path)) =
+                staged.iter().find(|(output, _)| path.starts_with(output))
+            else {
+                entry.unpack_in(destination(&path))?;
+                continue;
+            };
+
+            let relative_path = path.strip_prefix(output).unwrap();
+            if relative_path
+                .components()
+                .any(|component| !matches!(component, Component::Normal(_)))
+            {
+                return Err(std::io::Error::other(format!(
+                    "Invalid archive entry {}",
+                    path.display()
+                )));
+            }
+            let target_path = staged_path.join(relative_path);
+            if let Some(parent) = target_path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            entry.unpack(&target_path)?;
+        }
+
+        for (output, staged_path) in &staged {
+            if staged_path.symlink_metadata().is_ok() {
+                swap_path(staged_path, &destination(output).join(output))?;
+            }
+        }
+        Ok(())
+    })();
+
+    if result.is_err() {
+        for (_, staged_path) in &staged {
+            let _ = remove_path(staged_path);
+        }
+    }
+    result
+}
+
+/// Moves a path in place of another, removing what was there before
+fn swap_path(from: &Path, to: &Path) -> std::io::Result<()> {
+    let old_path = sibling_path(to, "old");
+    let _ = remove_path(&old_path);
+    let existed = to.symlink_metadata().is_ok();
+    if existed {
+        std::fs::rename(to, &old_path)?;
+    }
+    if let Err(err) = std::fs::rename(from, to) {
+        if existed {
+            let _ = std::fs::rename(&old_path, to);
+        }
+        return Err(err);
+    }
+    if existed {
+        remove_path(&old_path)?;
+    }
+    Ok(())
+}
+
+/// Returns a hidden path next to the given one, on the same filesystem so it can be renamed
+/// into place
+fn sibling_path(path: &Path, suffix: &str) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!(".{}.bake-{}", name, suffix))
+}
+
+/// Returns the canonical form of an output path without resolving the output itself if it is a
+/// symlink, so that links are cached as links
+fn canonicalize_output(path: &Path) -> std::io::Result<PathBuf> {
+    if path.is_symlink() {
+        if let (Some(parent), Some(file_name)) = (path.parent(), path.file_name()) {
+            return Ok(parent.canonicalize()?.join(file_name));
+        }
+    }
+    path.canonicalize()
+}
+
 #[cfg(test)]
 mod test {
     use std::{
         io::Write,
         path::PathBuf,
         sync::{Arc, Mutex},
+        time::{Duration, SystemTime},
     };
 
     use async_trait::async_trait;
+    use test_case::test_case;
 
     use crate::{
-        cache::{CacheBuilder, CacheResult, CacheResultData},
-        project::BakeProject,
+        cache::{local::LocalCacheStrategy, CacheBuilder, CacheResult, CacheResultData},
+        error::BakeError,
+        project::{
+            config::{CompressionLevel, LocalCacheBackend},
+            BakeProject, OutputMode, RecipeCacheConfig, RecipeOutput,
+        },
         test_utils::TestProjectBuilder,
     };
 
-    use super::{Cache, CacheStrategy};
+    use super::{list_local_entries, local_compression_stats, Cache, CacheStrategy};
 
     const FOO_BUILD_HASH: &str = "7d0ac2e376b5bb56bd6a1f283112bbcacba780c8fa58cec14149907a27083248";
 
@@ -214,13 +1006,13 @@ mod test {
 
     #[async_trait]
     impl CacheStrategy for TestCacheStrategy {
-        async fn get(&self, key: &str) -> super::CacheResult {
+        async fn get(&self, key: &str) -> anyhow::Result<super::CacheResult> {
             if key == FOO_BUILD_HASH {
-                return CacheResult::Hit(CacheResultData {
+                return Ok(CacheResult::Hit(CacheResultData {
                     archive_path: PathBuf::from(format!("{}.tar.gz", key)),
-                });
+                }));
             }
-            CacheResult::Miss
+            Ok(CacheResult::Miss)
         }
         async fn put(&self, key: &str, _: PathBuf) -> anyhow::Result<()> {
             self.cache.lock().unwrap().push_str(key);
@@ -233,6 +1025,60 @@ mod test {
         }
     }
 
+    #[derive(Clone, Debug)]
+    struct FailingCacheStrategy;
+
+    #[async_trait]
+    impl CacheStrategy for FailingCacheStrategy {
+        async fn get(&self, _: &str) -> anyhow::Result<super::CacheResult> {
+            anyhow::bail!("remote unavailable")
+        }
+        async fn put(&self, _: &str, _: PathBuf) -> anyhow::Result<()> {
+            anyhow::bail!("remote unavailable")
+        }
+        async fn from_config(_: Arc<BakeProject>) -> anyhow::Result<Box<dyn super::CacheStrategy>> {
+            Ok(Box::new(FailingCacheStrategy))
+        }
+    }
+
+    /// Remote that fails a number of times before succeeding, counting every attempt
+    #[derive(Clone, Debug, Default)]
+    struct FlakyCacheStrategy {
+        failures: usize,
+        transient: bool,
+        attempts: Arc<Mutex<usize>>,
+    }
+
+    impl FlakyCacheStrategy {
+        fn attempt(&self) -> anyhow::Result<()> {
+            let mut attempts = self.attempts.lock().unwrap();
+            *attempts += 1;
+            if *attempts <= self.failures {
+                if self.transient {
+                    anyhow::bail!(BakeError::TransientCacheError(String::from(
+                        "503 service unavailable"
+                    )));
+                }
+                anyhow::bail!("403 forbidden");
+            }
+            Ok(())
+        }
+    }
+
+    #[async_trait]
+    impl CacheStrategy for FlakyCacheStrategy {
+        async fn get(&self, _: &str) -> anyhow::Result<super::CacheResult> {
+            self.attempt()?;
+            Ok(CacheResult::Miss)
+        }
+        async fn put(&self, _: &str, _: PathBuf) -> anyhow::Result<()> {
+            self.attempt()
+        }
+        async fn from_config(_: Arc<BakeProject>) -> anyhow::Result<Box<dyn super::CacheStrategy>> {
+            Ok(Box::<FlakyCacheStrategy>::default())
+        }
+    }
+
     async fn build_cache(project: Arc<BakeProject>, filter: &str) -> Cache {
         CacheBuilder::new(project)
             .filter(filter)
@@ -254,12 +1100,16 @@ mod test {
     #[tokio::test]
     async fn get() {
         let project = Arc::new(create_test_project());
+        assert_eq!(
+            project.recipes["foo:build"].dependencies,
+            Some(vec!["foo:build-dep".into()])
+        );
 
         let cache = build_cache(project.clone(), "foo:build").await;
 
         // Test hit
         let result = cache.get("foo:build").await;
-        assert!(matches!(result, CacheResult::Hit(_)));
+        assert!(matches!(result, Ok(CacheResult::Hit(_))));
 
         // Miss if recipe command changes
         let mut project = create_test_project();
@@ -267,7 +1117,7 @@ mod test {
 
         let cache = build_cache(Arc::new(project), "foo:build").await;
         let result = cache.get("foo:build").await;
-        assert!(matches!(result, CacheResult::Miss));
+        assert!(matches!(result, Ok(CacheResult::Miss)));
 
         // Miss if dependency changes
         let mut project = create_test_project();
@@ -275,7 +1125,7 @@ mod test {
 
         let cache = build_cache(Arc::new(project), "foo:build").await;
         let result = cache.get("foo:build").await;
-        assert!(matches!(result, CacheResult::Miss));
+        assert!(matches!(result, Ok(CacheResult::Miss)));
     }
 
     #[tokio::test]
@@ -318,4 +1168,428 @@ mod test {
         assert!(res.is_ok());
         assert_eq!(cache_str.lock().unwrap().as_str(), FOO_BUILD_HASH);
     }
+
+    async fn build_failing_cache(strict_remote: bool) -> Cache {
+        let mut project = create_test_project();
+        project.config.cache.strict_remote = strict_remote;
+        let project = Arc::new(project);
+        project.create_project_bake_dirs().unwrap();
+        std::fs::write(project.get_recipe_log_path("foo:build"), b"foo").unwrap();
+
+        let mut cache = build_cache(project, "foo:build").await;
+        cache.strategies = vec![Arc::new(Box::new(FailingCacheStrategy))];
+        cache
+    }
+
+    #[tokio::test]
+    async fn remote_errors_tolerated() {
+        let cache = build_failing_cache(false).await;
+        assert!(matches!(
+            cache.get("foo:build").await,
+            Ok(CacheResult::Miss)
+        ));
+        assert!(cache.put("foo:build").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn remote_errors_strict() {
+        let cache = build_failing_cache(true).await;
+        assert!(cache.get("foo:build").await.is_err());
+        assert!(cache.put("foo:build").await.is_err());
+    }
+
+    #[test_case(0 => 100; "first retry")]
+    #[test_case(3 => 800; "doubled")]
+    #[test_case(9 => 30_000; "capped")]
+    #[test_case(40 => 30_000; "no overflow")]
+    fn retry_delay(attempt: u32) -> u128 {
+        super::retry_delay(attempt).as_millis()
+    }
+
+    #[test_case(0, true, 2 => (true, 1); "miss is not retried")]
+    #[test_case(2, true, 2 => (true, 3); "fails twice then succeeds")]
+    #[test_case(2, true, 1 => (false, 2); "gives up after retries")]
+    #[test_case(1, false, 2 => (false, 1); "forbidden is not retried")]
+    #[tokio::test]
+    async fn remote_retries(failures: usize, transient: bool, retries: u32) -> (bool, usize) {
+        let mut project = create_test_project();
+        project.config.cache.strict_remote = true;
+        project.config.cache.remote_retries = retries;
+        let project = Arc::new(project);
+        project.create_project_bake_dirs().unwrap();
+        std::fs::write(project.get_recipe_log_path("foo:build"), b"foo").unwrap();
+
+        let strategy = FlakyCacheStrategy {
+            failures,
+            transient,
+            ..Default::default()
+        };
+        let attempts = strategy.attempts.clone();
+        let mut cache = build_cache(project, "foo:build").await;
+        cache.strategies = vec![Arc::new(Box::new(strategy))];
+        let get_ok = cache.get("foo:build").await.is_ok();
+        let get_attempts = *attempts.lock().unwrap();
+
+        // Puts are retried the same way
+        *attempts.lock().unwrap() = 0;
+        let put_ok = cache.put("foo:build").await.is_ok();
+        assert_eq!((put_ok, *attempts.lock().unwrap()), (get_ok, get_attempts));
+        (get_ok, get_attempts)
+    }
+
+    #[tokio::test]
+    async fn symlink_round_trip() {
+        let mut project = TestProjectBuilder::new()
+            .with_cookbook("links", &["build"])
+            .build();
+        project.recipes.get_mut("links:build").unwrap().cache = Some(RecipeCacheConfig {
+            outputs: vec!["dist".into()],
+            ..Default::default()
+        });
+        let project = Arc::new(project);
+        project.create_project_bake_dirs().unwrap();
+        std::fs::write(project.get_recipe_log_path("links:build"), b"foo").unwrap();
+
+        let dist = project.root_path.join("dist");
+        std::fs::create_dir_all(&dist).unwrap();
+        std::fs::write(dist.join("file.txt"), b"foo").unwrap();
+        std::os::unix::fs::symlink("file.txt", dist.join("link")).unwrap();
+
+        let mut cache = build_cache(project.clone(), "links:build").await;
+        cache.strategies = vec![Arc::new(Box::new(LocalCacheStrategy {
+            path: project.get_project_bake_path().join("cache"),
+        }))];
+        cache.put("links:build").await.unwrap();
+
+        std::fs::remove_dir_all(&dist).unwrap();
+        let result = cache.get("links:build").await;
+        assert!(matches!(result, Ok(CacheResult::Hit(_))));
+
+        let link = dist.join("link");
+        assert!(link.is_symlink());
+        assert_eq!(
+            std::fs::read_link(&link).unwrap(),
+            PathBuf::from("file.txt")
+        );
+        assert_eq!(std::fs::read_to_string(&link).unwrap(), "foo");
+    }
+
+    #[tokio::test]
+    async fn permissions_round_trip() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let mut project = TestProjectBuilder::new()
+            .with_cookbook("perms", &["build"])
+            .build();
+        project.recipes.get_mut("perms:build").unwrap().cache = Some(RecipeCacheConfig {
+            outputs: vec!["bin".into()],
+            ..Default::default()
+        });
+        let project = Arc::new(project);
+        project.create_project_bake_dirs().unwrap();
+        std::fs::write(project.get_recipe_log_path("perms:build"), b"foo").unwrap();
+
+        let bin = project.root_path.join("bin");
+        std::fs::create_dir_all(&bin).unwrap();
+        std::fs::write(bin.join("run.sh"), b"#!/bin/sh\necho foo").unwrap();
+        std::fs::set_permissions(bin.join("run.sh"), std::fs::Permissions::from_mode(0o755))
+            .unwrap();
+
+        let mut cache = build_cache(project.clone(), "perms:build").await;
+        cache.strategies = vec![Arc::new(Box::new(LocalCacheStrategy {
+            path: project.get_project_bake_path().join("cache"),
+        }))];
+        cache.put("perms:build").await.unwrap();
+
+        std::fs::remove_dir_all(&bin).unwrap();
+        let result = cache.get("perms:build").await;
+        assert!(matches!(result, Ok(CacheResult::Hit(_))));
+
+        let mode = std::fs::metadata(bin.join("run.sh"))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o755);
+    }
+
+    #[tokio::test]
+    async fn tree_round_trip() {
+        let mut project = TestProjectBuilder::new()
+            .with_cookbook("tree", &["build"])
+            .build();
+        project.recipes.get_mut("tree:build").unwrap().cache = Some(RecipeCacheConfig {
+            outputs: vec![RecipeOutput {
+                path: String::from("dist"),
+                mode: OutputMode::Tree,
+                root_path: None,
+            }],
+            ..Default::default()
+        });
+        let project = Arc::new(project);
+        project.create_project_bake_dirs().unwrap();
+        std::fs::write(project.get_recipe_log_path("tree:build"), b"foo").unwrap();
+
+        let dist = project.root_path.join("dist");
+        for idx in 0..500 {
+            let path = dist
+                .join(format!("{}", idx % 10))
+                .join(format!("{}.txt", idx));
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, idx.to_string()).unwrap();
+        }
+        let recipe = &project.recipes["tree:build"];
+        let outputs_hash = recipe.get_outputs_hash().unwrap();
+
+        let mut cache = build_cache(project.clone(), "tree:build").await;
+        cache.strategies = vec![Arc::new(Box::new(LocalCacheStrategy {
+            path: project.get_project_bake_path().join("cache"),
+        }))];
+        cache.put("tree:build").await.unwrap();
+
+        // The tree is replaced as a whole, dropping files that weren't cached
+        std::fs::remove_dir_all(dist.join("3")).unwrap();
+        std::fs::write(dist.join("0").join("0.txt"), "changed").unwrap();
+        std::fs::write(dist.join("stale.txt"), "stale").unwrap();
+        let result = cache.get("tree:build").await;
+        assert!(matches!(result, Ok(CacheResult::Hit(_))));
+        assert_eq!(recipe.get_outputs_hash().unwrap(), outputs_hash);
+        assert!(!dist.join("stale.txt").exists());
+
+        // Nothing is left next to the tree
+        let mut names: Vec<String> = std::fs::read_dir(&project.root_path)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .filter(|name| name.contains("dist"))
+            .collect();
+        names.sort();
+        assert_eq!(names, vec!["dist"]);
+
+        std::fs::remove_dir_all(&dist).unwrap();
+        assert!(matches!(
+            cache.get("tree:build").await,
+            Ok(CacheResult::Hit(_))
+        ));
+        assert_eq!(recipe.get_outputs_hash().unwrap(), outputs_hash);
+    }
+
+    #[tokio::test]
+    async fn memory_backend() {
+        let mut project = TestProjectBuilder::new()
+            .with_cookbook("memory", &["build"])
+            .build();
+        project.config.cache.local.backend = LocalCacheBackend::Memory;
+        project.recipes.get_mut("memory:build").unwrap().cache = Some(RecipeCacheConfig {
+            outputs: vec!["memory-dist".into()],
+            ..Default::default()
+        });
+        let project = Arc::new(project);
+        project.create_project_bake_dirs().unwrap();
+        std::fs::write(project.get_recipe_log_path("memory:build"), b"foo").unwrap();
+        let dist = project.root_path.join("memory-dist");
+        std::fs::write(&dist, b"bar").unwrap();
+
+        let cache = CacheBuilder::new(project.clone())
+            .filter("memory:build")
+            .default_strategies()
+            .build()
+            .await
+            .unwrap();
+        cache.put("memory:build").await.unwrap();
+        assert!(!project.get_project_bake_path().join("cache").exists());
+
+        std::fs::remove_file(&dist).unwrap();
+        assert!(matches!(
+            cache.get("memory:build").await,
+            Ok(CacheResult::Hit(_))
+        ));
+        assert_eq!(std::fs::read_to_string(&dist).unwrap(), "bar");
+    }
+
+    #[test_case(None => true; "No ttl")]
+    #[test_case(Some(3) => true; "Fresh")]
+    #[test_case(Some(1) => false; "Expired")]
+    #[tokio::test]
+    async fn ttl(ttl_hours: Option<u64>) -> bool {
+        let mut project = TestProjectBuilder::new()
+            .with_cookbook("ttl", &["fetch"])
+            .build();
+        project.recipes.get_mut("ttl:fetch").unwrap().cache = Some(RecipeCacheConfig {
+            outputs: vec!["deps".into()],
+            ttl: ttl_hours.map(|hours| Duration::from_secs(hours * 60 * 60)),
+            ..Default::default()
+        });
+        let project = Arc::new(project);
+        project.create_project_bake_dirs().unwrap();
+        std::fs::write(project.root_path.join("deps"), b"deps").unwrap();
+
+        // The entry was created when the recipe's log was written, two hours ago
+        let log_path = project.get_recipe_log_path("ttl:fetch");
+        std::fs::write(&log_path, b"fetched").unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&log_path)
+            .unwrap()
+            .set_modified(SystemTime::now() - Duration::from_secs(2 * 60 * 60))
+            .unwrap();
+
+        let cache = CacheBuilder::new(project.clone())
+            .filter("ttl:fetch")
+            .default_strategies()
+            .build()
+            .await
+            .unwrap();
+        cache.put("ttl:fetch").await.unwrap();
+        matches!(cache.get("ttl:fetch").await, Ok(CacheResult::Hit(_)))
+    }
+
+    #[tokio::test]
+    async fn list_entries() {
+        let mut project = TestProjectBuilder::new()
+            .with_cookbook("list", &["build", "test", "lint"])
+            .build();
+        for name in ["list:build", "list:test"] {
+            project.recipes.get_mut(name).unwrap().cache = Some(RecipeCacheConfig::default());
+        }
+        let project = Arc::new(project);
+        project.create_project_bake_dirs().unwrap();
+
+        let mut cache = build_cache(project.clone(), "list:").await;
+        cache.strategies = vec![Arc::new(Box::new(LocalCacheStrategy {
+            path: project.get_project_bake_path().join("cache"),
+        }))];
+        for name in ["list:build", "list:test"] {
+            std::fs::write(project.get_recipe_log_path(name), b"foo").unwrap();
+            cache.put(name).await.unwrap();
+        }
+
+        // An entry no recipe uses anymore, eg. from before an input changed
+        let cache_path = project.get_project_bake_path().join("cache");
+        std::fs::copy(
+            cache_path.join(format!(
+                "{}.{}",
+                cache.hashes["list:build"],
+                super::ARCHIVE_EXTENSION
+            )),
+            cache_path.join(format!("stale.{}", super::ARCHIVE_EXTENSION)),
+        )
+        .unwrap();
+
+        let entries = list_local_entries(&project, &cache.hashes).unwrap();
+        assert_eq!(
+            entries
+                .iter()
+                .map(|e| e.recipe.as_deref())
+                .collect::<Vec<_>>(),
+            vec![Some("list:build"), Some("list:test"), None]
+        );
+        assert_eq!(entries[0].key, cache.hashes["list:build"]);
+        assert_eq!(entries[0].files, 1);
+        assert_eq!(entries[0].size, 3);
+        assert_eq!(entries[2].key, "stale");
+    }
+
+    #[test]
+    fn compression_stats() {
+        let project = TestProjectBuilder::new().build();
+        assert_eq!(local_compression_stats(&project).unwrap().total.archives, 0);
+
+        let cache_path = project.get_project_bake_path().join("cache");
+        std::fs::create_dir_all(&cache_path).unwrap();
+        let write_archive = |key: &str, contents: &[u8], level: Option<i32>| {
+            let mut data = tar::Header::new_gnu();
+            data.set_size(contents.len() as u64);
+            data.set_mode(0o644);
+            let mut tar = tar::Builder::new(Vec::new());
+            tar.append_data(&mut data, "out.bin", contents).unwrap();
+            let tar = tar.into_inner().unwrap();
+            let archive = match level {
+                Some(level) => zstd::encode_all(tar.as_slice(), level).unwrap(),
+                None => tar,
+            };
+            std::fs::write(
+                cache_path.join(format!("{}.{}", key, super::ARCHIVE_EXTENSION)),
+                archive,
+            )
+            .unwrap();
+        };
+        // Zeros compress very well, while the stored archive isn't compressed at all
+        write_archive("zeros", &[0; 2_000_000], Some(3));
+        let noise: Vec<u8> = (0..100_000u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8)
+            .collect();
+        write_archive("noise", &noise, None);
+        // Entries still being written are ignored
+        std::fs::write(cache_path.join(".tmp.tar.zst"), "partial").unwrap();
+
+        let report = local_compression_stats(&project).unwrap();
+        assert_eq!(report.total.archives, 2);
+        assert!(report.total.ratio() > 10.0, "{:?}", report);
+        assert_eq!(
+            report
+                .buckets
+                .iter()
+                .map(|(max, stats)| (*max, stats.archives))
+                .collect::<Vec<_>>(),
+            vec![
+                (Some(4096), 0),
+                (Some(1_048_576), 1),
+                (Some(67_108_864), 1),
+                (None, 0)
+            ]
+        );
+        let (_, stored) = &report.buckets[1];
+        assert!((0.99..=1.01).contains(&stored.ratio()), "{:?}", stored);
+        let (_, compressed) = &report.buckets[2];
+        assert!(compressed.ratio() > 100.0, "{:?}", compressed);
+    }
+
+    #[test_case(0 => None; "Empty")]
+    #[test_case(4095 => None; "Tiny")]
+    #[test_case(4096 => Some(1); "Small")]
+    #[test_case(1_048_576 => Some(6); "Large")]
+    fn auto_compression_level(size: u64) -> Option<i32> {
+        super::auto_compression_level(size)
+    }
+
+    #[test_case(10, false; "Tiny archive is stored uncompressed")]
+    #[test_case(2_000_000, true; "Large archive is compressed")]
+    #[tokio::test]
+    async fn auto_compression(output_size: usize, compressed: bool) {
+        let mut project = TestProjectBuilder::new()
+            .with_cookbook("compress", &["build"])
+            .build();
+        project.config.cache.local.compression_level = CompressionLevel::Auto;
+        project.recipes.get_mut("compress:build").unwrap().cache = Some(RecipeCacheConfig {
+            outputs: vec!["out.txt".into()],
+            ..Default::default()
+        });
+        let project = Arc::new(project);
+        project.create_project_bake_dirs().unwrap();
+        std::fs::write(project.get_recipe_log_path("compress:build"), b"").unwrap();
+        let output = project.root_path.join("out.txt");
+        std::fs::write(&output, "a".repeat(output_size)).unwrap();
+
+        let mut cache = build_cache(project.clone(), "compress:build").await;
+        let cache_path = project.get_project_bake_path().join("cache");
+        cache.strategies = vec![Arc::new(Box::new(LocalCacheStrategy {
+            path: cache_path.clone(),
+        }))];
+        cache.put("compress:build").await.unwrap();
+
+        let archive_path = cache_path.join(format!(
+            "{}.{}",
+            cache.hashes["compress:build"],
+            super::ARCHIVE_EXTENSION
+        ));
+        let archive = std::fs::read(&archive_path).unwrap();
+        assert_eq!(archive.starts_with(&super::ZSTD_MAGIC), compressed);
+        if compressed {
+            assert!(archive.len() < output_size);
+        }
+
+        std::fs::remove_file(&output).unwrap();
+        let result = cache.get("compress:build").await;
+        assert!(matches!(result, Ok(CacheResult::Hit(_))));
+        assert_eq!(std::fs::read(&output).unwrap().len(), output_size);
+    }
 }
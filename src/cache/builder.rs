@@ -1,16 +1,19 @@
 use std::{
-    collections::{BTreeMap, HashMap},
+    collections::{BTreeMap, HashMap, HashSet},
     future::Future,
     pin::Pin,
-    sync::Arc,
+    sync::{Arc, Mutex},
 };
 
-use anyhow::bail;
+use anyhow::{anyhow, bail};
 use log::debug;
 use serde::Serialize;
 
-use super::{Cache, CacheStrategy};
-use crate::project::BakeProject;
+use super::{remove_local_entries, Cache, CacheStrategy};
+use crate::project::{
+    config::LocalCacheBackend, file_hashes::FileHashCache, BakeProject, RecipeHashData,
+    RecipeSearch,
+};
 
 #[derive(Debug, Serialize)]
 struct CacheData {
@@ -29,27 +32,137 @@ type StrategyConstructor = Box<
 pub struct CacheBuilder {
     project: Arc<BakeProject>,
 
-    filter: Option<String>,
+    search: RecipeSearch,
 
     strategies: HashMap<String, StrategyConstructor>,
 
     hashes: HashMap<String, String>,
+
+    output_hashes: HashMap<String, String>,
+
+    force_rebuild: bool,
+}
+
+/// Returns whether other recipes in the project depend on a recipe
+pub(super) fn is_dependency(project: &BakeProject, recipe_name: &str) -> bool {
+    project
+        .dependency_map
+        .values()
+        .any(|deps| deps.contains(recipe_name))
+}
+
+/// Calculates the cache key of a recipe from its own hash and the hashes of its dependencies
+///
+/// Dependencies that have an output hash contribute it instead of their recipe hash, so that a
+/// recipe is still a cache hit when a dependency re-runs but produces identical outputs. The
+/// recipe's `cache.key_prefix`, if any, is prepended to the key.
+///
+/// # Arguments
+/// * `project` - The project the recipe belongs to
+/// * `recipe_name` - Fully qualified name of the recipe
+/// * `hashes` - Map of recipe names to the hash of their own fields
+/// * `output_hashes` - Map of recipe names to the hash of their outputs
+///
+pub(super) fn calculate_hash_with_deps(
+    project: &BakeProject,
+    recipe_name: &str,
+    hashes: &HashMap<String, String>,
+    output_hashes: &HashMap<String, String>,
+) -> String {
+    debug!("Calculating total hash for {}", recipe_name);
+    let cache_data = CacheData {
+        recipe: hashes
+            .get(recipe_name)
+            .cloned()
+            .unwrap_or_else(|| recipe_name.to_owned()),
+        deps: dependency_hashes(project, recipe_name, hashes, output_hashes),
+    };
+
+    debug!("Total cache data: {:?}", cache_data);
+
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(serde_json::to_string(&cache_data).unwrap().as_bytes());
+    let hash = hasher.finalize().to_hex().to_string();
+    match project
+        .recipes
+        .get(recipe_name)
+        .and_then(|recipe| recipe.cache.as_ref())
+    {
+        Some(cache) if !cache.key_prefix.is_empty() => format!("{}-{}", cache.key_prefix, hash),
+        _ => hash,
+    }
+}
+
+/// Returns the hashes that a recipe's dependencies contribute to its cache key
+fn dependency_hashes(
+    project: &BakeProject,
+    recipe_name: &str,
+    hashes: &HashMap<String, String>,
+    output_hashes: &HashMap<String, String>,
+) -> BTreeMap<String, String> {
+    project
+        .cache_dependencies(recipe_name)
+        .into_iter()
+        .filter_map(|dep| {
+            output_hashes
+                .get(&dep)
+                .or_else(|| hashes.get(&dep))
+                .map(|hash| (dep, hash.clone()))
+        })
+        .collect()
+}
+
+/// Breakdown of a recipe's cache key into the components folded into it
+#[derive(Debug)]
+pub struct CacheKeyExplanation {
+    /// Components of the recipe's own hash
+    pub recipe: RecipeHashData,
+
+    pub recipe_hash: String,
+
+    /// Hashes contributed by dependencies, either the hash of their outputs or their cache key
+    pub dependencies: BTreeMap<String, String>,
+
+    /// Final cache key
+    pub key: String,
 }
 
 impl CacheBuilder {
     pub fn new(project: Arc<BakeProject>) -> Self {
         Self {
             project,
-            filter: None,
+            search: RecipeSearch::All,
             strategies: HashMap::new(),
             hashes: HashMap::new(),
+            output_hashes: HashMap::new(),
+            force_rebuild: false,
         }
     }
 
+    /// Removes the local cache entries of the recipes matched by the search and makes them miss
+    /// the cache, so they are baked again and their outputs stored anew. Dependencies that aren't
+    /// matched themselves still use the cache.
+    pub fn force_rebuild(&mut self, force_rebuild: bool) -> &mut Self {
+        self.force_rebuild = force_rebuild;
+        self
+    }
+
+    /// Registers the local, S3 and GCS strategies, or only the local one when the network is
+    /// disabled with `--no-network`. The local strategy keeps entries in memory with
+    /// `cache.local.backend: memory`
     pub fn default_strategies(&mut self) -> &mut Self {
-        self.add_strategy("local", super::local::LocalCacheStrategy::from_config);
-        self.add_strategy("s3", super::s3::S3CacheStrategy::from_config);
-        self.add_strategy("gcs", super::gcs::GcsCacheStrategy::from_config);
+        match self.project.config.cache.local.backend {
+            LocalCacheBackend::Disk => {
+                self.add_strategy("local", super::local::LocalCacheStrategy::from_config)
+            }
+            LocalCacheBackend::Memory => {
+                self.add_strategy("local", super::memory::MemoryCacheStrategy::from_config)
+            }
+        };
+        if !self.project.config.no_network {
+            self.add_strategy("s3", super::s3::S3CacheStrategy::from_config);
+            self.add_strategy("gcs", super::gcs::GcsCacheStrategy::from_config);
+        }
         self
     }
 
@@ -67,74 +180,153 @@ impl CacheBuilder {
     }
 
     pub fn filter(&mut self, filter: &str) -> &mut Self {
-        self.filter = Some(filter.to_owned());
-        self
+        self.search(RecipeSearch::ByPattern(filter.to_owned()))
     }
 
-    fn calculate_hash_with_deps(&self, recipe_name: &str) -> String {
-        debug!("Calculating total hash for {}", recipe_name);
-        let mut cache_data = CacheData {
-            recipe: recipe_name.to_owned(),
-            deps: BTreeMap::new(),
-        };
-
-        if let Some(recipe_hash) = self.hashes.get(recipe_name) {
-            cache_data.recipe = recipe_hash.clone();
-        };
-
-        if let Some(deps) = self.project.clone().dependency_map.get(recipe_name) {
-            cache_data.deps = deps.iter().fold(BTreeMap::new(), |mut acc, x| {
-                if let Some(hash) = self.hashes.get(x) {
-                    acc.insert(x.clone(), hash.clone());
-                }
-                acc
-            });
-        }
-
-        debug!("Total cache data: {:?}", cache_data);
-
-        let mut hasher = blake3::Hasher::new();
-        hasher.update(serde_json::to_string(&cache_data).unwrap().as_bytes());
-        hasher.finalize().to_hex().to_string()
+    pub fn search(&mut self, search: RecipeSearch) -> &mut Self {
+        self.search = search;
+        self
     }
 
-    fn calculate_all_hashes(&mut self) -> anyhow::Result<HashMap<String, String>> {
-        let recipes = self.project.get_recipes(self.filter.as_deref());
+    pub fn calculate_all_hashes(&mut self) -> anyhow::Result<HashMap<String, String>> {
+        let recipes = self.project.get_recipes(&self.search);
 
+        let file_hashes = if self.project.config.no_hash_cache {
+            FileHashCache::default()
+        } else {
+            FileHashCache::load(self.project.get_file_hash_cache_path())
+        };
         self.hashes = recipes
             .iter()
-            .map(|(name, recipe)| match recipe.get_recipe_hash() {
-                Ok(hash) => Ok((name.clone(), hash)),
-                Err(e) => Err(e),
-            })
+            .map(
+                |(name, recipe)| match recipe.get_recipe_hash_with(&file_hashes) {
+                    Ok(hash) => Ok((name.clone(), hash)),
+                    Err(e) => Err(e),
+                },
+            )
             .collect::<anyhow::Result<_>>()?;
+        file_hashes.save();
+
+        // Use the outputs currently on disk until dependencies run again
+        self.output_hashes = recipes
+            .iter()
+            .filter(|(name, _)| is_dependency(&self.project, name))
+            .filter_map(|(name, recipe)| match recipe.get_outputs_hash() {
+                Ok(hash) => hash.map(|hash| (name.clone(), hash)),
+                Err(err) => {
+                    debug!("{}", err);
+                    None
+                }
+            })
+            .collect();
 
         recipes
             .keys()
             .map(|name| {
-                let hash = self.calculate_hash_with_deps(name);
+                let hash = calculate_hash_with_deps(
+                    &self.project,
+                    name,
+                    &self.hashes,
+                    &self.output_hashes,
+                );
                 Ok((name.clone(), hash))
             })
             .collect()
     }
 
-    pub async fn build(&mut self) -> anyhow::Result<Cache> {
-        let mut strategies: Vec<Arc<Box<dyn CacheStrategy>>> = Vec::new();
+    /// Explains how the cache key of a recipe is calculated, listing each component folded into
+    /// it
+    ///
+    /// # Arguments
+    /// * `recipe_name` - Fully qualified name of the recipe
+    ///
+    pub fn explain_key(&mut self, recipe_name: &str) -> anyhow::Result<CacheKeyExplanation> {
+        let project = self.project.clone();
+        let Some(recipe) = project.recipes.get(recipe_name) else {
+            bail!("Recipe {} not found", recipe_name);
+        };
+        self.search(RecipeSearch::ByNames(vec![recipe_name.to_owned()]));
+        let keys = self.calculate_all_hashes()?;
 
-        let mut order = self.project.config.cache.order.clone();
-        // If no order is defined, use local -> s3 -> gcs if configuration exists
-        if order.is_empty() {
-            if self.project.config.cache.local.enabled {
-                order.push("local".to_string());
+        let recipe_data = recipe.get_recipe_hash_data(&FileHashCache::default())?;
+        Ok(CacheKeyExplanation {
+            recipe_hash: recipe_data.hash(),
+            recipe: recipe_data,
+            dependencies: dependency_hashes(
+                &self.project,
+                recipe_name,
+                &self.hashes,
+                &self.output_hashes,
+            ),
+            key: keys[recipe_name].clone(),
+        })
+    }
+
+    /// Names of the strategies to use, in lookup order. If no order is configured, uses
+    /// local -> s3 -> gcs for the ones that are configured
+    fn strategy_order(&self) -> Vec<String> {
+        let config = &self.project.config.cache;
+        if !config.order.is_empty() {
+            return config.order.clone();
+        }
+
+        let mut order = Vec::new();
+        if config.local.enabled {
+            order.push("local".to_string());
+        }
+        if let Some(remotes) = &config.remotes {
+            if remotes.s3.is_some() {
+                order.push("s3".to_string());
             }
-            if let Some(remotes) = &self.project.config.cache.remotes {
-                if remotes.s3.is_some() {
-                    order.push("s3".to_string());
-                }
-                if remotes.gcs.is_some() {
-                    order.push("gcs".to_string());
-                }
+            if remotes.gcs.is_some() {
+                order.push("gcs".to_string());
+            }
+        }
+        order
+    }
+
+    /// Builds each remote strategy in the cache order and checks that it can be read from and
+    /// written to with a tiny test entry, without baking any recipe
+    ///
+    /// Returns the name of each remote along with the outcome of its check.
+    pub async fn check_remotes(&self) -> anyhow::Result<Vec<(String, anyhow::Result<()>)>> {
+        if self.project.config.no_network {
+            bail!(
+                "Remote caches can't be validated when the network is disabled with --no-network"
+            );
+        }
+
+        let mut results = Vec::new();
+        for item in self.strategy_order() {
+            if item == "local" {
+                continue;
             }
+            let result = match self.strategies.get(&item) {
+                Some(build_fn) => match build_fn(self.project.clone()).await {
+                    Ok(strategy) => strategy.check().await,
+                    Err(err) => Err(anyhow!("Could not create strategy: {}", err)),
+                },
+                None => Err(anyhow!("No cache strategy implementation found")),
+            };
+            results.push((item, result));
+        }
+        Ok(results)
+    }
+
+    pub async fn build(&mut self) -> anyhow::Result<Cache> {
+        let mut strategies: Vec<Arc<Box<dyn CacheStrategy>>> = Vec::new();
+
+        let config = &self.project.config;
+        if config.no_network && config.cache.strict_remote && config.cache.remotes.is_some() {
+            bail!(
+                "Remote caches are required by cache.strict_remote but the network is disabled \
+                 with --no-network"
+            );
+        }
+
+        let mut order = self.strategy_order();
+        if self.project.config.no_network {
+            order.retain(|item| item == "local");
         }
 
         for item in &order {
@@ -146,22 +338,43 @@ impl CacheBuilder {
             }
         }
 
+        let hashes = self.calculate_all_hashes()?;
+        let mut forced = HashSet::new();
+        if self.force_rebuild {
+            let forced_hashes: HashMap<String, String> = hashes
+                .iter()
+                .filter(|(name, _)| self.search.matches(name))
+                .map(|(name, hash)| (name.clone(), hash.clone()))
+                .collect();
+            let removed = remove_local_entries(&self.project, &forced_hashes)?;
+            debug!("Removed {} local cache entries", removed);
+            forced = forced_hashes.into_keys().collect();
+        }
+
         Ok(Cache {
             project: self.project.clone(),
             strategies,
-            hashes: self.calculate_all_hashes()?,
+            hashes,
+            recipe_hashes: self.hashes.clone(),
+            output_hashes: Mutex::new(self.output_hashes.clone()),
+            forced,
+            total_output_size: Mutex::new(0),
         })
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::{path::PathBuf, sync::Mutex};
+    use std::{collections::BTreeSet, path::PathBuf, sync::Mutex};
 
     use async_trait::async_trait;
 
     use crate::{
-        cache::{CacheResult, CacheResultData, ARCHIVE_EXTENSION},
+        cache::{local::LocalCacheStrategy, CacheResult, CacheResultData, ARCHIVE_EXTENSION},
+        project::{
+            config::{RemoteCacheConfig, S3CacheConfig},
+            RecipeCacheConfig, RecipeDependency,
+        },
         test_utils::TestProjectBuilder,
     };
 
@@ -175,11 +388,11 @@ mod tests {
 
     #[async_trait]
     impl CacheStrategy for TestCacheStrategy {
-        async fn get(&self, key: &str) -> CacheResult {
+        async fn get(&self, key: &str) -> anyhow::Result<CacheResult> {
             self.get_called.lock().unwrap().push_str(key);
-            CacheResult::Hit(CacheResultData {
+            Ok(CacheResult::Hit(CacheResultData {
                 archive_path: PathBuf::from(format!("{}.{}", key, ARCHIVE_EXTENSION)),
-            })
+            }))
         }
         async fn put(&self, key: &str, _: PathBuf) -> anyhow::Result<()> {
             self.put_called.lock().unwrap().push_str(key);
@@ -208,4 +421,233 @@ mod tests {
             .unwrap();
         assert!(cache.hashes.contains_key("foo:build"));
     }
+
+    #[tokio::test]
+    async fn no_network() {
+        let mut project = TestProjectBuilder::new()
+            .with_cookbook("foo", &["build"])
+            .build();
+        project.config.no_network = true;
+        project.config.cache.order = vec![String::from("local"), String::from("s3")];
+        project.config.cache.remotes = Some(RemoteCacheConfig {
+            s3: Some(S3CacheConfig {
+                bucket: String::from("bucket"),
+                region: None,
+                credentials_command: None,
+            }),
+            gcs: None,
+        });
+        let project = Arc::new(project);
+
+        let mut builder = CacheBuilder::new(project.clone());
+        builder.default_strategies();
+        assert_eq!(
+            builder.strategies.keys().collect::<Vec<_>>(),
+            vec![&String::from("local")]
+        );
+        assert_eq!(builder.build().await.unwrap().strategies.len(), 1);
+        drop(builder);
+
+        let mut project = Arc::into_inner(project).unwrap();
+        project.config.cache.strict_remote = true;
+        let err = CacheBuilder::new(Arc::new(project))
+            .default_strategies()
+            .build()
+            .await
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("--no-network"));
+    }
+
+    #[derive(Debug)]
+    struct UnauthorizedCacheStrategy;
+
+    #[async_trait]
+    impl CacheStrategy for UnauthorizedCacheStrategy {
+        async fn get(&self, _: &str) -> anyhow::Result<CacheResult> {
+            bail!("403 Forbidden: invalid credentials")
+        }
+        async fn put(&self, _: &str, _: PathBuf) -> anyhow::Result<()> {
+            Ok(())
+        }
+        async fn from_config(_: Arc<BakeProject>) -> anyhow::Result<Box<dyn super::CacheStrategy>> {
+            Ok(Box::new(UnauthorizedCacheStrategy))
+        }
+    }
+
+    #[tokio::test]
+    async fn check_remotes() {
+        let mut project = TestProjectBuilder::new().build();
+        project.config.cache.order = vec![
+            String::from("local"),
+            String::from("s3"),
+            String::from("gcs"),
+            String::from("other"),
+        ];
+        let local_path = project.root_path.join(".bake").join("cache");
+        let project = Arc::new(project);
+        let strategy_path = local_path.clone();
+
+        let results = CacheBuilder::new(project)
+            .add_strategy("local", TestCacheStrategy::from_config)
+            .add_strategy("s3", UnauthorizedCacheStrategy::from_config)
+            .add_strategy("gcs", move |_| {
+                let path = strategy_path.clone();
+                Box::pin(async move {
+                    Ok(Box::new(LocalCacheStrategy { path }) as Box<dyn CacheStrategy>)
+                })
+            })
+            .check_remotes()
+            .await
+            .unwrap();
+
+        let names: Vec<&str> = results.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, vec!["s3", "gcs", "other"]);
+        assert_eq!(
+            results[0].1.as_ref().unwrap_err().to_string(),
+            "Could not read: 403 Forbidden: invalid credentials"
+        );
+        // The test entry is removed after it was written
+        assert!(results[1].1.is_ok());
+        assert_eq!(std::fs::read_dir(&local_path).unwrap().count(), 0);
+        assert_eq!(
+            results[2].1.as_ref().unwrap_err().to_string(),
+            "No cache strategy implementation found"
+        );
+    }
+
+    #[tokio::test]
+    async fn key_prefix() {
+        let mut project = TestProjectBuilder::new()
+            .with_cookbook("main", &["build"])
+            .with_cookbook("dev", &["build"])
+            .build();
+        for name in ["main:build", "dev:build"] {
+            project.recipes.get_mut(name).unwrap().cache = Some(RecipeCacheConfig::default());
+        }
+        let project = Arc::new(project);
+        let keys = CacheBuilder::new(project.clone())
+            .calculate_all_hashes()
+            .unwrap();
+        // Recipes with identical definitions share their key
+        assert_eq!(keys["main:build"], keys["dev:build"]);
+
+        let mut project = Arc::into_inner(project).unwrap();
+
+        for name in ["main:build", "dev:build"] {
+            let cache = project
+                .recipes
+                .get_mut(name)
+                .unwrap()
+                .cache
+                .as_mut()
+                .unwrap();
+            cache.key_prefix = name.split(':').next().unwrap().to_owned();
+        }
+        let project = Arc::new(project);
+        let keys = CacheBuilder::new(project.clone())
+            .calculate_all_hashes()
+            .unwrap();
+        assert!(keys["main:build"].starts_with("main-"));
+        assert!(keys["dev:build"].starts_with("dev-"));
+        assert_eq!(keys["main:build"][5..], keys["dev:build"][4..]);
+
+        // Each prefix gets its own cache entry
+        let strategy = LocalCacheStrategy::from_config(project.clone())
+            .await
+            .unwrap();
+        for name in ["main:build", "dev:build"] {
+            let archive_path = project
+                .root_path
+                .join(format!("{}.tar.zst", name.replace(':', "-")));
+            std::fs::write(&archive_path, name).unwrap();
+            strategy.put(&keys[name], archive_path).await.unwrap();
+        }
+        for name in ["main:build", "dev:build"] {
+            match strategy.get(&keys[name]).await.unwrap() {
+                CacheResult::Hit(data) => {
+                    assert_eq!(std::fs::read_to_string(data.archive_path).unwrap(), name)
+                }
+                CacheResult::Miss => panic!("{} should be cached", name),
+            }
+        }
+    }
+
+    #[test]
+    fn order_only_dependencies() {
+        let build_project = |up_run: &str, build_run: &str| {
+            let mut project = TestProjectBuilder::new()
+                .with_cookbook("it", &["up", "build", "test"])
+                .with_dependency("it:test", "it:build")
+                .with_dependency("it:test", "it:up")
+                .build();
+            project.recipes.get_mut("it:test").unwrap().dependencies = Some(vec![
+                "it:build".into(),
+                RecipeDependency {
+                    recipe: String::from("it:up"),
+                    order_only: true,
+                },
+            ]);
+            project.recipes.get_mut("it:up").unwrap().run = up_run.to_owned();
+            project.recipes.get_mut("it:build").unwrap().run = build_run.to_owned();
+            let project = Arc::new(project);
+            assert_eq!(
+                project.cache_dependencies("it:test"),
+                BTreeSet::from([String::from("it:build")])
+            );
+            CacheBuilder::new(project).calculate_all_hashes().unwrap()
+        };
+
+        let keys = build_project("docker compose up", "cargo build");
+        // The order-only dependency doesn't change the key, the regular one does
+        assert_eq!(
+            build_project("docker compose up -d", "cargo build")["it:test"],
+            keys["it:test"]
+        );
+        assert_ne!(
+            build_project("docker compose up", "cargo build --release")["it:test"],
+            keys["it:test"]
+        );
+    }
+
+    #[test]
+    fn explain_key() {
+        let mut project = TestProjectBuilder::new()
+            .with_cookbook("foo", &["build", "test"])
+            .with_dependency("foo:test", "foo:build")
+            .build();
+        for name in ["b.txt", "a.txt", "debug.log"] {
+            std::fs::write(project.root_path.join(name), name).unwrap();
+        }
+        project.recipes.get_mut("foo:test").unwrap().cache = Some(RecipeCacheConfig {
+            inputs: vec![String::from("*.txt")],
+            ..Default::default()
+        });
+        let project = Arc::new(project);
+
+        let explanation = CacheBuilder::new(project.clone())
+            .explain_key("foo:test")
+            .unwrap();
+        let files: Vec<&PathBuf> = explanation.recipe.file_hashes.keys().collect();
+        assert_eq!(
+            files,
+            vec![&PathBuf::from("a.txt"), &PathBuf::from("b.txt")]
+        );
+        assert_eq!(
+            explanation.recipe_hash,
+            project.recipes["foo:test"].get_recipe_hash().unwrap()
+        );
+        assert_eq!(
+            explanation.dependencies.keys().collect::<Vec<_>>(),
+            vec!["foo:build"]
+        );
+
+        let keys = CacheBuilder::new(project).calculate_all_hashes().unwrap();
+        assert_eq!(explanation.key, keys["foo:test"]);
+        assert!(
+            CacheBuilder::new(Arc::new(TestProjectBuilder::new().build()))
+                .explain_key("foo:missing")
+                .is_err()
+        );
+    }
 }
@@ -0,0 +1,118 @@
+use std::{path::PathBuf, process::Stdio, time::SystemTime};
+
+use anyhow::bail;
+use aws_sdk_s3::primitives::{DateTime, DateTimeFormat};
+use serde::de::DeserializeOwned;
+
+/// Runs a `credentials_command` to obtain short-lived credentials for a remote cache, like
+/// docker and git credential helpers
+///
+/// The command runs with `sh -c` in the project root and must print a JSON object on stdout.
+#[derive(Debug, Clone)]
+pub struct CredentialsHelper {
+    command: String,
+    root_path: PathBuf,
+}
+
+impl CredentialsHelper {
+    /// Creates a helper for a command that runs in the given directory
+    ///
+    /// # Arguments
+    /// * `command` - Shell command printing credentials as JSON
+    /// * `root_path` - Directory to run the command in, usually the project root
+    ///
+    pub fn new(command: String, root_path: PathBuf) -> Self {
+        Self { command, root_path }
+    }
+
+    /// Runs the command and parses its output
+    pub async fn fetch<T: DeserializeOwned>(&self) -> anyhow::Result<T> {
+        let output = match tokio::process::Command::new("sh")
+            .current_dir(&self.root_path)
+            .arg("-c")
+            .arg(&self.command)
+            .stdin(Stdio::null())
+            .output()
+            .await
+        {
+            Ok(output) => output,
+            Err(err) => bail!(
+                "Could not run credentials command `{}`: {}",
+                self.command,
+                err
+            ),
+        };
+        if !output.status.success() {
+            bail!(
+                "Credentials command `{}` failed with {}: {}",
+                self.command,
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+
+        match serde_json::from_slice(&output.stdout) {
+            Ok(credentials) => Ok(credentials),
+            Err(err) => bail!(
+                "Could not parse output of credentials command `{}`: {}",
+                self.command,
+                err
+            ),
+        }
+    }
+}
+
+/// Parses an RFC 3339 expiration time printed by a credentials command
+pub fn parse_expiration(expiration: &str) -> anyhow::Result<SystemTime> {
+    match DateTime::from_str(expiration, DateTimeFormat::DateTime).map(SystemTime::try_from) {
+        Ok(Ok(time)) => Ok(time),
+        _ => bail!("Invalid credentials expiration time: {}", expiration),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use serde::Deserialize;
+
+    use super::CredentialsHelper;
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Token {
+        token: String,
+    }
+
+    #[tokio::test]
+    async fn fetch() {
+        let helper = CredentialsHelper::new(
+            String::from(r#"echo '{"token": "secret"}'"#),
+            std::env::temp_dir(),
+        );
+        assert_eq!(
+            helper.fetch::<Token>().await.unwrap(),
+            Token {
+                token: String::from("secret")
+            }
+        );
+
+        let helper = CredentialsHelper::new(
+            String::from("echo denied >&2; exit 1"),
+            std::env::temp_dir(),
+        );
+        let err = helper.fetch::<Token>().await.unwrap_err();
+        assert!(err.to_string().ends_with("denied"));
+
+        let helper = CredentialsHelper::new(String::from("echo nope"), std::env::temp_dir());
+        assert!(helper.fetch::<Token>().await.is_err());
+    }
+
+    #[test]
+    fn parse_expiration() {
+        assert_eq!(
+            super::parse_expiration("1970-01-01T00:01:40Z").unwrap(),
+            SystemTime::UNIX_EPOCH + Duration::from_secs(100)
+        );
+        assert!(super::parse_expiration("tomorrow").is_err());
+    }
+}
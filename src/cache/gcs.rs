@@ -1,28 +1,122 @@
-use std::{path::PathBuf, sync::Arc};
+use std::{
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
 use tokio::fs::File;
 use tokio::io::AsyncWriteExt;
 use tokio_stream::StreamExt;
 
 use anyhow::bail;
 use async_trait::async_trait;
-use log::{debug, error, warn};
+use google_cloud_token::{TokenSource, TokenSourceProvider};
+use log::debug;
+use serde::Deserialize;
+use tokio::sync::Mutex;
 
 use crate::{
     cache::{CacheResultData, ARCHIVE_EXTENSION},
+    error::BakeError,
     project::BakeProject,
 };
 
 use google_cloud_storage::{
     client::{Client, ClientConfig},
-    http::objects::{
-        download::Range,
-        get::GetObjectRequest,
-        upload::{Media, UploadObjectRequest, UploadType},
+    http::{
+        objects::{
+            delete::DeleteObjectRequest,
+            download::Range,
+            get::GetObjectRequest,
+            upload::{Media, UploadObjectRequest, UploadType},
+        },
+        Error as HttpError,
     },
 };
 
+use super::credentials::{parse_expiration, CredentialsHelper};
 use super::{CacheResult, CacheStrategy};
 
+/// How long before their expiration tokens from a `credentials_command` are refreshed
+const TOKEN_REFRESH_MARGIN: Duration = Duration::from_secs(60);
+
+/// Access token printed by a GCS `credentials_command`
+#[derive(Debug, Deserialize)]
+struct CommandToken {
+    access_token: String,
+
+    /// RFC 3339 time after which the token is refreshed, tokens without one are kept forever
+    expiration: Option<String>,
+}
+
+/// Token source running a `credentials_command`, caching its token until it expires
+#[derive(Debug)]
+struct CommandTokenSource {
+    helper: CredentialsHelper,
+    token: Mutex<Option<(String, Option<SystemTime>)>>,
+}
+
+#[async_trait]
+impl TokenSource for CommandTokenSource {
+    async fn token(&self) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        let mut cached = self.token.lock().await;
+        if let Some((token, expiration)) = cached.as_ref() {
+            if expiration.map_or(true, |expiration| {
+                expiration > SystemTime::now() + TOKEN_REFRESH_MARGIN
+            }) {
+                return Ok(format!("Bearer {}", token));
+            }
+        }
+
+        debug!("Fetching GCS token from credentials command");
+        let token: CommandToken = self.helper.fetch().await?;
+        let expiration = token
+            .expiration
+            .as_deref()
+            .map(parse_expiration)
+            .transpose()?;
+        let header = format!("Bearer {}", token.access_token);
+        *cached = Some((token.access_token, expiration));
+        Ok(header)
+    }
+}
+
+#[derive(Debug)]
+struct CommandTokenSourceProvider {
+    source: Arc<CommandTokenSource>,
+}
+
+impl TokenSourceProvider for CommandTokenSourceProvider {
+    fn token_source(&self) -> Arc<dyn TokenSource> {
+        self.source.clone()
+    }
+}
+
+/// Converts a failed GCS request to an error, marking timeouts, connection failures and server
+/// errors as transient so that they're retried
+///
+/// # Arguments
+/// * `err` - The request's error
+/// * `message` - Describes the failed request
+///
+#[coverage(off)]
+fn request_error(err: &HttpError, message: String) -> anyhow::Error {
+    let transient = match err {
+        HttpError::Response(response) => response.code >= 500,
+        HttpError::HttpClient(err) => {
+            err.is_timeout()
+                || err.is_connect()
+                || err.status().is_some_and(|status| status.is_server_error())
+        }
+        _ => false,
+    };
+    let message = format!("{message}: {err}");
+    if transient {
+        BakeError::TransientCacheError(message).into()
+    } else {
+        anyhow::anyhow!(message)
+    }
+}
+
 #[derive(Clone)]
 pub struct GcsCacheStrategy {
     pub bucket: String,
@@ -39,7 +133,7 @@ impl std::fmt::Debug for GcsCacheStrategy {
 #[async_trait]
 impl CacheStrategy for GcsCacheStrategy {
     #[coverage(off)]
-    async fn get(&self, key: &str) -> CacheResult {
+    async fn get(&self, key: &str) -> anyhow::Result<CacheResult> {
         let file_name = format!("{}.{}", key, ARCHIVE_EXTENSION);
         let archive_path = std::env::temp_dir().join(&file_name);
 
@@ -63,11 +157,10 @@ impl CacheStrategy for GcsCacheStrategy {
                         while let Some(bytes) = data.next().await {
                             if let Ok(bytes) = bytes {
                                 if file.write_all(&bytes).await.is_err() {
-                                    warn!(
-                                    "GCS Cache Strategy failed to write to file in temp dir: {}",
-                                    archive_path.display()
-                                );
-                                    return CacheResult::Miss;
+                                    bail!(
+                                        "GCS Cache Strategy failed to write to file in temp dir: {}",
+                                        archive_path.display()
+                                    );
                                 }
                             }
                         }
@@ -77,26 +170,28 @@ impl CacheStrategy for GcsCacheStrategy {
                             archive_path.display()
                         );
                         if let Err(err) = file.shutdown().await {
-                            error!("Error saving archive file: {:?}", err);
-                            return CacheResult::Miss;
+                            bail!("Error saving archive file: {:?}", err);
                         }
 
-                        return CacheResult::Hit(CacheResultData { archive_path });
+                        Ok(CacheResult::Hit(CacheResultData { archive_path }))
                     }
                     Err(err) => {
-                        debug!(
+                        bail!(
                             "GCS Cache Strategy failed to create file in temp dir: {}: {}",
                             archive_path.display(),
                             err
                         );
-                        return CacheResult::Miss;
                     }
                 }
             }
-            Err(err) => {
-                debug!("Error retrieving key {key} from GCS: {err}");
-                return CacheResult::Miss;
+            Err(HttpError::Response(response)) if response.code == 404 => {
+                debug!("Key {key} not found in GCS");
+                Ok(CacheResult::Miss)
             }
+            Err(err) => Err(request_error(
+                &err,
+                format!("Error retrieving key {key} from GCS"),
+            )),
         }
     }
 
@@ -122,9 +217,10 @@ impl CacheStrategy for GcsCacheStrategy {
                 .await
             {
                 Ok(_) => Ok(()),
-                Err(e) => {
-                    bail!("GCS Cache Strategy failed to upload file: {}", e)
-                }
+                Err(e) => Err(request_error(
+                    &e,
+                    String::from("GCS Cache Strategy failed to upload file"),
+                )),
             }
         } else {
             bail!(
@@ -134,10 +230,41 @@ impl CacheStrategy for GcsCacheStrategy {
         }
     }
     #[coverage(off)]
+    async fn delete(&self, key: &str) -> anyhow::Result<()> {
+        let file_name = format!("{}.{}", key, ARCHIVE_EXTENSION);
+        debug!("Deleting key {key} from GCS");
+        match self
+            .client
+            .delete_object(&DeleteObjectRequest {
+                bucket: self.bucket.clone(),
+                object: file_name,
+                ..Default::default()
+            })
+            .await
+        {
+            Ok(_) => Ok(()),
+            Err(e) => bail!("GCS Cache Strategy failed to delete key {key}: {e}"),
+        }
+    }
+    #[coverage(off)]
     async fn from_config(config: Arc<BakeProject>) -> anyhow::Result<Box<dyn CacheStrategy>> {
-        let client_config = ClientConfig::default().with_auth().await?;
         if let Some(remotes) = &config.config.cache.remotes {
             if let Some(gcs) = &remotes.gcs {
+                let client_config = match &gcs.credentials_command {
+                    Some(command) => ClientConfig {
+                        token_source_provider: Some(Box::new(CommandTokenSourceProvider {
+                            source: Arc::new(CommandTokenSource {
+                                helper: CredentialsHelper::new(
+                                    command.clone(),
+                                    config.root_path.clone(),
+                                ),
+                                token: Mutex::new(None),
+                            }),
+                        })),
+                        ..Default::default()
+                    },
+                    None => ClientConfig::default().with_auth().await?,
+                };
                 return Ok(Box::new(Self {
                     bucket: gcs.bucket.clone(),
                     client: Client::new(client_config),
@@ -148,3 +275,45 @@ impl CacheStrategy for GcsCacheStrategy {
         bail!("Failed to create GCS Cache Strategy")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use google_cloud_token::TokenSource;
+    use tokio::sync::Mutex;
+
+    use crate::{cache::credentials::CredentialsHelper, test_utils::TestProjectBuilder};
+
+    use super::CommandTokenSource;
+
+    #[tokio::test]
+    async fn command_token() {
+        let project = TestProjectBuilder::new().build();
+        let calls_path = project.root_path.join("calls.txt");
+        let source = |expiration: &str| CommandTokenSource {
+            helper: CredentialsHelper::new(
+                format!(
+                    r#"echo call >> {}; echo '{{"access_token": "TOKEN", "expiration": "{}"}}'"#,
+                    calls_path.display(),
+                    expiration
+                ),
+                project.root_path.clone(),
+            ),
+            token: Mutex::new(None),
+        };
+
+        // Valid tokens are reused
+        let valid = source("2100-01-01T00:00:00Z");
+        assert_eq!(valid.token().await.unwrap(), "Bearer TOKEN");
+        assert_eq!(valid.token().await.unwrap(), "Bearer TOKEN");
+        assert_eq!(std::fs::read_to_string(&calls_path).unwrap(), "call\n");
+
+        // Expired tokens are fetched again
+        let expired = source("2000-01-01T00:00:00Z");
+        assert_eq!(expired.token().await.unwrap(), "Bearer TOKEN");
+        assert_eq!(expired.token().await.unwrap(), "Bearer TOKEN");
+        assert_eq!(
+            std::fs::read_to_string(&calls_path).unwrap(),
+            "call\ncall\ncall\n"
+        );
+    }
+}
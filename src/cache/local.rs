@@ -3,6 +3,7 @@ use std::{path::PathBuf, sync::Arc};
 use anyhow::anyhow;
 use async_trait::async_trait;
 use log::debug;
+use rand::distributions::{Alphanumeric, DistString};
 
 use crate::{
     cache::{CacheResultData, ARCHIVE_EXTENSION},
@@ -16,17 +17,36 @@ pub struct LocalCacheStrategy {
     pub path: PathBuf,
 }
 
+impl LocalCacheStrategy {
+    /// Returns the local cache directory configured for a project
+    pub fn path_from_config(project: &BakeProject) -> PathBuf {
+        project
+            .config
+            .cache
+            .local
+            .path
+            .clone()
+            .unwrap_or(project.get_project_bake_path().join("cache"))
+    }
+
+    /// Whether the local cache lives outside of the project's `.bake` directory, in which case
+    /// it may be shared with other projects
+    pub fn is_shared(project: &BakeProject) -> bool {
+        !Self::path_from_config(project).starts_with(project.get_project_bake_path())
+    }
+}
+
 #[async_trait]
 impl CacheStrategy for LocalCacheStrategy {
-    async fn get(&self, key: &str) -> CacheResult {
+    async fn get(&self, key: &str) -> anyhow::Result<CacheResult> {
         let file_name = format!("{}.{}", key.to_owned(), ARCHIVE_EXTENSION);
         let archive_path = self.path.join(file_name.clone());
         debug!("Checking local cache for key {}", archive_path.display());
         if archive_path.is_file() {
             debug!("Cache hit for key {}", key);
-            return CacheResult::Hit(CacheResultData { archive_path });
+            return Ok(CacheResult::Hit(CacheResultData { archive_path }));
         }
-        CacheResult::Miss
+        Ok(CacheResult::Miss)
     }
     async fn put(&self, key: &str, archive_path: PathBuf) -> anyhow::Result<()> {
         let file_name = format!("{}.{}", key.to_owned(), ARCHIVE_EXTENSION);
@@ -45,14 +65,23 @@ impl CacheStrategy for LocalCacheStrategy {
         }
 
         // Check if cache folder with that key already exists
-        let cache_path = self.path.join(file_name);
+        let cache_path = self.path.join(&file_name);
         if cache_path.exists() {
             println!("Cache file already exists: {}", cache_path.display());
             return Ok(());
         }
 
-        // Copy archive to cache folder
-        if let Err(err) = std::fs::copy(archive_path, cache_path.clone()) {
+        // Copy archive to a temporary file first and move it in place, so that other projects
+        // sharing the cache never read a partially written entry
+        let temp_path = self.path.join(format!(
+            ".{}.{}",
+            Alphanumeric.sample_string(&mut rand::thread_rng(), 8),
+            file_name
+        ));
+        if let Err(err) = std::fs::copy(archive_path, &temp_path)
+            .and_then(|_| std::fs::rename(&temp_path, &cache_path))
+        {
+            let _ = std::fs::remove_file(&temp_path);
             Err(anyhow!(
                 "Failed to copy archive to cache folder {}: {}",
                 cache_path.display(),
@@ -63,15 +92,19 @@ impl CacheStrategy for LocalCacheStrategy {
         }
     }
 
+    async fn delete(&self, key: &str) -> anyhow::Result<()> {
+        let cache_path = self.path.join(format!("{}.{}", key, ARCHIVE_EXTENSION));
+        std::fs::remove_file(&cache_path)
+            .map_err(|err| anyhow!("Failed to remove {}: {}", cache_path.display(), err))
+    }
+
+    fn is_remote(&self) -> bool {
+        false
+    }
+
     async fn from_config(project: Arc<BakeProject>) -> anyhow::Result<Box<dyn CacheStrategy>> {
         debug!("Building local cache");
-        let path = project
-            .config
-            .cache
-            .local
-            .path
-            .clone()
-            .unwrap_or(project.get_project_bake_path().join("cache"));
+        let path = Self::path_from_config(&project);
         debug!("Local cache path: {}", path.display());
         Ok(Box::new(LocalCacheStrategy { path }))
     }
@@ -0,0 +1,114 @@
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Arc, LazyLock, RwLock},
+};
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use log::debug;
+
+use crate::{
+    cache::{CacheResultData, ARCHIVE_EXTENSION},
+    project::BakeProject,
+};
+
+use super::{CacheResult, CacheStrategy};
+
+/// Archives keyed by cache key
+type MemoryEntries = Arc<RwLock<HashMap<String, Vec<u8>>>>;
+
+/// Archives stored by `cache.local.backend: memory`, kept for the lifetime of the process so
+/// that they're shared by every bake it runs, eg. in watch mode
+static PROCESS_ENTRIES: LazyLock<MemoryEntries> = LazyLock::new(Default::default);
+
+/// Local cache keeping archives in memory instead of on disk, for tests and ephemeral runs
+/// where entries don't need to outlive the process
+///
+/// Hits are written to the temp dir to be unpacked, like the archives downloaded by remotes.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryCacheStrategy {
+    entries: MemoryEntries,
+}
+
+#[async_trait]
+impl CacheStrategy for MemoryCacheStrategy {
+    async fn get(&self, key: &str) -> anyhow::Result<CacheResult> {
+        let entries = self.entries.read().unwrap();
+        let Some(contents) = entries.get(key) else {
+            return Ok(CacheResult::Miss);
+        };
+        debug!("Cache hit for key {} in memory", key);
+        let archive_path = std::env::temp_dir().join(format!("{}.{}", key, ARCHIVE_EXTENSION));
+        std::fs::write(&archive_path, contents).map_err(|err| {
+            anyhow!(
+                "Failed to write archive to {}: {}",
+                archive_path.display(),
+                err
+            )
+        })?;
+        Ok(CacheResult::Hit(CacheResultData { archive_path }))
+    }
+
+    async fn put(&self, key: &str, archive_path: PathBuf) -> anyhow::Result<()> {
+        let contents = std::fs::read(&archive_path)
+            .map_err(|err| anyhow!("Failed to read archive {}: {}", archive_path.display(), err))?;
+        self.entries
+            .write()
+            .unwrap()
+            .insert(key.to_owned(), contents);
+        Ok(())
+    }
+
+    async fn delete(&self, key: &str) -> anyhow::Result<()> {
+        self.entries.write().unwrap().remove(key);
+        Ok(())
+    }
+
+    fn is_remote(&self) -> bool {
+        false
+    }
+
+    async fn from_config(_: Arc<BakeProject>) -> anyhow::Result<Box<dyn CacheStrategy>> {
+        debug!("Building in-memory local cache");
+        Ok(Box::new(MemoryCacheStrategy {
+            entries: PROCESS_ENTRIES.clone(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cache::{CacheResult, CacheStrategy, ARCHIVE_EXTENSION};
+
+    use super::MemoryCacheStrategy;
+
+    #[tokio::test]
+    async fn round_trip() {
+        let strategy = MemoryCacheStrategy::default();
+        let archive_path = std::env::temp_dir().join(format!("memory-put.{}", ARCHIVE_EXTENSION));
+        std::fs::write(&archive_path, "archive").unwrap();
+
+        assert_eq!(strategy.get("key").await.unwrap(), CacheResult::Miss);
+        strategy.put("key", archive_path.clone()).await.unwrap();
+        std::fs::remove_file(&archive_path).unwrap();
+
+        let CacheResult::Hit(data) = strategy.get("key").await.unwrap() else {
+            panic!("Expected a cache hit");
+        };
+        assert_eq!(
+            std::fs::read_to_string(data.archive_path).unwrap(),
+            "archive"
+        );
+
+        // Entries are only visible to strategies sharing the same store
+        assert_eq!(
+            MemoryCacheStrategy::default().get("key").await.unwrap(),
+            CacheResult::Miss
+        );
+        assert!(strategy.clone().check().await.is_ok());
+
+        strategy.delete("key").await.unwrap();
+        assert_eq!(strategy.get("key").await.unwrap(), CacheResult::Miss);
+    }
+}
@@ -5,14 +5,92 @@ use std::{fs::File, path::PathBuf};
 use anyhow::{anyhow, bail};
 use async_trait::async_trait;
 use aws_config::{meta::region::RegionProviderChain, BehaviorVersion, Region};
+use aws_credential_types::provider::{error::CredentialsError, future};
+use aws_sdk_s3::config::{Credentials, ProvideCredentials};
+use aws_sdk_s3::error::SdkError;
 use aws_sdk_s3::primitives::ByteStream;
 use aws_sdk_s3::Client;
-use log::{debug, warn};
+use log::debug;
+use serde::Deserialize;
 
-use crate::project::BakeProject;
+use crate::{error::BakeError, project::BakeProject};
 
+use super::credentials::{parse_expiration, CredentialsHelper};
 use super::{CacheResult, CacheResultData, CacheStrategy, ARCHIVE_EXTENSION};
 
+/// Credentials printed by an S3 `credentials_command`
+#[derive(Debug, Deserialize)]
+struct CommandCredentials {
+    access_key_id: String,
+    secret_access_key: String,
+    session_token: Option<String>,
+
+    /// RFC 3339 time after which the credentials are refreshed
+    expiration: Option<String>,
+}
+
+/// AWS credentials provider running a `credentials_command`
+///
+/// The SDK caches the returned credentials and asks for new ones once they expire.
+#[derive(Debug)]
+struct CommandCredentialsProvider {
+    helper: CredentialsHelper,
+}
+
+impl CommandCredentialsProvider {
+    async fn credentials(&self) -> anyhow::Result<Credentials> {
+        let credentials: CommandCredentials = self.helper.fetch().await?;
+        let expiration = credentials
+            .expiration
+            .as_deref()
+            .map(parse_expiration)
+            .transpose()?;
+        Ok(Credentials::new(
+            credentials.access_key_id,
+            credentials.secret_access_key,
+            credentials.session_token,
+            expiration,
+            "credentials_command",
+        ))
+    }
+}
+
+impl ProvideCredentials for CommandCredentialsProvider {
+    fn provide_credentials<'a>(&'a self) -> future::ProvideCredentials<'a>
+    where
+        Self: 'a,
+    {
+        future::ProvideCredentials::new(async {
+            self.credentials()
+                .await
+                .map_err(CredentialsError::provider_error)
+        })
+    }
+}
+
+/// Converts a failed S3 request to an error, marking timeouts, connection failures and server
+/// errors as transient so that they're retried
+///
+/// # Arguments
+/// * `err` - The request's error
+/// * `message` - Describes the failed request
+///
+fn request_error<E: std::fmt::Debug>(err: &SdkError<E>, message: String) -> anyhow::Error {
+    let transient = match err {
+        SdkError::TimeoutError(_) => true,
+        SdkError::DispatchFailure(failure) => failure.is_timeout() || failure.is_io(),
+        _ => err
+            .raw_response()
+            .is_some_and(|response| response.status().is_server_error()),
+    };
+    let message = format!("{message}: {err:?}");
+    if transient {
+        BakeError::TransientCacheError(message).into()
+    } else {
+        anyhow!(message)
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct S3CacheStrategy {
     pub bucket: String,
@@ -22,16 +100,14 @@ pub struct S3CacheStrategy {
 
 #[async_trait]
 impl CacheStrategy for S3CacheStrategy {
-    async fn get(&self, key: &str) -> CacheResult {
+    async fn get(&self, key: &str) -> anyhow::Result<CacheResult> {
         let file_name = format!("{}.{}", key, ARCHIVE_EXTENSION);
         // Try to get file with key from bucket
         let archive_path = std::env::temp_dir().join(&file_name);
-        let file = File::create(archive_path.clone());
-
-        if file.is_err() {
-            warn!("Failed to create file in temp dir: {}", file.unwrap_err());
-            return CacheResult::Miss;
-        }
+        let mut file = match File::create(archive_path.clone()) {
+            Ok(file) => file,
+            Err(err) => bail!("Failed to create file in temp dir: {}", err),
+        };
 
         match self
             .client
@@ -46,27 +122,36 @@ impl CacheStrategy for S3CacheStrategy {
                     Ok(Some(bytes)) => Some(bytes),
                     Ok(None) => None,
                     Err(err) => {
-                        warn!("Failed to read object body with key {file_name}: {err:?}");
-                        return CacheResult::Miss;
+                        // The connection dropped while downloading, which is worth retrying
+                        bail!(BakeError::TransientCacheError(format!(
+                            "Failed to read object body with key {file_name}: {err:?}"
+                        )));
                     }
                 } {
-                    let mut file = file.as_ref().unwrap();
                     if file.write_all(&bytes).is_err() {
-                        warn!(
+                        bail!(
                             "Failed to write to file in temp dir: {}",
                             archive_path.display()
                         );
-                        return CacheResult::Miss;
                     };
                 }
 
-                return CacheResult::Hit(CacheResultData { archive_path });
+                Ok(CacheResult::Hit(CacheResultData { archive_path }))
             }
             Err(err) => {
-                debug!("Failed to get object with key {file_name}: {err:?}");
-                return CacheResult::Miss;
+                if err
+                    .as_service_error()
+                    .is_some_and(|service_err| service_err.is_no_such_key())
+                {
+                    debug!("Key {file_name} not found in S3");
+                    return Ok(CacheResult::Miss);
+                }
+                return Err(request_error(
+                    &err,
+                    format!("Failed to get object with key {file_name}"),
+                ));
             }
-        };
+        }
     }
     async fn put(&self, key: &str, archive_path: PathBuf) -> anyhow::Result<()> {
         let file_name = format!("{key}.{ARCHIVE_EXTENSION}");
@@ -82,9 +167,26 @@ impl CacheStrategy for S3CacheStrategy {
             .await;
 
         match output {
+            Ok(_) => Ok(()),
+            Err(err) => Err(request_error(
+                &err,
+                format!("Failed to put object with key {file_name}"),
+            )),
+        }
+    }
+    async fn delete(&self, key: &str) -> anyhow::Result<()> {
+        let file_name = format!("{key}.{ARCHIVE_EXTENSION}");
+        match self
+            .client
+            .delete_object()
+            .bucket(&self.bucket)
+            .key(&file_name)
+            .send()
+            .await
+        {
             Ok(_) => Ok(()),
             Err(err) => Err(anyhow!(
-                "Failed to put object with key {file_name}: {err:?}"
+                "Failed to delete object with key {file_name}: {err:?}"
             )),
         }
     }
@@ -95,10 +197,14 @@ impl CacheStrategy for S3CacheStrategy {
                     RegionProviderChain::first_try(s3.region.clone().map(Region::new))
                         .or_default_provider()
                         .or_else("us-east-1");
-                let aws_config = aws_config::defaults(BehaviorVersion::latest())
-                    .region(region_provider)
-                    .load()
-                    .await;
+                let mut loader =
+                    aws_config::defaults(BehaviorVersion::latest()).region(region_provider);
+                if let Some(command) = &s3.credentials_command {
+                    loader = loader.credentials_provider(CommandCredentialsProvider {
+                        helper: CredentialsHelper::new(command.clone(), config.root_path.clone()),
+                    });
+                }
+                let aws_config = loader.load().await;
                 return Ok(Box::new(Self {
                     bucket: s3.bucket.clone(),
                     region: s3.region.clone(),
@@ -110,3 +216,29 @@ impl CacheStrategy for S3CacheStrategy {
         bail!("Failed to create S3 Cache Strategy")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use aws_sdk_s3::config::ProvideCredentials;
+
+    use crate::cache::credentials::CredentialsHelper;
+
+    use super::CommandCredentialsProvider;
+
+    #[tokio::test]
+    async fn command_credentials() {
+        let provider = CommandCredentialsProvider {
+            helper: CredentialsHelper::new(
+                String::from(
+                    r#"echo '{"access_key_id": "AKID", "secret_access_key": "SECRET", "session_token": "TOKEN", "expiration": "2100-01-01T00:00:00Z"}'"#,
+                ),
+                std::env::temp_dir(),
+            ),
+        };
+        let credentials = provider.provide_credentials().await.unwrap();
+        assert_eq!(credentials.access_key_id(), "AKID");
+        assert_eq!(credentials.secret_access_key(), "SECRET");
+        assert_eq!(credentials.session_token(), Some("TOKEN"));
+        assert!(credentials.expiry().is_some());
+    }
+}
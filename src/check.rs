@@ -0,0 +1,261 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    path::{Path, PathBuf},
+};
+
+use anyhow::bail;
+use log::{debug, warn};
+use rand::distributions::{Alphanumeric, DistString};
+
+use crate::{
+    baker::{outputs::remove_path, run_recipe},
+    cache::{archive_file_hashes, local::LocalCacheStrategy, ARCHIVE_EXTENSION},
+    project::{BakeProject, RecipeSearch},
+};
+
+/// Outcome of re-running a cached recipe to verify its outputs
+#[derive(Debug, PartialEq, Eq)]
+pub enum CheckStatus {
+    /// Fresh outputs are identical to the cached ones
+    Deterministic,
+
+    /// Output files whose contents differ from the cache, or that only exist on one side
+    Mismatch(Vec<PathBuf>),
+
+    /// The recipe could not be re-run
+    Failed(String),
+}
+
+/// Re-runs the recipes matching a search that have an entry in the local cache and compares their
+/// fresh outputs against the cached ones
+///
+/// Recipes run in a temporary copy of the project so real outputs are left untouched. Commands
+/// referencing absolute paths such as `{{ project.root }}` still reach the real project.
+/// Dependencies aren't re-run, their current outputs are copied along with the project.
+///
+/// # Arguments
+/// * `project` - The project to check
+/// * `hashes` - Map of recipe names to their cache keys
+/// * `search` - Criteria used to select the recipes to check
+///
+pub async fn check_recipes(
+    project: &BakeProject,
+    hashes: &HashMap<String, String>,
+    search: &RecipeSearch,
+) -> anyhow::Result<BTreeMap<String, CheckStatus>> {
+    let cache_path = LocalCacheStrategy::path_from_config(project);
+    let mut results = BTreeMap::new();
+
+    for (name, recipe) in project
+        .recipes
+        .iter()
+        .filter(|(name, recipe)| search.matches(name) && project.is_cached(recipe))
+    {
+        let Some(key) = hashes.get(name) else {
+            continue;
+        };
+        let archive_path = cache_path.join(format!("{}.{}", key, ARCHIVE_EXTENSION));
+        if !archive_path.is_file() {
+            debug!("No cache entry to check for recipe {}", name);
+            continue;
+        }
+        let cached: BTreeMap<PathBuf, String> = archive_file_hashes(&archive_path)?
+            .into_iter()
+            .filter(|(path, _)| !path.starts_with(".bake"))
+            .collect();
+
+        // Removed at the end of the iteration, including on early returns
+        let sandbox_dir = Sandbox::new();
+        let sandbox = &sandbox_dir.path;
+        copy_project(&project.root_path, sandbox)?;
+
+        let mut sandboxed_recipe = recipe.clone();
+        sandboxed_recipe.config_path =
+            sandbox.join(recipe.config_path.strip_prefix(&project.root_path)?);
+        let log_path = sandbox.join(
+            project
+                .get_recipe_log_path(name)
+                .strip_prefix(&project.root_path)?,
+        );
+        std::fs::create_dir_all(log_path.parent().unwrap())?;
+        sandboxed_recipe.output_root = Some(sandbox.to_path_buf());
+
+        let status = match run_recipe(&sandboxed_recipe, log_path, &project.config).await {
+            Ok(_) => {
+                let mut fresh = BTreeMap::new();
+                for output in recipe.cache.iter().flat_map(|cache| &cache.outputs) {
+                    hash_files(sandbox, &sandboxed_recipe.output_path(output), &mut fresh)?;
+                }
+                let mismatched: Vec<PathBuf> = cached
+                    .keys()
+                    .chain(fresh.keys())
+                    .collect::<BTreeSet<_>>()
+                    .into_iter()
+                    .filter(|path| cached.get(*path) != fresh.get(*path))
+                    .cloned()
+                    .collect();
+                if mismatched.is_empty() {
+                    CheckStatus::Deterministic
+                } else {
+                    CheckStatus::Mismatch(mismatched)
+                }
+            }
+            Err(err) => CheckStatus::Failed(err.to_string()),
+        };
+
+        results.insert(name.clone(), status);
+    }
+
+    Ok(results)
+}
+
+/// Temporary directory a recipe is checked in, removed when dropped so that it doesn't outlive
+/// a failed check
+struct Sandbox {
+    path: PathBuf,
+}
+
+impl Sandbox {
+    fn new() -> Self {
+        Self {
+            path: std::env::temp_dir().join(format!(
+                "bake-check-{}",
+                Alphanumeric.sample_string(&mut rand::thread_rng(), 16)
+            )),
+        }
+    }
+}
+
+impl Drop for Sandbox {
+    fn drop(&mut self) {
+        if let Err(err) = remove_path(&self.path) {
+            warn!(
+                "Could not remove check sandbox {}: {}",
+                self.path.display(),
+                err
+            );
+        }
+    }
+}
+
+/// Copies a project to another directory, skipping its `.bake` directory and keeping symlinks
+fn copy_project(source: &Path, destination: &Path) -> anyhow::Result<()> {
+    std::fs::create_dir_all(destination)?;
+    for entry in std::fs::read_dir(source)? {
+        let entry = entry?;
+        if entry.file_name() == ".bake" {
+            continue;
+        }
+        copy_path(&entry.path(), &destination.join(entry.file_name()))?;
+    }
+    Ok(())
+}
+
+fn copy_path(source: &Path, destination: &Path) -> anyhow::Result<()> {
+    let metadata = source.symlink_metadata()?;
+    if metadata.is_symlink() {
+        copy_symlink(source, destination)?;
+    } else if metadata.is_dir() {
+        std::fs::create_dir_all(destination)?;
+        for entry in std::fs::read_dir(source)? {
+            let entry = entry?;
+            copy_path(&entry.path(), &destination.join(entry.file_name()))?;
+        }
+    } else if let Err(err) = std::fs::copy(source, destination) {
+        bail!("Could not copy {}: {}", source.display(), err);
+    }
+    Ok(())
+}
+
+/// Creates a symlink at `destination` pointing where the one at `source` points
+#[cfg(unix)]
+fn copy_symlink(source: &Path, destination: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(std::fs::read_link(source)?, destination)
+}
+
+/// Creates a symlink at `destination` pointing where the one at `source` points. Windows has
+/// separate links to files and directories, so the link's kind follows its target
+#[cfg(windows)]
+fn copy_symlink(source: &Path, destination: &Path) -> std::io::Result<()> {
+    let target = std::fs::read_link(source)?;
+    if source.is_dir() {
+        std::os::windows::fs::symlink_dir(target, destination)
+    } else {
+        std::os::windows::fs::symlink_file(target, destination)
+    }
+}
+
+/// Adds the blake3 hashes of the regular files under a path, keyed by their path relative to root
+fn hash_files(
+    root: &Path,
+    path: &Path,
+    hashes: &mut BTreeMap<PathBuf, String>,
+) -> anyhow::Result<()> {
+    let Ok(metadata) = path.symlink_metadata() else {
+        return Ok(());
+    };
+    if metadata.is_dir() {
+        for entry in std::fs::read_dir(path)? {
+            hash_files(root, &entry?.path(), hashes)?;
+        }
+    } else if metadata.is_file() {
+        let mut hasher = blake3::Hasher::new();
+        std::io::copy(&mut std::fs::File::open(path)?, &mut hasher)?;
+        hashes.insert(
+            path.strip_prefix(root)?.to_path_buf(),
+            hasher.finalize().to_hex().to_string(),
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::PathBuf, sync::Arc};
+
+    use crate::{
+        cache::{local::LocalCacheStrategy, CacheBuilder, CacheStrategy},
+        project::RecipeSearch,
+        test_utils::TestProjectBuilder,
+    };
+
+    use super::CheckStatus;
+
+    #[tokio::test]
+    async fn check_recipes() {
+        let mut project = TestProjectBuilder::new()
+            .with_cookbook("check", &["stable", "clock"])
+            .with_output("check:stable", "echo same", "stable.txt")
+            .with_output("check:clock", "date +%s%N", "clock.txt")
+            .build();
+        project.config.max_parallel = 2;
+        let project = Arc::new(project);
+        let search = RecipeSearch::ByPattern("check:".to_owned());
+
+        let mut cache_builder = CacheBuilder::new(project.clone());
+        cache_builder
+            .add_strategy("local", LocalCacheStrategy::from_config)
+            .search(search.clone());
+        let cache = cache_builder.build().await.unwrap();
+        crate::baker::bake(project.clone(), cache, &search)
+            .await
+            .unwrap();
+        let clock_output = std::fs::read(project.root_path.join("clock.txt")).unwrap();
+
+        let hashes = cache_builder.calculate_all_hashes().unwrap();
+        let results = super::check_recipes(&project, &hashes, &search)
+            .await
+            .unwrap();
+        assert_eq!(results["check:stable"], CheckStatus::Deterministic);
+        assert_eq!(
+            results["check:clock"],
+            CheckStatus::Mismatch(vec![PathBuf::from("clock.txt")])
+        );
+
+        // Real outputs are untouched
+        assert_eq!(
+            std::fs::read(project.root_path.join("clock.txt")).unwrap(),
+            clock_output
+        );
+    }
+}
@@ -0,0 +1,189 @@
+use std::path::PathBuf;
+
+use anyhow::bail;
+use console::Term;
+
+use crate::{
+    baker::outputs::remove_path,
+    cache::local::LocalCacheStrategy,
+    project::{BakeProject, RecipeSearch},
+};
+
+/// Returns the existing declared outputs and logs of the recipes matching a search, which
+/// `--clean` removes
+///
+/// Only recipes matched directly by the search are cleaned, their dependencies are left
+/// untouched.
+///
+/// # Arguments
+/// * `project` - The project to clean
+/// * `search` - Criteria used to select the recipes
+///
+pub fn recipe_paths(project: &BakeProject, search: &RecipeSearch) -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+    for (name, recipe) in project
+        .recipes
+        .iter()
+        .filter(|(name, _)| search.matches(name))
+    {
+        if let Some(cache) = recipe.cache.as_ref() {
+            paths.extend(
+                cache
+                    .outputs
+                    .iter()
+                    .map(|output| recipe.output_path(output)),
+            );
+        }
+        paths.push(project.get_recipe_log_path(name));
+    }
+    existing(paths)
+}
+
+/// Returns the existing local cache, logs and output backups of a project, which `--clean-all`
+/// removes
+///
+/// The project lock file is kept so that cleaning can run while holding it. A local cache outside
+/// of the project's `.bake` directory is kept too since other projects may share it.
+///
+/// # Arguments
+/// * `project` - The project to clean
+///
+pub fn all_paths(project: &BakeProject) -> Vec<PathBuf> {
+    let mut paths = vec![
+        project.get_project_log_path(),
+        project.get_project_bake_path().join("backup"),
+    ];
+    if !LocalCacheStrategy::is_shared(project) {
+        paths.insert(0, LocalCacheStrategy::path_from_config(project));
+    }
+    existing(paths)
+}
+
+fn existing(paths: Vec<PathBuf>) -> Vec<PathBuf> {
+    paths
+        .into_iter()
+        .filter(|path| path.symlink_metadata().is_ok())
+        .collect()
+}
+
+/// Lists the paths about to be removed and asks for confirmation, unless `yes` is set
+///
+/// Fails without asking when stdin isn't a terminal, so scripts never delete files unless they
+/// pass `--yes`. Returns whether the paths can be removed.
+///
+/// # Arguments
+/// * `paths` - Paths that will be removed
+/// * `yes` - Whether removal was confirmed upfront with `--yes`
+/// * `interactive` - Whether stdin is a terminal the user can answer from
+///
+pub fn confirm_removal(paths: &[PathBuf], yes: bool, interactive: bool) -> anyhow::Result<bool> {
+    if yes || paths.is_empty() {
+        return Ok(true);
+    }
+    if !interactive {
+        bail!(
+            "Refusing to remove {} paths without confirmation since stdin is not a terminal. \
+             Pass --yes to remove them",
+            paths.len()
+        );
+    }
+
+    let term = Term::stdout();
+    term.write_line("The following paths will be removed:")?;
+    for path in paths {
+        term.write_line(&format!("  {}", path.display()))?;
+    }
+    term.write_str("Continue? [y/N] ")?;
+    let answer = term.read_line()?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// Removes the given paths, returning them
+pub fn remove_paths(paths: Vec<PathBuf>) -> anyhow::Result<Vec<PathBuf>> {
+    for path in &paths {
+        if let Err(err) = remove_path(path) {
+            bail!("Could not remove {}: {}", path.display(), err);
+        }
+    }
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        project::{RecipeCacheConfig, RecipeSearch},
+        test_utils::TestProjectBuilder,
+    };
+
+    #[test]
+    fn clean_recipes() {
+        let mut project = TestProjectBuilder::new()
+            .with_cookbook("foo", &["app", "lib"])
+            .with_dependency("foo:app", "foo:lib")
+            .build();
+        for (name, output) in [("foo:app", "app-dist"), ("foo:lib", "lib-dist")] {
+            project.recipes.get_mut(name).unwrap().cache = Some(RecipeCacheConfig {
+                outputs: vec![output.into()],
+                ..Default::default()
+            });
+            std::fs::create_dir_all(project.root_path.join(output).join("nested")).unwrap();
+        }
+        project.create_project_bake_dirs().unwrap();
+        std::fs::write(project.get_recipe_log_path("foo:app"), "app").unwrap();
+        std::fs::write(project.get_recipe_log_path("foo:lib"), "lib").unwrap();
+
+        let paths = super::recipe_paths(&project, &RecipeSearch::ByPattern("foo:app".to_owned()));
+        let removed = super::remove_paths(paths).unwrap();
+        assert_eq!(
+            removed,
+            vec![
+                project.root_path.join("app-dist"),
+                project.get_recipe_log_path("foo:app")
+            ]
+        );
+        assert!(!project.root_path.join("app-dist").exists());
+        assert!(project.root_path.join("lib-dist").exists());
+        assert!(project.get_recipe_log_path("foo:lib").exists());
+    }
+
+    #[test]
+    fn clean_all() {
+        let project = TestProjectBuilder::new()
+            .with_cookbook("foo", &["build"])
+            .build();
+        project.create_project_bake_dirs().unwrap();
+        let bake_path = project.get_project_bake_path();
+        std::fs::create_dir_all(bake_path.join("cache")).unwrap();
+        std::fs::write(bake_path.join("cache").join("key.tar.zst"), "cached").unwrap();
+        std::fs::write(project.get_recipe_log_path("foo:build"), "log").unwrap();
+        std::fs::write(bake_path.join("bake.lock"), "").unwrap();
+
+        let removed = super::remove_paths(super::all_paths(&project)).unwrap();
+        assert_eq!(
+            removed,
+            vec![bake_path.join("cache"), project.get_project_log_path()]
+        );
+        assert!(!bake_path.join("cache").exists());
+        assert!(!project.get_project_log_path().exists());
+        assert!(bake_path.join("bake.lock").exists());
+    }
+
+    #[test]
+    fn confirm_removal() {
+        let project = TestProjectBuilder::new()
+            .with_cookbook("foo", &["build"])
+            .build();
+        project.create_project_bake_dirs().unwrap();
+        std::fs::write(project.get_recipe_log_path("foo:build"), "log").unwrap();
+        let paths = super::recipe_paths(&project, &RecipeSearch::All);
+        assert_eq!(paths, vec![project.get_recipe_log_path("foo:build")]);
+
+        // Without a terminal to ask, nothing is removed unless confirmed upfront
+        let err = super::confirm_removal(&paths, false, false).unwrap_err();
+        assert!(err.to_string().contains("Pass --yes"));
+        assert!(project.get_recipe_log_path("foo:build").exists());
+
+        assert!(super::confirm_removal(&paths, true, false).unwrap());
+        assert!(super::confirm_removal(&[], false, false).unwrap());
+    }
+}
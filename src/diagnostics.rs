@@ -0,0 +1,114 @@
+use std::{fmt, path::PathBuf};
+
+use serde::Serialize;
+
+/// How important a diagnostic is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Info,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Info => write!(f, "{}", console::style("info").cyan()),
+            Self::Warning => write!(f, "{}", console::style("warning").yellow()),
+        }
+    }
+}
+
+/// A problem found in the project that doesn't prevent it from being baked
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Diagnostic {
+    pub severity: Severity,
+
+    /// Identifier of the kind of diagnostic, used to ignore it in `config.ignore_diagnostics`
+    pub code: &'static str,
+
+    pub message: String,
+
+    /// File the diagnostic refers to
+    pub source: Option<PathBuf>,
+}
+
+/// Output format of collected diagnostics
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum DiagnosticsFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+/// Collects diagnostics while loading a project so they can be printed together
+#[derive(Debug, Default)]
+pub struct Diagnostics {
+    ignored: Vec<String>,
+    entries: Vec<Diagnostic>,
+}
+
+impl Diagnostics {
+    /// Creates a collector that drops diagnostics whose code is in `ignored`
+    pub fn new(ignored: &[String]) -> Self {
+        Self {
+            ignored: ignored.to_vec(),
+            entries: Vec::new(),
+        }
+    }
+
+    /// Adds a diagnostic unless its code is ignored
+    ///
+    /// # Arguments
+    /// * `severity` - How important the diagnostic is
+    /// * `code` - Identifier of the kind of diagnostic
+    /// * `message` - Description of the problem
+    /// * `source` - File the diagnostic refers to
+    ///
+    pub fn push(
+        &mut self,
+        severity: Severity,
+        code: &'static str,
+        message: String,
+        source: Option<PathBuf>,
+    ) {
+        if self.ignored.iter().any(|ignored| ignored == code) {
+            return;
+        }
+        self.entries.push(Diagnostic {
+            severity,
+            code,
+            message,
+            source,
+        });
+    }
+
+    pub fn entries(&self) -> &[Diagnostic] {
+        &self.entries
+    }
+
+    /// Prints all diagnostics to stderr, one per line
+    pub fn print(&self, format: DiagnosticsFormat) {
+        for diagnostic in &self.entries {
+            match format {
+                DiagnosticsFormat::Text => match diagnostic.source.as_ref() {
+                    Some(source) => eprintln!(
+                        "{}[{}]: {} ({})",
+                        diagnostic.severity,
+                        diagnostic.code,
+                        diagnostic.message,
+                        source.display()
+                    ),
+                    None => eprintln!(
+                        "{}[{}]: {}",
+                        diagnostic.severity, diagnostic.code, diagnostic.message
+                    ),
+                },
+                DiagnosticsFormat::Json => match serde_json::to_string(diagnostic) {
+                    Ok(json) => eprintln!("{}", json),
+                    Err(err) => eprintln!("Could not serialize diagnostic: {}", err),
+                },
+            }
+        }
+    }
+}
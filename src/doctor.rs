@@ -0,0 +1,192 @@
+use std::{path::Path, sync::Arc};
+
+use indexmap::IndexMap;
+
+use crate::{
+    cache::{gcs::GcsCacheStrategy, s3::S3CacheStrategy, CacheStrategy},
+    project::BakeProject,
+};
+
+/// Key looked up in remote caches to check they can be reached
+const PROBE_KEY: &str = "bake-doctor-probe";
+
+/// Outcome of a single doctor check
+#[derive(Debug, PartialEq, Eq)]
+pub enum DoctorStatus {
+    Pass,
+    Warn(String),
+    Fail(String),
+}
+
+/// A named check performed by `--doctor`
+#[derive(Debug)]
+pub struct DoctorCheck {
+    pub name: String,
+    pub status: DoctorStatus,
+}
+
+impl DoctorCheck {
+    fn new(name: &str, status: DoctorStatus) -> Self {
+        Self {
+            name: name.to_owned(),
+            status,
+        }
+    }
+}
+
+/// Checks that a project can be baked on this machine, to help diagnose setup problems
+///
+/// Checks after loading the config are skipped when it can't be loaded.
+///
+/// # Arguments
+/// * `bake_path` - Path to the config file or a directory containing it
+/// * `override_variables` - Variables overriding those declared in the project
+/// * `offline` - Skip checks that need network access, such as reaching remote caches
+///
+pub async fn run_checks(
+    bake_path: &Path,
+    override_variables: IndexMap<String, String>,
+    offline: bool,
+) -> Vec<DoctorCheck> {
+    let mut checks = Vec::new();
+
+    let project = match BakeProject::from(bake_path, override_variables) {
+        Ok(project) => Arc::new(project),
+        Err(err) => {
+            checks.push(DoctorCheck::new(
+                "Config is valid",
+                DoctorStatus::Fail(err.to_string()),
+            ));
+            return checks;
+        }
+    };
+    checks.push(DoctorCheck::new("Config is valid", DoctorStatus::Pass));
+
+    let warnings = project.diagnostics.entries().len();
+    checks.push(DoctorCheck::new(
+        "Config has no warnings",
+        if warnings == 0 {
+            DoctorStatus::Pass
+        } else {
+            DoctorStatus::Warn(format!(
+                "{} warnings, bake the project to see them",
+                warnings
+            ))
+        },
+    ));
+
+    checks.push(DoctorCheck::new(
+        ".bake is writable",
+        check_writable(&project),
+    ));
+    checks.push(DoctorCheck::new("Shell is available", check_shell().await));
+
+    if let Some(remotes) = project.config.cache.remotes.as_ref() {
+        if remotes.s3.is_some() {
+            let status = check_remote(project.clone(), S3CacheStrategy::from_config, offline).await;
+            checks.push(DoctorCheck::new("S3 cache is reachable", status));
+        }
+        if remotes.gcs.is_some() {
+            let status =
+                check_remote(project.clone(), GcsCacheStrategy::from_config, offline).await;
+            checks.push(DoctorCheck::new("GCS cache is reachable", status));
+        }
+    }
+
+    checks
+}
+
+fn check_writable(project: &BakeProject) -> DoctorStatus {
+    if let Err(err) = project.create_project_bake_dirs() {
+        return DoctorStatus::Fail(err.to_string());
+    }
+    let probe_path = project.get_project_bake_path().join(PROBE_KEY);
+    match std::fs::write(&probe_path, "") {
+        Ok(_) => {
+            let _ = std::fs::remove_file(&probe_path);
+            DoctorStatus::Pass
+        }
+        Err(err) => {
+            DoctorStatus::Fail(format!("Could not write {}: {}", probe_path.display(), err))
+        }
+    }
+}
+
+/// Recipes run with `sh -c`, so it must be in the PATH
+async fn check_shell() -> DoctorStatus {
+    match tokio::process::Command::new("sh")
+        .arg("-c")
+        .arg("true")
+        .status()
+        .await
+    {
+        Ok(status) if status.success() => DoctorStatus::Pass,
+        Ok(status) => DoctorStatus::Fail(format!("sh exited with {}", status)),
+        Err(err) => DoctorStatus::Fail(format!("Could not run sh: {}", err)),
+    }
+}
+
+async fn check_remote<F, Fut>(
+    project: Arc<BakeProject>,
+    from_config: F,
+    offline: bool,
+) -> DoctorStatus
+where
+    F: FnOnce(Arc<BakeProject>) -> Fut,
+    Fut: std::future::Future<Output = anyhow::Result<Box<dyn CacheStrategy>>>,
+{
+    if offline {
+        return DoctorStatus::Warn(String::from("Skipped in offline mode"));
+    }
+    let strategy = match from_config(project).await {
+        Ok(strategy) => strategy,
+        Err(err) => return DoctorStatus::Fail(err.to_string()),
+    };
+    // A miss is fine, only errors mean the cache can't be used
+    match strategy.get(PROBE_KEY).await {
+        Ok(_) => DoctorStatus::Pass,
+        Err(err) => DoctorStatus::Fail(err.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use indexmap::IndexMap;
+
+    use crate::test_utils::TestProjectBuilder;
+
+    use super::DoctorStatus;
+
+    #[tokio::test]
+    async fn run_checks() {
+        let root_path = TestProjectBuilder::temp_dir(&[]);
+        let checks = super::run_checks(&root_path, IndexMap::new(), true).await;
+        assert_eq!(checks.len(), 1);
+        assert!(matches!(checks[0].status, DoctorStatus::Fail(_)));
+
+        std::fs::write(
+            root_path.join("bake.yml"),
+            "name: doctor\nconfig:\n  cache:\n    remotes:\n      s3:\n        bucket: test\n",
+        )
+        .unwrap();
+        let checks = super::run_checks(&root_path, IndexMap::new(), true).await;
+        let statuses: Vec<(&str, &DoctorStatus)> = checks
+            .iter()
+            .map(|check| (check.name.as_str(), &check.status))
+            .collect();
+        assert_eq!(
+            statuses,
+            vec![
+                ("Config is valid", &DoctorStatus::Pass),
+                ("Config has no warnings", &DoctorStatus::Pass),
+                (".bake is writable", &DoctorStatus::Pass),
+                ("Shell is available", &DoctorStatus::Pass),
+                (
+                    "S3 cache is reachable",
+                    &DoctorStatus::Warn(String::from("Skipped in offline mode"))
+                ),
+            ]
+        );
+        assert!(!root_path.join(".bake").join(super::PROBE_KEY).exists());
+    }
+}
@@ -0,0 +1,104 @@
+use std::{fmt, path::PathBuf};
+
+/// Errors raised while loading and baking a project
+///
+/// Core functions return them wrapped in `anyhow::Error`, so callers can inspect the failure mode
+/// with `err.downcast_ref::<BakeError>()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BakeError {
+    /// No bake.yml file could be found for the given path
+    ConfigNotFound { path: PathBuf },
+
+    /// A project or cookbook file could not be parsed
+    ParseError { path: PathBuf, message: String },
+
+    /// The project config is well formed but has invalid values
+    ValidationError(String),
+
+    /// Recipes depend on each other in a cycle, each entry is one cycle of recipe names
+    CyclicDependency(Vec<Vec<String>>),
+
+    /// Recipes depend on recipes that don't exist, grouped by cookbook file
+    MissingDependency(Vec<(PathBuf, Vec<String>)>),
+
+    /// A cache strategy failed
+    CacheError(String),
+
+    /// A remote cache request failed in a way that may succeed when retried, such as a timeout
+    /// or a server error
+    TransientCacheError(String),
+
+    /// A recipe's process exited unsuccessfully, `code` is None if it was killed by a signal
+    RecipeFailed { fqn: String, code: Option<i32> },
+
+    /// One or more recipes failed while baking, with the exit codes of their processes
+    RecipesFailed(Vec<(String, Option<i32>)>),
+}
+
+impl BakeError {
+    /// Returns the status bake exits with when failing with this error
+    ///
+    /// When recipes fail, it's the exit code of the first failed recipe by fully qualified name,
+    /// or 1 if that recipe failed without one, eg. because it timed out or was killed by a
+    /// signal. Any other error exits with 1.
+    pub fn exit_code(&self) -> u8 {
+        let code = match self {
+            Self::RecipeFailed { code, .. } => *code,
+            Self::RecipesFailed(recipes) => recipes.first().and_then(|(_, code)| *code),
+            _ => None,
+        };
+        code.and_then(|code| u8::try_from(code).ok())
+            .filter(|code| *code != 0)
+            .unwrap_or(1)
+    }
+}
+
+impl fmt::Display for BakeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ConfigNotFound { path } => {
+                write!(f, "Could not find bake.yml in {}", path.display())
+            }
+            Self::ParseError { path, message } => {
+                write!(f, "Could not parse {}: {}", path.display(), message)
+            }
+            Self::ValidationError(message) => write!(f, "Invalid config: {}", message),
+            Self::CyclicDependency(cycles) => {
+                write!(f, "Circular dependencies detected:")?;
+                cycles
+                    .iter()
+                    .try_for_each(|cycle| write!(f, "\n{}", cycle.join(" => ")))
+            }
+            Self::MissingDependency(missing) => {
+                writeln!(
+                    f,
+                    "{}:",
+                    console::style("Recipe dependencies not found").bold()
+                )?;
+                missing.iter().try_for_each(|(path, deps)| {
+                    writeln!(f, "{} {}:", console::Emoji("📖", "in"), path.display())?;
+                    deps.iter().try_for_each(|dep| writeln!(f, "\t- {}", dep))
+                })
+            }
+            Self::CacheError(message) | Self::TransientCacheError(message) => {
+                write!(f, "{}", message)
+            }
+            Self::RecipeFailed {
+                fqn,
+                code: Some(code),
+            } => write!(f, "Recipe {} failed with exit code {}", fqn, code),
+            Self::RecipeFailed { fqn, code: None } => {
+                write!(f, "Recipe {} was terminated by a signal", fqn)
+            }
+            Self::RecipesFailed(recipes) => {
+                let failed = console::style("✗").red();
+                write!(f, "Some recipes failed to run: ")?;
+                recipes
+                    .iter()
+                    .try_for_each(|(recipe, _)| write!(f, "\n{} {}", failed, recipe))
+            }
+        }
+    }
+}
+
+impl std::error::Error for BakeError {}
@@ -0,0 +1,270 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::bail;
+use serde_yaml::{Mapping, Value};
+
+use crate::project::BakeProject;
+
+/// Order of the keys of a project config, other keys follow in their original order
+const PROJECT_KEYS: [&str; 8] = [
+    "name",
+    "description",
+    "variables",
+    "environment",
+    "aliases",
+    "overrides",
+    "remote_cookbooks",
+    "config",
+];
+
+/// Order of the keys of a cookbook
+const COOKBOOK_KEYS: [&str; 6] = [
+    "name",
+    "environment",
+    "variables",
+    "max_parallel",
+    "recipe_files",
+    "recipes",
+];
+
+/// Order of the keys of a recipe, with its command last since it's usually the longest
+const RECIPE_KEYS: [&str; 13] = [
+    "description",
+    "dependencies",
+    "environment",
+    "variables",
+    "platforms",
+    "requires_clean_git",
+    "weight",
+    "timeout",
+    "output",
+    "shell",
+    "cache",
+    "run_file",
+    "run",
+];
+
+/// Order of the keys of a recipe's cache configuration
+const CACHE_KEYS: [&str; 6] = [
+    "inputs",
+    "outputs",
+    "key_prefix",
+    "auto_inputs",
+    "archive",
+    "ttl",
+];
+
+/// Kind of config file, which decides the order of its keys
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigKind {
+    Project,
+    Cookbook,
+    Recipe,
+}
+
+/// Result of formatting a config file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FormatStatus {
+    /// The file was rewritten in canonical form
+    Formatted,
+
+    /// The file was already in canonical form
+    Unchanged,
+
+    /// The file has comments, which formatting would drop, so it was left as it is
+    SkippedComments,
+}
+
+/// Formats the config file and the cookbook and recipe files of a project in place
+///
+/// Cookbooks loaded from `remote_cookbooks` aren't formatted since they're not part of the
+/// project.
+///
+/// # Arguments
+/// * `project` - The project to format
+///
+pub fn format_project(project: &BakeProject) -> anyhow::Result<Vec<(PathBuf, FormatStatus)>> {
+    let mut files = vec![(project.config_path().to_path_buf(), ConfigKind::Project)];
+    for cookbook in project.cookbooks.values() {
+        if !cookbook.config_path.starts_with(&project.root_path)
+            || cookbook
+                .config_path
+                .starts_with(project.get_project_bake_path())
+        {
+            continue;
+        }
+        files.push((cookbook.config_path.clone(), ConfigKind::Cookbook));
+        let cookbook_path = cookbook.config_path.parent().unwrap();
+        files.extend(
+            cookbook
+                .recipe_files
+                .iter()
+                .map(|file| (cookbook_path.join(file), ConfigKind::Recipe)),
+        );
+    }
+
+    files
+        .into_iter()
+        .map(|(path, kind)| {
+            let status = format_file(&path, kind)?;
+            Ok((path, status))
+        })
+        .collect()
+}
+
+/// Formats a config file in place, unless it has comments
+///
+/// # Arguments
+/// * `path` - Path of the file
+/// * `kind` - Kind of config the file holds
+///
+pub fn format_file(path: &Path, kind: ConfigKind) -> anyhow::Result<FormatStatus> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(err) => bail!("Could not read {}: {}", path.display(), err),
+    };
+    if has_comments(&contents) {
+        return Ok(FormatStatus::SkippedComments);
+    }
+    let formatted = match format_str(&contents, kind) {
+        Ok(formatted) => formatted,
+        Err(err) => bail!("Could not format {}: {}", path.display(), err),
+    };
+    if formatted == contents {
+        return Ok(FormatStatus::Unchanged);
+    }
+    if let Err(err) = std::fs::write(path, formatted) {
+        bail!("Could not write {}: {}", path.display(), err);
+    }
+    Ok(FormatStatus::Formatted)
+}
+
+/// Returns a config in canonical form: known keys in a fixed order followed by unknown ones in
+/// their original order, two space indentation and no blank lines
+///
+/// # Arguments
+/// * `contents` - The YAML config
+/// * `kind` - Kind of config
+///
+pub fn format_str(contents: &str, kind: ConfigKind) -> anyhow::Result<String> {
+    let mut value: Value = serde_yaml::from_str(contents)?;
+    match kind {
+        ConfigKind::Project => reorder(&mut value, &PROJECT_KEYS),
+        ConfigKind::Cookbook => {
+            reorder(&mut value, &COOKBOOK_KEYS);
+            if let Some(Value::Mapping(recipes)) = value.get_mut("recipes") {
+                recipes.values_mut().for_each(reorder_recipe);
+            }
+        }
+        ConfigKind::Recipe => reorder_recipe(&mut value),
+    }
+    Ok(serde_yaml::to_string(&value)?)
+}
+
+fn reorder_recipe(recipe: &mut Value) {
+    reorder(recipe, &RECIPE_KEYS);
+    if let Some(cache) = recipe.get_mut("cache") {
+        reorder(cache, &CACHE_KEYS);
+    }
+}
+
+/// Sorts the keys of a mapping in the given order, leaving other values untouched
+fn reorder(value: &mut Value, order: &[&str]) {
+    let Value::Mapping(mapping) = value else {
+        return;
+    };
+    let mut rest = std::mem::take(mapping);
+    let mut sorted = Mapping::new();
+    for key in order {
+        if let Some(entry) = rest.remove(*key) {
+            sorted.insert(Value::from(*key), entry);
+        }
+    }
+    sorted.extend(rest);
+    *mapping = sorted;
+}
+
+/// Returns whether a YAML document seems to have comments. Errs on the side of finding them,
+/// eg. in a `#` following a space inside a string, since skipping a file is harmless
+fn has_comments(contents: &str) -> bool {
+    contents
+        .lines()
+        .any(|line| line.trim_start().starts_with('#') || line.contains(" #"))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_utils::TestProjectBuilder;
+
+    use super::{ConfigKind, FormatStatus};
+
+    const COOKBOOK: &str = r#"
+recipes:
+  build:
+    run: |
+      cargo build
+      cargo test
+    cache:
+      outputs: [target]
+      inputs:
+        - "src/**/*.rs"
+    dependencies: [lint]
+
+  lint:
+      run: cargo clippy
+name: app
+variables: { mode: release }
+"#;
+
+    #[test]
+    fn format_str() {
+        let formatted = super::format_str(COOKBOOK, ConfigKind::Cookbook).unwrap();
+        assert_eq!(
+            formatted,
+            "name: app
+variables:
+  mode: release
+recipes:
+  build:
+    dependencies:
+    - lint
+    cache:
+      inputs:
+      - src/**/*.rs
+      outputs:
+      - target
+    run: |
+      cargo build
+      cargo test
+  lint:
+    run: cargo clippy
+"
+        );
+        assert_eq!(
+            super::format_str(&formatted, ConfigKind::Cookbook).unwrap(),
+            formatted,
+            "formatting is idempotent"
+        );
+    }
+
+    #[test]
+    fn format_file() {
+        let path = TestProjectBuilder::temp_dir(&[("cookbook.yml", COOKBOOK)]).join("cookbook.yml");
+        assert_eq!(
+            super::format_file(&path, ConfigKind::Cookbook).unwrap(),
+            FormatStatus::Formatted
+        );
+        assert_eq!(
+            super::format_file(&path, ConfigKind::Cookbook).unwrap(),
+            FormatStatus::Unchanged
+        );
+
+        let commented = format!("# Application recipes\n{}", COOKBOOK);
+        std::fs::write(&path, &commented).unwrap();
+        assert_eq!(
+            super::format_file(&path, ConfigKind::Cookbook).unwrap(),
+            FormatStatus::SkippedComments
+        );
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), commented);
+    }
+}
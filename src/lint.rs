@@ -0,0 +1,138 @@
+use crate::{
+    diagnostics::{Diagnostics, Severity},
+    project::{config::Shell, BakeProject, RecipeSearch},
+};
+
+/// Checks the run commands of the selected recipes for patterns likely to swallow the exit code
+/// of a failing command
+///
+/// Recipes already run with `set -e`, but it doesn't catch failures masked by a pipe or
+/// explicitly ignored, so those recipes could succeed without doing their job. The checks are
+/// textual and only advisory, and recipes run with another shell than `sh` are skipped.
+///
+/// # Arguments
+/// * `project` - The project to lint
+/// * `search` - The recipes to lint
+///
+pub fn lint_recipes(project: &BakeProject, search: &RecipeSearch) -> Diagnostics {
+    let mut diagnostics = Diagnostics::new(&project.config.ignore_diagnostics);
+    for (fqn, recipe) in project.get_recipes(search) {
+        if project.config.shell(recipe.shell) != Shell::Sh {
+            continue;
+        }
+        let lines: Vec<&str> = recipe
+            .run
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .collect();
+
+        if !recipe.run.contains("pipefail") {
+            if let Some(line) = lines.iter().find(|line| has_pipe(line)) {
+                diagnostics.push(
+                    Severity::Warning,
+                    "run-unguarded-pipe",
+                    format!(
+                        "Recipe {} pipes `{}` without `set -o pipefail`, so only the last command's failure is caught. Consider starting it with `set -euo pipefail`",
+                        fqn, line
+                    ),
+                    Some(recipe.config_path.clone()),
+                );
+            }
+        }
+
+        if let Some(line) = lines.iter().find(|line| ignores_failure(line)) {
+            diagnostics.push(
+                Severity::Warning,
+                "run-ignores-failure",
+                format!(
+                    "Recipe {} ignores failures in `{}`, so it succeeds even if its commands fail. Consider removing it and starting the recipe with `set -euo pipefail`",
+                    fqn, line
+                ),
+                Some(recipe.config_path.clone()),
+            );
+        }
+    }
+    diagnostics
+}
+
+/// Returns whether a shell line has a pipe outside of quotes, not counting `||`
+fn has_pipe(line: &str) -> bool {
+    let chars: Vec<char> = line.chars().collect();
+    let mut quote = None;
+    for (i, &c) in chars.iter().enumerate() {
+        match (quote, c) {
+            (Some(q), _) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '\'' | '"') => quote = Some(c),
+            (None, '#') if i == 0 || chars[i - 1].is_whitespace() => return false,
+            (None, '|') => {
+                let doubled = chars.get(i + 1) == Some(&'|') || (i > 0 && chars[i - 1] == '|');
+                if !doubled {
+                    return true;
+                }
+            }
+            _ => {}
+        }
+    }
+    false
+}
+
+/// Returns whether a shell line ends with `|| true` or runs `exit 0` after another command
+fn ignores_failure(line: &str) -> bool {
+    let line = line.trim_end_matches(';').trim_end();
+    if let Some(rest) = line.strip_suffix("true").or_else(|| line.strip_suffix(':')) {
+        if rest.trim_end().ends_with("||") {
+            return true;
+        }
+    }
+    line.split(';')
+        .skip(1)
+        .any(|command| command.trim() == "exit 0")
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use crate::{
+        project::{config::Shell, RecipeSearch},
+        test_utils::TestProjectBuilder,
+    };
+
+    #[test_case("cargo test | tee test.log", &["run-unguarded-pipe"]; "pipe")]
+    #[test_case("set -euo pipefail\ncargo test | tee test.log", &[]; "guarded pipe")]
+    #[test_case("cargo test || echo failed", &[]; "or")]
+    #[test_case("echo 'a | b' # c | d", &[]; "quoted pipe")]
+    #[test_case("rm -r dist || true", &["run-ignores-failure"]; "or true")]
+    #[test_case("cargo test; exit 0", &["run-ignores-failure"]; "exit")]
+    #[test_case("grep foo log | wc -l || :", &["run-unguarded-pipe", "run-ignores-failure"]; "both")]
+    fn lint_recipes(run: &str, expected: &[&str]) {
+        lint_recipes_with_shell(run, None, expected);
+    }
+
+    #[test_case(Shell::Sh, &["run-unguarded-pipe"]; "sh")]
+    #[test_case(Shell::Cmd, &[]; "cmd")]
+    #[test_case(Shell::Powershell, &[]; "powershell")]
+    #[test_case(Shell::Pwsh, &[]; "pwsh")]
+    fn other_shells_skipped(shell: Shell, expected: &[&str]) {
+        lint_recipes_with_shell("Get-ChildItem | Remove-Item", Some(shell), expected);
+    }
+
+    fn lint_recipes_with_shell(run: &str, shell: Option<Shell>, expected: &[&str]) {
+        let mut project = TestProjectBuilder::new()
+            .with_cookbook("foo", &["build"])
+            .build();
+        let recipe = project.recipes.get_mut("foo:build").unwrap();
+        recipe.run = run.to_owned();
+        recipe.shell = shell;
+
+        let diagnostics = super::lint_recipes(&project, &RecipeSearch::All);
+        let codes: Vec<_> = diagnostics
+            .entries()
+            .iter()
+            .map(|diagnostic| diagnostic.code)
+            .collect();
+        assert_eq!(codes, expected);
+    }
+}
@@ -0,0 +1,100 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{Read, Seek, Write},
+    path::PathBuf,
+};
+
+use anyhow::bail;
+use log::debug;
+
+use crate::project::BakeProject;
+
+/// Advisory lock on `.bake/bake.lock` that prevents concurrent bakes in the same project from
+/// writing to the cache at the same time. The lock is released when dropped or when the process
+/// exits for any reason.
+#[derive(Debug)]
+pub struct ProjectLock {
+    file: File,
+    path: PathBuf,
+}
+
+impl ProjectLock {
+    /// Acquires the project lock, failing immediately if another process holds it
+    ///
+    /// # Arguments
+    /// * `project` - The project to lock
+    ///
+    pub fn acquire(project: &BakeProject) -> anyhow::Result<Self> {
+        project.create_project_bake_dirs()?;
+        let path = project.get_project_bake_path().join("bake.lock");
+        let mut file = match OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+        {
+            Ok(file) => file,
+            Err(err) => bail!("Could not open lock file {}: {}", path.display(), err),
+        };
+
+        if !try_lock(&file) {
+            let mut pid = String::new();
+            let _ = file.read_to_string(&mut pid);
+            bail!(
+                "Another bake is running in this project (pid {}). Use --no-lock to skip this check",
+                pid.trim()
+            );
+        }
+
+        file.set_len(0)?;
+        file.rewind()?;
+        write!(file, "{}", std::process::id())?;
+        debug!("Acquired project lock {}", path.display());
+
+        Ok(Self { file, path })
+    }
+}
+
+/// Takes an exclusive advisory lock on a file without waiting, returning whether it succeeded
+#[cfg(unix)]
+fn try_lock(file: &File) -> bool {
+    use std::os::fd::AsRawFd;
+
+    // SAFETY: the file descriptor is valid for as long as `file` is alive
+    unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) == 0 }
+}
+
+/// Advisory locks are only taken on unix, elsewhere concurrent bakes aren't detected
+#[cfg(not(unix))]
+fn try_lock(_file: &File) -> bool {
+    true
+}
+
+impl Drop for ProjectLock {
+    fn drop(&mut self) {
+        // Closing the file releases the lock, we only clear the pid
+        let _ = self.file.set_len(0);
+        debug!("Released project lock {}", self.path.display());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_utils::TestProjectBuilder;
+
+    use super::ProjectLock;
+
+    #[cfg(unix)]
+    #[test]
+    fn acquire() {
+        let project = TestProjectBuilder::new().build();
+
+        let lock = ProjectLock::acquire(&project).unwrap();
+        let err = ProjectLock::acquire(&project).unwrap_err().to_string();
+        assert!(err.contains(&format!("pid {}", std::process::id())));
+
+        drop(lock);
+        assert!(ProjectLock::acquire(&project).is_ok());
+    }
+}
@@ -1,22 +1,58 @@
 #![feature(coverage_attribute)]
 mod baker;
+mod build_info;
 mod cache;
+mod check;
+mod clean;
+mod diagnostics;
+mod doctor;
+mod error;
+mod fmt;
+mod lint;
+mod lock;
 mod project;
 mod template;
+mod touch;
+mod watch;
 
 #[cfg(test)]
 mod test_utils;
 
 use anyhow::bail;
 use indexmap::IndexMap;
-use project::BakeProject;
-use std::{path::PathBuf, sync::Arc};
+use project::{
+    config::{OutputCapture, ToolConfig},
+    BakeProject, PlanGroupBy, RecipeSearch,
+};
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{IsTerminal, Seek, SeekFrom, Write},
+    num::NonZeroUsize,
+    path::PathBuf,
+    process::ExitCode,
+    sync::Arc,
+    time::Duration,
+};
 
 use clap::Parser;
 use console::Term;
 use env_logger::Env;
 
-use crate::cache::CacheBuilder;
+use crate::baker::EnvChange;
+use crate::cache::{
+    builder::CacheKeyExplanation, list_local_entries, local_compression_stats, CacheBuilder,
+    CacheEntry, CompressionReport, CompressionStats,
+};
+use crate::check::CheckStatus;
+use crate::diagnostics::DiagnosticsFormat;
+use crate::doctor::{DoctorCheck, DoctorStatus};
+use crate::error::BakeError;
+use crate::fmt::FormatStatus;
+use crate::lock::ProjectLock;
+use crate::template::TRACE_VARS_TARGET;
+use crate::touch::TouchStatus;
+use crate::watch::WatchExec;
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 const WELCOME_MSG: &str = "
@@ -42,6 +78,25 @@ struct Args {
     /// :<recipe>            - for all recipes in all cookbooks{n}
     recipe: Option<String>,
 
+    /// Arguments after `--`, passed to the selected recipe's run command as its positional
+    /// parameters (`$@`) and expanded by `{{args}}`. Only allowed when a single recipe is selected
+    #[arg(last = true, value_name = "ARGS")]
+    recipe_args: Vec<String>,
+
+    /// Read fully qualified recipe names (eg. foo:build) to bake from stdin, one per line
+    #[arg(long, conflicts_with = "recipe")]
+    recipes_stdin: bool,
+
+    /// Select recipes from a file of patterns, one per line, with the same semantics as the
+    /// recipe argument. Lines starting with `-` exclude matching recipes and `#` starts a comment
+    #[arg(long, value_name = "PATH", conflicts_with = "recipes_stdin")]
+    select_file: Option<PathBuf>,
+
+    /// Remove recipes whose fully qualified name matches this regular expression from the
+    /// selection. Dependencies of the remaining recipes are still baked. Can be repeated
+    #[arg(long, value_name = "PATTERN")]
+    exclude: Vec<String>,
+
     /// Path fo config file or directory containing a bake.yml file
     #[arg(short, long)]
     path: Option<String>,
@@ -50,9 +105,237 @@ struct Args {
     #[arg(long, num_args = 1, value_name = "VAR>=<VALUE")]
     var: Vec<String>,
 
+    /// Pass the environment variables starting with this prefix as variable values, named
+    /// without the prefix (eg. `BAKE_VAR_foo=bar` sets `foo` with `BAKE_VAR_`). `--var` takes
+    /// precedence
+    #[arg(long, value_name = "PREFIX")]
+    env_from_shell: Option<String>,
+
+    /// Apply the settings of an environment declared in the project's `overrides`, such as its
+    /// cache configuration
+    #[arg(long, value_name = "NAME")]
+    env: Option<String>,
+
+    /// Print the configuration resulting from the project's config and command line flags as
+    /// YAML instead of baking
+    #[arg(long)]
+    print_config: bool,
+
     /// Skip using and saving to cache
     #[arg(long)]
     skip_cache: bool,
+
+    /// Use this directory as the local cache instead of the project's, eg. to share it between
+    /// projects on a CI agent. Defaults to the BAKE_CACHE_DIR environment variable
+    #[arg(long, value_name = "PATH")]
+    cache_dir: Option<PathBuf>,
+
+    /// Hash every input file instead of reusing the hashes of files that didn't change since the
+    /// last run
+    #[arg(long)]
+    no_hash_cache: bool,
+
+    /// Remove cached results of the selected recipes and bake them again, storing their new
+    /// outputs in the cache
+    #[arg(long, conflicts_with = "skip_cache")]
+    force_rebuild: bool,
+
+    /// Remove the declared outputs and logs of the selected recipes, without their dependencies,
+    /// instead of baking
+    #[arg(long, conflicts_with_all = ["list_cache", "force_rebuild"])]
+    clean: bool,
+
+    /// Remove the project's local cache and logs instead of baking
+    #[arg(long, conflicts_with_all = ["recipe", "recipes_stdin", "clean", "list_cache", "force_rebuild"])]
+    clean_all: bool,
+
+    /// Remove files with --clean or --clean-all without asking for confirmation. Required when
+    /// stdin isn't a terminal
+    #[arg(short, long)]
+    yes: bool,
+
+    /// Store the current outputs of the selected recipes in the cache without running them, so the
+    /// next bake is a cache hit. Useful after building manually
+    #[arg(long, conflicts_with_all = ["skip_cache", "force_rebuild", "list_cache", "clean", "clean_all", "watch"])]
+    touch: bool,
+
+    /// Re-run the selected recipes that have a local cache entry in a temporary copy of the
+    /// project and report those whose outputs differ from the cache
+    #[arg(long, conflicts_with_all = ["list_cache", "clean", "clean_all", "force_rebuild"])]
+    check: bool,
+
+    /// Rewrite bake.yml and the project's cookbook files in canonical form instead of baking. Files
+    /// with comments are left as they are since formatting would drop them
+    #[arg(long, conflicts_with_all = ["recipe", "recipes_stdin", "list_cache", "check", "clean", "clean_all", "touch"])]
+    fmt: bool,
+
+    /// Check the run commands of the selected recipes for patterns that hide failures, such as
+    /// pipes without `set -o pipefail` or a trailing `|| true`, instead of baking
+    #[arg(long, conflicts_with_all = ["list_cache", "check", "clean", "clean_all", "touch", "fmt", "watch"])]
+    lint: bool,
+
+    /// Keep baking the selected recipes whenever their inputs change
+    #[arg(long, conflicts_with_all = ["list_cache", "check", "clean", "clean_all"])]
+    watch: bool,
+
+    /// Shell command to run in the project root after each successful bake in watch mode. The
+    /// previous instance is killed if it's still running
+    #[arg(long, value_name = "CMD", requires = "watch")]
+    watch_exec: Option<String>,
+
+    /// Print the environment the given recipe (eg. foo:build) would run with instead of baking.
+    /// Values of variables that look like secrets are redacted
+    #[arg(long, value_name = "RECIPE", conflicts_with_all = ["list_cache", "check", "clean", "clean_all", "watch"])]
+    recipe_env_dump: Option<String>,
+
+    /// Print only the variables the given recipe's (eg. foo:build) environment adds, changes or
+    /// removes compared to bake's own environment instead of baking. Values of variables that
+    /// look like secrets are redacted
+    #[arg(long, value_name = "RECIPE", conflicts_with_all = ["list_cache", "check", "clean", "clean_all", "watch", "recipe_env_dump"])]
+    print_env_diff: Option<String>,
+
+    /// Print the components folded into the cache key of the given recipe (eg. foo:build), such
+    /// as the hashes of its input files, instead of baking
+    #[arg(long, value_name = "RECIPE", conflicts_with_all = ["list_cache", "check", "clean", "clean_all", "watch"])]
+    explain_cache_key: Option<String>,
+
+    /// Print the resolved definition of the given recipe (eg. foo:build) instead of baking
+    #[arg(long, value_name = "RECIPE", conflicts_with_all = ["list_cache", "check", "clean", "clean_all", "watch"])]
+    describe: Option<String>,
+
+    /// Sort the variables and environment entries printed by `--describe` alphabetically instead
+    /// of in declaration order, so the output is stable for snapshot tests
+    #[arg(long, requires = "describe")]
+    sorted: bool,
+
+    /// Print the log of the last run of the given recipe (eg. foo:build) instead of baking
+    #[arg(long, value_name = "RECIPE", conflicts_with_all = ["list_cache", "check", "clean", "clean_all", "watch", "describe"])]
+    log: Option<String>,
+
+    /// Keep printing what's appended to the log given to `--log`, eg. while another bake runs the
+    /// recipe, until interrupted
+    #[arg(long, requires = "log")]
+    follow: bool,
+
+    /// Print only the fully rendered run command of the given recipe (eg. foo:build) instead of
+    /// baking, to reproduce it manually or pipe it into a shell
+    #[arg(long, value_name = "RECIPE", conflicts_with_all = ["list_cache", "check", "clean", "clean_all", "watch", "describe"])]
+    print_run: Option<String>,
+
+    /// Check that the project can be baked on this machine and print a checklist instead of
+    /// baking
+    #[arg(long, conflicts_with_all = ["recipe", "recipes_stdin", "list_cache", "check", "clean", "clean_all", "watch"])]
+    doctor: bool,
+
+    /// Guarantee that bake makes no network requests by disabling remote caches. Can also be
+    /// enabled with the BAKE_OFFLINE environment variable
+    #[arg(long)]
+    no_network: bool,
+
+    /// Check that each configured remote cache can be reached and written to with a tiny test
+    /// entry, and print the result per remote instead of baking
+    #[arg(long, conflicts_with_all = ["list_cache", "check", "clean", "clean_all", "watch", "doctor", "no_network"])]
+    validate_cache_config: bool,
+
+    /// Skip checks that need network access in doctor mode
+    #[arg(long, requires = "doctor")]
+    offline: bool,
+
+    /// Print the full recipe dependency graph as JSON instead of baking
+    #[arg(long, conflicts_with_all = ["list_cache", "check", "clean", "clean_all", "watch"])]
+    dump_graph_json: bool,
+
+    /// Print the recipes that would be baked, including dependencies, grouped with a count per
+    /// group instead of baking
+    #[arg(long, conflicts_with_all = ["list_cache", "check", "clean", "clean_all", "watch", "dump_graph_json"])]
+    show_plan: bool,
+
+    /// How `--show-plan` groups recipes: by cookbook or by dependency level
+    #[arg(long, value_enum, default_value_t, requires = "show_plan")]
+    plan_group_by: PlanGroupBy,
+
+    /// List entries stored in the local cache for the selected recipes, and stale entries no recipe
+    /// uses anymore, instead of baking
+    #[arg(long)]
+    list_cache: bool,
+
+    /// Print how well the archives in the local cache compress, overall and by size, instead of
+    /// baking
+    #[arg(long, conflicts_with_all = ["list_cache", "check", "clean", "clean_all", "watch"])]
+    cache_compression_stats: bool,
+
+    /// Write a JUnit XML report of the bake to this file
+    #[arg(long, value_name = "PATH")]
+    summary_file: Option<PathBuf>,
+
+    /// Write an HTML report of the bake with its timeline, cache stats and dependency graph to
+    /// this file
+    #[arg(long, value_name = "PATH")]
+    report: Option<PathBuf>,
+
+    /// Maximum number of recipes baked at once, overriding the project's `max_parallel`
+    #[arg(short, long, value_name = "N", conflicts_with = "serial")]
+    jobs: Option<NonZeroUsize>,
+
+    /// Maximum number of recipes of the same cookbook baked at once, for cookbooks that don't set
+    /// their own `max_parallel`
+    #[arg(long, value_name = "N")]
+    cookbook_max_parallel: Option<NonZeroUsize>,
+
+    /// Write and restore the outputs declared relative to the project root (`//`) under this
+    /// directory instead, for this run only. Recipes get it as `BAKE_OUTPUT_ROOT`
+    #[arg(long, value_name = "DIR")]
+    output_root: Option<PathBuf>,
+
+    /// Maximum number of lines kept in each recipe's log file, keeping the first and last lines
+    /// of longer output. Overrides `logs.max_lines`
+    #[arg(long, value_name = "N")]
+    max_output_lines: Option<NonZeroUsize>,
+
+    /// Run recipes one at a time in dependency order, ignoring `max_parallel`. Useful to debug
+    /// recipes that misbehave when running concurrently
+    #[arg(long)]
+    serial: bool,
+
+    /// Stream the result of each recipe as newline delimited JSON to this file descriptor as soon
+    /// as it finishes, eg. `--events-fd 3 3>events.ndjson`. Only supported on unix
+    #[arg(long, value_name = "FD")]
+    events_fd: Option<i32>,
+
+    /// Run recipes with `requires_clean_git` even when the git tree has uncommitted changes
+    #[arg(long)]
+    allow_dirty: bool,
+
+    /// Timeout in seconds for recipes without their own timeout, overriding the project's
+    /// `default_timeout`
+    #[arg(long, value_name = "SECONDS")]
+    recipe_timeout_default: Option<u64>,
+
+    /// Don't lock the project, allowing multiple bakes to run concurrently in it
+    #[arg(long)]
+    no_lock: bool,
+
+    /// Where recipe output goes: written to the log file and printed (tee), only written to the
+    /// log file (file) or discarded (null). Recipes can override it with `output`
+    #[arg(long, value_enum, value_name = "MODE")]
+    output_capture: Option<OutputCapture>,
+
+    /// Format of the warnings printed after baking
+    #[arg(long, value_enum, default_value_t)]
+    log_format: DiagnosticsFormat,
+
+    /// Print the version along with build details such as the git commit, to include in bug
+    /// reports
+    #[arg(long)]
+    build_info: bool,
+
+    /// Print the recipe names and patterns shell completion scripts offer, one per line
+    #[arg(long, hide = true)]
+    complete_recipes: bool,
+
+    /// Log how each variable is resolved in every project, cookbook and recipe scope
+    #[arg(long)]
+    trace_vars: bool,
 }
 
 fn parse_key_val(s: &str) -> anyhow::Result<(String, String)> {
@@ -62,50 +345,697 @@ fn parse_key_val(s: &str) -> anyhow::Result<(String, String)> {
     }
 }
 
+/// Builds the variable overrides passed with `--env-from-shell` and `--var`, which takes
+/// precedence
+///
+/// # Arguments
+/// * `args` - The command line arguments
+/// * `environment` - The environment bake runs in
+///
+fn override_variables(
+    args: &Args,
+    environment: impl IntoIterator<Item = (String, String)>,
+) -> anyhow::Result<IndexMap<String, String>> {
+    let mut overrides = IndexMap::new();
+    if let Some(prefix) = args.env_from_shell.as_deref() {
+        let shell_variables: BTreeMap<String, String> = environment
+            .into_iter()
+            .filter_map(|(name, value)| {
+                let name = name.strip_prefix(prefix)?;
+                (!name.is_empty()).then(|| (name.to_owned(), value))
+            })
+            .collect();
+        overrides.extend(shell_variables);
+    }
+    for var in &args.var {
+        let (k, v) = parse_key_val(var)?;
+        overrides.insert(k, v);
+    }
+    Ok(overrides)
+}
+
+/// Writes the log of a recipe's last run, then what's appended to it while following
+///
+/// A log shorter than what was already written was started again by a new run, so it's written
+/// from its beginning.
+///
+/// # Arguments
+/// * `project` - The project the recipe belongs to
+/// * `name` - Fully qualified name of the recipe
+/// * `follow` - Keep writing what's appended to the log, waiting for it if it doesn't exist yet
+/// * `out` - Where the log is written
+///
+async fn print_recipe_log(
+    project: &BakeProject,
+    name: &str,
+    follow: bool,
+    out: &mut impl Write,
+) -> anyhow::Result<()> {
+    if !project.recipes.contains_key(name) {
+        bail!("Recipe {} not found", name);
+    }
+    let log_path = project.get_recipe_log_path(name);
+    if !follow && !log_path.is_file() {
+        bail!(
+            "Recipe {} has no log yet, it's written to {} when the recipe runs",
+            name,
+            log_path.display()
+        );
+    }
+
+    let mut position = 0;
+    loop {
+        if let Ok(mut file) = File::open(&log_path) {
+            if file.metadata()?.len() < position {
+                position = 0;
+            }
+            file.seek(SeekFrom::Start(position))?;
+            position += std::io::copy(&mut file, out)?;
+            out.flush()?;
+        }
+        if !follow {
+            return Ok(());
+        }
+        tokio::time::sleep(Duration::from_millis(250)).await;
+    }
+}
+
+fn print_removed_paths(paths: &[PathBuf]) {
+    if paths.is_empty() {
+        println!("Nothing to clean");
+    }
+    for path in paths {
+        println!("Removed {}", path.display());
+    }
+}
+
+fn print_format_results(results: &[(PathBuf, FormatStatus)]) -> anyhow::Result<()> {
+    for (path, status) in results {
+        match status {
+            FormatStatus::Formatted => println!("Formatted {}", path.display()),
+            FormatStatus::Unchanged => {}
+            FormatStatus::SkippedComments => println!(
+                "{} Skipped {}: it has comments, which formatting would remove",
+                console::style("!").yellow(),
+                path.display()
+            ),
+        }
+    }
+    if results
+        .iter()
+        .all(|(_, status)| *status == FormatStatus::Unchanged)
+    {
+        println!("All files are formatted");
+    }
+    Ok(())
+}
+
+fn print_touch_results(results: &BTreeMap<String, TouchStatus>) -> anyhow::Result<()> {
+    if results.is_empty() {
+        println!("No cached recipes to touch");
+        return Ok(());
+    }
+
+    let mut failed = 0;
+    for (recipe, status) in results {
+        match status {
+            TouchStatus::Stored => println!("{}: {}", recipe, console::style("✓").green()),
+            TouchStatus::MissingOutputs(outputs) => println!(
+                "{}: {} missing outputs: {}",
+                recipe,
+                console::style("!").yellow(),
+                outputs.join(", ")
+            ),
+            TouchStatus::Failed(err) => {
+                failed += 1;
+                println!("{}: {} {}", recipe, console::style("✗").red(), err);
+            }
+        }
+    }
+
+    if failed > 0 {
+        bail!("Failed to touch {} recipes", failed);
+    }
+    Ok(())
+}
+
+fn print_check_results(results: &BTreeMap<String, CheckStatus>) -> anyhow::Result<()> {
+    if results.is_empty() {
+        println!("No cached recipes to check");
+        return Ok(());
+    }
+
+    let mut failed = 0;
+    for (recipe, status) in results {
+        match status {
+            CheckStatus::Deterministic => {
+                println!("{}: {}", recipe, console::style("✓").green())
+            }
+            CheckStatus::Mismatch(paths) => {
+                failed += 1;
+                println!(
+                    "{}: {} outputs differ from cache",
+                    recipe,
+                    console::style("✗").red()
+                );
+                paths
+                    .iter()
+                    .for_each(|path| println!("\t- {}", path.display()));
+            }
+            CheckStatus::Failed(err) => {
+                failed += 1;
+                println!("{}: {} {}", recipe, console::style("✗").red(), err);
+            }
+        }
+    }
+
+    if failed > 0 {
+        bail!(
+            "{} of {} recipes could not be verified",
+            failed,
+            results.len()
+        );
+    }
+    Ok(())
+}
+
+fn print_doctor_checks(checks: &[DoctorCheck]) -> anyhow::Result<()> {
+    let mut failed = 0;
+    for check in checks {
+        match &check.status {
+            DoctorStatus::Pass => println!("{} {}", console::style("✓").green(), check.name),
+            DoctorStatus::Warn(message) => println!(
+                "{} {}: {}",
+                console::style("!").yellow(),
+                check.name,
+                message
+            ),
+            DoctorStatus::Fail(message) => {
+                failed += 1;
+                println!("{} {}: {}", console::style("✗").red(), check.name, message)
+            }
+        }
+    }
+
+    if failed > 0 {
+        bail!("{} of {} checks failed", failed, checks.len());
+    }
+    Ok(())
+}
+
+fn print_remote_checks(results: &[(String, anyhow::Result<()>)]) -> anyhow::Result<()> {
+    if results.is_empty() {
+        println!("No remote caches configured");
+        return Ok(());
+    }
+
+    let mut failed = 0;
+    for (name, result) in results {
+        match result {
+            Ok(_) => println!("{}: {}", name, console::style("✓").green()),
+            Err(err) => {
+                failed += 1;
+                println!("{}: {} {}", name, console::style("✗").red(), err);
+            }
+        }
+    }
+
+    if failed > 0 {
+        bail!(
+            "{} of {} remote caches failed validation",
+            failed,
+            results.len()
+        );
+    }
+    Ok(())
+}
+
+/// Returns the first characters of a cache key's hash, without the recipe's `key_prefix`
+fn short_key(key: &str) -> &str {
+    let hash = key.rsplit('-').next().unwrap_or(key);
+    hash.get(..12).unwrap_or(hash)
+}
+
+fn print_cache_entries(entries: &[CacheEntry]) {
+    if entries.is_empty() {
+        println!("No cache entries found");
+        return;
+    }
+
+    let name = |entry: &CacheEntry| entry.recipe.as_deref().unwrap_or("(stale)").to_owned();
+    let name_width = entries.iter().map(|e| name(e).len()).max().unwrap_or(0);
+    for entry in entries {
+        let age = entry.modified.elapsed().unwrap_or_default().as_secs();
+        let age = match age {
+            0..=59 => format!("{}s ago", age),
+            60..=3599 => format!("{}m ago", age / 60),
+            3600..=86399 => format!("{}h ago", age / 3600),
+            _ => format!("{}d ago", age / 86400),
+        };
+        println!(
+            "{:<name_width$}  {:<12}  {:>8}  {:>10}  {} files",
+            name(entry),
+            short_key(&entry.key),
+            age,
+            format_size(entry.size),
+            entry.files,
+        );
+    }
+}
+
+fn print_compression_report(report: &CompressionReport) {
+    if report.total.archives == 0 {
+        println!("No cache entries found");
+        return;
+    }
+
+    let print_row = |name: &str, stats: &CompressionStats| {
+        println!(
+            "{:<12}  {:>8}  {:>10}  {:>10}  {:>6.2}x",
+            name,
+            stats.archives,
+            format_size(stats.logical_size),
+            format_size(stats.physical_size),
+            stats.ratio(),
+        );
+    };
+    println!(
+        "{:<12}  {:>8}  {:>10}  {:>10}  {:>7}",
+        "Size", "Archives", "Logical", "Stored", "Ratio"
+    );
+    let mut min = 0;
+    for (max, stats) in &report.buckets {
+        let name = match max {
+            Some(max) => format!("< {}", format_size(*max)),
+            None => format!(">= {}", format_size(min)),
+        };
+        print_row(&name, stats);
+        min = max.unwrap_or(min);
+    }
+    print_row("Total", &report.total);
+}
+
+fn print_cache_key_explanation(name: &str, explanation: &CacheKeyExplanation) {
+    let recipe = &explanation.recipe;
+    println!(
+        "Cache key of {}: {}",
+        console::style(name).bold(),
+        explanation.key
+    );
+    println!("  Recipe hash: {}", explanation.recipe_hash);
+    println!("    Input files:");
+    for (path, hash) in &recipe.file_hashes {
+        println!("      {}  {}", hash, path.display());
+    }
+    println!("    Environment:");
+    for (key, value) in &recipe.environment {
+        println!("      {}={}", key, value);
+    }
+    println!("    Variables:");
+    for (key, value) in &recipe.variables {
+        println!("      {}={}", key, value);
+    }
+    println!("    Run: {}", blake3::hash(recipe.run.as_bytes()));
+    println!("  Dependencies:");
+    for (dependency, hash) in &explanation.dependencies {
+        println!("    {}  {}", hash, dependency);
+    }
+}
+
+fn format_size(size: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut value = size as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", size, UNITS[0])
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+/// Whether network access is disabled with `--no-network` or `BAKE_OFFLINE`
+fn no_network(args: &Args) -> bool {
+    args.no_network || std::env::var_os("BAKE_OFFLINE").is_some_and(|value| value != "0")
+}
+
+/// Applies the command line flags that override the project's configuration
+fn apply_config_args(args: &Args, config: &mut ToolConfig) -> anyhow::Result<()> {
+    if let Some(cache_dir) = args
+        .cache_dir
+        .clone()
+        .or_else(|| std::env::var_os("BAKE_CACHE_DIR").map(PathBuf::from))
+    {
+        config.cache.local.path = Some(std::path::absolute(cache_dir)?);
+    }
+    config.no_hash_cache = args.no_hash_cache;
+    config.no_network = no_network(args);
+
+    if args.skip_cache {
+        config.cache.local.enabled = false;
+        config.cache.remotes = None;
+    }
+
+    if let Some(summary_file) = args.summary_file.as_ref() {
+        config.summary_file = Some(std::path::absolute(summary_file)?);
+    }
+
+    if let Some(report) = args.report.as_ref() {
+        config.report_file = Some(std::path::absolute(report)?);
+    }
+
+    if let Some(output_capture) = args.output_capture {
+        config.output_capture = Some(output_capture);
+    }
+
+    if let Some(jobs) = args.jobs {
+        config.max_parallel = jobs.get();
+    }
+
+    if args.serial {
+        config.max_parallel = 1;
+    }
+
+    if args.cookbook_max_parallel.is_some() {
+        config.cookbook_max_parallel = args.cookbook_max_parallel;
+    }
+
+    if args.max_output_lines.is_some() {
+        config.logs.max_lines = args.max_output_lines;
+    }
+
+    if args.events_fd.is_some() {
+        config.events_fd = args.events_fd;
+    }
+
+    if args.allow_dirty {
+        config.allow_dirty = true;
+    }
+
+    if args.recipe_timeout_default.is_some() {
+        config.default_timeout = args.recipe_timeout_default;
+    }
+    Ok(())
+}
+
+/// Exits with 0 on success. When recipes fail, the exit code is the one of the first failed
+/// recipe, see `BakeError::exit_code`
 #[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    env_logger::Builder::from_env(Env::default().default_filter_or("warn")).init();
+async fn main() -> ExitCode {
+    match run().await {
+        Ok(_) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("Error: {:?}", err);
+            ExitCode::from(
+                err.downcast_ref::<BakeError>()
+                    .map_or(1, BakeError::exit_code),
+            )
+        }
+    }
+}
+
+async fn run() -> anyhow::Result<()> {
+    let args = Args::parse();
+    if args.build_info {
+        println!("{}", build_info::build_info());
+        return Ok(());
+    }
+    if args.complete_recipes {
+        let path = args
+            .path
+            .as_ref()
+            .map_or_else(std::env::current_dir, std::path::absolute);
+        if let Ok(path) = path {
+            BakeProject::completion_names(&path)
+                .iter()
+                .for_each(|name| println!("{}", name));
+        }
+        return Ok(());
+    }
+
+    let mut logger = env_logger::Builder::from_env(Env::default().default_filter_or("warn"));
+    if args.trace_vars {
+        logger.filter_module(TRACE_VARS_TARGET, log::LevelFilter::Trace);
+    }
+    logger.init();
 
     let term = Term::stdout();
     let padded_version = format!("{:<8}", VERSION);
     term.set_title("Bake");
     println!("{}", WELCOME_MSG.replace("xx.xx.xx", &padded_version));
 
-    let args = Args::parse();
     let bake_path = if args.path.is_none() {
         std::env::current_dir().unwrap()
     } else {
-        std::path::absolute(args.path.unwrap())?
+        std::path::absolute(args.path.as_ref().unwrap())?
     };
 
+    let override_variables = override_variables(
+        &args,
+        std::env::vars_os().filter_map(|(name, value)| {
+            Some((name.into_string().ok()?, value.into_string().ok()?))
+        }),
+    )?;
+
+    if args.doctor {
+        let offline = args.offline || no_network(&args);
+        let checks = doctor::run_checks(&bake_path, override_variables, offline).await;
+        return print_doctor_checks(&checks);
+    }
+
     println!("Loading project...");
     term.move_cursor_up(1)?;
 
-    let override_variables =
-        args.var
-            .iter()
-            .try_fold(IndexMap::new(), |mut acc, s| -> anyhow::Result<_> {
-                let (k, v) = parse_key_val(s)?;
-                acc.insert(k, v);
-                Ok(acc)
-            })?;
-
     match BakeProject::from(&bake_path, override_variables) {
         Ok(mut project) => {
             println!("Loading project... {}", console::style("✓").green());
-            let recipe_filter = args.recipe.as_deref();
+            if let Some(env) = args.env.as_deref() {
+                project.apply_environment(env)?;
+            }
+            apply_config_args(&args, &mut project.config)?;
+            if let Some(output_root) = args.output_root.as_ref() {
+                project.set_output_root(std::path::absolute(output_root)?);
+            }
+            if args.print_config {
+                print!("{}", serde_yaml::to_string(&project.config)?);
+                return Ok(());
+            }
+            let search = if args.recipes_stdin {
+                let list = std::io::read_to_string(std::io::stdin())?;
+                RecipeSearch::ByNames(project.parse_recipe_list(&list)?)
+            } else if let Some(select_file) = args.select_file.as_ref() {
+                let mut selection = match std::fs::read_to_string(select_file) {
+                    Ok(selection) => selection,
+                    Err(err) => bail!("Could not read {}: {}", select_file.display(), err),
+                };
+                // The recipe argument is added to the selection
+                if let Some(recipe) = args.recipe.as_deref() {
+                    selection.push('\n');
+                    selection.push_str(recipe);
+                }
+                RecipeSearch::ByNames(project.parse_selection(&selection))
+            } else if let Some(alias) = args.recipe.as_deref().and_then(|r| r.strip_prefix('@')) {
+                RecipeSearch::ByNames(project.expand_alias(alias)?)
+            } else if let Some(recipe) = args.recipe.as_deref() {
+                RecipeSearch::ByPattern(recipe.to_owned())
+            } else {
+                RecipeSearch::All
+            };
+            let search = if args.exclude.is_empty() {
+                search
+            } else {
+                RecipeSearch::ByNames(project.exclude_recipes(&search, &args.exclude)?)
+            };
+            if !args.recipe_args.is_empty() {
+                project.set_recipe_args(&search, args.recipe_args.clone())?;
+            }
+
+            if args.validate_cache_config {
+                let results = CacheBuilder::new(Arc::new(project))
+                    .default_strategies()
+                    .check_remotes()
+                    .await?;
+                return print_remote_checks(&results);
+            }
+
+            if args.dump_graph_json {
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&project.dependency_graph_json())?
+                );
+                return Ok(());
+            }
+
+            if args.show_plan {
+                println!("{}", project.format_plan(&search, args.plan_group_by));
+                return Ok(());
+            }
+
+            if let Some(name) = args.explain_cache_key.as_deref() {
+                let explanation = CacheBuilder::new(Arc::new(project)).explain_key(name)?;
+                print_cache_key_explanation(name, &explanation);
+                return Ok(());
+            }
+
+            if let Some(name) = args.describe.as_deref() {
+                println!("{}", project.describe_recipe(name, args.sorted)?);
+                return Ok(());
+            }
+
+            if let Some(name) = args.log.as_deref() {
+                return print_recipe_log(&project, name, args.follow, &mut std::io::stdout()).await;
+            }
+
+            if let Some(name) = args.print_run.as_deref() {
+                println!("{}", project.rendered_run(name)?.trim_end_matches('\n'));
+                return Ok(());
+            }
+
+            if let Some(name) = args.recipe_env_dump.as_deref() {
+                let Some(recipe) = project.recipes.get(name) else {
+                    bail!("Recipe {} not found", name);
+                };
+                for (key, value) in baker::redacted_recipe_environment(recipe, &project.config) {
+                    println!("{}={}", key, value);
+                }
+                return Ok(());
+            }
+
+            if let Some(name) = args.print_env_diff.as_deref() {
+                let Some(recipe) = project.recipes.get(name) else {
+                    bail!("Recipe {} not found", name);
+                };
+                let parent = std::env::vars_os()
+                    .map(|(name, value)| {
+                        (
+                            name.to_string_lossy().into_owned(),
+                            value.to_string_lossy().into_owned(),
+                        )
+                    })
+                    .collect();
+                let diff = baker::recipe_environment_diff(recipe, &project.config, &parent);
+                for (key, change) in diff {
+                    match change {
+                        EnvChange::Added(value) => println!("+ {}={}", key, value),
+                        EnvChange::Changed { from, to } => {
+                            println!("~ {}={} (was {})", key, to, from)
+                        }
+                        EnvChange::Removed => println!("- {}", key),
+                    }
+                }
+                return Ok(());
+            }
+
+            if args.fmt {
+                return print_format_results(&fmt::format_project(&project)?);
+            }
+
+            if args.lint {
+                let diagnostics = lint::lint_recipes(&project, &search);
+                if diagnostics.entries().is_empty() {
+                    println!("No problems found");
+                }
+                diagnostics.print(args.log_format);
+                return Ok(());
+            }
+
+            if args.list_cache {
+                let project = Arc::new(project);
+                // Keys of every recipe are needed to tell stale entries apart
+                let hashes = CacheBuilder::new(project.clone()).calculate_all_hashes()?;
+                let selected = project.get_recipes(&search);
+                let entries: Vec<CacheEntry> = list_local_entries(&project, &hashes)?
+                    .into_iter()
+                    .filter(|entry| {
+                        entry
+                            .recipe
+                            .as_ref()
+                            .map_or(true, |recipe| selected.contains_key(recipe))
+                    })
+                    .collect();
+                print_cache_entries(&entries);
+                return Ok(());
+            }
+
+            if args.cache_compression_stats {
+                print_compression_report(&local_compression_stats(&project)?);
+                return Ok(());
+            }
+
+            if args.check {
+                let project = Arc::new(project);
+                let hashes = CacheBuilder::new(project.clone())
+                    .search(search.clone())
+                    .calculate_all_hashes()?;
+                let results = check::check_recipes(&project, &hashes, &search).await?;
+                return print_check_results(&results);
+            }
+
+            if args.touch {
+                let _lock = if args.no_lock {
+                    None
+                } else {
+                    Some(ProjectLock::acquire(&project)?)
+                };
+                let project = Arc::new(project);
+                let cache = CacheBuilder::new(project.clone())
+                    .search(search.clone())
+                    .default_strategies()
+                    .build()
+                    .await?;
+                let results = touch::touch_recipes(&project, &cache, &search).await?;
+                return print_touch_results(&results);
+            }
+
+            if args.clean || args.clean_all {
+                let _lock = if args.no_lock {
+                    None
+                } else {
+                    Some(ProjectLock::acquire(&project)?)
+                };
+                let paths = if args.clean_all {
+                    clean::all_paths(&project)
+                } else {
+                    clean::recipe_paths(&project, &search)
+                };
+                if !clean::confirm_removal(&paths, args.yes, std::io::stdin().is_terminal())? {
+                    println!("Aborted");
+                    return Ok(());
+                }
+                print_removed_paths(&clean::remove_paths(paths)?);
+                return Ok(());
+            }
+
+            if args.serial {
+                println!("Serial mode: baking one recipe at a time");
+            }
+
+            // Keep the lock alive until baking finishes
+            let _lock = if args.no_lock {
+                None
+            } else {
+                Some(ProjectLock::acquire(&project)?)
+            };
 
             // Build cache using project and Local, S3 and GCS strategies
             if args.skip_cache {
                 println!("Skipping cache...");
-                project.config.cache.local.enabled = false;
-                project.config.cache.remotes = None;
+            }
+            if args.watch {
+                let exec = args
+                    .watch_exec
+                    .map(|command| WatchExec::new(command, project.root_path.clone()));
+                return watch::watch(project, &search, args.force_rebuild, exec).await;
             }
             let arc_project = Arc::new(project);
+
             let mut cache_builder = CacheBuilder::new(arc_project.clone());
-            if let Some(recipe_filter) = recipe_filter {
-                cache_builder.filter(recipe_filter);
-            }
+            cache_builder
+                .search(search.clone())
+                .force_rebuild(args.force_rebuild);
 
             let cache = match cache_builder.default_strategies().build().await {
                 Ok(cache) => cache,
@@ -115,7 +1045,9 @@ async fn main() -> anyhow::Result<()> {
                 }
             };
 
-            match baker::bake(arc_project.clone(), cache, args.recipe.as_deref()).await {
+            let result = baker::bake(arc_project.clone(), cache, &search).await;
+            arc_project.diagnostics.print(args.log_format);
+            match result {
                 Ok(_) => {}
                 Err(err) => {
                     return Err(err);
@@ -130,3 +1062,164 @@ async fn main() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::Write,
+        sync::{Arc, Mutex},
+    };
+
+    use clap::Parser;
+    use test_case::test_case;
+
+    use crate::{
+        baker,
+        cache::{local::LocalCacheStrategy, CacheBuilder, CacheStrategy},
+        project::{config::ToolConfig, BakeProject, RecipeSearch},
+        test_utils::{wait_until, TestProjectBuilder},
+    };
+
+    use super::Args;
+
+    #[test_case("0123456789abcdef" => "0123456789ab"; "hash")]
+    #[test_case("api-0123456789abcdef" => "0123456789ab"; "prefixed")]
+    #[test_case("stale" => "stale"; "short")]
+    fn short_key(key: &str) -> &str {
+        super::short_key(key)
+    }
+
+    #[test]
+    fn apply_config_args() {
+        let args = Args::parse_from([
+            "bake",
+            "--jobs",
+            "8",
+            "--skip-cache",
+            "--cookbook-max-parallel",
+            "2",
+        ]);
+        let mut config = ToolConfig::default();
+        super::apply_config_args(&args, &mut config).unwrap();
+
+        let printed = serde_yaml::to_string(&config).unwrap();
+        assert!(printed.contains("max_parallel: 8\n"));
+        assert!(printed.contains("cookbook_max_parallel: 2\n"));
+        assert!(printed.contains("  local:\n    enabled: false\n"));
+        assert!(Args::try_parse_from(["bake", "--jobs", "0"]).is_err());
+    }
+
+    #[tokio::test]
+    async fn serial() {
+        let mut project = TestProjectBuilder::new()
+            .with_cookbook("serial", &["a", "b", "c"])
+            .build();
+        project.config.max_parallel = 4;
+        let args = Args::parse_from(["bake", "--serial"]);
+        super::apply_config_args(&args, &mut project.config).unwrap();
+        let trace_path = project.root_path.join("trace.txt");
+        for name in ["serial:a", "serial:b", "serial:c"] {
+            project.recipes.get_mut(name).unwrap().run = format!(
+                "echo start >> {0}; sleep 0.1; echo end >> {0}",
+                trace_path.display()
+            );
+        }
+        let project = Arc::new(project);
+        let cache = CacheBuilder::new(project.clone())
+            .add_strategy("local", LocalCacheStrategy::from_config)
+            .build()
+            .await
+            .unwrap();
+        baker::bake(project, cache, &RecipeSearch::All)
+            .await
+            .unwrap();
+
+        // Recipes never overlap
+        assert_eq!(
+            std::fs::read_to_string(&trace_path).unwrap(),
+            "start\nend\n".repeat(3)
+        );
+    }
+
+    #[test]
+    fn env_from_shell() {
+        let root_path = TestProjectBuilder::temp_dir(&[(
+            "bake.yml",
+            "name: shell\nvariables:\n  foo: default\n  bar: default\n  baz: default\n",
+        )]);
+        let args = Args::parse_from(["bake", "--env-from-shell", "BAKE_VAR_", "--var", "bar=cli"]);
+        let environment = [
+            ("BAKE_VAR_foo", "bar"),
+            ("BAKE_VAR_bar", "shell"),
+            ("BAKE_VAR_", "empty"),
+            ("baz", "unprefixed"),
+        ]
+        .map(|(name, value)| (name.to_owned(), value.to_owned()));
+        let overrides = super::override_variables(&args, environment).unwrap();
+
+        let project = BakeProject::from(&root_path, overrides).unwrap();
+        assert_eq!(project.variables["foo"], "bar");
+        assert_eq!(project.variables["bar"], "cli", "--var takes precedence");
+        assert_eq!(project.variables["baz"], "default");
+    }
+
+    #[tokio::test]
+    async fn print_recipe_log() {
+        let project = TestProjectBuilder::new()
+            .with_cookbook("foo", &["build"])
+            .build();
+        let mut out = Vec::new();
+        let err = super::print_recipe_log(&project, "foo:build", false, &mut out)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("has no log yet"));
+        assert!(
+            super::print_recipe_log(&project, "foo:missing", false, &mut out)
+                .await
+                .is_err()
+        );
+
+        let log_path = project.get_recipe_log_path("foo:build");
+        std::fs::create_dir_all(log_path.parent().unwrap()).unwrap();
+        std::fs::write(&log_path, "Compiling foo\nFinished\n").unwrap();
+        super::print_recipe_log(&project, "foo:build", false, &mut out)
+            .await
+            .unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "Compiling foo\nFinished\n");
+
+        // Following keeps writing to the output, which is shared to check it meanwhile
+        let out = SharedOutput::default();
+        let contents = || String::from_utf8(out.0.lock().unwrap().clone()).unwrap();
+        let append = async {
+            wait_until(|| contents() == "Compiling foo\nFinished\n").await;
+            let mut log = std::fs::OpenOptions::new()
+                .append(true)
+                .open(&log_path)
+                .unwrap();
+            log.write_all(b"Running\n").unwrap();
+            wait_until(|| contents().ends_with("Running\n")).await;
+        };
+        let mut follow_out = out.clone();
+        tokio::select! {
+            result = super::print_recipe_log(&project, "foo:build", true, &mut follow_out) => {
+                panic!("following the log stopped: {:?}", result)
+            }
+            _ = append => {}
+        }
+        assert_eq!(contents(), "Compiling foo\nFinished\nRunning\n");
+    }
+
+    /// Output shared between the writer and the test reading it
+    #[derive(Clone, Default)]
+    struct SharedOutput(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedOutput {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+}
@@ -1,6 +1,8 @@
 pub mod config;
 pub mod cookbook;
+pub mod file_hashes;
 pub mod recipe;
+pub mod remote;
 
 use anyhow::bail;
 
@@ -11,15 +13,67 @@ pub use recipe::*;
 pub use validator::Validate;
 
 use std::{
-    collections::{BTreeMap, HashSet},
+    collections::{BTreeMap, BTreeSet, HashSet},
+    num::NonZeroUsize,
     path::{Path, PathBuf},
+    time::SystemTime,
 };
 
+use log::debug;
+use regex::Regex;
 use serde::Deserialize;
+use serde_json::json;
 
-use crate::template::parse_variable_list;
+use crate::{
+    diagnostics::{Diagnostics, Severity},
+    error::BakeError,
+    template::{
+        clear_file_digests, expand_process_env_vars, merge_environment, parse_template,
+        parse_variable_list,
+    },
+};
+
+use self::{
+    config::{EnvironmentOverrides, ToolConfig},
+    remote::RemoteCookbooks,
+};
+
+/// Criteria used to select which recipes should be baked
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum RecipeSearch {
+    /// All recipes in the project
+    #[default]
+    All,
+
+    /// Recipes whose fully qualified name contains the pattern (eg. `foo:`)
+    ByPattern(String),
+
+    /// Recipes matching exactly the given fully qualified names (eg. `foo:build`)
+    ByNames(Vec<String>),
+}
+
+impl RecipeSearch {
+    /// Returns whether a fully qualified recipe name is selected by the search, without taking
+    /// dependencies into account
+    pub fn matches(&self, name: &str) -> bool {
+        match self {
+            Self::All => true,
+            Self::ByPattern(pattern) => name.contains(pattern.as_str()),
+            Self::ByNames(names) => names.iter().any(|n| n == name),
+        }
+    }
+}
 
-use self::config::ToolConfig;
+/// How `--show-plan` groups the recipes it prints
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum PlanGroupBy {
+    /// One group per cookbook
+    #[default]
+    Cookbook,
+
+    /// One group per dependency level, recipes of a level only depending on lower levels
+    Level,
+}
 
 #[derive(Debug, Deserialize, Validate)]
 pub struct BakeProject {
@@ -45,6 +99,18 @@ pub struct BakeProject {
     #[serde(default)]
     pub environment: Vec<String>,
 
+    /// Named lists of recipe patterns selected with `@name`, which can include other aliases
+    #[serde(default)]
+    pub aliases: BTreeMap<String, Vec<String>>,
+
+    /// Settings overridden per environment, applied with `--env <name>`
+    #[serde(default)]
+    pub overrides: BTreeMap<String, EnvironmentOverrides>,
+
+    /// Cookbooks loaded from git repositories, pinned to a revision
+    #[serde(default)]
+    pub remote_cookbooks: Vec<RemoteCookbooks>,
+
     #[serde(default)]
     #[validate(nested)]
     /// Main configuration of the project
@@ -57,6 +123,71 @@ pub struct BakeProject {
     #[serde(skip)]
     /// Maps all dependencies, direct and indirect of each recipe in the project
     pub dependency_map: BTreeMap<String, HashSet<String>>,
+
+    #[serde(skip)]
+    /// Warnings collected while loading the project
+    pub diagnostics: Diagnostics,
+
+    #[serde(skip)]
+    /// Files the project was loaded from, used to reload only what changed
+    pub source: ProjectSource,
+}
+
+/// Files a project was loaded from and their state when they were read
+#[derive(Debug, Default)]
+pub struct ProjectSource {
+    config_path: PathBuf,
+
+    /// Modification time and size of the config file, None if the config was loaded from memory
+    config_state: Option<(SystemTime, u64)>,
+
+    override_variables: IndexMap<String, String>,
+
+    /// Modification time and size of each cookbook file
+    cookbooks: BTreeMap<PathBuf, (SystemTime, u64)>,
+
+    /// Modification time and size of the `recipe_files` of each cookbook, keyed by cookbook file
+    recipe_files: BTreeMap<PathBuf, Vec<Option<(SystemTime, u64)>>>,
+
+    /// Names of the cookbooks loaded from `remote_cookbooks`, which are kept as they are on
+    /// reload
+    remote_cookbooks: BTreeSet<String>,
+
+    /// Environment applied with `apply_environment`, applied again when the project is reloaded
+    environment: Option<String>,
+}
+
+/// Returns the Levenshtein distance between two strings, the number of characters inserted,
+/// removed or replaced to turn one into the other
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let replace = previous[j] + usize::from(a_char != *b_char);
+            current.push(replace.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// Returns the modification time and size of a file, None if it can't be read
+fn file_state(path: &Path) -> Option<(SystemTime, u64)> {
+    let metadata = std::fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+/// Returns the modification time and size of each of a cookbook's `recipe_files`, None for files
+/// that can't be read
+fn recipe_file_states(cookbook: &Cookbook) -> Vec<Option<(SystemTime, u64)>> {
+    let dir = cookbook.config_path.parent().unwrap();
+    cookbook
+        .recipe_files
+        .iter()
+        .map(|file| file_state(&dir.join(file)))
+        .collect()
 }
 
 impl BakeProject {
@@ -69,10 +200,11 @@ impl BakeProject {
     pub fn from(path: &Path, override_variables: IndexMap<String, String>) -> anyhow::Result<Self> {
         // TODO: Better organize validation for config and recipes
         let file_path: PathBuf;
-        let mut project: Self;
 
         if !path.exists() {
-            bail!("Path does not exist: {}", path.display());
+            bail!(BakeError::ConfigNotFound {
+                path: path.to_path_buf()
+            });
         }
 
         if path.is_dir() {
@@ -83,6 +215,7 @@ impl BakeProject {
             bail!("Invalid path");
         }
 
+        let config_state = file_state(&file_path);
         let config_str = match std::fs::read_to_string(&file_path) {
             Ok(contents) => contents,
             Err(_) => {
@@ -90,15 +223,55 @@ impl BakeProject {
             }
         };
 
-        match serde_yaml::from_str::<Self>(&config_str) {
+        let mut project = Self::load(&config_str, &file_path, override_variables)?;
+        project.source.config_state = config_state;
+        Ok(project)
+    }
+
+    /// Creates a bake project from the contents of a bake.yml file
+    ///
+    /// Useful when embedding bake and the config is already in memory. Cookbooks are still
+    /// loaded from the root directory.
+    ///
+    /// # Arguments
+    /// * `config` - Contents of a bake.yml file
+    /// * `root` - Root directory of the project
+    /// * `override_variables` - Variables that override the ones defined in the project
+    ///
+    pub fn from_str(
+        config: &str,
+        root: &Path,
+        override_variables: IndexMap<String, String>,
+    ) -> anyhow::Result<Self> {
+        Self::load(config, &root.join("bake.yml"), override_variables)
+    }
+
+    /// Parses and validates a project config, then loads all of its cookbooks
+    ///
+    /// # Arguments
+    /// * `config` - Contents of the config file
+    /// * `config_path` - Path of the config file, its parent is the project's root
+    /// * `override_variables` - Variables that override the ones defined in the project
+    ///
+    fn load(
+        config: &str,
+        config_path: &Path,
+        override_variables: IndexMap<String, String>,
+    ) -> anyhow::Result<Self> {
+        let mut project: Self;
+
+        match serde_yaml::from_str::<Self>(config) {
             Ok(mut parsed) => {
                 if let Err(err) = parsed.validate() {
-                    bail!("Could not parse config file: {}", err);
+                    bail!(BakeError::ValidationError(err.to_string()));
                 }
-                parsed.root_path = file_path.parent().unwrap().to_path_buf();
+                parsed.root_path = config_path.parent().unwrap().to_path_buf();
                 project = parsed;
             }
-            Err(err) => bail!("Could not parse config file: {}", err),
+            Err(err) => bail!(BakeError::ParseError {
+                path: config_path.to_path_buf(),
+                message: err.to_string(),
+            }),
         }
 
         let project_constants = IndexMap::from([(
@@ -106,163 +279,1069 @@ impl BakeProject {
             project.root_path.clone().display().to_string(),
         )]);
 
+        project.environment = merge_environment(&[], &project.environment);
         project.variables = parse_variable_list(
+            "project",
             project.environment.as_slice(),
             &project.variables,
             &IndexMap::from([("project".to_owned(), project_constants.clone())]),
             &override_variables,
         )?;
+        project.resolve_local_cache_path()?;
 
-        project.cookbooks = Cookbook::map_from(
-            &project.root_path,
-            &project.environment,
-            &project.variables,
+        project.source = ProjectSource {
+            config_path: config_path.to_path_buf(),
+            override_variables,
+            ..Default::default()
+        };
+        project.load_cookbooks()?;
+        project.resolve()?;
+        Ok(project)
+    }
+
+    /// Renders `${NAME}` references to bake's environment and templates in `cache.local.path`,
+    /// eg. to keep the cache in a directory of the CI runner
+    fn resolve_local_cache_path(&mut self) -> anyhow::Result<()> {
+        let Some(path) = self
+            .config
+            .cache
+            .local
+            .path
+            .as_ref()
+            .and_then(|path| path.to_str())
+            .map(str::to_owned)
+        else {
+            return Ok(());
+        };
+        let constants = IndexMap::from([(
+            "project".to_owned(),
+            IndexMap::from([("root".to_owned(), self.root_path.display().to_string())]),
+        )]);
+        let rendered = expand_process_env_vars(&path).and_then(|expanded| {
+            parse_template(&expanded, &self.environment, &self.variables, &constants)
+        });
+        match rendered {
+            Ok(rendered) if !rendered.trim().is_empty() => {
+                self.config.cache.local.path = Some(PathBuf::from(rendered));
+                Ok(())
+            }
+            Ok(_) => bail!(BakeError::ValidationError(format!(
+                "cache.local.path {} is empty once rendered",
+                path
+            ))),
+            Err(err) => bail!(BakeError::ValidationError(format!(
+                "Invalid cache.local.path {}: {}",
+                path, err
+            ))),
+        }
+    }
+
+    /// Loads the cookbooks of the project directory and of `remote_cookbooks`
+    fn load_cookbooks(&mut self) -> anyhow::Result<()> {
+        let project_constants = IndexMap::from([(
+            "root".to_owned(),
+            self.root_path.clone().display().to_string(),
+        )]);
+        let environment_overrides = self
+            .source
+            .environment
+            .as_ref()
+            .and_then(|name| self.overrides.get(name));
+
+        let mut cookbooks = Cookbook::map_from(
+            &self.root_path,
+            &self.environment,
+            &self.variables,
             &project_constants,
-            &override_variables,
+            &self.source.override_variables,
+            environment_overrides,
+            self.config.shell_style_vars,
         )?;
+        let mut remote_cookbooks = BTreeSet::new();
+        for remote in &self.remote_cookbooks {
+            let path = remote.checkout(&self.get_project_bake_path())?;
+            let remote_map = Cookbook::map_from(
+                &path,
+                &self.environment,
+                &self.variables,
+                &project_constants,
+                &self.source.override_variables,
+                environment_overrides,
+                self.config.shell_style_vars,
+            )?;
+            for (name, cookbook) in remote_map {
+                if cookbooks.contains_key(&name) {
+                    bail!(BakeError::ValidationError(format!(
+                        "Cookbook {} from {} conflicts with a cookbook of the same name",
+                        name, remote.git
+                    )));
+                }
+                remote_cookbooks.insert(name.clone());
+                cookbooks.insert(name, cookbook);
+            }
+        }
+        self.source.cookbooks = cookbooks
+            .values()
+            .filter(|cookbook| !remote_cookbooks.contains(&cookbook.name))
+            .filter_map(|cookbook| {
+                file_state(&cookbook.config_path).map(|state| (cookbook.config_path.clone(), state))
+            })
+            .collect();
+        self.source.recipe_files = cookbooks
+            .values()
+            .filter(|cookbook| !remote_cookbooks.contains(&cookbook.name))
+            .map(|cookbook| (cookbook.config_path.clone(), recipe_file_states(cookbook)))
+            .collect();
+        self.source.remote_cookbooks = remote_cookbooks;
+        self.cookbooks = cookbooks;
+        Ok(())
+    }
 
+    /// Reloads the project, re-parsing only the cookbook files that were added or changed since
+    /// they were last read, for long-lived processes such as watch mode
+    ///
+    /// The whole project is loaded again if its config file changed. If reloading fails, the
+    /// project is left as it was. Returns the cookbook files that were parsed again.
+    pub fn reload(&mut self) -> anyhow::Result<Vec<PathBuf>> {
+        clear_file_digests();
+        if let Some(config_state) = self.source.config_state {
+            let config_path = &self.source.config_path;
+            if file_state(config_path) != Some(config_state) {
+                debug!("Config file changed, reloading project");
+                let mut project = Self::from(config_path, self.source.override_variables.clone())?;
+                if let Some(environment) = self.source.environment.as_deref() {
+                    project.apply_environment(environment)?;
+                }
+                *self = project;
+                return Ok(self.source.cookbooks.keys().cloned().collect());
+            }
+        }
+
+        let project_constants = IndexMap::from([(
+            "root".to_owned(),
+            self.root_path.clone().display().to_string(),
+        )]);
+        let mut cookbooks = BTreeMap::new();
+        let mut states = BTreeMap::new();
+        let mut recipe_files = BTreeMap::new();
+        let mut reparsed = Vec::new();
+        for file in Cookbook::find_files(&self.root_path) {
+            let state = file_state(&file);
+            let unchanged = self
+                .cookbooks
+                .values()
+                .find(|cookbook| cookbook.config_path == file)
+                .filter(|cookbook| {
+                    state.is_some()
+                        && self.source.cookbooks.get(&file) == state.as_ref()
+                        && self.source.recipe_files.get(&file)
+                            == Some(&recipe_file_states(cookbook))
+                });
+            let cookbook = match unchanged {
+                Some(cookbook) => cookbook.clone(),
+                None => {
+                    debug!("Reloading cookbook {}", file.display());
+                    reparsed.push(file.clone());
+                    Cookbook::from(
+                        &file,
+                        &self.environment,
+                        &self.variables,
+                        &project_constants,
+                        &self.source.override_variables,
+                        self.source
+                            .environment
+                            .as_ref()
+                            .and_then(|name| self.overrides.get(name)),
+                        self.config.shell_style_vars,
+                    )?
+                }
+            };
+            if let Some(state) = state {
+                recipe_files.insert(file.clone(), recipe_file_states(&cookbook));
+                states.insert(file, state);
+            }
+            cookbooks.insert(cookbook.name.clone(), cookbook);
+        }
+        for name in &self.source.remote_cookbooks {
+            if cookbooks.contains_key(name) {
+                bail!(BakeError::ValidationError(format!(
+                    "Cookbook {} conflicts with a remote cookbook of the same name",
+                    name
+                )));
+            }
+            cookbooks.insert(name.clone(), self.cookbooks[name].clone());
+        }
+
+        let previous = std::mem::replace(&mut self.cookbooks, cookbooks);
+        if let Err(err) = self.resolve() {
+            self.cookbooks = previous;
+            self.resolve()?;
+            return Err(err);
+        }
+        self.source.cookbooks = states;
+        self.source.recipe_files = recipe_files;
+        Ok(reparsed)
+    }
+
+    /// Writes and restores the outputs declared relative to the project root (`//`) under another
+    /// directory for this run, eg. to bake several builds of the same project in isolation. Cache
+    /// keys don't depend on where outputs are written
+    ///
+    /// # Arguments
+    /// * `output_root` - Directory replacing the project root for these outputs
+    ///
+    pub fn set_output_root(&mut self, output_root: PathBuf) {
+        for recipe in self.recipes.values_mut() {
+            recipe.output_root = Some(output_root.clone());
+        }
+        self.config.output_root = Some(output_root);
+    }
+
+    /// Applies the overrides of an environment declared in `overrides`, such as using a remote
+    /// cache only in CI
+    ///
+    /// Cookbooks are loaded again if the environment overrides variables of specific cookbooks or
+    /// recipes, which fails if any of them doesn't exist.
+    ///
+    /// # Arguments
+    /// * `name` - Name of the environment
+    ///
+    pub fn apply_environment(&mut self, name: &str) -> anyhow::Result<()> {
+        let Some(overrides) = self.overrides.get(name) else {
+            bail!(
+                "Environment {} not found, declared environments: {}",
+                name,
+                self.overrides
+                    .keys()
+                    .map(String::as_str)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        };
+        if let Some(cache) = overrides.cache.as_ref() {
+            if let Err(err) = self.config.cache.merge(cache) {
+                bail!(BakeError::ValidationError(format!(
+                    "Invalid cache overrides for environment {}: {}",
+                    name, err
+                )));
+            }
+        }
+
+        // Kept so that cookbooks loaded from now on, including on reload, get the overrides
+        self.source.environment = Some(name.to_owned());
+        if overrides.has_variables() {
+            let unknown = overrides
+                .cookbooks
+                .keys()
+                .filter(|cookbook| !self.cookbooks.contains_key(*cookbook))
+                .chain(
+                    overrides
+                        .recipes
+                        .keys()
+                        .filter(|recipe| !self.recipes.contains_key(*recipe)),
+                )
+                .cloned()
+                .collect::<Vec<_>>();
+            if !unknown.is_empty() {
+                bail!(BakeError::ValidationError(format!(
+                    "Overrides of environment {} target unknown cookbooks or recipes: {}",
+                    name,
+                    unknown.join(", ")
+                )));
+            }
+            self.load_cookbooks()?;
+            self.resolve()?;
+        }
+        self.resolve_local_cache_path()
+    }
+
+    /// Returns whether the config file or any cookbook file was added, removed or changed since
+    /// the project was loaded
+    pub fn is_stale(&self) -> bool {
+        if let Some(config_state) = self.source.config_state {
+            if file_state(&self.source.config_path) != Some(config_state) {
+                return true;
+            }
+        }
+        let files = Cookbook::find_files(&self.root_path);
+        files.len() != self.source.cookbooks.len()
+            || files
+                .iter()
+                .any(|file| self.source.cookbooks.get(file) != file_state(file).as_ref())
+            || self.cookbooks.values().any(|cookbook| {
+                self.source
+                    .recipe_files
+                    .get(&cookbook.config_path)
+                    .is_some_and(|states| *states != recipe_file_states(cookbook))
+            })
+    }
+
+    /// Collects the recipes of all cookbooks, validates them and maps their dependencies
+    fn resolve(&mut self) -> anyhow::Result<()> {
+        let project = self;
+        let output_root = project
+            .config
+            .output_root
+            .clone()
+            .unwrap_or(project.root_path.clone());
         project.recipes = project
             .cookbooks
             .iter()
             .flat_map(|(_, cookbook)| {
-                cookbook
-                    .recipes
-                    .values()
-                    .map(|recipe| (recipe.full_name(), recipe.clone()))
+                cookbook.recipes.values().map(|recipe| {
+                    let mut recipe = recipe.clone();
+                    recipe.output_root = Some(output_root.clone());
+                    (recipe.full_name(), recipe)
+                })
             })
             .collect();
 
+        project.validate_name_pattern()?;
+
         // let all_recipes = project.recipes(RecipeSearch::All);
         //
         // Validate if all recipe dependencies exist
-        let err_msg = project
+        let missing: Vec<(PathBuf, Vec<String>)> = project
             .recipes
-            .iter()
-            .fold("".to_owned(), |msg, (_, recipe)| {
-                let mut missing_deps: Vec<String> = Vec::new();
-                if let Some(dependencies) = recipe.dependencies.as_ref() {
-                    dependencies.iter().for_each(|dep| {
-                        if project.recipes.get(dep).is_none() {
-                            missing_deps.push(format!("\t- {}", dep));
-                        }
-                    });
+            .values()
+            .filter_map(|recipe| {
+                let missing_deps: Vec<String> = recipe
+                    .dependencies
+                    .iter()
+                    .flatten()
+                    .filter(|dep| !project.recipes.contains_key(&dep.recipe))
+                    .map(|dep| dep.recipe.clone())
+                    .collect();
+                if missing_deps.is_empty() {
+                    None
+                } else {
+                    Some((recipe.config_path.clone(), missing_deps))
                 }
-                if !missing_deps.is_empty() {
+            })
+            .collect();
+
+        if !missing.is_empty() {
+            bail!(BakeError::MissingDependency(missing));
+        }
+
+        // Validate if project doesn't have circular dependencies
+        match project.get_dependencies() {
+            Ok(deps) => {
+                project.dependency_map = deps;
+            }
+            Err(circular_dependency) => {
+                bail!(BakeError::CyclicDependency(circular_dependency));
+            }
+        }
+
+        // Validate that aliases only reference existing aliases, without cycles
+        project
+            .aliases
+            .keys()
+            .try_for_each(|alias| project.expand_alias(alias).map(|_| ()))?;
+
+        project.diagnostics = project.collect_diagnostics();
+
+        Ok(())
+    }
+
+    /// Checks the project for problems that don't prevent it from being baked
+    fn collect_diagnostics(&self) -> Diagnostics {
+        let mut diagnostics = Diagnostics::new(&self.config.ignore_diagnostics);
+        let cache_enabled = self.config.cache.local.enabled
+            || self
+                .config
+                .cache
+                .remotes
+                .as_ref()
+                .is_some_and(|remotes| remotes.s3.is_some() || remotes.gcs.is_some());
+
+        for recipe in self.recipes.values() {
+            let Some(cache) = recipe.cache.as_ref() else {
+                continue;
+            };
+            if !cache_enabled {
+                diagnostics.push(
+                    Severity::Info,
+                    "cache-disabled",
                     format!(
-                        "{}{} {}:\n{}\n",
-                        msg,
-                        console::Emoji("📖", "in"),
-                        recipe.config_path.display(),
-                        missing_deps.join("\n"),
-                    )
-                } else {
-                    msg
+                        "Recipe {} declares a cache but no cache is enabled in the project",
+                        recipe.full_name()
+                    ),
+                    Some(recipe.config_path.clone()),
+                );
+            }
+            if cache.inputs.is_empty() {
+                diagnostics.push(
+                    Severity::Warning,
+                    "cache-without-inputs",
+                    format!(
+                        "Recipe {} is cached but declares no inputs, file changes won't trigger a rebuild",
+                        recipe.full_name()
+                    ),
+                    Some(recipe.config_path.clone()),
+                );
+            }
+        }
+
+        diagnostics
+    }
+
+    pub fn create_project_bake_dirs(&self) -> anyhow::Result<()> {
+        // Create .bake directories
+        if let Err(err) = std::fs::create_dir_all(self.get_project_bake_path()) {
+            bail!("Could not create .bake directory: {}", err);
+        };
+
+        if let Err(err) = std::fs::create_dir_all(self.get_project_log_path()) {
+            bail!("Could not create logs directory: {}", err);
+        };
+
+        for cookbook in self.cookbooks.keys() {
+            let log_path = self.get_project_log_path().join(cookbook);
+            if let Err(err) = std::fs::create_dir_all(&log_path) {
+                bail!(
+                    "Could not create logs directory {}: {}",
+                    log_path.display(),
+                    err
+                );
+            };
+        }
+
+        Ok(())
+    }
+
+    /// Checks that all cookbook and recipe names fully match the configured `name_pattern`
+    fn validate_name_pattern(&self) -> anyhow::Result<()> {
+        let Some(pattern) = self.config.name_pattern.as_ref() else {
+            return Ok(());
+        };
+        let regex = match Regex::new(&format!("^(?:{})$", pattern)) {
+            Ok(regex) => regex,
+            Err(err) => bail!(BakeError::ValidationError(format!(
+                "Invalid name_pattern '{}': {}",
+                pattern, err
+            ))),
+        };
+
+        let invalid_names: Vec<&String> = self
+            .cookbooks
+            .keys()
+            .chain(self.recipes.values().map(|recipe| &recipe.name))
+            .filter(|name| !regex.is_match(name))
+            .collect::<std::collections::BTreeSet<_>>()
+            .into_iter()
+            .collect();
+        if !invalid_names.is_empty() {
+            bail!(BakeError::ValidationError(format!(
+                "Names not matching name_pattern '{}': {}",
+                pattern,
+                invalid_names
+                    .iter()
+                    .map(|name| name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )));
+        }
+        Ok(())
+    }
+
+    /// Returns the names offered when completing a recipe pattern in a shell: the fully qualified
+    /// name of every recipe and the `cookbook:` and `:recipe` patterns, sorted
+    ///
+    /// Only the names are read from the cookbook files, without loading the project, since it
+    /// runs on every TAB. Cookbooks that can't be read and remote cookbooks are left out, and
+    /// nothing is returned outside of a project.
+    ///
+    /// # Arguments
+    /// * `path` - Path to either a config file or a directory in the project
+    ///
+    pub fn completion_names(path: &Path) -> Vec<String> {
+        let config_path = if path.is_file() {
+            path.to_path_buf()
+        } else {
+            match Self::find_config_file_in_dir(path) {
+                Ok(config_path) => config_path,
+                Err(_) => return Vec::new(),
+            }
+        };
+        let mut names = BTreeSet::new();
+        for file in Cookbook::find_files(config_path.parent().unwrap()) {
+            let Ok((cookbook, recipes)) = Cookbook::recipe_names(&file) else {
+                continue;
+            };
+            for recipe in recipes {
+                names.insert(format!("{}:{}", cookbook, recipe));
+                names.insert(format!(":{}", recipe));
+            }
+            names.insert(format!("{}:", cookbook));
+        }
+        names.into_iter().collect()
+    }
+
+    /// Recursively find a config file in a directory or its parent up until /
+    /// or until the git repo root.
+    fn find_config_file_in_dir(dir: &Path) -> anyhow::Result<PathBuf> {
+        let file_yml = dir.join("bake.yml");
+        let file_yaml = dir.join("bake.yaml");
+
+        if file_yml.exists() {
+            Ok(file_yml)
+        } else if file_yaml.exists() {
+            Ok(file_yaml)
+        } else {
+            let parent = dir.parent();
+
+            // Stop if directory is root in the file system or in a git repository
+            if let Some(parent) = parent {
+                if !dir.join(".git").is_dir() {
+                    return Self::find_config_file_in_dir(&PathBuf::from(parent));
                 }
+            }
+
+            bail!(BakeError::ConfigNotFound {
+                path: dir.to_path_buf()
             });
+        }
+    }
+
+    /// Returns a list of recipes matching a search, including all dependent recipes
+    /// recursively
+    ///
+    /// # Arguments
+    /// * `search` - Criteria used to select the recipes
+    ///
+    pub fn get_recipes(&self, search: &RecipeSearch) -> BTreeMap<String, Recipe> {
+        if *search == RecipeSearch::All {
+            return self.recipes.clone();
+        }
+        let filtered_recipes: BTreeMap<String, Recipe> = self
+            .recipes
+            .iter()
+            .filter(|(name, _)| search.matches(name))
+            .map(|(name, recipe)| (name.clone(), recipe.clone()))
+            .collect();
+
+        let mut recipes = filtered_recipes
+            .keys()
+            .flat_map(|name| {
+                self.dependency_map
+                    .get(name)
+                    .unwrap()
+                    .iter()
+                    .map(|dep| {
+                        let dep_recipe = self.recipes.get(dep).unwrap().clone();
+                        (dep.clone(), dep_recipe)
+                    })
+                    .collect::<Vec<(String, Recipe)>>()
+            })
+            .collect::<BTreeMap<String, Recipe>>();
+
+        recipes.extend(filtered_recipes);
+
+        recipes
+    }
+
+    /// Returns up to 3 existing recipe names, cookbook patterns (eg. `foo:`) or fully qualified
+    /// names closest to a pattern that matches no recipes, closest first, to suggest fixes for
+    /// typos
+    ///
+    /// # Arguments
+    /// * `pattern` - The pattern that matched no recipes
+    ///
+    pub fn suggest_patterns(&self, pattern: &str) -> Vec<String> {
+        let max_distance = (pattern.chars().count() / 3).max(1);
+        let candidates: BTreeSet<String> = self
+            .recipes
+            .iter()
+            .flat_map(|(fqn, recipe)| {
+                [
+                    fqn.clone(),
+                    format!("{}:", recipe.cookbook),
+                    recipe.name.clone(),
+                ]
+            })
+            .collect();
+        let mut suggestions: Vec<(usize, String)> = candidates
+            .into_iter()
+            .map(|candidate| (edit_distance(pattern, &candidate), candidate))
+            .filter(|(distance, _)| *distance <= max_distance)
+            .collect();
+        suggestions.sort();
+        suggestions
+            .into_iter()
+            .take(3)
+            .map(|(_, candidate)| candidate)
+            .collect()
+    }
+
+    /// Removes the recipes that don't run on a platform from the recipes to bake
+    ///
+    /// Fails if one of the remaining recipes depends on a removed one, since it couldn't be baked
+    /// without it.
+    ///
+    /// # Arguments
+    /// * `recipes` - Recipes to bake, as returned by `get_recipes`
+    /// * `platform` - Platform the recipes are baked on
+    ///
+    pub fn filter_platform(
+        &self,
+        mut recipes: BTreeMap<String, Recipe>,
+        platform: &Platform,
+    ) -> anyhow::Result<BTreeMap<String, Recipe>> {
+        let unsupported: BTreeSet<String> = recipes
+            .iter()
+            .filter(|(_, recipe)| !recipe.supports_platform(platform))
+            .map(|(name, _)| name.clone())
+            .collect();
+        if unsupported.is_empty() {
+            return Ok(recipes);
+        }
+
+        for name in recipes.keys().filter(|name| !unsupported.contains(*name)) {
+            let dependencies = self.dependency_map.get(name).into_iter().flatten();
+            if let Some(dependency) = dependencies
+                .filter(|dependency| unsupported.contains(*dependency))
+                .min()
+            {
+                bail!(
+                    "Recipe {} depends on {}, which only runs on {} and not on {}",
+                    name,
+                    dependency,
+                    self.recipes[dependency].platforms.join(", "),
+                    platform
+                );
+            }
+        }
+
+        for name in &unsupported {
+            debug!(
+                "Skipping recipe {}, which doesn't run on {}",
+                name, platform
+            );
+            recipes.remove(name);
+        }
+        Ok(recipes)
+    }
+
+    /// Parses a list of fully qualified recipe names, one per line, validating that each of them
+    /// exists in the project. Empty lines are ignored.
+    ///
+    /// # Arguments
+    /// * `list` - Newline separated list of recipe names (eg. `foo:build`)
+    ///
+    pub fn parse_recipe_list(&self, list: &str) -> anyhow::Result<Vec<String>> {
+        let mut names = Vec::new();
+        let mut errors = Vec::new();
+
+        for (idx, line) in list.lines().enumerate() {
+            let name = line.trim();
+            if name.is_empty() {
+                continue;
+            }
 
-        if !err_msg.is_empty() {
+            if !name.contains(':') {
+                errors.push(format!(
+                    "\tline {}: '{}' is not a fully qualified recipe name (<cookbook>:<recipe>)",
+                    idx + 1,
+                    name
+                ));
+            } else if !self.recipes.contains_key(name) {
+                errors.push(format!("\tline {}: recipe '{}' not found", idx + 1, name));
+            } else if !names.iter().any(|n| n == name) {
+                names.push(name.to_owned());
+            }
+        }
+
+        if !errors.is_empty() {
             bail!(
                 "{}:\n{}",
-                console::style("Recipe dependencies not found").bold(),
-                err_msg
+                console::style("Invalid recipe list").bold(),
+                errors.join("\n")
             );
         }
 
-        // Validate if project doesn't have circular dependencies
-        match project.get_dependencies() {
-            Ok(deps) => {
-                project.dependency_map = deps;
+        Ok(names)
+    }
+
+    /// Returns the full dependency graph of the project as JSON, for use in external tools
+    ///
+    /// The graph has a node per recipe and an edge from each recipe to each of its direct
+    /// dependencies, regardless of which recipes would be baked.
+    pub fn dependency_graph_json(&self) -> serde_json::Value {
+        let nodes: Vec<serde_json::Value> = self
+            .recipes
+            .iter()
+            .map(|(fqn, recipe)| {
+                json!({
+                    "fqn": fqn,
+                    "cookbook": recipe.cookbook,
+                    "name": recipe.name,
+                    "description": recipe.description,
+                })
+            })
+            .collect();
+        let edges: Vec<serde_json::Value> = self
+            .recipes
+            .iter()
+            .flat_map(|(fqn, recipe)| {
+                recipe.dependencies.iter().flatten().map(move |dependency| {
+                    json!({
+                        "from": fqn,
+                        "to": dependency.recipe,
+                        "order_only": dependency.order_only,
+                    })
+                })
+            })
+            .collect();
+        json!({ "nodes": nodes, "edges": edges })
+    }
+
+    /// Returns the recipes a search would bake, including their dependencies, under a header per
+    /// group with the number of recipes it has
+    ///
+    /// A recipe's level is 0 when it has no dependencies and one more than its deepest dependency
+    /// otherwise.
+    ///
+    /// # Arguments
+    /// * `search` - Criteria used to select the recipes
+    /// * `group_by` - Whether recipes are grouped by cookbook or by level
+    ///
+    pub fn format_plan(&self, search: &RecipeSearch, group_by: PlanGroupBy) -> String {
+        fn level(project: &BakeProject, fqn: &str, levels: &mut BTreeMap<String, usize>) -> usize {
+            if let Some(level) = levels.get(fqn) {
+                return *level;
+            }
+            let level = project.recipes[fqn]
+                .dependencies
+                .iter()
+                .flatten()
+                .map(|dependency| level(project, &dependency.recipe, levels) + 1)
+                .max()
+                .unwrap_or(0);
+            levels.insert(fqn.to_owned(), level);
+            level
+        }
+
+        let recipes = self.get_recipes(search);
+        let mut levels = BTreeMap::new();
+        let mut groups: BTreeMap<(usize, String), Vec<&str>> = BTreeMap::new();
+        for (fqn, recipe) in &recipes {
+            let group = match group_by {
+                PlanGroupBy::Cookbook => (0, recipe.cookbook.clone()),
+                PlanGroupBy::Level => {
+                    let level = level(self, fqn, &mut levels);
+                    (level, format!("level {}", level))
+                }
+            };
+            groups.entry(group).or_default().push(fqn);
+        }
+
+        let count = |count: usize, noun: &str| {
+            format!("{} {}{}", count, noun, if count == 1 { "" } else { "s" })
+        };
+        let mut plan = String::new();
+        for ((_, name), fqns) in &groups {
+            plan.push_str(&format!("{} ({})\n", name, count(fqns.len(), "recipe")));
+            for fqn in fqns {
+                plan.push_str(&format!("  {}\n", fqn));
+            }
+        }
+        plan.push_str(&format!(
+            "{} in {}",
+            count(recipes.len(), "recipe"),
+            count(groups.len(), "group")
+        ));
+        plan
+    }
+
+    /// Returns whether a recipe's results are cached: it declares a cache and either outputs or
+    /// `cache.cache_empty_outputs` is enabled
+    pub fn is_cached(&self, recipe: &Recipe) -> bool {
+        recipe
+            .cache
+            .as_ref()
+            .is_some_and(|cache| !cache.outputs.is_empty() || self.config.cache.cache_empty_outputs)
+    }
+
+    /// Returns the run command of a recipe exactly as it will be run, after variables and
+    /// templates are rendered
+    ///
+    /// # Arguments
+    /// * `fqn` - Fully qualified name of the recipe, such as `foo:build`
+    ///
+    pub fn rendered_run(&self, fqn: &str) -> anyhow::Result<&str> {
+        match self.recipes.get(fqn) {
+            Some(recipe) => Ok(&recipe.run),
+            None => bail!("Recipe {} not found", fqn),
+        }
+    }
+
+    /// Returns a human readable description of a recipe's resolved definition: its variables,
+    /// environment, dependencies and dependents, cache configuration and rendered run command
+    ///
+    /// # Arguments
+    /// * `fqn` - Fully qualified name of the recipe, such as `foo:build`
+    /// * `sorted` - Whether variables and environment entries are sorted by name instead of
+    ///   listed in declaration order
+    ///
+    pub fn describe_recipe(&self, fqn: &str, sorted: bool) -> anyhow::Result<String> {
+        let Some(recipe) = self.recipes.get(fqn) else {
+            bail!("Recipe {} not found", fqn);
+        };
+
+        let mut lines = vec![console::style(fqn).bold().to_string()];
+        lines.push(format!("  Cookbook: {}", recipe.cookbook));
+        if let Some(description) = recipe.description.as_ref() {
+            lines.push(format!("  Description: {}", description));
+        }
+
+        let mut section = |title: &str, entries: Vec<String>| {
+            if !entries.is_empty() {
+                lines.push(format!("  {}:", title));
+                lines.extend(entries.iter().map(|entry| format!("    {}", entry)));
+            }
+        };
+        let mut variables: Vec<String> = recipe
+            .variables
+            .iter()
+            .map(|(name, value)| format!("{} = {}", name, value))
+            .collect();
+        let mut environment = recipe.environment.clone();
+        if sorted {
+            variables.sort();
+            environment.sort();
+        }
+        section("Variables", variables);
+        section("Environment", environment);
+        section(
+            "Dependencies",
+            recipe
+                .dependencies
+                .iter()
+                .flatten()
+                .map(RecipeDependency::to_string)
+                .collect(),
+        );
+        section(
+            "Dependents",
+            self.recipes
+                .iter()
+                .filter(|(_, other)| {
+                    other
+                        .dependencies
+                        .iter()
+                        .flatten()
+                        .any(|dependency| dependency.recipe == fqn)
+                })
+                .map(|(name, _)| name.clone())
+                .collect(),
+        );
+        section("Platforms", recipe.platforms.clone());
+        if let Some(cache) = recipe.cache.as_ref() {
+            section(
+                "Cache",
+                vec![
+                    format!("inputs: {}", cache.inputs.join(", ")),
+                    format!(
+                        "outputs: {}",
+                        cache
+                            .outputs
+                            .iter()
+                            .map(|output| output.path.as_str())
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    ),
+                ],
+            );
+        }
+        if let Some(timeout) = recipe.timeout {
+            section("Timeout", vec![format!("{}s", timeout)]);
+        }
+        match recipe.run_file.as_ref() {
+            Some(run_file) => section("Run file", vec![run_file.clone()]),
+            None => section("Run", recipe.run.lines().map(str::to_owned).collect()),
+        }
+
+        Ok(lines.join("\n"))
+    }
+
+    /// Selects recipes from a list of patterns, one per line, each matching recipes the same way as
+    /// the recipe argument
+    ///
+    /// Lines starting with `-` remove the recipes they match from the selection, regardless of
+    /// their position in the list. Lines starting with `#` and empty lines are ignored.
+    ///
+    /// # Arguments
+    /// * `selection` - Newline separated list of patterns (eg. `foo:` or `-:test`)
+    ///
+    pub fn parse_selection(&self, selection: &str) -> Vec<String> {
+        let mut includes = Vec::new();
+        let mut excludes = Vec::new();
+        for line in selection.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
             }
-            Err(circular_dependency) => {
-                let message = circular_dependency.iter().fold("".to_owned(), |acc, x| {
-                    format!("{}\n{}", acc, x.join(" => "))
-                });
-                bail!("Circular dependencies detected:\n{:}", message);
+            match line.strip_prefix('-') {
+                Some(pattern) => excludes.push(RecipeSearch::ByPattern(pattern.trim().to_owned())),
+                None => includes.push(RecipeSearch::ByPattern(line.to_owned())),
             }
         }
 
-        Ok(project)
+        self.recipes
+            .keys()
+            .filter(|name| includes.iter().any(|search| search.matches(name)))
+            .filter(|name| !excludes.iter().any(|search| search.matches(name)))
+            .cloned()
+            .collect()
     }
 
-    pub fn create_project_bake_dirs(&self) -> anyhow::Result<()> {
-        // Create .bake directories
-        if let Err(err) = std::fs::create_dir_all(self.get_project_bake_path()) {
-            bail!("Could not create .bake directory: {}", err);
-        };
+    /// Returns the recipes selected by a search without the ones whose fully qualified name
+    /// matches any of the exclude patterns
+    ///
+    /// Exclusions apply before dependencies are added, so dependencies of the remaining recipes
+    /// are still baked even when they match an exclude pattern.
+    ///
+    /// # Arguments
+    /// * `search` - Criteria used to select the recipes
+    /// * `excludes` - Regular expressions matched against fully qualified names (eg. `^foo:`)
+    ///
+    pub fn exclude_recipes(
+        &self,
+        search: &RecipeSearch,
+        excludes: &[String],
+    ) -> anyhow::Result<Vec<String>> {
+        let excludes = excludes
+            .iter()
+            .map(|pattern| match Regex::new(pattern) {
+                Ok(regex) => Ok(regex),
+                Err(err) => bail!("Invalid exclude pattern {}: {}", pattern, err),
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
 
-        if let Err(err) = std::fs::create_dir_all(self.get_project_log_path()) {
-            bail!("Could not create logs directory: {}", err);
-        };
+        Ok(self
+            .recipes
+            .keys()
+            .filter(|name| search.matches(name))
+            .filter(|name| !excludes.iter().any(|regex| regex.is_match(name)))
+            .cloned()
+            .collect())
+    }
 
+    /// Passes the arguments given after `--` to the single recipe selected by a search
+    ///
+    /// Dependencies of the recipe don't get them. Fails unless exactly one recipe is selected,
+    /// since arguments meant for one command rarely make sense for another.
+    ///
+    /// # Arguments
+    /// * `search` - Criteria used to select the recipes
+    /// * `args` - Arguments to pass to the recipe
+    ///
+    pub fn set_recipe_args(
+        &mut self,
+        search: &RecipeSearch,
+        args: Vec<String>,
+    ) -> anyhow::Result<()> {
+        let selected: Vec<&String> = self
+            .recipes
+            .keys()
+            .filter(|name| search.matches(name))
+            .collect();
+        let [name] = selected[..] else {
+            bail!(
+                "Arguments after -- can only be passed to a single recipe, but {} recipes are \
+                 selected",
+                selected.len()
+            );
+        };
+        let name = name.clone();
+        self.recipes.get_mut(&name).unwrap().args = args;
         Ok(())
     }
 
-    /// Recursively find a config file in a directory or its parent up until /
-    /// or until the git repo root.
-    fn find_config_file_in_dir(dir: &Path) -> anyhow::Result<PathBuf> {
-        let file_yml = dir.join("bake.yml");
-        let file_yaml = dir.join("bake.yaml");
+    /// Returns the recipes selected by an alias, the union of the recipes matched by each of its
+    /// patterns
+    ///
+    /// Patterns starting with `@` include the recipes of another alias.
+    ///
+    /// # Arguments
+    /// * `alias` - Name of the alias, without the leading `@`
+    ///
+    pub fn expand_alias(&self, alias: &str) -> anyhow::Result<Vec<String>> {
+        fn collect_patterns<'a>(
+            project: &'a BakeProject,
+            alias: &'a str,
+            path: &mut Vec<&'a str>,
+            patterns: &mut Vec<&'a str>,
+        ) -> anyhow::Result<()> {
+            if path.contains(&alias) {
+                path.push(alias);
+                bail!("Circular alias: @{}", path.join(" -> @"));
+            }
+            let Some(entries) = project.aliases.get(alias) else {
+                match path.last() {
+                    Some(parent) => bail!("Alias @{} includes unknown alias @{}", parent, alias),
+                    None => bail!("Alias @{} not found", alias),
+                }
+            };
+            path.push(alias);
+            for entry in entries {
+                match entry.strip_prefix('@') {
+                    Some(included) => collect_patterns(project, included, path, patterns)?,
+                    None => patterns.push(entry),
+                }
+            }
+            path.pop();
+            Ok(())
+        }
 
-        if file_yml.exists() {
-            Ok(file_yml)
-        } else if file_yaml.exists() {
-            Ok(file_yaml)
-        } else {
-            let parent = dir.parent();
+        let mut patterns = Vec::new();
+        collect_patterns(self, alias, &mut Vec::new(), &mut patterns)?;
+        let searches: Vec<RecipeSearch> = patterns
+            .into_iter()
+            .map(|pattern| RecipeSearch::ByPattern(pattern.to_owned()))
+            .collect();
+        Ok(self
+            .recipes
+            .keys()
+            .filter(|name| searches.iter().any(|search| search.matches(name)))
+            .cloned()
+            .collect())
+    }
 
-            // Stop if directory is root in the file system or in a git repository
-            if let Some(parent) = parent {
-                if !dir.join(".git").is_dir() {
-                    return Self::find_config_file_in_dir(&PathBuf::from(parent));
+    /// Returns the recipes whose results are folded into a recipe's cache key: its dependencies
+    /// and theirs, recursively, without following order-only dependencies
+    ///
+    /// # Arguments
+    /// * `fqn` - Fully qualified name of the recipe, such as `foo:build`
+    ///
+    pub fn cache_dependencies(&self, fqn: &str) -> BTreeSet<String> {
+        let mut dependencies = BTreeSet::new();
+        let mut pending = vec![fqn];
+        while let Some(name) = pending.pop() {
+            let Some(recipe) = self.recipes.get(name) else {
+                continue;
+            };
+            for dependency in recipe.dependencies.iter().flatten() {
+                if !dependency.order_only && dependencies.insert(dependency.recipe.clone()) {
+                    pending.push(&dependency.recipe);
                 }
             }
-
-            bail!("Could not find bake.yml");
         }
+        dependencies
     }
 
-    /// Returns a list of recipes given a recipe name pattern, including all dependent
-    /// recipes recursively
+    /// Returns the maximum number of recipes of a cookbook baked at once, from the cookbook's
+    /// `max_parallel` or else `config.cookbook_max_parallel`. None if it's unlimited
     ///
     /// # Arguments
-    /// * `cookbook_name` - Cookbook name
-    /// * `recipe_name` - Recipe name
+    /// * `cookbook` - Name of the cookbook
     ///
-    pub fn get_recipes(&self, pattern: Option<&str>) -> BTreeMap<String, Recipe> {
-        if let Some(pattern) = pattern {
-            let filtered_recipes: BTreeMap<String, Recipe> = self
-                .recipes
-                .iter()
-                .filter_map(|(name, recipe)| {
-                    if name.contains(pattern) {
-                        Some((name.clone(), recipe.clone()))
-                    } else {
-                        None
-                    }
-                })
-                .collect();
-
-            let mut recipes = filtered_recipes
-                .iter()
-                .flat_map(|(name, _)| {
-                    self.dependency_map
-                        .get(name)
-                        .unwrap()
-                        .iter()
-                        .map(|dep| {
-                            let dep_recipe = self.recipes.get(dep).unwrap().clone();
-                            (dep.clone(), dep_recipe)
-                        })
-                        .collect::<Vec<(String, Recipe)>>()
-                })
-                .collect::<BTreeMap<String, Recipe>>();
-
-            recipes.extend(filtered_recipes.clone());
-
-            recipes
-        } else {
-            self.recipes.clone()
-        }
+    pub fn cookbook_max_parallel(&self, cookbook: &str) -> Option<NonZeroUsize> {
+        self.cookbooks
+            .get(cookbook)
+            .and_then(|cookbook| cookbook.max_parallel)
+            .or(self.config.cookbook_max_parallel)
     }
 
     /// Returns a map of all direct and indirect dependencies of all recipes if there are no circular dependencies
@@ -309,7 +1388,8 @@ impl BakeProject {
                 .dependencies
                 .as_ref()
             {
-                dependencies.iter().for_each(|dep_name| {
+                dependencies.iter().for_each(|dep| {
+                    let dep_name = &dep.recipe;
                     // If current path contains dep_name, then it has a circular dependency.
                     // Add it to the result variable of Context
                     if ctx.cur_path.contains(dep_name) {
@@ -342,15 +1422,40 @@ impl BakeProject {
         }
     }
 
+    /// Returns the log file of a recipe, `.bake/logs/<cookbook>/<recipe>.log`
+    ///
+    /// Logs are grouped in a directory per cookbook so that names containing dots can't collide.
     pub fn get_recipe_log_path(&self, recipe_name: &str) -> PathBuf {
+        let (cookbook, recipe) = recipe_name.split_once(':').unwrap_or(("", recipe_name));
         self.get_project_log_path()
-            .join(format!("{}.log", recipe_name.replace(':', ".")))
+            .join(cookbook)
+            .join(format!("{}.log", recipe))
+    }
+
+    /// Returns the directory a recipe's outputs are moved to while it runs with `atomic_outputs`,
+    /// `.bake/backup/<cookbook>/<recipe>`, grouped per cookbook like logs
+    pub fn get_recipe_backup_path(&self, recipe_name: &str) -> PathBuf {
+        let (cookbook, recipe) = recipe_name.split_once(':').unwrap_or(("", recipe_name));
+        self.get_project_bake_path()
+            .join("backup")
+            .join(cookbook)
+            .join(recipe)
     }
 
-    fn get_project_log_path(&self) -> PathBuf {
+    pub fn get_project_log_path(&self) -> PathBuf {
         self.get_project_bake_path().join("logs")
     }
 
+    /// Path of the cache of input file hashes kept between runs
+    pub fn get_file_hash_cache_path(&self) -> PathBuf {
+        self.get_project_bake_path().join("file-hashes.json")
+    }
+
+    /// Path of the config file the project was loaded from
+    pub fn config_path(&self) -> &Path {
+        &self.source.config_path
+    }
+
     pub fn get_project_bake_path(&self) -> PathBuf {
         self.root_path.join(".bake")
     }
@@ -363,6 +1468,13 @@ mod tests {
     use indexmap::IndexMap;
     use test_case::test_case;
 
+    use crate::{
+        diagnostics::Severity,
+        error::BakeError,
+        project::{PlanGroupBy, RecipeSearch},
+        test_utils::TestProjectBuilder,
+    };
+
     fn config_path(path_str: &str) -> String {
         env!("CARGO_MANIFEST_DIR").to_owned() + "/resources/tests" + path_str
     }
@@ -425,12 +1537,681 @@ mod tests {
     #[test_case(config_path("/invalid/circular") => matches Err(_); "Circular dependencies")]
     #[test_case(config_path("/invalid/recipes") => matches Err(_); "Inexistent recipes")]
     #[test_case(config_path("/invalid/config") => matches Err(_); "Invalid config")]
+    #[test_case(config_path("/invalid/aliases") => matches Err(_); "Circular aliases")]
     #[test_case(config_path("/invalid/nobake/internal") => matches Err(_); "No bake file with .git root")]
     fn read_config(path_str: String) -> anyhow::Result<super::BakeProject> {
         std::env::set_var("TEST_BAKE_VAR", "test");
         super::BakeProject::from(&PathBuf::from(path_str), IndexMap::new())
     }
 
+    #[test]
+    fn dependency_graph_json() {
+        std::env::set_var("TEST_BAKE_VAR", "test");
+        let project =
+            super::BakeProject::from(&PathBuf::from(config_path("/valid")), IndexMap::new())
+                .unwrap();
+
+        let graph = project.dependency_graph_json();
+        assert_eq!(graph["nodes"].as_array().unwrap().len(), 7);
+        assert_eq!(graph["nodes"][0]["fqn"], "bar:build");
+        assert_eq!(graph["nodes"][0]["cookbook"], "bar");
+        let edges: Vec<(&str, &str)> = graph["edges"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|edge| (edge["from"].as_str().unwrap(), edge["to"].as_str().unwrap()))
+            .collect();
+        assert_eq!(
+            edges,
+            vec![
+                ("bar:test", "foo:build"),
+                ("bar:unique-recipe", "bar:build"),
+                ("foo:build", "foo:build-dep"),
+                ("foo:post-test", "foo:test"),
+                ("foo:test", "foo:build"),
+            ]
+        );
+    }
+
+    #[test]
+    fn describe_recipe() {
+        std::env::set_var("TEST_BAKE_VAR", "test");
+        let project =
+            super::BakeProject::from(&PathBuf::from(config_path("/valid")), IndexMap::new())
+                .unwrap();
+
+        let description =
+            console::strip_ansi_codes(&project.describe_recipe("foo:build", false).unwrap())
+                .to_string();
+        assert!(description.contains("  Dependencies:\n    foo:build-dep\n"));
+        assert!(description.contains("  Dependents:\n    bar:test\n    foo:test\n"));
+        assert!(description.contains("    outputs: target\n"));
+        assert!(description.contains(&format!(
+            "  Run:\n    ./build.sh build-bar test {}",
+            project.root_path.display()
+        )));
+        assert!(project.describe_recipe("foo:missing", false).is_err());
+    }
+
+    #[test_case(PlanGroupBy::Cookbook, RecipeSearch::All => "bar (1 recipe)
+  bar:build
+foo (3 recipes)
+  foo:build
+  foo:lint
+  foo:test
+4 recipes in 2 groups"; "By cookbook")]
+    #[test_case(PlanGroupBy::Level, RecipeSearch::All => "level 0 (2 recipes)
+  foo:build
+  foo:lint
+level 1 (1 recipe)
+  foo:test
+level 2 (1 recipe)
+  bar:build
+4 recipes in 3 groups"; "By level")]
+    #[test_case(PlanGroupBy::Cookbook, RecipeSearch::ByPattern("foo:test".to_owned()) => "foo (2 recipes)
+  foo:build
+  foo:test
+2 recipes in 1 group"; "Dependencies of the selection")]
+    fn format_plan(group_by: PlanGroupBy, search: RecipeSearch) -> String {
+        let project = TestProjectBuilder::new()
+            .with_cookbook("foo", &["build", "lint", "test"])
+            .with_cookbook("bar", &["build"])
+            .with_dependency("foo:test", "foo:build")
+            .with_dependency("bar:build", "foo:test")
+            .build();
+        project.format_plan(&search, group_by)
+    }
+
+    #[test]
+    fn describe_recipe_sorted() {
+        let describe = |variables: &str, environment: &str, sorted: bool| {
+            let cookbook = format!(
+                "name: foo\nrecipes:\n  build:\n    run: echo build\n    variables:\n{}    environment:\n{}",
+                variables, environment
+            );
+            let root_path = TestProjectBuilder::temp_dir(&[
+                ("bake.yml", "name: sorted\n"),
+                ("foo/cookbook.yml", &cookbook),
+            ]);
+            let project = super::BakeProject::from(&root_path, IndexMap::new()).unwrap();
+            project.describe_recipe("foo:build", sorted).unwrap()
+        };
+        let variables = ["      b: second\n", "      a: first\n"];
+        let environment = ["      - B_VAR\n", "      - A_VAR\n"];
+        let declared = (variables.concat(), environment.concat());
+        let reversed = (
+            variables.iter().rev().copied().collect::<String>(),
+            environment.iter().rev().copied().collect::<String>(),
+        );
+
+        // Declaration order is kept by default
+        assert_ne!(
+            describe(&declared.0, &declared.1, false),
+            describe(&reversed.0, &reversed.1, false)
+        );
+        let sorted = describe(&declared.0, &declared.1, true);
+        assert_eq!(sorted, describe(&reversed.0, &reversed.1, true));
+        assert!(console::strip_ansi_codes(&sorted).contains(
+            "  Variables:\n    a = first\n    b = second\n  Environment:\n    A_VAR\n    B_VAR\n"
+        ));
+    }
+
+    #[test]
+    fn rendered_run() {
+        std::env::set_var("TEST_BAKE_VAR", "test");
+        let project =
+            super::BakeProject::from(&PathBuf::from(config_path("/valid")), IndexMap::new())
+                .unwrap();
+
+        assert_eq!(
+            project.rendered_run("foo:build").unwrap(),
+            format!(
+                "./build.sh build-bar test {}\n",
+                project.root_path.display()
+            )
+        );
+        assert!(project.rendered_run("foo:missing").is_err());
+    }
+
+    #[test]
+    fn expand_alias() {
+        std::env::set_var("TEST_BAKE_VAR", "test");
+        let mut project =
+            super::BakeProject::from(&PathBuf::from(config_path("/valid")), IndexMap::new())
+                .unwrap();
+
+        assert_eq!(
+            project.expand_alias("build").unwrap(),
+            vec!["bar:unique-recipe", "foo:build", "foo:build-dep"]
+        );
+        assert_eq!(
+            project.expand_alias("ci").unwrap(),
+            vec![
+                "bar:unique-recipe",
+                "foo:build",
+                "foo:build-dep",
+                "foo:test"
+            ]
+        );
+        assert!(project.expand_alias("missing").is_err());
+
+        project
+            .aliases
+            .insert("build".to_owned(), vec!["@ci".to_owned()]);
+        let err = project.expand_alias("ci").unwrap_err();
+        assert_eq!(err.to_string(), "Circular alias: @ci -> @build -> @ci");
+    }
+
+    #[test]
+    fn parse_selection() {
+        std::env::set_var("TEST_BAKE_VAR", "test");
+        let project =
+            super::BakeProject::from(&PathBuf::from(config_path("/valid")), IndexMap::new())
+                .unwrap();
+
+        let names = project.parse_selection("# Everything in foo\nfoo:\n\n:unique\n");
+        assert_eq!(
+            names,
+            vec![
+                "bar:unique-recipe",
+                "foo:build",
+                "foo:build-dep",
+                "foo:post-test",
+                "foo:test"
+            ]
+        );
+
+        // Exclusions apply regardless of their position
+        let names = project.parse_selection("-:test\nfoo:\n  - foo:build-dep\n");
+        assert_eq!(names, vec!["foo:build", "foo:post-test"]);
+    }
+
+    #[test_case(RecipeSearch::All, &["^foo:"] => vec!["bar:build", "bar:test", "bar:unique-recipe"]; "Exclude cookbook")]
+    #[test_case(RecipeSearch::ByPattern("foo:".to_owned()), &["build$"] => vec!["foo:build-dep", "foo:post-test", "foo:test"]; "Exclude recipe in cookbook")]
+    #[test_case(RecipeSearch::ByPattern("foo:".to_owned()), &["foo:build", "test"] => Vec::<String>::new(); "Exclude everything")]
+    fn exclude_recipes(search: RecipeSearch, excludes: &[&str]) -> Vec<String> {
+        std::env::set_var("TEST_BAKE_VAR", "test");
+        let project =
+            super::BakeProject::from(&PathBuf::from(config_path("/valid")), IndexMap::new())
+                .unwrap();
+        let excludes: Vec<String> = excludes.iter().map(|e| e.to_string()).collect();
+        project.exclude_recipes(&search, &excludes).unwrap()
+    }
+
+    fn platform_project() -> super::BakeProject {
+        let mut project = TestProjectBuilder::new()
+            .with_cookbook("app", &["build", "sign", "test", "release"])
+            .with_dependency("app:release", "app:sign")
+            .with_dependency("app:release", "app:build")
+            .build();
+        for (name, platforms) in [
+            ("app:sign", vec!["macos"]),
+            ("app:test", vec!["linux", "x86_64-pc-windows-msvc"]),
+            ("app:release", vec!["aarch64"]),
+        ] {
+            project.recipes.get_mut(name).unwrap().platforms =
+                platforms.into_iter().map(String::from).collect();
+        }
+        project
+    }
+
+    fn platform(os: &str, arch: &str, target: &str) -> super::Platform {
+        super::Platform {
+            os: os.to_owned(),
+            arch: arch.to_owned(),
+            target: target.to_owned(),
+        }
+    }
+
+    #[test_case(platform("linux", "x86_64", "x86_64-unknown-linux-gnu"), RecipeSearch::All => vec!["app:build", "app:test"]; "Linux")]
+    #[test_case(platform("macos", "aarch64", "aarch64-apple-darwin"), RecipeSearch::All => vec!["app:build", "app:release", "app:sign"]; "Mac")]
+    #[test_case(platform("windows", "x86_64", "x86_64-pc-windows-msvc"), RecipeSearch::All => vec!["app:build", "app:test"]; "Target triple")]
+    #[test_case(platform("windows", "x86_64", "x86_64-pc-windows-gnu"), RecipeSearch::ByPattern("app:".to_owned()) => vec!["app:build"]; "Only any platform")]
+    fn filter_platform(platform: super::Platform, search: RecipeSearch) -> Vec<String> {
+        let project = platform_project();
+        project
+            .filter_platform(project.get_recipes(&search), &platform)
+            .unwrap()
+            .into_keys()
+            .collect()
+    }
+
+    #[test_case("foo:buidl" => vec!["foo:build"]; "Typo in recipe")]
+    #[test_case("fo:" => vec!["foo:"]; "Typo in cookbook")]
+    #[test_case("buil" => vec!["build"]; "Recipe name")]
+    #[test_case("uniqe-recipe" => vec!["unique-recipe"]; "Unique recipe")]
+    #[test_case("deploy" => Vec::<String>::new(); "Nothing close")]
+    fn suggest_patterns(pattern: &str) -> Vec<String> {
+        std::env::set_var("TEST_BAKE_VAR", "test");
+        let project =
+            super::BakeProject::from(&PathBuf::from(config_path("/valid")), IndexMap::new())
+                .unwrap();
+        project.suggest_patterns(pattern)
+    }
+
+    #[test]
+    fn filter_platform_missing_dependency() {
+        let project = platform_project();
+        let search = RecipeSearch::ByNames(vec![String::from("app:release")]);
+        let err = project
+            .filter_platform(
+                project.get_recipes(&search),
+                &platform("linux", "aarch64", "aarch64-unknown-linux-gnu"),
+            )
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Recipe app:release depends on app:sign, which only runs on macos and not on \
+             aarch64-unknown-linux-gnu"
+        );
+    }
+
+    #[test]
+    fn apply_environment() {
+        let root_path = TestProjectBuilder::temp_dir(&[]);
+        let config = "name: envs
+config:
+  cache:
+    order: [local, s3]
+    remotes:
+      s3:
+        bucket: shared
+overrides:
+  dev:
+    cache:
+      order: [local]
+      remotes: null
+  prod:
+    cache:
+      remotes:
+        s3:
+          bucket: prod
+      strict_remote: true
+  broken:
+    cache:
+      order: [ftp]
+";
+        let load = || super::BakeProject::from_str(config, &root_path, IndexMap::new()).unwrap();
+
+        let mut project = load();
+        project.apply_environment("dev").unwrap();
+        assert_eq!(project.config.cache.order, vec!["local"]);
+        assert!(project.config.cache.remotes.is_none());
+
+        let mut project = load();
+        project.apply_environment("prod").unwrap();
+        assert_eq!(project.config.cache.order, vec!["local", "s3"]);
+        let s3 = project.config.cache.remotes.as_ref().unwrap().s3.as_ref();
+        assert_eq!(s3.unwrap().bucket, "prod");
+        assert!(project.config.cache.strict_remote);
+
+        let mut project = load();
+        assert!(project.apply_environment("broken").is_err());
+        assert!(project.apply_environment("staging").is_err());
+        assert_eq!(project.config.cache.order, vec!["local", "s3"]);
+    }
+
+    #[test]
+    fn completion_names() {
+        let names = super::BakeProject::completion_names(&PathBuf::from(config_path("/valid/foo")));
+        assert_eq!(
+            names,
+            vec![
+                ":build",
+                ":build-dep",
+                ":post-test",
+                ":test",
+                ":unique-recipe",
+                "bar:",
+                "bar:build",
+                "bar:test",
+                "bar:unique-recipe",
+                "foo:",
+                "foo:build",
+                "foo:build-dep",
+                "foo:post-test",
+                "foo:test",
+            ]
+        );
+
+        let root_path = TestProjectBuilder::temp_dir(&[]);
+        assert!(super::BakeProject::completion_names(&root_path).is_empty());
+    }
+
+    #[test_case("${BAKE_TEST_RUNNER_TEMP}/bake-cache" => Ok(PathBuf::from("/runner/tmp/bake-cache")); "Environment variable")]
+    #[test_case("{{ project.root }}/{{ var.cache_dir }}" => Ok(PathBuf::from("/project/ci-cache")); "Template")]
+    #[test_case("/var/cache/bake" => Ok(PathBuf::from("/var/cache/bake")); "Plain path")]
+    #[test_case("${BAKE_TEST_UNSET}/bake-cache" => Err("Invalid config: Invalid cache.local.path ${BAKE_TEST_UNSET}/bake-cache: Environment variable BAKE_TEST_UNSET is not set".to_owned()); "Unset variable")]
+    #[test_case("{{ var.empty }}" => Err("Invalid config: cache.local.path {{ var.empty }} is empty once rendered".to_owned()); "Empty path")]
+    fn local_cache_path(path: &str) -> Result<PathBuf, String> {
+        std::env::set_var("BAKE_TEST_RUNNER_TEMP", "/runner/tmp");
+        let mut project = TestProjectBuilder::new().build();
+        project.config.cache.local.path = Some(PathBuf::from(path));
+        project.variables = IndexMap::from([
+            ("cache_dir".to_owned(), "ci-cache".to_owned()),
+            ("empty".to_owned(), String::new()),
+        ]);
+        project.root_path = PathBuf::from("/project");
+        project
+            .resolve_local_cache_path()
+            .map_err(|err| err.to_string())?;
+        Ok(project.config.cache.local.path.unwrap())
+    }
+
+    #[test]
+    fn apply_environment_variables() {
+        let cookbook = |name: &str| {
+            format!(
+                "name: {}
+recipes:
+  build:
+    run: echo {{{{ var.replicas }}}} {{{{ var.region }}}}
+  deploy:
+    run: echo {{{{ var.replicas }}}} {{{{ var.region }}}}
+",
+                name
+            )
+        };
+        let root_path = TestProjectBuilder::temp_dir(&[
+            ("app/cookbook.yml", &cookbook("app")),
+            ("api/cookbook.yml", &cookbook("api")),
+        ]);
+        let config = "name: envs
+variables:
+  replicas: '1'
+  region: eu
+  endpoint: localhost
+overrides:
+  prod:
+    cookbooks:
+      app:
+        region: us
+        endpoint: '{{ var.region }}.example.com'
+    recipes:
+      app:deploy:
+        replicas: '5'
+  broken:
+    recipes:
+      app:missing:
+        replicas: '5'
+";
+        let load = |vars: &[(&str, &str)]| {
+            let vars = vars
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect();
+            super::BakeProject::from_str(config, &root_path, vars).unwrap()
+        };
+        let runs = |project: &super::BakeProject| {
+            ["app:build", "app:deploy", "api:build", "api:deploy"]
+                .map(|fqn| project.recipes[fqn].run.trim().to_owned())
+        };
+
+        let mut project = load(&[]);
+        assert_eq!(runs(&project), ["echo 1 eu"; 4]);
+        project.apply_environment("prod").unwrap();
+        assert_eq!(
+            runs(&project),
+            ["echo 1 us", "echo 5 us", "echo 1 eu", "echo 1 eu"],
+            "overrides only apply to the targeted cookbook and recipe"
+        );
+        assert_eq!(project.variables["replicas"], "1");
+        assert_eq!(
+            project.recipes["app:build"].variables["endpoint"], "us.example.com",
+            "overrides are templates seeing other overridden variables"
+        );
+
+        let mut project = load(&[("replicas", "3"), ("region", "{{ region }}")]);
+        project.apply_environment("prod").unwrap();
+        assert_eq!(
+            project.recipes["app:deploy"].variables["replicas"], "3",
+            "--var takes precedence over environment overrides"
+        );
+        assert_eq!(
+            runs(&project)[1],
+            "echo 3 {{ region }}",
+            "--var values are used as they are"
+        );
+
+        let err = load(&[]).apply_environment("broken").unwrap_err();
+        assert!(err.to_string().contains("app:missing"));
+    }
+
+    #[test]
+    fn reload() {
+        let root_path = TestProjectBuilder::temp_dir(&[
+            ("bake.yml", "name: reload\n"),
+            (
+                "foo/cookbook.yml",
+                "name: foo\nrecipes:\n  build:\n    run: echo foo\n",
+            ),
+            (
+                "bar/cookbook.yml",
+                "name: bar\nrecipes:\n  build:\n    run: echo bar\n",
+            ),
+        ]);
+        let mut project = super::BakeProject::from(&root_path, IndexMap::new()).unwrap();
+        assert!(!project.is_stale());
+        assert!(project.reload().unwrap().is_empty());
+
+        // Only the changed cookbook is parsed again
+        let foo_path = root_path.join("foo/cookbook.yml");
+        std::fs::write(
+            &foo_path,
+            "name: foo\nrecipes:\n  build:\n    run: echo changed\n    dependencies: [bar:build]\n",
+        )
+        .unwrap();
+        assert!(project.is_stale());
+        assert_eq!(project.reload().unwrap(), vec![foo_path.clone()]);
+        assert!(!project.is_stale());
+        assert_eq!(project.recipes["foo:build"].run, "echo changed");
+        assert!(project.dependency_map["foo:build"].contains("bar:build"));
+
+        // So is a cookbook whose recipe files changed
+        let bar_path = root_path.join("bar/cookbook.yml");
+        std::fs::create_dir(root_path.join("bar/recipes")).unwrap();
+        std::fs::write(root_path.join("bar/recipes/test.yml"), "run: echo test\n").unwrap();
+        std::fs::write(
+            &bar_path,
+            "name: bar\nrecipes:\n  build:\n    run: echo bar\nrecipe_files: [recipes/test.yml]\n",
+        )
+        .unwrap();
+        assert_eq!(project.reload().unwrap(), vec![bar_path.clone()]);
+        std::fs::write(root_path.join("bar/recipes/test.yml"), "run: echo tested\n").unwrap();
+        assert!(project.is_stale());
+        assert_eq!(project.reload().unwrap(), vec![bar_path]);
+        assert!(!project.is_stale());
+        assert_eq!(project.recipes["bar:test"].run.trim(), "echo tested");
+
+        // A broken cookbook leaves the project as it was
+        std::fs::write(&foo_path, "name: foo\nrecipes: [").unwrap();
+        assert!(project.reload().is_err());
+        assert_eq!(project.recipes["foo:build"].run, "echo changed");
+
+        // Changing the config reloads everything
+        std::fs::write(
+            &foo_path,
+            "name: foo\nrecipes:\n  build:\n    run: echo foo\n",
+        )
+        .unwrap();
+        std::fs::write(root_path.join("bake.yml"), "name: renamed\n").unwrap();
+        let mut reloaded = project.reload().unwrap();
+        reloaded.sort();
+        assert_eq!(reloaded, vec![root_path.join("bar/cookbook.yml"), foo_path]);
+        assert_eq!(project.name, "renamed");
+    }
+
+    #[test]
+    fn recipes_from_list() {
+        std::env::set_var("TEST_BAKE_VAR", "test");
+        let project =
+            super::BakeProject::from(&PathBuf::from(config_path("/valid")), IndexMap::new())
+                .unwrap();
+
+        let names = project
+            .parse_recipe_list("foo:test\n\n  bar:build  \nfoo:test\n")
+            .unwrap();
+        assert_eq!(names, vec!["foo:test".to_owned(), "bar:build".to_owned()]);
+
+        let recipes = project.get_recipes(&super::RecipeSearch::ByNames(names));
+        assert_eq!(
+            recipes.keys().collect::<Vec<_>>(),
+            vec!["bar:build", "foo:build", "foo:build-dep", "foo:test"]
+        );
+
+        let err = project
+            .parse_recipe_list("foo:build\nfoo:nope\nbuild")
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("line 2: recipe 'foo:nope' not found"));
+        assert!(err.contains("line 3: 'build' is not a fully qualified recipe name"));
+    }
+
+    #[test]
+    fn error_variants() {
+        let load = |path: &str| {
+            super::BakeProject::from(&PathBuf::from(config_path(path)), IndexMap::new())
+                .unwrap_err()
+                .downcast::<BakeError>()
+                .unwrap()
+        };
+
+        assert!(matches!(
+            load("/invalid/asdf"),
+            BakeError::ConfigNotFound { .. }
+        ));
+        assert!(matches!(
+            load("/invalid/nobake/internal"),
+            BakeError::ConfigNotFound { .. }
+        ));
+        assert!(matches!(
+            load("/invalid/config"),
+            BakeError::ParseError { path, .. } if path.ends_with("config/cookbook.yml")
+        ));
+        assert!(matches!(
+            load("/invalid/circular"),
+            BakeError::CyclicDependency(cycles) if !cycles.is_empty()
+        ));
+        assert!(matches!(
+            load("/invalid/recipes"),
+            BakeError::MissingDependency(missing) if missing.len() == 2
+        ));
+
+        let err = super::BakeProject::from_str(
+            "name: test\nconfig:\n  cache:\n    order: [ftp]",
+            &PathBuf::from(config_path("/valid")),
+            IndexMap::new(),
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<BakeError>(),
+            Some(BakeError::ValidationError(_))
+        ));
+    }
+
+    #[test_case("[a-z-]+" => matches Ok(_); "All names match")]
+    #[test_case("[a-z]+" => matches Err(_); "Dashes not allowed")]
+    #[test_case("[a-z" => matches Err(_); "Invalid regex")]
+    fn name_pattern(pattern: &str) -> anyhow::Result<super::BakeProject> {
+        std::env::set_var("TEST_BAKE_VAR", "test");
+        super::BakeProject::from_str(
+            &format!("name: test\nconfig:\n  name_pattern: \"{}\"", pattern),
+            &PathBuf::from(config_path("/valid")),
+            IndexMap::new(),
+        )
+    }
+
+    #[test]
+    fn environment_precedence() {
+        std::env::remove_var("TEST_BAKE_ENV_LEVEL");
+        let project =
+            super::BakeProject::from(&PathBuf::from(config_path("/environment")), IndexMap::new())
+                .unwrap();
+
+        let recipe = &project.recipes["env:override"];
+        assert_eq!(
+            recipe.environment,
+            vec![
+                "TEST_BAKE_ENV_PROJECT=project",
+                "TEST_BAKE_ENV_LEVEL=recipe"
+            ]
+        );
+        assert_eq!(recipe.variables["level"], "recipe");
+        assert_eq!(recipe.run, "echo \"recipe\"");
+        assert_eq!(
+            crate::baker::recipe_environment(recipe, &project.config)
+                [std::ffi::OsStr::new("TEST_BAKE_ENV_LEVEL")],
+            "recipe"
+        );
+
+        let recipe = &project.recipes["env:inherited"];
+        assert_eq!(recipe.variables["level"], "cookbook");
+    }
+
+    #[test]
+    fn diagnostics() {
+        let project =
+            super::BakeProject::from(&PathBuf::from(config_path("/diagnostics")), IndexMap::new())
+                .unwrap();
+        let entries = project.diagnostics.entries();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].severity, Severity::Warning);
+        assert_eq!(entries[0].code, "cache-without-inputs");
+        assert!(entries[0].message.contains("diag:lint"));
+        assert_eq!(
+            entries[0].source,
+            Some(PathBuf::from(config_path("/diagnostics/cookbook.yml")))
+        );
+
+        let project = super::BakeProject::from_str(
+            "name: diagnostics\nconfig:\n  ignore_diagnostics: [cache-without-inputs]",
+            &PathBuf::from(config_path("/diagnostics")),
+            IndexMap::new(),
+        )
+        .unwrap();
+        assert!(project.diagnostics.entries().is_empty());
+    }
+
+    #[test]
+    fn from_str() {
+        std::env::set_var("TEST_BAKE_VAR", "test");
+        let config = std::fs::read_to_string(config_path("/valid/bake.yml")).unwrap();
+        let root = PathBuf::from(config_path("/valid"));
+        let project = super::BakeProject::from_str(&config, &root, IndexMap::new());
+        assert_eq!(project.as_ref().unwrap().root_path, root);
+        validate_project(project);
+
+        assert!(super::BakeProject::from_str("name: [", &root, IndexMap::new()).is_err());
+    }
+
+    #[test]
+    fn recipe_log_path() {
+        let project = crate::test_utils::TestProjectBuilder::new()
+            .with_cookbook("foo", &["bar.baz"])
+            .with_cookbook("foo.bar", &["baz"])
+            .build();
+        let first = project.get_recipe_log_path("foo:bar.baz");
+        let second = project.get_recipe_log_path("foo.bar:baz");
+        assert_ne!(first, second);
+        assert_eq!(
+            first,
+            project
+                .get_project_log_path()
+                .join("foo")
+                .join("bar.baz.log")
+        );
+
+        project.create_project_bake_dirs().unwrap();
+        std::fs::write(&first, "first").unwrap();
+        std::fs::write(&second, "second").unwrap();
+        assert_eq!(std::fs::read_to_string(&first).unwrap(), "first");
+
+        assert_ne!(
+            project.get_recipe_backup_path("foo:bar.baz"),
+            project.get_recipe_backup_path("foo.bar:baz")
+        );
+    }
+
     #[test]
     fn invalid_permission() {
         let path = config_path("/invalid/permission/bake.yml");
@@ -1,14 +1,80 @@
-use std::path::PathBuf;
+use std::{collections::BTreeMap, num::NonZeroUsize, path::PathBuf};
 
-use serde::Deserialize;
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
 
 use validator::{Validate, ValidationError};
 
-#[derive(Debug, Deserialize)]
+/// zstd compression level used for cache archives
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "CompressionLevelSetting", into = "CompressionLevelSetting")]
+pub enum CompressionLevel {
+    /// Picks a level based on the size of the archived files
+    Auto,
+    Level(i32),
+}
+
+impl Default for CompressionLevel {
+    fn default() -> Self {
+        Self::Level(1)
+    }
+}
+
+/// Compression level declaration, either a number or `auto`
+#[derive(Deserialize, Serialize)]
+#[serde(untagged)]
+enum CompressionLevelSetting {
+    Level(i32),
+    Mode(String),
+}
+
+impl From<CompressionLevel> for CompressionLevelSetting {
+    fn from(value: CompressionLevel) -> Self {
+        match value {
+            CompressionLevel::Auto => Self::Mode(String::from("auto")),
+            CompressionLevel::Level(level) => Self::Level(level),
+        }
+    }
+}
+
+impl TryFrom<CompressionLevelSetting> for CompressionLevel {
+    type Error = String;
+
+    fn try_from(value: CompressionLevelSetting) -> Result<Self, Self::Error> {
+        match value {
+            CompressionLevelSetting::Level(level) => Ok(Self::Level(level)),
+            CompressionLevelSetting::Mode(mode) if mode == "auto" => Ok(Self::Auto),
+            CompressionLevelSetting::Mode(mode) => Err(format!(
+                "invalid compression level '{}', expected a number or 'auto'",
+                mode
+            )),
+        }
+    }
+}
+
+/// Where the local cache keeps its entries
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LocalCacheBackend {
+    /// In the local cache directory
+    #[default]
+    Disk,
+
+    /// In memory, for tests and ephemeral runs that don't need entries to outlive the process
+    Memory,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
 pub struct LocalCacheConfig {
     #[serde(default = "bool_true_default")]
     pub enabled: bool,
     pub path: Option<PathBuf>,
+
+    #[serde(default)]
+    pub compression_level: CompressionLevel,
+
+    #[serde(default)]
+    pub backend: LocalCacheBackend,
 }
 
 impl Default for LocalCacheConfig {
@@ -16,28 +82,38 @@ impl Default for LocalCacheConfig {
         Self {
             enabled: true,
             path: None,
+            compression_level: CompressionLevel::default(),
+            backend: LocalCacheBackend::default(),
         }
     }
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct RemoteCacheConfig {
     pub s3: Option<S3CacheConfig>,
     pub gcs: Option<GcsCacheConfig>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct S3CacheConfig {
     pub bucket: String,
     pub region: Option<String>,
+
+    /// Command printing short-lived credentials as JSON, used instead of the default AWS
+    /// credentials chain
+    pub credentials_command: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct GcsCacheConfig {
     pub bucket: String,
+
+    /// Command printing a short-lived access token as JSON, used instead of the default Google
+    /// credentials
+    pub credentials_command: Option<String>,
 }
 
-#[derive(Debug, Deserialize, Validate)]
+#[derive(Debug, Deserialize, Serialize, Validate)]
 pub struct CacheConfig {
     #[serde(default)]
     pub local: LocalCacheConfig,
@@ -48,6 +124,32 @@ pub struct CacheConfig {
     #[validate(custom(function = "validate_order"))]
     #[serde(default)]
     pub order: Vec<String>,
+
+    /// Turns remote cache errors into build failures instead of warnings
+    #[serde(default)]
+    pub strict_remote: bool,
+
+    /// Fails recipes that don't produce all of their declared outputs instead of skipping caching
+    #[serde(default = "bool_true_default")]
+    pub require_outputs: bool,
+
+    /// Maximum size in bytes of a recipe's outputs and log, larger outputs aren't cached
+    pub max_output_size: Option<u64>,
+
+    /// Maximum size in bytes of all outputs cached during a run, outputs that don't fit aren't
+    /// cached
+    pub max_total_output_size: Option<u64>,
+
+    /// Number of times a remote cache operation failing with a timeout or a server error is
+    /// retried, with exponential backoff, before giving up. Misses are never retried
+    #[serde(default = "remote_retries_default")]
+    pub remote_retries: u32,
+
+    /// Cache recipes that declare no outputs, such as test runs, so that they're skipped while
+    /// their inputs don't change. Only their log is stored. Disable it to always run such
+    /// recipes, eg. deploys whose side effects shouldn't be skipped
+    #[serde(default = "bool_true_default")]
+    pub cache_empty_outputs: bool,
 }
 
 impl Default for CacheConfig {
@@ -57,6 +159,12 @@ impl Default for CacheConfig {
             local: LocalCacheConfig::default(),
             remotes: None,
             order: vec![],
+            strict_remote: false,
+            require_outputs: true,
+            max_output_size: None,
+            max_total_output_size: None,
+            remote_retries: remote_retries_default(),
+            cache_empty_outputs: true,
         }
     }
 }
@@ -74,9 +182,153 @@ fn validate_order(value: &[String]) -> Result<(), ValidationError> {
     }
 }
 
-#[derive(Debug, Deserialize, Validate)]
+/// Where the output of recipes goes while baking
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputCapture {
+    /// Written to the recipe's log file and printed to the console
+    Tee,
+
+    /// Only written to the recipe's log file
+    File,
+
+    /// Discarded, leaving the recipe's log file empty
+    Null,
+}
+
+/// Interpreter running the `run` command of recipes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Shell {
+    /// POSIX shell, run with `set -e`
+    #[default]
+    Sh,
+
+    /// Windows command interpreter, with each line of `run` chained with `&&`
+    Cmd,
+
+    /// Windows PowerShell, stopping on the first error
+    Powershell,
+
+    /// PowerShell 7 and later, stopping on the first error including failed native commands
+    Pwsh,
+}
+
+/// Settings overridden when baking with `--env <name>`
+#[derive(Debug, Deserialize, Default)]
+pub struct EnvironmentOverrides {
+    /// Fields of `config.cache` to override, merged into the project's cache config. Setting a
+    /// field to `null`, such as `remotes`, resets it
+    #[serde(default)]
+    pub cache: Option<serde_yaml::Value>,
+
+    /// Variables overridden only in the given cookbooks, keyed by cookbook name
+    #[serde(default)]
+    pub cookbooks: BTreeMap<String, IndexMap<String, String>>,
+
+    /// Variables overridden only in the given recipes, keyed by fully qualified name (eg.
+    /// `foo:build`). They take precedence over the overrides of the recipe's cookbook
+    #[serde(default)]
+    pub recipes: BTreeMap<String, IndexMap<String, String>>,
+}
+
+impl EnvironmentOverrides {
+    /// Returns whether the environment overrides any cookbook or recipe variables, which
+    /// requires loading the cookbooks again
+    pub fn has_variables(&self) -> bool {
+        !self.cookbooks.is_empty() || !self.recipes.is_empty()
+    }
+}
+
+impl ToolConfig {
+    /// Returns the shell running a recipe, its own `shell` setting taking precedence over the
+    /// project's `windows_shell`
+    ///
+    /// # Arguments
+    /// * `recipe_shell` - The recipe's `shell` setting
+    ///
+    pub fn shell(&self, recipe_shell: Option<Shell>) -> Shell {
+        recipe_shell.unwrap_or(if cfg!(windows) {
+            self.windows_shell
+        } else {
+            Shell::Sh
+        })
+    }
+
+    /// Returns where the output of a recipe goes, its own `output` setting taking precedence
+    /// over the project's
+    ///
+    /// # Arguments
+    /// * `recipe_output` - The recipe's `output` setting
+    ///
+    pub fn output_capture(&self, recipe_output: Option<OutputCapture>) -> OutputCapture {
+        recipe_output
+            .or(self.output_capture)
+            .unwrap_or(if self.verbose {
+                OutputCapture::Tee
+            } else {
+                OutputCapture::File
+            })
+    }
+}
+
+impl CacheConfig {
+    /// Merges overridden fields into the cache config. Nested mappings are merged and any other
+    /// value replaces the current one
+    ///
+    /// # Arguments
+    /// * `overrides` - Mapping of cache config fields, as they are declared in `config.cache`
+    ///
+    pub fn merge(&mut self, overrides: &serde_yaml::Value) -> anyhow::Result<()> {
+        fn merge_value(base: &mut serde_yaml::Value, overrides: &serde_yaml::Value) {
+            match (base, overrides) {
+                (serde_yaml::Value::Mapping(base), serde_yaml::Value::Mapping(overrides)) => {
+                    for (key, value) in overrides {
+                        match base.get_mut(key) {
+                            Some(current) => merge_value(current, value),
+                            None => {
+                                base.insert(key.clone(), value.clone());
+                            }
+                        }
+                    }
+                }
+                (base, overrides) => *base = overrides.clone(),
+            }
+        }
+
+        let mut value = serde_yaml::to_value(&*self)?;
+        merge_value(&mut value, overrides);
+        let merged: Self = serde_yaml::from_value(value)?;
+        merged.validate()?;
+        *self = merged;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Default)]
+pub struct HooksConfig {
+    /// Shell command run in the project root after a successful bake
+    pub on_success: Option<String>,
+
+    /// Shell command run in the project root after a bake with failed recipes
+    pub on_failure: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Default)]
+pub struct LogsConfig {
+    /// Maximum number of lines kept in a recipe's log file. Longer output keeps its first and last
+    /// lines, half of the limit each, around a marker counting the lines left out
+    pub max_lines: Option<NonZeroUsize>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Validate)]
 pub struct ToolConfig {
-    #[serde(default = "max_parallel_default")]
+    /// Maximum number of recipes baked at once, either a count or a fraction of the available
+    /// cores up to 1 such as `50%` or `0.5`
+    #[serde(
+        default = "max_parallel_default",
+        deserialize_with = "deserialize_max_parallel"
+    )]
     pub max_parallel: usize,
 
     #[serde(default)]
@@ -85,12 +337,86 @@ pub struct ToolConfig {
     #[serde(default)]
     pub verbose: bool,
 
+    /// Default destination of recipe output, recipes can override it with `output`. Defaults to
+    /// `tee` in verbose mode and `file` otherwise
+    #[serde(default)]
+    pub output_capture: Option<OutputCapture>,
+
     #[serde(default)]
     #[validate(nested)]
     pub cache: CacheConfig,
 
     #[serde(default)]
     pub clean_environment: bool,
+
+    /// Moves existing outputs aside while a recipe runs and restores them if it fails, so failed
+    /// runs never leave partially written outputs behind. Off by default since recipes then start
+    /// without their previous outputs, losing incremental build state
+    #[serde(default)]
+    pub atomic_outputs: bool,
+
+    /// Codes of diagnostics that shouldn't be reported (eg. `cache-without-inputs`)
+    #[serde(default)]
+    pub ignore_diagnostics: Vec<String>,
+
+    /// Regex that all cookbook and recipe names must fully match
+    pub name_pattern: Option<String>,
+
+    /// Total weight of the recipes running at once, defaults to `max_parallel` so that each
+    /// recipe takes one slot unless it sets a `weight`
+    pub total_capacity: Option<u32>,
+
+    /// Maximum number of recipes of the same cookbook baked at once, for cookbooks that don't
+    /// set their own `max_parallel`. Unlimited by default
+    pub cookbook_max_parallel: Option<NonZeroUsize>,
+
+    /// Timeout in seconds applied to recipes that don't set their own `timeout`
+    pub default_timeout: Option<u64>,
+
+    #[serde(default)]
+    pub hooks: HooksConfig,
+
+    #[serde(default)]
+    pub logs: LogsConfig,
+
+    /// Expand `${NAME}` references to declared environment variables in run commands, inputs and
+    /// outputs before rendering templates, so paths that aren't run by a shell can use them
+    #[serde(default)]
+    pub shell_style_vars: bool,
+
+    /// Shell running recipes without a `shell` of their own on Windows. Other platforms always
+    /// default to `sh`
+    #[serde(default)]
+    pub windows_shell: Shell,
+
+    /// Path to write a JUnit XML report to after baking
+    #[serde(skip_deserializing)]
+    pub summary_file: Option<PathBuf>,
+
+    /// Path to write a self-contained HTML report to after baking
+    #[serde(skip_deserializing)]
+    pub report_file: Option<PathBuf>,
+
+    /// File descriptor to stream recipe results to as newline delimited JSON while baking
+    #[serde(skip_deserializing)]
+    pub events_fd: Option<i32>,
+
+    /// Run recipes with `requires_clean_git` even when the git tree has uncommitted changes
+    #[serde(skip_deserializing)]
+    pub allow_dirty: bool,
+
+    /// Hash every input file instead of reusing the hashes of unchanged files from previous runs
+    #[serde(skip_deserializing)]
+    pub no_hash_cache: bool,
+
+    /// Guarantees bake makes no network requests by disabling remote caches
+    #[serde(skip_deserializing)]
+    pub no_network: bool,
+
+    /// Directory where outputs declared relative to the project root are written instead of the
+    /// project root, for this run only
+    #[serde(skip_deserializing)]
+    pub output_root: Option<PathBuf>,
 }
 
 impl Default for ToolConfig {
@@ -99,8 +425,26 @@ impl Default for ToolConfig {
             max_parallel: max_parallel_default(),
             fast_fail: true,
             verbose: false,
+            output_capture: None,
             cache: CacheConfig::default(),
             clean_environment: false,
+            atomic_outputs: false,
+            total_capacity: None,
+            cookbook_max_parallel: None,
+            default_timeout: None,
+            name_pattern: None,
+            ignore_diagnostics: vec![],
+            hooks: HooksConfig::default(),
+            logs: LogsConfig::default(),
+            shell_style_vars: false,
+            windows_shell: Shell::Sh,
+            summary_file: None,
+            report_file: None,
+            events_fd: None,
+            allow_dirty: false,
+            no_hash_cache: false,
+            no_network: false,
+            output_root: None,
         }
     }
 }
@@ -109,6 +453,79 @@ fn bool_true_default() -> bool {
     true
 }
 
+fn remote_retries_default() -> u32 {
+    2
+}
+
 fn max_parallel_default() -> usize {
     std::thread::available_parallelism().unwrap().get() - 1
 }
+
+/// `max_parallel` declaration, either a count or a fraction of the available cores
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum MaxParallelSetting {
+    Count(usize),
+    Fraction(f64),
+    Text(String),
+}
+
+fn deserialize_max_parallel<'de, D>(deserializer: D) -> Result<usize, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    // Only values with a decimal point or a `%` suffix are fractions, so '4' is still 4 recipes
+    let fraction = match MaxParallelSetting::deserialize(deserializer)? {
+        MaxParallelSetting::Count(count) => return Ok(count),
+        MaxParallelSetting::Fraction(fraction) => Some(fraction),
+        MaxParallelSetting::Text(text) => {
+            let text = text.trim();
+            match text.strip_suffix('%') {
+                Some(percent) => percent.trim().parse::<f64>().ok().map(|p| p / 100.0),
+                None if text.contains('.') => text.parse::<f64>().ok(),
+                None => match text.parse::<usize>() {
+                    Ok(count) => return Ok(count),
+                    Err(_) => None,
+                },
+            }
+        }
+    };
+    match fraction {
+        Some(fraction) if fraction > 0.0 && fraction <= 1.0 => {
+            let cores = std::thread::available_parallelism().unwrap().get();
+            Ok(((cores as f64 * fraction) as usize).max(1))
+        }
+        _ => Err(serde::de::Error::custom(
+            "invalid max_parallel, expected a number of recipes or a fraction of the cores between \
+             0 and 1 such as '50%' or '0.5'",
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::ToolConfig;
+
+    fn cores() -> usize {
+        std::thread::available_parallelism().unwrap().get()
+    }
+
+    #[test_case("max_parallel: 3" => Some(3); "Integer")]
+    #[test_case("max_parallel: '100%'" => Some(cores()); "All cores")]
+    #[test_case("max_parallel: '50%'" => Some((cores() / 2).max(1)); "Half of the cores")]
+    #[test_case("max_parallel: '0.5'" => Some((cores() / 2).max(1)); "Fraction string")]
+    #[test_case("max_parallel: 0.5" => Some((cores() / 2).max(1)); "Fraction")]
+    #[test_case("max_parallel: '1%'" => Some(1); "At least one")]
+    #[test_case("max_parallel: '-50%'" => None; "Negative")]
+    #[test_case("max_parallel: '4'" => Some(4); "Integer string")]
+    #[test_case("max_parallel: 1.5" => None; "Fraction above one")]
+    #[test_case("max_parallel: '150%'" => None; "Percentage above one hundred")]
+    #[test_case("max_parallel: half" => None; "Invalid")]
+    fn max_parallel(config: &str) -> Option<usize> {
+        serde_yaml::from_str::<ToolConfig>(config)
+            .ok()
+            .map(|config| config.max_parallel)
+    }
+}
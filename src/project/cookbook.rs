@@ -1,16 +1,24 @@
-use std::{collections::BTreeMap, path::PathBuf};
+use std::{
+    collections::BTreeMap,
+    num::NonZeroUsize,
+    path::{Component, Path, PathBuf},
+};
 
 use crate::{
-    project::Recipe,
-    template::{parse_template, parse_variable_list},
+    error::BakeError,
+    project::{config::EnvironmentOverrides, detect_run_inputs, Recipe, RecipeDependency},
+    template::{
+        expand_shell_vars, merge_environment, parse_template, parse_variable_list,
+        replace_variables, trace_inherited_variables,
+    },
 };
 use anyhow::bail;
 use ignore::WalkBuilder;
 use indexmap::IndexMap;
-use log::debug;
-use serde::Deserialize;
+use log::{debug, info};
+use serde::{de::IgnoredAny, Deserialize};
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct Cookbook {
     pub name: String,
 
@@ -20,16 +28,64 @@ pub struct Cookbook {
     #[serde(default)]
     pub variables: IndexMap<String, String>,
 
+    #[serde(default)]
     pub recipes: BTreeMap<String, Recipe>,
 
+    /// Files defining one recipe each, relative to the cookbook and named after the file, eg.
+    /// `recipes/build.yml` defines the `build` recipe
+    #[serde(default)]
+    pub recipe_files: Vec<String>,
+
+    /// Maximum number of the cookbook's recipes baked at once, eg. when they share a database,
+    /// on top of the project's `max_parallel`
+    pub max_parallel: Option<NonZeroUsize>,
+
     #[serde(skip)]
     pub config_path: PathBuf,
 }
+
+/// Fields of a cookbook file needed to list its recipes
+#[derive(Deserialize)]
+struct CookbookRecipeNames {
+    name: String,
+
+    #[serde(default)]
+    recipes: BTreeMap<String, IgnoredAny>,
+
+    #[serde(default)]
+    recipe_files: Vec<String>,
+}
+
 impl Cookbook {
+    /// Returns the name of the cookbook in a file and the names of its recipes, without
+    /// validating the cookbook or rendering its templates
+    ///
+    /// # Arguments
+    /// * `path` - Path to a cookbook file
+    ///
+    pub fn recipe_names(path: &Path) -> anyhow::Result<(String, Vec<String>)> {
+        let contents = std::fs::read_to_string(path)?;
+        let cookbook: CookbookRecipeNames = serde_yaml::from_str(&contents)?;
+        let names = cookbook
+            .recipes
+            .into_keys()
+            .chain(cookbook.recipe_files.iter().filter_map(|file| {
+                Path::new(file)
+                    .file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .map(str::to_owned)
+            }))
+            .collect();
+        Ok((cookbook.name, names))
+    }
+
     /// Creates a cookbook config from a path to a cookbook file
     ///
     /// # Arguments
     /// * `path` - Path to a cookbook file
+    /// * `environment_overrides` - Overrides of the environment selected with `--env`, whose
+    ///   cookbook and recipe variables apply below `override_variables`
+    /// * `shell_style_vars` - Expand `${NAME}` references in run commands, inputs and outputs
     ///
     pub fn from(
         path: &PathBuf,
@@ -37,22 +93,49 @@ impl Cookbook {
         project_variables: &IndexMap<String, String>,
         project_constants: &IndexMap<String, String>,
         override_variables: &IndexMap<String, String>,
+        environment_overrides: Option<&EnvironmentOverrides>,
+        shell_style_vars: bool,
     ) -> anyhow::Result<Self> {
         let config: Cookbook;
 
         let config_str = match std::fs::read_to_string(path) {
             Ok(contents) => contents,
-            Err(_) => bail!("Could not read config file: {}", path.display()),
+            Err(err) => bail!("Could not read cookbook file {}: {}", path.display(), err),
         };
 
         match serde_yaml::from_str::<Self>(&config_str) {
             Ok(mut parsed) => {
                 parsed.config_path = path.to_path_buf();
 
+                validate_name("cookbook", &parsed.name)?;
+                for recipe_file in &parsed.recipe_files {
+                    let (name, recipe) =
+                        read_recipe_file(&path.parent().unwrap().join(recipe_file))?;
+                    if parsed.recipes.contains_key(&name) {
+                        bail!(
+                            "Recipe {} of cookbook {} is defined more than once, last in {}",
+                            name,
+                            parsed.name,
+                            recipe_file
+                        );
+                    }
+                    parsed.recipes.insert(name, recipe);
+                }
+                if parsed.recipes.is_empty() {
+                    bail!(BakeError::ParseError {
+                        path: path.to_path_buf(),
+                        message: String::from(
+                            "no recipes defined, add them under recipes or recipe_files"
+                        ),
+                    });
+                }
+                parsed
+                    .recipes
+                    .keys()
+                    .try_for_each(|name| validate_name("recipe", name))?;
+
                 // Inherit environment and variables from project
-                let mut cookbook_environment = project_environment.to_owned();
-                cookbook_environment.extend(parsed.environment.iter().cloned());
-                parsed.environment = cookbook_environment;
+                parsed.environment = merge_environment(project_environment, &parsed.environment);
 
                 let mut cookbook_variables = project_variables.clone();
                 cookbook_variables.extend(parsed.variables.clone());
@@ -67,7 +150,23 @@ impl Cookbook {
                     )]),
                 );
 
+                // Variables the environment overrides in this cookbook are templates replacing
+                // the declared values, while `--var` values take precedence as they are
+                let cookbook_overrides = environment_overrides
+                    .and_then(|overrides| overrides.cookbooks.get(&parsed.name));
+                if let Some(overrides) = cookbook_overrides {
+                    replace_variables(&mut cookbook_variables, overrides);
+                }
+
+                let cookbook_scope = format!("cookbook {}", parsed.name);
+                trace_inherited_variables(
+                    &cookbook_scope,
+                    "project",
+                    project_variables,
+                    &parsed.variables,
+                );
                 parsed.variables = parse_variable_list(
+                    &cookbook_scope,
                     &parsed.environment,
                     &cookbook_variables,
                     &cookbook_constants,
@@ -80,13 +179,30 @@ impl Cookbook {
                     recipe.config_path = path.to_path_buf();
 
                     // Inherit environment and variables from cookbook
-                    let mut recipe_environment = parsed.environment.clone();
-                    recipe_environment.extend(recipe.environment.iter().cloned());
-                    recipe.environment = recipe_environment;
+                    recipe.environment =
+                        merge_environment(&parsed.environment, &recipe.environment);
 
+                    let recipe_scope = recipe.full_name();
+                    trace_inherited_variables(
+                        &recipe_scope,
+                        &cookbook_scope,
+                        &parsed.variables,
+                        &recipe.variables,
+                    );
+                    // Cookbook overrides already apply to the inherited variables, so only the
+                    // recipe's own declarations are replaced again
+                    if let Some(overrides) = cookbook_overrides {
+                        replace_variables(&mut recipe.variables, overrides);
+                    }
                     let mut recipe_variables = parsed.variables.clone();
                     recipe_variables.extend(recipe.variables.clone());
+                    if let Some(overrides) = environment_overrides
+                        .and_then(|overrides| overrides.recipes.get(&recipe_scope))
+                    {
+                        replace_variables(&mut recipe_variables, overrides);
+                    }
                     if let Ok(variables) = parse_variable_list(
+                        &recipe_scope,
                         recipe.environment.as_slice(),
                         &recipe_variables,
                         &cookbook_constants,
@@ -97,6 +213,37 @@ impl Cookbook {
                         bail!("Could not parse recipe variables: {}", recipe.name)
                     }
 
+                    if let Some(run_file) = recipe.run_file.as_ref() {
+                        if !recipe.run.is_empty() {
+                            bail!(
+                                "Recipe {} can't have both run and run_file",
+                                recipe.full_name()
+                            );
+                        }
+                        let run_file_path = path.parent().unwrap().join(run_file);
+                        recipe.run = match std::fs::read_to_string(&run_file_path) {
+                            Ok(contents) => contents,
+                            Err(err) => bail!(
+                                "Could not read run_file {} of recipe {}: {}",
+                                run_file_path.display(),
+                                recipe.full_name(),
+                                err
+                            ),
+                        };
+                    } else if recipe.run.is_empty() {
+                        bail!("Recipe {} must have run or run_file", recipe.full_name());
+                    }
+
+                    if shell_style_vars {
+                        recipe.run = expand_shell_vars(&recipe.run, &recipe.environment);
+                        if let Some(cache) = recipe.cache.as_mut() {
+                            let outputs = cache.outputs.iter_mut().map(|output| &mut output.path);
+                            for cache_path in cache.inputs.iter_mut().chain(outputs) {
+                                *cache_path = expand_shell_vars(cache_path, &recipe.environment);
+                            }
+                        }
+                    }
+
                     recipe.run = parse_template(
                         &recipe.run,
                         &recipe.environment,
@@ -104,13 +251,90 @@ impl Cookbook {
                         &cookbook_constants,
                     )?;
 
-                    if let Some(dependencies) = recipe.dependencies.as_ref() {
-                        let new_deps = dependencies.iter().map(|dep| {
-                            if !dep.contains(':') {
-                                recipe.cookbook.clone() + ":" + dep
+                    let full_name = recipe.full_name();
+                    if let Some(cache) = recipe.cache.as_mut().filter(|cache| cache.auto_inputs) {
+                        for input in detect_run_inputs(&recipe.run, path.parent().unwrap()) {
+                            if !cache.inputs.contains(&input) {
+                                info!(
+                                    "Auto-detected input {} of recipe {}, add it to cache.inputs \
+                                     to make it explicit",
+                                    input, full_name
+                                );
+                                cache.inputs.push(input);
+                            }
+                        }
+                    }
+
+                    if let (Some(cache), Some(root)) =
+                        (recipe.cache.as_mut(), project_constants.get("root"))
+                    {
+                        let resolve = |kind, paths: &mut dyn Iterator<Item = &mut String>| {
+                            for cache_path in paths {
+                                *cache_path = resolve_cache_path(
+                                    kind,
+                                    &full_name,
+                                    cache_path,
+                                    path.parent().unwrap(),
+                                    Path::new(root),
+                                )?;
+                            }
+                            anyhow::Ok(())
+                        };
+                        resolve("Input", &mut cache.inputs.iter_mut())?;
+                        for output in &mut cache.outputs {
+                            if output.path.starts_with("//") {
+                                // Resolving from the root gives the path relative to the root
+                                output.root_path = Some(resolve_cache_path(
+                                    "Output",
+                                    &full_name,
+                                    &output.path,
+                                    Path::new(root),
+                                    Path::new(root),
+                                )?);
+                            }
+                        }
+                        resolve(
+                            "Output",
+                            &mut cache.outputs.iter_mut().map(|output| &mut output.path),
+                        )?;
+                        if let Some(archive) = cache.archive.as_ref() {
+                            cache.archive = Some(resolve_cache_path(
+                                "Archive",
+                                &full_name,
+                                archive,
+                                path.parent().unwrap(),
+                                Path::new(root),
+                            )?);
+                        }
+                    }
+
+                    if let Some(cache) = recipe.cache.as_mut() {
+                        // Keys are used as file and object names, so only keep safe characters
+                        cache.key_prefix = parse_template(
+                            &cache.key_prefix,
+                            &recipe.environment,
+                            &recipe.variables,
+                            &cookbook_constants,
+                        )?
+                        .chars()
+                        .map(|c| {
+                            if c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.') {
+                                c
                             } else {
-                                dep.clone()
+                                '-'
                             }
+                        })
+                        .collect();
+                    }
+
+                    if let Some(dependencies) = recipe.dependencies.as_ref() {
+                        let new_deps = dependencies.iter().map(|dep| RecipeDependency {
+                            recipe: if !dep.recipe.contains(':') {
+                                recipe.cookbook.clone() + ":" + &dep.recipe
+                            } else {
+                                dep.recipe.clone()
+                            },
+                            ..dep.clone()
                         });
                         recipe.dependencies = Some(new_deps.collect());
                     }
@@ -119,7 +343,10 @@ impl Cookbook {
                 })?;
                 config = parsed;
             }
-            Err(err) => bail!("Could not parse cookbook file: {}", err),
+            Err(err) => bail!(BakeError::ParseError {
+                path: path.to_path_buf(),
+                message: err.to_string(),
+            }),
         }
 
         Ok(config)
@@ -134,32 +361,56 @@ impl Cookbook {
     /// * `path` - Path to a directory
     ///
     pub fn map_from(
-        path: &PathBuf,
+        path: &Path,
         project_environment: &[String],
         project_variables: &IndexMap<String, String>,
         project_constants: &IndexMap<String, String>,
         override_variables: &IndexMap<String, String>,
+        environment_overrides: Option<&EnvironmentOverrides>,
+        shell_style_vars: bool,
     ) -> anyhow::Result<BTreeMap<String, Self>> {
+        Self::find_files(path)
+            .iter()
+            .map(|file| {
+                let cookbook = Self::from(
+                    file,
+                    project_environment,
+                    project_variables,
+                    project_constants,
+                    override_variables,
+                    environment_overrides,
+                    shell_style_vars,
+                )?;
+                Ok((cookbook.name.clone(), cookbook))
+            })
+            .collect()
+    }
+
+    /// Returns the paths of all cookbook files in a directory, searched recursively respecting
+    /// `.gitignore` and `.bakeignore` files
+    ///
+    /// # Arguments
+    /// * `path` - Path to a directory
+    ///
+    pub fn find_files(path: &Path) -> Vec<PathBuf> {
         let all_files = WalkBuilder::new(path)
             .add_custom_ignore_filename(".bakeignore")
             .build();
         all_files
             .filter_map(|x| match x {
                 Ok(file) => {
-                    let filename = file.file_name().to_str().unwrap();
-                    if filename.contains("cookbook.yaml") || filename.contains("cookbook.yml") {
-                        match Self::from(
-                            &file.into_path(),
-                            project_environment,
-                            project_variables,
-                            project_constants,
-                            override_variables,
-                        ) {
-                            Ok(cookbook) => Some(Ok((cookbook.name.clone(), cookbook))),
-                            Err(err) => Some(Err(err)),
-                        }
-                    } else {
+                    let filename = file.file_name().to_string_lossy();
+                    if !filename.contains("cookbook.yaml") && !filename.contains("cookbook.yml") {
                         None
+                    } else if !file.path().is_file() {
+                        // Such as directories or broken symlinks
+                        debug!(
+                            "Ignored cookbook that is not a file: {}",
+                            file.path().display()
+                        );
+                        None
+                    } else {
+                        Some(file.into_path())
                     }
                 }
                 Err(_) => {
@@ -170,6 +421,95 @@ impl Cookbook {
             .collect()
     }
 }
+
+/// Checks that a cookbook or recipe name can be used unambiguously in recipe patterns, which
+/// use `:` to separate cookbook and recipe names
+///
+/// # Arguments
+/// * `kind` - What is being named, used in the error message
+/// * `name` - The name to validate
+///
+fn validate_name(kind: &str, name: &str) -> anyhow::Result<()> {
+    if name.is_empty() || name.contains(':') || name.contains(char::is_whitespace) {
+        bail!(BakeError::ValidationError(format!(
+            "Invalid {} name '{}': names can't be empty or contain ':' or whitespace",
+            kind, name
+        )));
+    }
+    Ok(())
+}
+
+/// Reads a file defining a single recipe, returning it along with its name, the file's name
+/// without extension
+///
+/// # Arguments
+/// * `path` - Path of the recipe file
+///
+fn read_recipe_file(path: &Path) -> anyhow::Result<(String, Recipe)> {
+    let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) else {
+        bail!("Invalid recipe file name {}", path.display());
+    };
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(err) => bail!("Could not read recipe file {}: {}", path.display(), err),
+    };
+    match serde_yaml::from_str::<Recipe>(&contents) {
+        Ok(recipe) => Ok((name.to_owned(), recipe)),
+        Err(err) => bail!("Could not parse recipe file {}: {}", path.display(), err),
+    }
+}
+
+/// Resolves a declared cache input or output to a path relative to the cookbook's directory
+///
+/// Paths starting with `//` are relative to the project root. Paths that resolve outside of the
+/// project root are rejected so that cleaning or restoring outputs can't touch other files.
+///
+/// # Arguments
+/// * `kind` - Either `Input`, `Output` or `Archive`, used in the error message
+/// * `recipe` - Fully qualified name of the recipe declaring the path
+/// * `output` - The path or glob as declared in the cookbook
+/// * `cookbook_dir` - Directory of the cookbook file
+/// * `root` - Root directory of the project
+///
+fn resolve_cache_path(
+    kind: &str,
+    recipe: &str,
+    output: &str,
+    cookbook_dir: &Path,
+    root: &Path,
+) -> anyhow::Result<String> {
+    let cookbook_dir = cookbook_dir.strip_prefix(root).unwrap_or(Path::new(""));
+    let from_root = match output.strip_prefix("//") {
+        Some(output) => PathBuf::from(output),
+        None => match Path::new(output).strip_prefix(root) {
+            Ok(output) => output.to_path_buf(),
+            Err(_) => cookbook_dir.join(output),
+        },
+    };
+
+    // Resolve `.` and `..` without touching the filesystem, outputs may not exist yet
+    let mut normalized = PathBuf::new();
+    for component in from_root.components() {
+        match component {
+            Component::CurDir => {}
+            Component::Normal(part) => normalized.push(part),
+            Component::ParentDir if normalized.pop() => {}
+            _ => bail!(BakeError::ValidationError(format!(
+                "{} {} of recipe {} is outside of the project root",
+                kind, output, recipe
+            ))),
+        }
+    }
+
+    if output.starts_with("//") || Path::new(output).is_absolute() {
+        let mut relative: PathBuf = cookbook_dir.components().map(|_| "..").collect();
+        relative.push(normalized);
+        Ok(relative.display().to_string())
+    } else {
+        Ok(output.to_owned())
+    }
+}
+
 #[cfg(test)]
 mod test {
     use std::{collections::BTreeMap, path::PathBuf};
@@ -177,6 +517,8 @@ mod test {
     use indexmap::IndexMap;
     use test_case::test_case;
 
+    use crate::{project::file_hashes::FileHashCache, test_utils::TestProjectBuilder};
+
     fn config_path(path_str: &str) -> String {
         env!("CARGO_MANIFEST_DIR").to_owned() + "/resources/tests" + path_str
     }
@@ -199,9 +541,244 @@ mod test {
             &IndexMap::new(),
             &IndexMap::new(),
             &IndexMap::new(),
+            None,
+            false,
         )
     }
 
+    #[test]
+    fn run_file() {
+        let cookbook = super::Cookbook::from(
+            &PathBuf::from(config_path("/run_file/cookbook.yml")),
+            &[],
+            &IndexMap::new(),
+            &IndexMap::new(),
+            &IndexMap::new(),
+            None,
+            false,
+        )
+        .unwrap();
+        assert_eq!(
+            cookbook.recipes["build"].run.trim(),
+            "echo \"Building run-file\"\n./build.sh"
+        );
+    }
+
+    #[test_case(true => (vec![String::from("dist/app")], String::from("mkdir -p dist && echo ${UNDECLARED} > dist/app")); "Enabled")]
+    #[test_case(false => (vec![String::from("${BUILD_DIR}/app")], String::from("mkdir -p ${BUILD_DIR} && echo ${UNDECLARED} > ${BUILD_DIR}/app")); "Disabled")]
+    fn shell_style_vars(enabled: bool) -> (Vec<String>, String) {
+        let cookbook = super::Cookbook::from(
+            &PathBuf::from(config_path("/shell_vars/cookbook.yml")),
+            &[],
+            &IndexMap::new(),
+            &IndexMap::new(),
+            &IndexMap::new(),
+            None,
+            enabled,
+        )
+        .unwrap();
+        let recipe = &cookbook.recipes["build"];
+        (
+            recipe
+                .cache
+                .as_ref()
+                .unwrap()
+                .outputs
+                .iter()
+                .map(|output| output.path.clone())
+                .collect(),
+            recipe.run.clone(),
+        )
+    }
+
+    #[test]
+    fn auto_inputs() {
+        let cookbook = super::Cookbook::from(
+            &PathBuf::from(config_path("/auto_inputs/cookbook.yml")),
+            &[],
+            &IndexMap::new(),
+            &IndexMap::new(),
+            &IndexMap::new(),
+            None,
+            false,
+        )
+        .unwrap();
+        let inputs = |name: &str| {
+            cookbook.recipes[name]
+                .cache
+                .as_ref()
+                .unwrap()
+                .inputs
+                .clone()
+        };
+
+        // Only referenced files that exist are added, after the declared inputs
+        assert_eq!(
+            inputs("build"),
+            vec!["config.json", "build.sh", "src/main.rs"]
+        );
+        assert_eq!(inputs("manual"), vec!["config.json"]);
+    }
+
+    #[test]
+    fn recipe_files() {
+        let path = PathBuf::from(config_path("/recipe_files/cookbook.yml"));
+        let cookbook = super::Cookbook::from(
+            &path,
+            &[],
+            &IndexMap::new(),
+            &IndexMap::new(),
+            &IndexMap::new(),
+            None,
+            false,
+        )
+        .unwrap();
+
+        assert_eq!(
+            cookbook.recipes.keys().collect::<Vec<_>>(),
+            vec!["build", "lint", "test"]
+        );
+        let build = &cookbook.recipes["build"];
+        assert_eq!(build.full_name(), "split:build");
+        assert_eq!(build.run, "cargo build --release");
+        assert_eq!(build.config_path, path);
+        assert_eq!(
+            cookbook.recipes["test"].dependencies,
+            Some(vec!["split:build".into()])
+        );
+    }
+
+    #[test_case(config_path("/invalid/recipe_files/missing.cookbook.yml") => matches Err(_); "Missing recipe file")]
+    #[test_case(config_path("/invalid/recipe_files/duplicate.cookbook.yml") => matches Err(_); "Duplicate recipe")]
+    fn invalid_recipe_files(path_str: String) -> anyhow::Result<super::Cookbook> {
+        super::Cookbook::from(
+            &PathBuf::from(path_str),
+            &[],
+            &IndexMap::new(),
+            &IndexMap::new(),
+            &IndexMap::new(),
+            None,
+            false,
+        )
+    }
+
+    #[test_case(config_path("/invalid/run_file/missing.cookbook.yml") => matches Err(_); "Missing run_file")]
+    #[test_case(config_path("/invalid/run_file/both.cookbook.yml") => matches Err(_); "Both run and run_file")]
+    #[test_case(config_path("/invalid/run_file/none.cookbook.yml") => matches Err(_); "No run or run_file")]
+    fn invalid_run_file(path_str: String) -> anyhow::Result<super::Cookbook> {
+        super::Cookbook::from(
+            &PathBuf::from(path_str),
+            &[],
+            &IndexMap::new(),
+            &IndexMap::new(),
+            &IndexMap::new(),
+            None,
+            false,
+        )
+    }
+
+    #[test_case(config_path("/invalid/names/recipe.cookbook.yml") => matches Err(_); "Recipe name with colon")]
+    #[test_case(config_path("/invalid/names/cookbook.cookbook.yml") => matches Err(_); "Cookbook name with whitespace")]
+    fn invalid_names(path_str: String) -> anyhow::Result<super::Cookbook> {
+        super::Cookbook::from(
+            &PathBuf::from(path_str),
+            &[],
+            &IndexMap::new(),
+            &IndexMap::new(),
+            &IndexMap::new(),
+            None,
+            false,
+        )
+    }
+
+    #[test_case("foo" => matches Ok(_); "Simple name")]
+    #[test_case("post-test_2.v1" => matches Ok(_); "Name with punctuation")]
+    #[test_case("foo:bar" => matches Err(_); "Name with colon")]
+    #[test_case("foo bar" => matches Err(_); "Name with whitespace")]
+    #[test_case("" => matches Err(_); "Empty name")]
+    fn validate_name(name: &str) -> anyhow::Result<()> {
+        super::validate_name("recipe", name)
+    }
+
+    #[test]
+    fn render_run_with_cookbook_context() {
+        let root = crate::test_utils::TestProjectBuilder::temp_dir(&[(
+            "app/cookbook.yml",
+            "name: app\nvariables:\n  target: release\nrecipes:\n  build:\n    \
+             run: cd {{ cookbook.root }} && make {{ var.target }} -C {{ project.root }}\n",
+        )]);
+        let cookbook_dir = root.join("app");
+        let path = cookbook_dir.join("cookbook.yml");
+
+        let cookbook = super::Cookbook::from(
+            &path,
+            &[],
+            &IndexMap::new(),
+            &IndexMap::from([("root".to_owned(), root.display().to_string())]),
+            &IndexMap::new(),
+            None,
+            false,
+        )
+        .unwrap();
+        assert_eq!(
+            cookbook.recipes["build"].run,
+            format!(
+                "cd {} && make release -C {}",
+                cookbook_dir.display(),
+                root.display()
+            )
+        );
+    }
+
+    #[test_case("dist" => matches Ok(ref output) if output == "dist"; "Cookbook relative")]
+    #[test_case("//dist/app" => matches Ok(ref output) if output == "../../dist/app"; "Root relative")]
+    #[test_case("/project/dist" => matches Ok(ref output) if output == "../../dist"; "Absolute in project")]
+    #[test_case("../../dist" => matches Ok(_); "Parent in project")]
+    #[test_case("../../../dist" => matches Err(_); "Parent outside project")]
+    #[test_case("//../dist" => matches Err(_); "Root relative outside project")]
+    #[test_case("/etc" => matches Err(_); "Absolute outside project")]
+    fn resolve_cache_path(output: &str) -> anyhow::Result<String> {
+        super::resolve_cache_path(
+            "Output",
+            "foo:build",
+            output,
+            &PathBuf::from("/project/apps/foo"),
+            &PathBuf::from("/project"),
+        )
+    }
+
+    #[test]
+    fn broken_symlinks() {
+        let root_path = TestProjectBuilder::temp_dir(&[(
+            "cookbook.yml",
+            "name: foo\nrecipes:\n  build:\n    run: echo\n    cache:\n      inputs: ['*']\n",
+        )]);
+        std::fs::create_dir(root_path.join("broken")).unwrap();
+        std::os::unix::fs::symlink("missing.yml", root_path.join("broken/cookbook.yml")).unwrap();
+        std::os::unix::fs::symlink("missing.txt", root_path.join("dangling.txt")).unwrap();
+
+        let cookbooks = super::Cookbook::map_from(
+            &root_path,
+            &[],
+            &IndexMap::new(),
+            &IndexMap::new(),
+            &IndexMap::new(),
+            None,
+            false,
+        )
+        .unwrap();
+        assert_eq!(cookbooks.keys().collect::<Vec<_>>(), vec!["foo"]);
+
+        // Dangling inputs are skipped when hashing
+        let data = cookbooks["foo"].recipes["build"]
+            .get_recipe_hash_data(&FileHashCache::default())
+            .unwrap();
+        assert_eq!(
+            data.file_hashes.keys().collect::<Vec<_>>(),
+            vec![&PathBuf::from("cookbook.yml")]
+        );
+    }
+
     #[test_case(config_path("/valid/") => using validate_cookbook_vec; "Root dir")]
     #[test_case(config_path("/invalid/config") => matches Err(_); "Invalid dir")]
     fn read_all_cookbooks(path_str: String) -> anyhow::Result<BTreeMap<String, super::Cookbook>> {
@@ -211,6 +788,8 @@ mod test {
             &IndexMap::new(),
             &IndexMap::new(),
             &IndexMap::new(),
+            None,
+            false,
         )
     }
 }
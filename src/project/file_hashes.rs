@@ -0,0 +1,171 @@
+use std::{
+    collections::HashMap,
+    io::Read,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::SystemTime,
+};
+
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+
+#[cfg(test)]
+use std::sync::atomic::AtomicUsize;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct FileHashEntry {
+    modified: SystemTime,
+    size: u64,
+    hash: String,
+}
+
+/// Cache of input file hashes persisted between runs, so that files that didn't change aren't
+/// read and hashed again when calculating recipe hashes
+///
+/// Entries are keyed by path and invalidated whenever the file's modification time or size
+/// change.
+#[derive(Debug, Default)]
+pub struct FileHashCache {
+    path: Option<PathBuf>,
+    entries: Mutex<HashMap<PathBuf, FileHashEntry>>,
+
+    /// Whether entries were added or updated since the cache was loaded
+    changed: AtomicBool,
+
+    /// Number of files read, to check which hashes were served from the cache
+    #[cfg(test)]
+    reads: AtomicUsize,
+}
+
+impl FileHashCache {
+    /// Loads the cache stored at the given path, starting empty if it doesn't exist or can't be
+    /// read
+    ///
+    /// # Arguments
+    /// * `path` - Path of the cache file, read now and written by `save`
+    ///
+    pub fn load(path: PathBuf) -> Self {
+        let entries = match std::fs::read(&path) {
+            Ok(contents) => serde_json::from_slice(&contents).unwrap_or_else(|err| {
+                debug!(
+                    "Ignoring invalid file hash cache {}: {}",
+                    path.display(),
+                    err
+                );
+                HashMap::new()
+            }),
+            Err(_) => HashMap::new(),
+        };
+        Self {
+            path: Some(path),
+            entries: Mutex::new(entries),
+            ..Default::default()
+        }
+    }
+
+    /// Returns the blake3 hash of a file, reading it only if it changed since it was last hashed
+    pub fn hash(&self, path: &Path) -> std::io::Result<String> {
+        let metadata = std::fs::metadata(path)?;
+        let modified = metadata.modified()?;
+        if let Some(entry) = self.entries.lock().unwrap().get(path) {
+            if entry.modified == modified && entry.size == metadata.len() {
+                return Ok(entry.hash.clone());
+            }
+        }
+
+        #[cfg(test)]
+        self.reads.fetch_add(1, Ordering::Relaxed);
+        let mut hasher = blake3::Hasher::new();
+        let mut buf = Vec::new();
+        std::fs::File::open(path)?.read_to_end(&mut buf)?;
+        hasher.update(buf.as_slice());
+        let hash = hasher.finalize().to_string();
+
+        self.entries.lock().unwrap().insert(
+            path.to_path_buf(),
+            FileHashEntry {
+                modified,
+                size: metadata.len(),
+                hash: hash.clone(),
+            },
+        );
+        self.changed.store(true, Ordering::Relaxed);
+        Ok(hash)
+    }
+
+    /// Writes the cache to disk if it changed. Failures are only logged since the cache is an
+    /// optimization
+    pub fn save(&self) {
+        let Some(path) = self.path.as_ref() else {
+            return;
+        };
+        if !self.changed.load(Ordering::Relaxed) {
+            return;
+        }
+        let contents = serde_json::to_vec(&*self.entries.lock().unwrap()).unwrap();
+        // Write to a temporary file first so concurrent runs never read a partial cache
+        let temp_path = path.with_extension(format!("{}.tmp", std::process::id()));
+        let result = path
+            .parent()
+            .map_or(Ok(()), std::fs::create_dir_all)
+            .and_then(|_| std::fs::write(&temp_path, contents))
+            .and_then(|_| std::fs::rename(&temp_path, path));
+        if let Err(err) = result {
+            let _ = std::fs::remove_file(&temp_path);
+            warn!("Could not save file hash cache {}: {}", path.display(), err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::atomic::Ordering, time::SystemTime};
+
+    use crate::test_utils::TestProjectBuilder;
+
+    use super::FileHashCache;
+
+    #[test]
+    fn hash() {
+        let root_path = TestProjectBuilder::temp_dir(&[("input.txt", "foo")]);
+        let file_path = root_path.join("input.txt");
+        let cache_path = root_path.join(".bake").join("file-hashes.json");
+
+        let cache = FileHashCache::load(cache_path.clone());
+        let hash = cache.hash(&file_path).unwrap();
+        assert_eq!(hash, blake3::hash(b"foo").to_string());
+        assert_eq!(cache.hash(&file_path).unwrap(), hash);
+        assert_eq!(cache.reads.load(Ordering::Relaxed), 1);
+        cache.save();
+
+        // Unchanged files are served from the saved cache
+        let cache = FileHashCache::load(cache_path.clone());
+        assert_eq!(cache.hash(&file_path).unwrap(), hash);
+        assert_eq!(cache.reads.load(Ordering::Relaxed), 0);
+
+        // Changing the size invalidates the entry
+        std::fs::write(&file_path, "foobar").unwrap();
+        assert_eq!(
+            cache.hash(&file_path).unwrap(),
+            blake3::hash(b"foobar").to_string()
+        );
+        assert_eq!(cache.reads.load(Ordering::Relaxed), 1);
+
+        // So does changing the modification time
+        std::fs::write(&file_path, "bazbar").unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&file_path)
+            .unwrap()
+            .set_modified(SystemTime::UNIX_EPOCH)
+            .unwrap();
+        assert_eq!(
+            cache.hash(&file_path).unwrap(),
+            blake3::hash(b"bazbar").to_string()
+        );
+        assert_eq!(cache.reads.load(Ordering::Relaxed), 2);
+    }
+}
@@ -1,4 +1,8 @@
-use std::{collections::BTreeMap, io::Read, path::PathBuf};
+use std::{
+    collections::BTreeMap,
+    path::{Component, Path, PathBuf},
+    time::{Duration, SystemTime},
+};
 
 use anyhow::bail;
 use globset::{GlobBuilder, GlobSetBuilder};
@@ -7,6 +11,14 @@ use indexmap::IndexMap;
 use log::{debug, warn};
 use serde::{Deserialize, Serialize};
 
+use crate::{
+    project::{
+        config::{OutputCapture, Shell},
+        file_hashes::FileHashCache,
+    },
+    template::get_environment_values,
+};
+
 #[derive(Debug, PartialOrd, Ord, Deserialize, Clone, PartialEq, Eq, Hash, Default)]
 pub enum Status {
     Done,
@@ -20,6 +32,19 @@ pub enum Status {
 pub struct RunStatus {
     pub status: Status,
     pub output: String,
+
+    /// Time spent baking the recipe, including cache retrieval
+    pub duration: Duration,
+
+    /// Whether the recipe was restored from cache instead of running
+    pub cached: bool,
+
+    /// Exit code of the recipe's process, None if it didn't run, eg. when restored from cache,
+    /// or was killed by a signal
+    pub exit_code: Option<i32>,
+
+    /// When the recipe started baking, used to lay out the report timeline
+    pub started_at: Option<SystemTime>,
 }
 
 #[derive(Debug, PartialOrd, Ord, Deserialize, Clone, PartialEq, Eq, Hash, Default)]
@@ -28,7 +53,237 @@ pub struct RecipeCacheConfig {
     pub inputs: Vec<String>,
 
     #[serde(default)]
-    pub outputs: Vec<String>,
+    pub outputs: Vec<RecipeOutput>,
+
+    /// Prepended to the recipe's cache key to keep separate cache namespaces, eg. per branch.
+    /// It's rendered as a template and characters other than alphanumerics, `-`, `_` and `.`
+    /// are replaced with `-`
+    #[serde(default)]
+    pub key_prefix: String,
+
+    /// Path of a reproducible archive of the outputs written after each successful bake, eg.
+    /// `dist.tar.zst`
+    #[serde(default)]
+    pub archive: Option<String>,
+
+    /// Add the files referenced by the rendered `run` command to `inputs`. Heuristic, so it's
+    /// opt-in and detected inputs are logged to be declared explicitly
+    #[serde(default)]
+    pub auto_inputs: bool,
+
+    /// Maximum age of a cached result, such as `30m`, `12h` or `7d`, after which the recipe runs
+    /// again even if its inputs didn't change. Cached results never expire by default
+    #[serde(default, deserialize_with = "deserialize_ttl")]
+    pub ttl: Option<Duration>,
+}
+
+/// Parses a duration made of a number and a unit, `s`, `m`, `h`, `d` or `w`, such as `12h`.
+/// Numbers without a unit are seconds
+fn parse_duration(text: &str) -> Option<Duration> {
+    let text = text.trim();
+    let split = text
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(text.len());
+    let (amount, unit) = text.split_at(split);
+    let seconds = match unit.trim() {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return None,
+    };
+    amount
+        .parse::<u64>()
+        .ok()?
+        .checked_mul(seconds)
+        .map(Duration::from_secs)
+}
+
+/// Cache `ttl` declaration, either a number of seconds or a duration with a unit
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TtlSetting {
+    Seconds(u64),
+    Text(String),
+}
+
+fn deserialize_ttl<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let ttl = match Option::<TtlSetting>::deserialize(deserializer)? {
+        None => return Ok(None),
+        Some(TtlSetting::Seconds(seconds)) => return Ok(Some(Duration::from_secs(seconds))),
+        Some(TtlSetting::Text(ttl)) => ttl,
+    };
+    match parse_duration(&ttl) {
+        Some(duration) => Ok(Some(duration)),
+        None => Err(serde::de::Error::custom(format!(
+            "invalid cache ttl {}, expected a duration such as '30m', '12h' or '7d'",
+            ttl
+        ))),
+    }
+}
+
+/// How an output is stored in and restored from the cache
+#[derive(Debug, PartialOrd, Ord, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputMode {
+    /// Restored file by file over whatever is already on disk
+    #[default]
+    Files,
+
+    /// A directory restored as a single unit: it's unpacked next to its destination and swapped
+    /// in place, so it never mixes files from different bakes. Meant for large trees
+    Tree,
+}
+
+/// A declared cache output, either a path (`dist`) or a path with its mode
+/// (`{ path: dist, mode: tree }`)
+#[derive(Debug, PartialOrd, Ord, Deserialize, Clone, PartialEq, Eq, Hash, Default)]
+#[serde(from = "RecipeOutputSetting")]
+pub struct RecipeOutput {
+    /// Path of the output relative to the cookbook
+    pub path: String,
+
+    pub mode: OutputMode,
+
+    /// Path of the output relative to the project root when it's declared with `//`, which
+    /// `--output-root` moves
+    pub root_path: Option<String>,
+}
+
+impl From<String> for RecipeOutput {
+    fn from(path: String) -> Self {
+        Self {
+            path,
+            mode: OutputMode::default(),
+            root_path: None,
+        }
+    }
+}
+
+impl From<&str> for RecipeOutput {
+    fn from(path: &str) -> Self {
+        Self::from(path.to_owned())
+    }
+}
+
+impl AsRef<Path> for RecipeOutput {
+    fn as_ref(&self) -> &Path {
+        Path::new(&self.path)
+    }
+}
+
+impl std::fmt::Display for RecipeOutput {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.path)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RecipeOutputSetting {
+    Path(String),
+    Config {
+        path: String,
+        #[serde(default)]
+        mode: OutputMode,
+    },
+}
+
+impl From<RecipeOutputSetting> for RecipeOutput {
+    fn from(setting: RecipeOutputSetting) -> Self {
+        match setting {
+            RecipeOutputSetting::Path(path) => Self::from(path),
+            RecipeOutputSetting::Config { path, mode } => Self {
+                path,
+                mode,
+                root_path: None,
+            },
+        }
+    }
+}
+
+/// A recipe dependency, either a recipe name (`foo:build`) or a recipe with options
+/// (`{ recipe: foo:up, order_only: true }`)
+#[derive(Debug, PartialOrd, Ord, Deserialize, Clone, PartialEq, Eq, Hash, Default)]
+#[serde(from = "RecipeDependencySetting")]
+pub struct RecipeDependency {
+    /// Name of the recipe, fully qualified once the cookbook is loaded
+    pub recipe: String,
+
+    /// Only orders the recipe after its dependency, eg. a recipe starting a service used by
+    /// tests. The dependency's results don't change the recipe's cache key
+    pub order_only: bool,
+}
+
+impl From<String> for RecipeDependency {
+    fn from(recipe: String) -> Self {
+        Self {
+            recipe,
+            order_only: false,
+        }
+    }
+}
+
+impl From<&str> for RecipeDependency {
+    fn from(recipe: &str) -> Self {
+        Self::from(recipe.to_owned())
+    }
+}
+
+impl std::fmt::Display for RecipeDependency {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.order_only {
+            write!(f, "{} (order only)", self.recipe)
+        } else {
+            write!(f, "{}", self.recipe)
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RecipeDependencySetting {
+    Recipe(String),
+    Config {
+        recipe: String,
+        #[serde(default)]
+        order_only: bool,
+    },
+}
+
+impl From<RecipeDependencySetting> for RecipeDependency {
+    fn from(setting: RecipeDependencySetting) -> Self {
+        match setting {
+            RecipeDependencySetting::Recipe(recipe) => Self::from(recipe),
+            RecipeDependencySetting::Config { recipe, order_only } => Self { recipe, order_only },
+        }
+    }
+}
+
+/// Recipe cache declaration, either a full configuration or a bool that enables caching with
+/// default configuration (`cache: true`) or disables it entirely (`cache: false`)
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RecipeCacheSetting {
+    Enabled(bool),
+    Config(RecipeCacheConfig),
+}
+
+fn deserialize_cache<'de, D>(deserializer: D) -> Result<Option<RecipeCacheConfig>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    Ok(
+        match Option::<RecipeCacheSetting>::deserialize(deserializer)? {
+            Some(RecipeCacheSetting::Config(config)) => Some(config),
+            Some(RecipeCacheSetting::Enabled(true)) => Some(RecipeCacheConfig::default()),
+            Some(RecipeCacheSetting::Enabled(false)) | None => None,
+        },
+    )
 }
 
 #[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
@@ -42,7 +297,7 @@ pub struct Recipe {
     #[serde(skip)]
     pub config_path: PathBuf,
 
-    #[serde(default)]
+    #[serde(default, deserialize_with = "deserialize_cache")]
     pub cache: Option<RecipeCacheConfig>,
 
     pub description: Option<String>,
@@ -53,19 +308,146 @@ pub struct Recipe {
     #[serde(default)]
     pub environment: Vec<String>,
 
-    pub dependencies: Option<Vec<String>>,
+    pub dependencies: Option<Vec<RecipeDependency>>,
+
+    #[serde(default)]
     pub run: String,
 
+    /// Path to a script, relative to the cookbook, used instead of `run`
+    pub run_file: Option<String>,
+
+    /// Maximum time in seconds the recipe may run before it's killed
+    pub timeout: Option<u64>,
+
+    /// Refuse to run when the git tree has uncommitted changes, eg. for release recipes
+    #[serde(default)]
+    pub requires_clean_git: bool,
+
+    /// Where the recipe's output goes, overriding `config.output_capture`
+    #[serde(default)]
+    pub output: Option<OutputCapture>,
+
+    /// Shell running `run`, overriding `config.windows_shell`
+    #[serde(default)]
+    pub shell: Option<Shell>,
+
+    /// Share of `config.total_capacity` taken while running, eg. 4 for a memory hungry linker
+    #[serde(default = "weight_default")]
+    pub weight: u32,
+
+    /// Platforms the recipe runs on, as OS names (`linux`, `macos`), architectures (`aarch64`)
+    /// or target triples (`x86_64-unknown-linux-gnu`). Empty means any platform
+    #[serde(default)]
+    pub platforms: Vec<String>,
+
+    /// Arguments passed after `--` on the command line, given to `run` as its positional
+    /// parameters and expanded by `{{args}}`
+    #[serde(skip)]
+    pub args: Vec<String>,
+
+    /// Directory where outputs declared relative to the project root (`//`) are written and
+    /// restored, the project root unless overridden with `--output-root`. Passed to the run
+    /// command as `BAKE_OUTPUT_ROOT`
+    #[serde(skip)]
+    pub output_root: Option<PathBuf>,
+
     #[serde(skip)]
     pub run_status: RunStatus,
 }
 
+/// Platform recipes are baked on, matched against their `platforms`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Platform {
+    pub os: String,
+    pub arch: String,
+    pub target: String,
+}
+
+impl Platform {
+    /// Returns the platform bake is running on
+    pub fn host() -> Self {
+        Self {
+            os: std::env::consts::OS.to_owned(),
+            arch: std::env::consts::ARCH.to_owned(),
+            target: env!("BAKE_TARGET").to_owned(),
+        }
+    }
+
+    /// Whether an entry of a recipe's `platforms` designates this platform
+    pub fn matches(&self, platform: &str) -> bool {
+        platform == self.os || platform == self.arch || platform == self.target
+    }
+}
+
+impl std::fmt::Display for Platform {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.target)
+    }
+}
+
+/// Components folded into a recipe's hash, serialized in this order to calculate it
 #[derive(Serialize, Debug)]
-struct RecipeHashData {
-    environment: BTreeMap<String, String>,
-    file_hashes: BTreeMap<PathBuf, String>,
-    run: String,
-    variables: BTreeMap<String, String>,
+pub struct RecipeHashData {
+    /// Values of the recipe's environment variables
+    pub environment: BTreeMap<String, String>,
+
+    /// Hashes of the input files, keyed by their path relative to the cookbook
+    pub file_hashes: BTreeMap<PathBuf, String>,
+
+    pub run: String,
+
+    /// Arguments passed after `--`, left out when empty so that other keys don't change
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<String>,
+
+    pub variables: BTreeMap<String, String>,
+}
+
+impl RecipeHashData {
+    /// Hashes the components into the recipe's hash
+    pub fn hash(&self) -> String {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(serde_json::to_string(self).unwrap().as_bytes());
+        hasher.finalize().to_string()
+    }
+}
+
+/// Finds the files a run command references, relative to the cookbook directory
+///
+/// Matching is conservative: only words that resolve to existing files inside the cookbook are
+/// returned, skipping flags, absolute paths, variables and globs.
+///
+/// # Arguments
+/// * `run` - The rendered run command
+/// * `cookbook_path` - Directory of the recipe's cookbook
+///
+pub fn detect_run_inputs(run: &str, cookbook_path: &Path) -> Vec<String> {
+    let mut inputs = Vec::new();
+    let words = run.split(|c: char| c.is_whitespace() || ";|&<>()=\"'`,".contains(c));
+    for word in words {
+        let word = word.strip_prefix("./").unwrap_or(word);
+        if word.is_empty()
+            || word.starts_with('-')
+            || word.contains(|c: char| "$*?[]{}~".contains(c))
+        {
+            continue;
+        }
+        let path = Path::new(word);
+        if !path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+        {
+            continue;
+        }
+        if cookbook_path.join(path).is_file() && !inputs.iter().any(|input| input == word) {
+            inputs.push(word.to_owned());
+        }
+    }
+    inputs
+}
+
+fn weight_default() -> u32 {
+    1
 }
 
 impl Recipe {
@@ -73,18 +455,112 @@ impl Recipe {
         format!("{}:{}", self.cookbook, self.name)
     }
 
+    /// Whether the recipe runs on the given platform
+    pub fn supports_platform(&self, platform: &Platform) -> bool {
+        self.platforms.is_empty() || self.platforms.iter().any(|entry| platform.matches(entry))
+    }
+
+    /// Returns where a declared output is on disk, under the output root for outputs relative to
+    /// the project root and in the cookbook's directory otherwise
+    ///
+    /// # Arguments
+    /// * `output` - One of the recipe's declared outputs
+    ///
+    pub fn output_path(&self, output: &RecipeOutput) -> PathBuf {
+        match (self.output_root.as_ref(), output.root_path.as_ref()) {
+            (Some(output_root), Some(root_path)) => output_root.join(root_path),
+            _ => self.config_path.parent().unwrap().join(output),
+        }
+    }
+
+    /// Returns the declared cache outputs that don't exist on disk, relative to the cookbook
+    pub fn missing_outputs(&self) -> Vec<String> {
+        self.cache
+            .as_ref()
+            .map(|cache| {
+                cache
+                    .outputs
+                    .iter()
+                    .filter(|output| !self.output_path(output).exists())
+                    .map(|output| output.path.clone())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Gets a hash of the contents of the recipe's declared outputs
+    ///
+    /// Returns None if the recipe declares no outputs or if some of them don't exist
+    pub fn get_outputs_hash(&self) -> anyhow::Result<Option<String>> {
+        let outputs = match self.cache.as_ref() {
+            Some(cache) if !cache.outputs.is_empty() => &cache.outputs,
+            _ => return Ok(None),
+        };
+        if !self.missing_outputs().is_empty() {
+            return Ok(None);
+        }
+
+        let mut hasher = blake3::Hasher::new();
+        for output in outputs {
+            // Hashed by declared path so that moving outputs doesn't change dependents' keys
+            if let Err(err) =
+                hash_output_path(&mut hasher, &self.output_path(output), output.as_ref())
+            {
+                bail!(
+                    "Could not hash output {} of recipe {}: {}",
+                    output,
+                    self.full_name(),
+                    err
+                );
+            }
+        }
+        Ok(Some(hasher.finalize().to_hex().to_string()))
+    }
+
     /// Gets the hash of the recipes fields, not including its dependencies
     pub fn get_recipe_hash(&self) -> anyhow::Result<String> {
+        self.get_recipe_hash_with(&FileHashCache::default())
+    }
+
+    /// Same as `get_recipe_hash`, looking up the hashes of input files in a cache
+    ///
+    /// # Arguments
+    /// * `file_hashes` - Cache of file hashes, updated with the files that had to be read
+    ///
+    pub fn get_recipe_hash_with(&self, file_hashes: &FileHashCache) -> anyhow::Result<String> {
+        Ok(self.get_recipe_hash_data(file_hashes)?.hash())
+    }
+
+    /// Gets the components of the recipe's hash, such as the hashes of its input files
+    ///
+    /// # Arguments
+    /// * `file_hashes` - Cache of file hashes, updated with the files that had to be read
+    ///
+    pub fn get_recipe_hash_data(
+        &self,
+        file_hashes: &FileHashCache,
+    ) -> anyhow::Result<RecipeHashData> {
         debug!("Getting hash for recipe: {}", self.name);
-        let mut walk_builder = WalkBuilder::new(self.config_path.clone().parent().unwrap());
-        let mut globset_builder = GlobSetBuilder::new();
-        let mut file_hashes = BTreeMap::<PathBuf, String>::new();
+        let cookbook_path = self.config_path.parent().unwrap();
+        let mut input_hashes = BTreeMap::<PathBuf, String>::new();
 
         if let Some(cache) = &self.cache {
+            // Inputs starting with `../` are matched from the corresponding ancestor of the
+            // cookbook directory, which is how project root relative inputs are resolved
+            let mut globset_builders = BTreeMap::<usize, GlobSetBuilder>::new();
             for input in &cache.inputs {
                 debug!("Adding input: {}", input);
-                match GlobBuilder::new(input).literal_separator(true).build() {
-                    Ok(glob) => globset_builder.add(glob),
+                let mut pattern = input.as_str();
+                let mut depth = 0;
+                while let Some(rest) = pattern.strip_prefix("../") {
+                    pattern = rest;
+                    depth += 1;
+                }
+                match GlobBuilder::new(pattern).literal_separator(true).build() {
+                    Ok(glob) => globset_builders
+                        .entry(depth)
+                        .or_insert_with(GlobSetBuilder::new)
+                        .add(glob),
                     Err(err) => {
                         bail!(
                             "Failed to get hash for recipe {:?}. Error adding input: {:?}",
@@ -95,73 +571,101 @@ impl Recipe {
                 };
             }
 
-            let globset = match globset_builder.build() {
-                Ok(globset) => globset,
-                Err(err) => {
+            for (depth, globset_builder) in globset_builders {
+                let globset = match globset_builder.build() {
+                    Ok(globset) => globset,
+                    Err(err) => {
+                        bail!(
+                            "Failed to get hash for recipe {:?}. Error building globset: {:?}",
+                            self.name,
+                            err
+                        );
+                    }
+                };
+                let Some(base_path) = cookbook_path.ancestors().nth(depth) else {
                     bail!(
-                        "Failed to get hash for recipe {:?}. Error building globset: {:?}",
-                        self.name,
-                        err
+                        "Failed to get hash for recipe {:?}. Inputs go above the root directory",
+                        self.name
                     );
-                }
-            };
-
-            // Hash all input files
-            let walker = walk_builder.hidden(false).build();
-            for result in walker {
-                match result {
-                    Ok(entry) => {
-                        let path = entry.path();
-                        let relative_path = path
-                            .strip_prefix(self.config_path.clone().parent().unwrap())
-                            .unwrap()
-                            .to_path_buf();
-                        if entry.file_type().unwrap().is_file() && globset.is_match(&relative_path)
-                        {
-                            debug!("Hashing file: {:?}", entry.path());
-                            let mut hasher = blake3::Hasher::new();
-                            let mut file = std::fs::File::open(path).unwrap();
-                            let mut buf = Vec::new();
-                            if let Err(err) = file.read_to_end(&mut buf) {
-                                warn!("Error reading file: {:?}", err);
+                };
+                let prefix: PathBuf = (0..depth).map(|_| "..").collect();
+
+                // Hash all input files
+                let walker = WalkBuilder::new(base_path).hidden(false).build();
+                for result in walker {
+                    match result {
+                        Ok(entry) => {
+                            let path = entry.path();
+                            let relative_path = path.strip_prefix(base_path).unwrap();
+                            if entry
+                                .file_type()
+                                .is_some_and(|file_type| file_type.is_file())
+                                && globset.is_match(relative_path)
+                            {
+                                debug!("Hashing file: {:?}", entry.path());
+                                match file_hashes.hash(path) {
+                                    Ok(hash) => {
+                                        input_hashes.insert(prefix.join(relative_path), hash);
+                                    }
+                                    Err(err) => warn!("Error reading file: {:?}", err),
+                                }
                             }
-                            hasher.update(buf.as_slice());
-                            let hash = hasher.finalize();
-                            file_hashes.insert(relative_path, hash.to_string());
                         }
-                    }
-                    Err(err) => {
-                        warn!("Error reading file: {:?}", err);
+                        Err(err) => {
+                            warn!("Error reading file: {:?}", err);
+                        }
                     }
                 }
             }
         }
 
         // Add environment variables
-        let environment = self
-            .environment
-            .iter()
-            .map(|env| (env.clone(), std::env::var(env).unwrap_or_default()))
-            .collect::<BTreeMap<String, String>>();
+        let environment = get_environment_values(&self.environment);
 
         // We need to sort the hashes so that the hash is always the same independently of the order which they are declared
         let variables = BTreeMap::from_iter(self.variables.clone());
 
         // Create hash data structure and hash it
         let hash_data = RecipeHashData {
-            file_hashes,
+            file_hashes: input_hashes,
             environment,
             variables,
             run: self.run.clone(),
+            args: self.args.clone(),
         };
 
         debug!("Hash data: {:?}", hash_data);
+        Ok(hash_data)
+    }
+}
 
-        let mut hasher = blake3::Hasher::new();
-        hasher.update(serde_json::to_string(&hash_data).unwrap().as_bytes());
-        let hash = hasher.finalize();
-        Ok(hash.to_string())
+/// Adds the relative path and contents of an output to a hasher, recursing into directories in
+/// a stable order
+fn hash_output_path(
+    hasher: &mut blake3::Hasher,
+    path: &Path,
+    relative_path: &Path,
+) -> std::io::Result<()> {
+    let metadata = path.symlink_metadata()?;
+    hasher.update(relative_path.to_string_lossy().as_bytes());
+    hasher.update(&[0]);
+
+    if metadata.is_symlink() {
+        hasher.update(b"link:");
+        hasher.update(std::fs::read_link(path)?.to_string_lossy().as_bytes());
+    } else if metadata.is_dir() {
+        let mut names = std::fs::read_dir(path)?
+            .map(|entry| entry.map(|entry| entry.file_name()))
+            .collect::<std::io::Result<Vec<_>>>()?;
+        names.sort();
+        for name in names {
+            hash_output_path(hasher, &path.join(&name), &relative_path.join(&name))?;
+        }
+    } else {
+        hasher.update(&metadata.len().to_le_bytes());
+        std::io::copy(&mut std::fs::File::open(path)?, hasher)?;
     }
+    Ok(())
 }
 
 #[cfg(test)]
@@ -186,6 +690,15 @@ mod tests {
             environment: vec!["FOO".to_owned()],
             variables: IndexMap::new(),
             run: String::from("test"),
+            run_file: None,
+            timeout: None,
+            requires_clean_git: false,
+            output: None,
+            shell: None,
+            weight: 1,
+            platforms: vec![],
+            args: vec![],
+            output_root: None,
             cache: Some(RecipeCacheConfig {
                 inputs: vec![String::from("build.sh")],
                 ..Default::default()
@@ -216,4 +729,105 @@ mod tests {
         assert!(set.insert(hash4));
         assert!(set.insert(hash5));
     }
+
+    #[test]
+    fn deserialize_cache() {
+        let recipe: Recipe = serde_yaml::from_str("run: echo\ncache: false").unwrap();
+        assert_eq!(recipe.cache, None);
+
+        let recipe: Recipe = serde_yaml::from_str("run: echo\ncache: true").unwrap();
+        assert_eq!(recipe.cache, Some(RecipeCacheConfig::default()));
+
+        let recipe: Recipe = serde_yaml::from_str("run: echo").unwrap();
+        assert_eq!(recipe.cache, None);
+
+        let recipe: Recipe =
+            serde_yaml::from_str("run: echo\ncache:\n  inputs: [src]\n  outputs: [dist]").unwrap();
+        assert_eq!(
+            recipe.cache,
+            Some(RecipeCacheConfig {
+                inputs: vec![String::from("src")],
+                outputs: vec!["dist".into()],
+                ..Default::default()
+            })
+        );
+
+        let recipe: Recipe = serde_yaml::from_str(
+            "run: echo\ncache:\n  outputs: [bin, { path: dist, mode: tree }, { path: lib }]",
+        )
+        .unwrap();
+        assert_eq!(
+            recipe.cache.unwrap().outputs,
+            vec![
+                RecipeOutput::from("bin"),
+                RecipeOutput {
+                    path: String::from("dist"),
+                    mode: OutputMode::Tree,
+                    root_path: None,
+                },
+                RecipeOutput::from("lib"),
+            ]
+        );
+
+        for (ttl, seconds) in [
+            ("90", 90),
+            ("30m", 30 * 60),
+            ("12h", 12 * 60 * 60),
+            ("7d", 604800),
+        ] {
+            let recipe: Recipe =
+                serde_yaml::from_str(&format!("run: echo\ncache:\n  ttl: '{}'", ttl)).unwrap();
+            assert_eq!(
+                recipe.cache.unwrap().ttl,
+                Some(Duration::from_secs(seconds))
+            );
+        }
+        let recipe: Recipe = serde_yaml::from_str("run: echo\ncache:\n  ttl: 90").unwrap();
+        assert_eq!(recipe.cache.unwrap().ttl, Some(Duration::from_secs(90)));
+        assert!(serde_yaml::from_str::<Recipe>("run: echo\ncache:\n  ttl: soon").is_err());
+    }
+
+    #[test]
+    fn deserialize_dependencies() {
+        let recipe: Recipe = serde_yaml::from_str(
+            "run: echo\ndependencies: [build, { recipe: \"db:up\", order_only: true }, { recipe: lint }]",
+        )
+        .unwrap();
+        assert_eq!(
+            recipe.dependencies.unwrap(),
+            vec![
+                RecipeDependency::from("build"),
+                RecipeDependency {
+                    recipe: String::from("db:up"),
+                    order_only: true,
+                },
+                RecipeDependency::from("lint"),
+            ]
+        );
+    }
+
+    #[test]
+    fn root_relative_inputs() {
+        let root = crate::test_utils::TestProjectBuilder::temp_dir(&[
+            ("shared/config.txt", "v1"),
+            ("bake.yml", "name: inputs\n"),
+            (
+                "apps/web/cookbook.yml",
+                "name: web\nrecipes:\n  build:\n    cache:\n      inputs:\n        - //shared/*.txt\n    \
+                 run: echo build\n",
+            ),
+        ]);
+
+        let project = crate::project::BakeProject::from(&root, IndexMap::new()).unwrap();
+        let recipe = &project.recipes["web:build"];
+        assert_eq!(
+            recipe.cache.as_ref().unwrap().inputs,
+            vec![String::from("../../shared/*.txt")]
+        );
+
+        let hash1 = recipe.get_recipe_hash().unwrap();
+        assert_eq!(recipe.get_recipe_hash().unwrap(), hash1);
+        std::fs::write(root.join("shared").join("config.txt"), "v2").unwrap();
+        assert_ne!(recipe.get_recipe_hash().unwrap(), hash1);
+    }
 }
@@ -0,0 +1,253 @@
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use anyhow::bail;
+use log::debug;
+use serde::Deserialize;
+
+/// Cookbooks loaded from a git repository shared between projects, pinned to a revision
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct RemoteCookbooks {
+    /// URL of the repository, anything `git clone` accepts
+    pub git: String,
+
+    /// Commit, tag or branch to check out. Repositories are only fetched again when it changes
+    pub rev: String,
+
+    /// Directory of the repository searched for cookbooks, the whole repository by default
+    pub path: Option<String>,
+}
+
+impl RemoteCookbooks {
+    /// Checks out the pinned revision of the repository and returns the directory to load
+    /// cookbooks from
+    ///
+    /// Each repository and revision is cloned once into `remote-cookbooks` under the bake
+    /// directory, so entries pinning the same repository to different revisions don't share a
+    /// checkout. They are only fetched again when the revision isn't available locally, so
+    /// branches are never updated unless the checkout is removed.
+    ///
+    /// # Arguments
+    /// * `bake_path` - Path of the project's `.bake` directory
+    ///
+    pub fn checkout(&self, bake_path: &Path) -> anyhow::Result<PathBuf> {
+        // Values starting with a dash would be taken as options by git
+        if self.git.starts_with('-') || self.rev.starts_with('-') {
+            bail!(
+                "Invalid remote cookbooks {} at {}: the repository and revision can't start with -",
+                self.git,
+                self.rev
+            );
+        }
+        let key = format!("{}\n{}", self.git, self.rev);
+        let checkout_path = bake_path
+            .join("remote-cookbooks")
+            .join(&blake3::hash(key.as_bytes()).to_string()[..16]);
+
+        if !checkout_path.join(".git").exists() {
+            debug!(
+                "Cloning remote cookbooks {} into {}",
+                self.git,
+                checkout_path.display()
+            );
+            let _ = std::fs::remove_dir_all(&checkout_path);
+            std::fs::create_dir_all(&checkout_path)?;
+            git(
+                &checkout_path,
+                &["clone", "--quiet", "--no-checkout", "--", &self.git, "."],
+            )?;
+        }
+
+        let commit = format!("{}^{{commit}}", self.rev);
+        let target = match git(
+            &checkout_path,
+            &["rev-parse", "--verify", "--quiet", &commit],
+        ) {
+            Ok(target) => target,
+            Err(_) => {
+                debug!("Fetching revision {} of {}", self.rev, self.git);
+                git(
+                    &checkout_path,
+                    &["fetch", "--quiet", "--tags", "--", "origin"],
+                )?;
+                // Commits that aren't reachable from any ref have to be fetched explicitly
+                if git(
+                    &checkout_path,
+                    &["rev-parse", "--verify", "--quiet", &commit],
+                )
+                .is_err()
+                {
+                    let _ = git(
+                        &checkout_path,
+                        &["fetch", "--quiet", "--", "origin", &self.rev],
+                    );
+                }
+                match git(
+                    &checkout_path,
+                    &["rev-parse", "--verify", "--quiet", &commit],
+                ) {
+                    Ok(target) => target,
+                    Err(_) => bail!(
+                        "Revision {} not found in remote cookbooks {}",
+                        self.rev,
+                        self.git
+                    ),
+                }
+            }
+        };
+
+        if git(
+            &checkout_path,
+            &["rev-parse", "--verify", "--quiet", "HEAD"],
+        )
+        .ok()
+            != Some(target.clone())
+        {
+            debug!("Checking out {} of {}", self.rev, self.git);
+            git(
+                &checkout_path,
+                &["checkout", "--quiet", "--force", "--detach", &target],
+            )?;
+        }
+
+        let path = checkout_path.join(self.path.as_deref().unwrap_or_default());
+        if !path.is_dir() {
+            bail!(
+                "Path {} not found in remote cookbooks {} at {}",
+                self.path.as_deref().unwrap_or_default(),
+                self.git,
+                self.rev
+            );
+        }
+        Ok(path)
+    }
+}
+
+/// Runs git in a directory and returns its trimmed output
+fn git(path: &Path, args: &[&str]) -> anyhow::Result<String> {
+    let output = match Command::new("git").current_dir(path).args(args).output() {
+        Ok(output) => output,
+        Err(err) => bail!("Could not run git: {}", err),
+    };
+    if !output.status.success() {
+        bail!(
+            "git {} failed with {}: {}",
+            args.join(" "),
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::Path, process::Command};
+
+    use indexmap::IndexMap;
+
+    use crate::{project::BakeProject, test_utils::TestProjectBuilder};
+
+    fn git(path: &Path, args: &[&str]) {
+        assert!(Command::new("git")
+            .current_dir(path)
+            .args(["-c", "user.name=bake", "-c", "user.email=bake@example.com"])
+            .args(args)
+            .status()
+            .unwrap()
+            .success());
+    }
+
+    /// Commits a shared cookbook running the given command and tags the commit
+    fn commit_cookbook(work_path: &Path, run: &str, tag: &str) {
+        std::fs::write(
+            work_path.join("cookbooks/shared/cookbook.yml"),
+            format!("name: shared\nrecipes:\n  lint:\n    run: {}\n", run),
+        )
+        .unwrap();
+        git(work_path, &["add", "."]);
+        git(work_path, &["commit", "--quiet", "-m", tag]);
+        git(work_path, &["tag", tag]);
+        git(
+            work_path,
+            &["push", "--quiet", "--tags", "origin", "HEAD:main"],
+        );
+    }
+
+    #[test]
+    fn load() {
+        let root_path = TestProjectBuilder::temp_dir(&[(
+            "project/app/cookbook.yml",
+            "name: app\nrecipes:\n  build:\n    run: echo app\n    dependencies: [shared:lint]\n",
+        )]);
+        let remote_path = root_path.join("remote.git");
+        let work_path = root_path.join("work");
+        std::fs::create_dir_all(&remote_path).unwrap();
+        std::fs::create_dir_all(work_path.join("cookbooks/shared")).unwrap();
+        git(&remote_path, &["init", "--quiet", "--bare"]);
+        git(&work_path, &["init", "--quiet"]);
+        git(
+            &work_path,
+            &["remote", "add", "origin", remote_path.to_str().unwrap()],
+        );
+        commit_cookbook(&work_path, "echo v1", "v1");
+        commit_cookbook(&work_path, "echo v2", "v2");
+
+        let project_path = root_path.join("project");
+        let write_config = |rev: &str| {
+            std::fs::write(
+                project_path.join("bake.yml"),
+                format!(
+                    "name: remote\nremote_cookbooks:\n  - git: {}\n    rev: {}\n    path: cookbooks/\n",
+                    remote_path.display(),
+                    rev
+                ),
+            )
+            .unwrap();
+        };
+
+        write_config("v1");
+        let mut project = BakeProject::from(&project_path, IndexMap::new()).unwrap();
+        assert_eq!(
+            project.recipes["shared:lint"].run.trim(),
+            "echo v1",
+            "the pinned revision is checked out"
+        );
+        assert!(project.recipes["app:build"]
+            .dependencies
+            .iter()
+            .flatten()
+            .any(|dep| dep.recipe == "shared:lint"));
+
+        // Remote cookbooks are kept when local cookbooks are reloaded
+        assert!(!project.is_stale());
+        project.reload().unwrap();
+        assert!(project.recipes.contains_key("shared:lint"));
+
+        // Each revision is checked out in its own clone
+        write_config("v2");
+        let project = BakeProject::from(&project_path, IndexMap::new()).unwrap();
+        assert_eq!(project.recipes["shared:lint"].run.trim(), "echo v2");
+
+        write_config("v3");
+        let err = BakeProject::from(&project_path, IndexMap::new()).unwrap_err();
+        assert!(err.to_string().contains("Revision v3 not found"));
+
+        write_config("--upload-pack=false");
+        let err = BakeProject::from(&project_path, IndexMap::new()).unwrap_err();
+        assert!(err.to_string().contains("can't start with -"));
+
+        // Local cookbooks can't share a name with remote ones
+        write_config("v1");
+        std::fs::create_dir_all(project_path.join("shared")).unwrap();
+        std::fs::write(
+            project_path.join("shared/cookbook.yml"),
+            "name: shared\nrecipes:\n  lint:\n    run: echo local\n",
+        )
+        .unwrap();
+        let err = BakeProject::from(&project_path, IndexMap::new()).unwrap_err();
+        assert!(err.to_string().contains("conflicts with a cookbook"));
+    }
+}
@@ -1,9 +1,209 @@
-use std::{collections::BTreeMap, env};
+use std::{
+    collections::{BTreeMap, HashMap},
+    env,
+    path::{Path, PathBuf},
+    sync::{LazyLock, Mutex},
+    time::SystemTime,
+};
 
 use anyhow::bail;
-use handlebars::Handlebars;
+use handlebars::{
+    Context, Handlebars, Helper, HelperDef, HelperResult, Output, RenderContext, RenderErrorReason,
+};
 use indexmap::IndexMap;
+use log::trace;
+use md5::Md5;
+use regex::Regex;
 use serde_json::json;
+use sha2::{Digest, Sha256};
+
+/// Digests of files already computed by template helpers, keyed by helper name, file path and
+/// the file's modification time and size, so a file changed since is digested again
+type FileDigestKey = (&'static str, PathBuf, Option<SystemTime>, u64);
+static FILE_DIGESTS: LazyLock<Mutex<HashMap<FileDigestKey, String>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Forgets the file digests computed so far, eg. when a long-lived process reloads the project
+pub fn clear_file_digests() {
+    FILE_DIGESTS.lock().unwrap().clear();
+}
+
+#[derive(Clone, Copy)]
+enum DigestAlgorithm {
+    Sha256,
+    Md5,
+}
+
+impl DigestAlgorithm {
+    fn helper_name(&self) -> &'static str {
+        match self {
+            DigestAlgorithm::Sha256 => "sha256-file",
+            DigestAlgorithm::Md5 => "md5-file",
+        }
+    }
+}
+
+/// Template helper that renders the hex digest of a file, eg. `{{sha256-file "build.sh"}}`.
+/// Relative paths are resolved from `base_dir`.
+struct FileDigestHelper {
+    algorithm: DigestAlgorithm,
+    base_dir: PathBuf,
+}
+
+impl HelperDef for FileDigestHelper {
+    fn call<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'rc>,
+        _: &'reg Handlebars<'reg>,
+        _: &'rc Context,
+        _: &mut RenderContext<'reg, 'rc>,
+        out: &mut dyn Output,
+    ) -> HelperResult {
+        let name = self.algorithm.helper_name();
+        let path = h
+            .param(0)
+            .and_then(|param| param.value().as_str())
+            .ok_or(RenderErrorReason::ParamNotFoundForIndex(name, 0))?;
+        let digest = file_digest(self.algorithm, &self.base_dir.join(path)).map_err(|err| {
+            RenderErrorReason::Other(format!("{} could not read {}: {}", name, path, err))
+        })?;
+        out.write(&digest)?;
+        Ok(())
+    }
+}
+
+/// Renders `{{args}}` as `"$@"`, so the arguments passed after `--` reach the recipe's shell
+/// as its positional parameters, each quoted as a single word
+struct ArgsHelper;
+
+impl HelperDef for ArgsHelper {
+    fn call<'reg: 'rc, 'rc>(
+        &self,
+        _: &Helper<'rc>,
+        _: &'reg Handlebars<'reg>,
+        _: &'rc Context,
+        _: &mut RenderContext<'reg, 'rc>,
+        out: &mut dyn Output,
+    ) -> HelperResult {
+        out.write("\"$@\"")?;
+        Ok(())
+    }
+}
+
+fn file_digest(algorithm: DigestAlgorithm, path: &Path) -> std::io::Result<String> {
+    let metadata = std::fs::metadata(path)?;
+    let key = (
+        algorithm.helper_name(),
+        path.to_path_buf(),
+        metadata.modified().ok(),
+        metadata.len(),
+    );
+    if let Some(digest) = FILE_DIGESTS.lock().unwrap().get(&key) {
+        return Ok(digest.clone());
+    }
+
+    let contents = std::fs::read(path)?;
+    let digest = match algorithm {
+        DigestAlgorithm::Sha256 => format!("{:x}", Sha256::digest(&contents)),
+        DigestAlgorithm::Md5 => format!("{:x}", Md5::digest(&contents)),
+    };
+    FILE_DIGESTS.lock().unwrap().insert(key, digest.clone());
+    Ok(digest)
+}
+
+/// Gets the values of a list of environment variables. Entries can be in the form `NAME=default`,
+/// in which case `default` is used when the variable is not set.
+pub fn get_environment_values(environment: &[String]) -> BTreeMap<String, String> {
+    environment
+        .iter()
+        .map(|entry| {
+            let (name, default) = parse_environment_entry(entry);
+            let value = env::var(name).unwrap_or_else(|_| default.unwrap_or_default().to_owned());
+            (name.to_owned(), value)
+        })
+        .collect()
+}
+
+/// Merges the environment entries of a scope into those inherited from its parent scope
+///
+/// Entries are deduplicated by name so the most specific scope wins: an entry declared by
+/// `child` replaces any parent entry with the same name, with or without a default.
+///
+/// # Arguments
+/// * `parent` - Entries of the enclosing scope, eg. the project for a cookbook
+/// * `child` - Entries declared by the scope itself
+///
+pub fn merge_environment(parent: &[String], child: &[String]) -> Vec<String> {
+    let child_names: Vec<&str> = child
+        .iter()
+        .map(|entry| parse_environment_entry(entry).0)
+        .collect();
+    let mut merged: Vec<String> = parent
+        .iter()
+        .filter(|entry| !child_names.contains(&parse_environment_entry(entry).0))
+        .cloned()
+        .collect();
+    for (index, entry) in child.iter().enumerate() {
+        // Within a scope the last entry for a name wins
+        if !child_names[index + 1..].contains(&child_names[index]) {
+            merged.push(entry.clone());
+        }
+    }
+    merged
+}
+
+/// Matches shell style `${NAME}` references
+static SHELL_VAR_PATTERN: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\$\{([A-Za-z_][A-Za-z0-9_]*)\}").unwrap());
+
+/// Expands shell style `${NAME}` references to the values of the declared environment variables
+///
+/// References to variables that aren't declared are left untouched, so that a shell running the
+/// result can still expand them.
+///
+/// # Arguments
+/// * `template` - Text to expand, such as a recipe's run command or an output path
+/// * `environment` - Environment variables available to the template
+///
+pub fn expand_shell_vars(template: &str, environment: &[String]) -> String {
+    let env_values = get_environment_values(environment);
+    SHELL_VAR_PATTERN
+        .replace_all(template, |captures: &regex::Captures| {
+            env_values
+                .get(&captures[1])
+                .cloned()
+                .unwrap_or_else(|| captures[0].to_owned())
+        })
+        .into_owned()
+}
+
+/// Expands shell style `${NAME}` references to the values of variables in bake's own
+/// environment, failing on variables that aren't set rather than leaving them empty
+///
+/// # Arguments
+/// * `template` - Text to expand, such as a configured path
+///
+pub fn expand_process_env_vars(template: &str) -> anyhow::Result<String> {
+    if let Some(captures) = SHELL_VAR_PATTERN
+        .captures_iter(template)
+        .find(|captures| env::var(&captures[1]).is_err())
+    {
+        bail!("Environment variable {} is not set", &captures[1]);
+    }
+    Ok(SHELL_VAR_PATTERN
+        .replace_all(template, |captures: &regex::Captures| {
+            env::var(&captures[1]).unwrap_or_default()
+        })
+        .into_owned())
+}
+
+/// Splits an environment entry in the form `NAME` or `NAME=default` into its name and default
+pub fn parse_environment_entry(entry: &str) -> (&str, Option<&str>) {
+    match entry.split_once('=') {
+        Some((name, default)) => (name.trim(), Some(default)),
+        None => (entry.trim(), None),
+    }
+}
 
 pub fn parse_template(
     template: &str,
@@ -12,15 +212,29 @@ pub fn parse_template(
     constants: &IndexMap<String, IndexMap<String, String>>,
 ) -> anyhow::Result<String> {
     // Get environment variables list from environment list
-    let env_values: BTreeMap<String, String> = environment
+    let env_values = get_environment_values(environment);
+
+    // File helpers resolve relative paths from the innermost scope's root
+    let base_dir = ["cookbook", "project"]
         .iter()
-        .map(|name| (name.to_string(), env::var(name).unwrap_or_default()))
-        .collect();
+        .find_map(|scope| constants.get(*scope).and_then(|c| c.get("root")))
+        .map(PathBuf::from)
+        .unwrap_or_else(|| env::current_dir().unwrap_or_default());
 
     let mut handlebars = Handlebars::new();
     handlebars
         .register_template_string("template", template)
         .expect("Failed to register template");
+    for algorithm in [DigestAlgorithm::Sha256, DigestAlgorithm::Md5] {
+        handlebars.register_helper(
+            algorithm.helper_name(),
+            Box::new(FileDigestHelper {
+                algorithm,
+                base_dir: base_dir.clone(),
+            }),
+        );
+    }
+    handlebars.register_helper("args", Box::new(ArgsHelper));
 
     let mut data = BTreeMap::from([("env", json!(env_values)), ("var", json!(variables))]);
     data.extend(constants.iter().map(|(k, v)| (k.as_ref(), json!(v))));
@@ -31,7 +245,25 @@ pub fn parse_template(
     }
 }
 
+/// Log target used to trace variable resolution, enabled by `--trace-vars`
+pub const TRACE_VARS_TARGET: &str = "bake::vars";
+
+/// Parses a list of variables in order, so that each variable can reference the ones declared
+/// before it
+///
+/// Override values come from the command line and are used as they are, so they can contain
+/// `{{` without being rendered. Overrides that are templates are applied with
+/// `replace_variables` instead.
+///
+/// # Arguments
+/// * `scope` - Name of the scope being resolved, used when tracing (eg. `cookbook foo`)
+/// * `environment` - Environment variables available to templates
+/// * `variables` - Variables to parse
+/// * `constants` - Constants available to templates such as `project.root`
+/// * `override_variables` - Variables passed in the command line that take precedence
+///
 pub fn parse_variable_list(
+    scope: &str,
     environment: &[String],
     variables: &IndexMap<String, String>,
     constants: &IndexMap<String, IndexMap<String, String>>,
@@ -40,20 +272,97 @@ pub fn parse_variable_list(
     variables
         .iter()
         .try_fold(IndexMap::new(), |mut acc, (k, v)| {
-            if override_variables.contains_key(k) {
-                acc.insert(k.clone(), override_variables[k].clone());
+            if let Some(override_value) = override_variables.get(k) {
+                trace!(
+                    target: TRACE_VARS_TARGET,
+                    "[{}] {}: overridden with {:?}",
+                    scope,
+                    k,
+                    override_value
+                );
+                acc.insert(k.clone(), override_value.clone());
                 return Ok(acc);
             }
             let parsed_var = parse_template(v, environment, &acc, constants)?;
+            trace!(
+                target: TRACE_VARS_TARGET,
+                "[{}] {}: {:?} => {:?}",
+                scope,
+                k,
+                v,
+                parsed_var
+            );
             acc.insert(k.clone(), parsed_var);
             Ok(acc)
         })
 }
 
+/// Replaces the values of declared variables with overrides that are templates, such as the
+/// ones of an environment, keeping their position so they're rendered in declaration order and
+/// can reference other overridden variables. Overrides of undeclared variables are ignored.
+///
+/// # Arguments
+/// * `variables` - Declared variables, not rendered yet
+/// * `overrides` - Values replacing the declared ones
+///
+pub fn replace_variables(
+    variables: &mut IndexMap<String, String>,
+    overrides: &IndexMap<String, String>,
+) {
+    for (k, v) in overrides {
+        if let Some(value) = variables.get_mut(k) {
+            *value = v.clone();
+        }
+    }
+}
+
+/// Traces which variables of a scope are inherited from its parent scope
+pub fn trace_inherited_variables(
+    scope: &str,
+    parent_scope: &str,
+    parent_variables: &IndexMap<String, String>,
+    variables: &IndexMap<String, String>,
+) {
+    for (k, v) in parent_variables {
+        if !variables.contains_key(k) {
+            trace!(
+                target: TRACE_VARS_TARGET,
+                "[{}] {}: inherited from {} as {:?}",
+                scope,
+                k,
+                parent_scope,
+                v
+            );
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
 
+    #[test]
+    fn test_merge_environment() {
+        let project = vec!["LEVEL=project".to_owned(), "PROJECT".to_owned()];
+        let cookbook = merge_environment(&project, &["LEVEL=cookbook".to_owned()]);
+        assert_eq!(cookbook, vec!["PROJECT", "LEVEL=cookbook"]);
+
+        let recipe = merge_environment(&cookbook, &["LEVEL".to_owned(), "LEVEL=recipe".to_owned()]);
+        assert_eq!(recipe, vec!["PROJECT", "LEVEL=recipe"]);
+    }
+
+    #[test]
+    fn test_expand_shell_vars() {
+        let environment = vec!["SHELL_VARS_DIR=dist".to_owned()];
+        assert_eq!(
+            expand_shell_vars(
+                "${SHELL_VARS_DIR}/app ${UNDECLARED} $SHELL_VARS_DIR {{var.foo}}",
+                &environment
+            ),
+            "dist/app ${UNDECLARED} $SHELL_VARS_DIR {{var.foo}}"
+        );
+    }
+
     #[test]
     fn test_parse_template() {
         let variables = IndexMap::from([("foo".to_owned(), "bar".to_owned())]);
@@ -91,6 +400,79 @@ mod test {
         )
         .unwrap();
         assert_eq!(result, "env_var");
+
+        let result = parse_template("serve {{args}}", &[], &variables, &constants).unwrap();
+        assert_eq!(result, "serve \"$@\"");
+    }
+
+    #[test]
+    fn test_file_digest_helpers() {
+        let root = env::temp_dir().join("test_file_digest_helpers");
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("hello.txt"), "hello").unwrap();
+
+        let constants = IndexMap::from([(
+            "project".to_owned(),
+            IndexMap::from([("root".to_owned(), root.display().to_string())]),
+        )]);
+
+        let result = parse_template(
+            "{{sha256-file \"hello.txt\"}} {{md5-file \"hello.txt\"}}",
+            &[],
+            &IndexMap::new(),
+            &constants,
+        )
+        .unwrap();
+        assert_eq!(
+            result,
+            "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824 \
+             5d41402abc4b2a76b9719d911017c592"
+        );
+
+        assert!(parse_template(
+            "{{sha256-file \"missing.txt\"}}",
+            &[],
+            &IndexMap::new(),
+            &constants
+        )
+        .is_err());
+
+        // Changed files are digested again
+        std::fs::write(root.join("hello.txt"), "hello!").unwrap();
+        let result = parse_template(
+            "{{md5-file \"hello.txt\"}}",
+            &[],
+            &IndexMap::new(),
+            &constants,
+        )
+        .unwrap();
+        assert_eq!(result, "5a8dd3ad0756a93ded72b823b19dd877");
+    }
+
+    #[test]
+    fn test_environment_defaults() {
+        let environment = vec![
+            "TEST_ENV_DEFAULT_UNSET=fallback".to_owned(),
+            "TEST_ENV_DEFAULT_SET=fallback".to_owned(),
+            "TEST_ENV_DEFAULT_PLAIN".to_owned(),
+        ];
+        env::remove_var("TEST_ENV_DEFAULT_UNSET");
+        env::remove_var("TEST_ENV_DEFAULT_PLAIN");
+        env::set_var("TEST_ENV_DEFAULT_SET", "real");
+
+        let values = get_environment_values(&environment);
+        assert_eq!(values["TEST_ENV_DEFAULT_UNSET"], "fallback");
+        assert_eq!(values["TEST_ENV_DEFAULT_SET"], "real");
+        assert_eq!(values["TEST_ENV_DEFAULT_PLAIN"], "");
+
+        let result = parse_template(
+            "{{env.TEST_ENV_DEFAULT_UNSET}} {{env.TEST_ENV_DEFAULT_SET}}",
+            &environment,
+            &IndexMap::new(),
+            &IndexMap::new(),
+        )
+        .unwrap();
+        assert_eq!(result, "fallback real");
     }
 
     #[test]
@@ -115,12 +497,42 @@ mod test {
             ("goo".to_owned(), "{{ var.bar }}".to_owned()),
         ]);
 
-        let result =
-            parse_variable_list(environment.as_slice(), &variables, &constants, &overrides)
-                .unwrap();
+        let result = parse_variable_list(
+            "test",
+            environment.as_slice(),
+            &variables,
+            &constants,
+            &overrides,
+        )
+        .unwrap();
         assert_eq!(result.get("foo").unwrap(), "bar");
         assert_eq!(result.get("baz").unwrap(), "bar");
         assert_eq!(result.get("bar").unwrap(), "override");
         assert_eq!(result.get("goo").unwrap(), "override");
     }
+
+    #[test]
+    fn test_parse_variable_list_dependent_overrides() {
+        let mut variables = IndexMap::from([
+            ("host".to_owned(), "localhost".to_owned()),
+            ("port".to_owned(), "8080".to_owned()),
+            ("url".to_owned(), "http://{{var.host}}".to_owned()),
+        ]);
+        replace_variables(
+            &mut variables,
+            &IndexMap::from([
+                ("url".to_owned(), "{{var.host}}:{{var.port}}".to_owned()),
+                ("port".to_owned(), "9090".to_owned()),
+                ("undeclared".to_owned(), "ignored".to_owned()),
+            ]),
+        );
+        // Command line overrides are literal
+        let overrides = IndexMap::from([("host".to_owned(), "{{ host }}".to_owned())]);
+
+        let result =
+            parse_variable_list("test", &[], &variables, &IndexMap::new(), &overrides).unwrap();
+        assert_eq!(result.get("port").unwrap(), "9090");
+        assert_eq!(result.get("url").unwrap(), "{{ host }}:9090");
+        assert!(!result.contains_key("undeclared"));
+    }
 }
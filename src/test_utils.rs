@@ -1,9 +1,13 @@
-use std::collections::{BTreeMap, HashSet};
+use std::{
+    collections::{BTreeMap, HashSet},
+    path::PathBuf,
+};
 
 use indexmap::IndexMap;
 
+use crate::diagnostics::Diagnostics;
 use crate::project::config::ToolConfig;
-use crate::project::{BakeProject, Cookbook, Recipe};
+use crate::project::{BakeProject, Cookbook, Recipe, RecipeCacheConfig};
 use rand::distributions::{Alphanumeric, DistString};
 
 pub struct TestProjectBuilder {
@@ -12,9 +16,7 @@ pub struct TestProjectBuilder {
 
 impl TestProjectBuilder {
     pub fn new() -> Self {
-        let temp_dir =
-            std::env::temp_dir().join(Alphanumeric.sample_string(&mut rand::thread_rng(), 16));
-        std::fs::create_dir_all(&temp_dir).unwrap();
+        let temp_dir = Self::temp_dir(&[]);
         let project = BakeProject {
             name: "test".to_owned(),
             cookbooks: BTreeMap::new(),
@@ -22,13 +24,36 @@ impl TestProjectBuilder {
             description: Some("".to_owned()),
             variables: IndexMap::new(),
             environment: vec![],
+            aliases: BTreeMap::new(),
+            overrides: BTreeMap::new(),
+            remote_cookbooks: vec![],
             config: ToolConfig::default(),
             root_path: temp_dir,
             dependency_map: BTreeMap::new(),
+            diagnostics: Diagnostics::default(),
+            source: Default::default(),
         };
         Self { project }
     }
 
+    /// Creates an empty temporary directory and writes the given files into it, such as a
+    /// `bake.yml` and cookbooks to load a project from
+    ///
+    /// # Arguments
+    /// * `files` - Paths relative to the directory and their contents
+    ///
+    pub fn temp_dir(files: &[(&str, &str)]) -> PathBuf {
+        let temp_dir =
+            std::env::temp_dir().join(Alphanumeric.sample_string(&mut rand::thread_rng(), 16));
+        std::fs::create_dir_all(&temp_dir).unwrap();
+        for (path, contents) in files {
+            let path = temp_dir.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        }
+        temp_dir
+    }
+
     pub fn with_cookbook(mut self, name: &str, recipes: &[&str]) -> Self {
         let config_path = self.project.root_path.join(format!("{}.yml", name));
         let recipes: BTreeMap<String, Recipe> = recipes
@@ -45,6 +70,15 @@ impl TestProjectBuilder {
                         environment: vec![],
                         variables: IndexMap::new(),
                         run: format!("echo Hello from recipe {}", recipe),
+                        run_file: None,
+                        timeout: None,
+                        requires_clean_git: false,
+                        output: None,
+                        shell: None,
+                        weight: 1,
+                        platforms: vec![],
+                        args: vec![],
+                        output_root: None,
                         run_status: Default::default(),
                         config_path: config_path.clone(),
                     },
@@ -69,6 +103,8 @@ impl TestProjectBuilder {
             environment: vec![],
             variables: IndexMap::new(),
             recipes,
+            recipe_files: vec![],
+            max_parallel: None,
             config_path: config_path.clone(),
         };
 
@@ -79,6 +115,13 @@ impl TestProjectBuilder {
         self
     }
 
+    /// Makes a recipe depend on another, both in the project's dependency map and in the
+    /// recipe's own `dependencies`, which start out empty
+    ///
+    /// # Arguments
+    /// * `recipe` - Full name of the dependent recipe
+    /// * `dependency` - Full name of the recipe it depends on
+    ///
     pub fn with_dependency(mut self, recipe: &str, dependency: &str) -> Self {
         self.project
             .dependency_map
@@ -91,9 +134,25 @@ impl TestProjectBuilder {
             .get_mut(recipe)
             .unwrap()
             .dependencies
-            .as_mut()
-            .unwrap_or(Vec::new().as_mut())
-            .push(dependency.to_owned());
+            .get_or_insert_with(Vec::new)
+            .push(dependency.into());
+        self
+    }
+
+    /// Makes a recipe write a command's output to a file and cache it as its only output
+    ///
+    /// # Arguments
+    /// * `recipe` - Full name of the recipe
+    /// * `command` - Command whose standard output is written to the file
+    /// * `output` - Path of the output file, relative to the recipe's cookbook
+    ///
+    pub fn with_output(mut self, recipe: &str, command: &str, output: &str) -> Self {
+        let recipe = self.project.recipes.get_mut(recipe).unwrap();
+        recipe.run = format!("{} > {}", command, output);
+        recipe.cache = Some(RecipeCacheConfig {
+            outputs: vec![output.into()],
+            ..Default::default()
+        });
         self
     }
 
@@ -101,3 +160,19 @@ impl TestProjectBuilder {
         self.project
     }
 }
+
+/// Polls a condition until it holds, giving up after a few seconds so that a failing test
+/// reports its assertion instead of hanging. Returns whether the condition held
+///
+/// # Arguments
+/// * `condition` - The condition to wait for
+///
+pub async fn wait_until(mut condition: impl FnMut() -> bool) -> bool {
+    for _ in 0..200 {
+        if condition() {
+            return true;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(25)).await;
+    }
+    condition()
+}
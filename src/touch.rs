@@ -0,0 +1,125 @@
+use std::collections::BTreeMap;
+
+use crate::{
+    cache::Cache,
+    project::{BakeProject, RecipeSearch},
+};
+
+/// Outcome of storing a recipe's current outputs in the cache without running it
+#[derive(Debug, PartialEq, Eq)]
+pub enum TouchStatus {
+    /// The outputs were stored under the recipe's current cache key
+    Stored,
+
+    /// Declared outputs that don't exist on disk, nothing was stored
+    MissingOutputs(Vec<String>),
+
+    /// The outputs could not be stored
+    Failed(String),
+}
+
+/// Marks the cached recipes matching a search as up to date by storing their current outputs
+/// in the cache, without running them
+///
+/// Useful to seed the cache after building manually, so the next bake is a cache hit. A recipe
+/// that never ran gets a log noting it was touched.
+///
+/// # Arguments
+/// * `project` - The project the recipes belong to
+/// * `cache` - Cache to store the outputs in, keyed by the outputs currently on disk
+/// * `search` - Criteria used to select the recipes to touch
+///
+pub async fn touch_recipes(
+    project: &BakeProject,
+    cache: &Cache,
+    search: &RecipeSearch,
+) -> anyhow::Result<BTreeMap<String, TouchStatus>> {
+    project.create_project_bake_dirs()?;
+    let mut results = BTreeMap::new();
+
+    for (name, recipe) in project
+        .recipes
+        .iter()
+        .filter(|(name, recipe)| search.matches(name) && project.is_cached(recipe))
+    {
+        let missing = recipe.missing_outputs();
+        if !missing.is_empty() {
+            results.insert(name.clone(), TouchStatus::MissingOutputs(missing));
+            continue;
+        }
+
+        let log_path = project.get_recipe_log_path(name);
+        if !log_path.exists() {
+            std::fs::write(&log_path, "Touched without running\n")?;
+        }
+
+        let status = match cache.put(name).await {
+            Ok(_) => TouchStatus::Stored,
+            Err(err) => TouchStatus::Failed(err.to_string()),
+        };
+        results.insert(name.clone(), status);
+    }
+
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::{
+        cache::{local::LocalCacheStrategy, CacheBuilder, CacheStrategy},
+        project::{RecipeCacheConfig, RecipeSearch},
+        test_utils::TestProjectBuilder,
+    };
+
+    use super::TouchStatus;
+
+    #[tokio::test]
+    async fn touch_recipes() {
+        let mut project = TestProjectBuilder::new()
+            .with_cookbook("touch", &["build", "missing"])
+            .build();
+        project.config.max_parallel = 2;
+        let runs_path = project.root_path.join("runs.txt");
+        for name in ["build", "missing"] {
+            let recipe = project.recipes.get_mut(&format!("touch:{}", name)).unwrap();
+            recipe.cache = Some(RecipeCacheConfig {
+                outputs: vec![format!("{}-dist", name).into()],
+                ..Default::default()
+            });
+            recipe.run = format!(
+                "echo {} >> {} && mkdir -p {}-dist",
+                name,
+                runs_path.display(),
+                name
+            );
+        }
+        std::fs::create_dir_all(project.root_path.join("build-dist")).unwrap();
+        let project = Arc::new(project);
+        let search = RecipeSearch::All;
+        let build_cache = || async {
+            CacheBuilder::new(project.clone())
+                .add_strategy("local", LocalCacheStrategy::from_config)
+                .build()
+                .await
+                .unwrap()
+        };
+
+        let results = super::touch_recipes(&project, &build_cache().await, &search)
+            .await
+            .unwrap();
+        assert_eq!(results["touch:build"], TouchStatus::Stored);
+        assert_eq!(
+            results["touch:missing"],
+            TouchStatus::MissingOutputs(vec![String::from("missing-dist")])
+        );
+        assert!(!runs_path.exists());
+
+        // Only the recipe without outputs runs afterwards
+        crate::baker::bake(project.clone(), build_cache().await, &search)
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read_to_string(&runs_path).unwrap(), "missing\n");
+    }
+}
@@ -0,0 +1,262 @@
+use std::{path::PathBuf, sync::Arc, time::Duration};
+
+use anyhow::bail;
+use log::{debug, warn};
+use tokio::process::Child;
+
+use crate::{
+    baker,
+    cache::CacheBuilder,
+    project::{BakeProject, RecipeSearch},
+};
+
+/// Interval between checks for changes in recipe inputs
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Command started after each successful bake in watch mode, such as a dev server
+///
+/// The previous instance and any processes it started are killed before starting a new one, and
+/// when the watcher is dropped.
+#[derive(Debug)]
+pub struct WatchExec {
+    command: String,
+    root_path: PathBuf,
+    child: Option<Child>,
+}
+
+impl WatchExec {
+    /// Creates a watch command that runs in the given directory
+    ///
+    /// # Arguments
+    /// * `command` - Shell command to run
+    /// * `root_path` - Directory to run the command in, usually the project root
+    ///
+    pub fn new(command: String, root_path: PathBuf) -> Self {
+        Self {
+            command,
+            root_path,
+            child: None,
+        }
+    }
+
+    /// Kills the previous instance of the command if it's still running and starts it again
+    pub async fn restart(&mut self) {
+        if let Some(mut child) = self.child.take() {
+            match child.try_wait() {
+                Ok(Some(status)) if !status.success() => {
+                    warn!("Watch command `{}` failed with {}", self.command, status)
+                }
+                Ok(Some(_)) => {}
+                Ok(None) | Err(_) => {
+                    debug!("Killing previous watch command");
+                    kill_process_group(&child);
+                    if let Err(err) = child.wait().await {
+                        warn!("Could not kill watch command `{}`: {}", self.command, err);
+                    }
+                }
+            }
+        }
+
+        let mut command = tokio::process::Command::new("sh");
+        command
+            .current_dir(&self.root_path)
+            .arg("-c")
+            .arg(&self.command);
+        // Run in its own process group so processes started by the command are killed too
+        #[cfg(unix)]
+        command.process_group(0);
+        match command.spawn() {
+            Ok(child) => self.child = Some(child),
+            Err(err) => warn!("Could not spawn watch command `{}`: {}", self.command, err),
+        }
+    }
+}
+
+impl Drop for WatchExec {
+    fn drop(&mut self) {
+        if let Some(child) = self.child.as_ref() {
+            kill_process_group(child);
+        }
+    }
+}
+
+/// Kills a child process and every process in its group
+fn kill_process_group(child: &Child) {
+    if let Some(pid) = child.id() {
+        baker::kill_process_group(pid);
+    }
+}
+
+/// Bakes the selected recipes once, restarting the watch command if baking succeeded
+///
+/// # Arguments
+/// * `project` - The project to bake
+/// * `search` - Criteria used to select the recipes to bake
+/// * `force_rebuild` - Whether to bake the recipes even if they're cached
+/// * `exec` - Command to restart after a successful bake
+///
+pub async fn run_cycle(
+    project: Arc<BakeProject>,
+    search: &RecipeSearch,
+    force_rebuild: bool,
+    exec: Option<&mut WatchExec>,
+) -> anyhow::Result<()> {
+    let cache = CacheBuilder::new(project.clone())
+        .default_strategies()
+        .search(search.clone())
+        .force_rebuild(force_rebuild)
+        .build()
+        .await?;
+    baker::bake(project, cache, search).await?;
+
+    if let Some(exec) = exec {
+        exec.restart().await;
+    }
+    Ok(())
+}
+
+/// Bakes the selected recipes and bakes them again whenever their inputs change, until
+/// interrupted
+///
+/// Changes are detected by polling the recipes' cache keys and the project's config and cookbook
+/// files. Only the cookbooks that changed are parsed again before the next bake.
+///
+/// # Arguments
+/// * `project` - The project to bake
+/// * `search` - Criteria used to select the recipes to bake
+/// * `force_rebuild` - Whether to bake the recipes even if they're cached, on every bake
+/// * `exec` - Command to restart after each successful bake
+///
+pub async fn watch(
+    mut project: BakeProject,
+    search: &RecipeSearch,
+    force_rebuild: bool,
+    mut exec: Option<WatchExec>,
+) -> anyhow::Result<()> {
+    loop {
+        let shared_project = Arc::new(project);
+        let result = run_cycle(shared_project.clone(), search, force_rebuild, exec.as_mut()).await;
+        if let Err(err) = result {
+            println!("{}", err);
+        }
+        println!("Watching for changes...");
+
+        let mut hash_builder = CacheBuilder::new(shared_project.clone());
+        hash_builder.search(search.clone());
+        let hashes = hash_builder.calculate_all_hashes()?;
+        loop {
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => return Ok(()),
+                _ = tokio::time::sleep(POLL_INTERVAL) => {}
+            }
+            if shared_project.is_stale() || hash_builder.calculate_all_hashes()? != hashes {
+                break;
+            }
+        }
+        drop(hash_builder);
+
+        project = match Arc::into_inner(shared_project) {
+            Some(project) => project,
+            None => bail!("Could not reload project, it is still used by the previous bake"),
+        };
+        match project.reload() {
+            Ok(reloaded) if !reloaded.is_empty() => {
+                println!("Reloaded {} cookbook files", reloaded.len());
+            }
+            Ok(_) => {}
+            Err(err) => println!(
+                "Could not reload project, keeping the previous one: {}",
+                err
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use test_case::test_case;
+
+    use crate::{
+        project::{RecipeCacheConfig, RecipeSearch},
+        test_utils::{wait_until, TestProjectBuilder},
+    };
+
+    use super::WatchExec;
+
+    #[tokio::test]
+    async fn exec_once_per_successful_cycle() {
+        let mut project = TestProjectBuilder::new()
+            .with_cookbook("watch", &["build"])
+            .build();
+        project.config.max_parallel = 2;
+        project.recipes.get_mut("watch:build").unwrap().run = String::from("exit 0");
+        let runs_path = project.root_path.join("runs.txt");
+        let mut exec = WatchExec::new(
+            format!("echo run >> {}; sleep 30", runs_path.display()),
+            project.root_path.clone(),
+        );
+        let project = Arc::new(project);
+        let search = RecipeSearch::All;
+
+        for _ in 0..2 {
+            super::run_cycle(project.clone(), &search, false, Some(&mut exec))
+                .await
+                .unwrap();
+        }
+
+        let mut failing_project = TestProjectBuilder::new()
+            .with_cookbook("watch", &["build"])
+            .build();
+        failing_project.config.max_parallel = 2;
+        failing_project.recipes.get_mut("watch:build").unwrap().run = String::from("false");
+        let failing_project = Arc::new(failing_project);
+        assert!(
+            super::run_cycle(failing_project, &search, false, Some(&mut exec))
+                .await
+                .is_err()
+        );
+
+        // The failed cycle doesn't restart the last instance, which keeps running
+        let runs = || std::fs::read_to_string(&runs_path).unwrap_or_default();
+        wait_until(|| runs() == "run\nrun\n").await;
+        assert_eq!(runs(), "run\nrun\n");
+        let child = exec.child.as_mut().unwrap();
+        assert!(child.try_wait().unwrap().is_none());
+    }
+
+    #[test_case(false => "run\n"; "Cached after the first cycle")]
+    #[test_case(true => "run\nrun\n"; "Forced on every cycle")]
+    #[tokio::test]
+    async fn force_rebuild_every_cycle(force_rebuild: bool) -> String {
+        let mut project = TestProjectBuilder::new()
+            .with_cookbook("watch", &["build"])
+            .build();
+        project.config.max_parallel = 2;
+        let runs_path = project.root_path.join("runs.txt");
+        let recipe = project.recipes.get_mut("watch:build").unwrap();
+        recipe.cache = Some(RecipeCacheConfig::default());
+        recipe.run = format!("echo run >> {}", runs_path.display());
+        let project = Arc::new(project);
+
+        for _ in 0..2 {
+            super::run_cycle(project.clone(), &RecipeSearch::All, force_rebuild, None)
+                .await
+                .unwrap();
+        }
+        std::fs::read_to_string(&runs_path).unwrap()
+    }
+
+    #[tokio::test]
+    async fn failing_exec() {
+        let project = TestProjectBuilder::new().build();
+        let mut exec = WatchExec::new(String::from("exit 1"), project.root_path.clone());
+        exec.restart().await;
+        let child = exec.child.as_mut().unwrap();
+        assert!(wait_until(|| child.try_wait().unwrap().is_some()).await);
+        // A failed previous instance is only logged
+        exec.restart().await;
+        assert!(exec.child.is_some());
+    }
+}